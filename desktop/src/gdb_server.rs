@@ -0,0 +1,112 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use riscv_emu::debug::gdb_stub::GdbConnection;
+
+/// Stream socket which GDB is connected to.
+trait Socket: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Socket for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+struct SocketConnection<S: Socket> {
+    socket: S,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<S: Socket> SocketConnection<S> {
+    fn new(socket: S) -> Self {
+        SocketConnection {
+            socket,
+            buffer: vec![],
+            position: 0,
+        }
+    }
+
+    /// Fills the buffer. Returns false when the connection is closed or no
+    /// data has arrived yet in the non-blocking mode.
+    fn fill(&mut self, nonblocking: bool) -> bool {
+        if self.socket.set_nonblocking(nonblocking).is_err() {
+            return false;
+        }
+        let mut buffer = [0; 4096];
+        match self.socket.read(&mut buffer) {
+            Ok(0) => false,
+            Ok(length) => {
+                self.buffer = buffer[..length].to_vec();
+                self.position = 0;
+                true
+            }
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => self.fill(nonblocking),
+            Err(_) => false,
+        }
+    }
+
+    fn next(&mut self, nonblocking: bool) -> Option<u8> {
+        if self.position >= self.buffer.len() && !self.fill(nonblocking) {
+            return None;
+        }
+        self.position += 1;
+        Some(self.buffer[self.position - 1])
+    }
+}
+
+impl<S: Socket> GdbConnection for SocketConnection<S> {
+    fn read(&mut self) -> Option<u8> {
+        self.next(false)
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.next(true)
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        if self.socket.set_nonblocking(false).is_ok() {
+            let _ = self.socket.write_all(data);
+        }
+    }
+}
+
+/// Waits for GDB to connect to `address`, which is a TCP port on localhost
+/// or "unix:PATH" of a Unix domain socket.
+pub fn wait_for_gdb(address: &str) -> Box<dyn GdbConnection> {
+    #[cfg(unix)]
+    {
+        if let Some(path) = address.strip_prefix("unix:") {
+            let _ = std::fs::remove_file(path);
+            let listener = UnixListener::bind(path)
+                .unwrap_or_else(|why| panic!("Failed to listen on {}: {}", path, why));
+            println!("Waiting for GDB on {}", path);
+            let (stream, _) = listener
+                .accept()
+                .unwrap_or_else(|why| panic!("Failed to accept GDB: {}", why));
+            return Box::new(SocketConnection::new(stream));
+        }
+    }
+    let port = address
+        .parse::<u16>()
+        .unwrap_or_else(|_| panic!("Invalid GDB port: {}", address));
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .unwrap_or_else(|why| panic!("Failed to listen on port {}: {}", port, why));
+    println!("Waiting for GDB on localhost:{}", port);
+    let (stream, _) = listener
+        .accept()
+        .unwrap_or_else(|why| panic!("Failed to accept GDB: {}", why));
+    let _ = stream.set_nodelay(true);
+    Box::new(SocketConnection::new(stream))
+}
@@ -1 +1,3 @@
+pub mod gdb_server;
+pub mod remote_bitbang;
 pub mod tty;
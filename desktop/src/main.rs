@@ -3,15 +3,33 @@ extern crate riscv_emu;
 
 use riscv_emu::bus::bus::Device;
 use riscv_emu::console::TtyDummy;
-use riscv_emu::emulator::Emulator;
+use riscv_emu::cosim::Divergence;
+use riscv_emu::debug::gdb_stub::GdbStub;
+use riscv_emu::emulator::{Emulator, RunError, StopConditions, StopReason};
 use riscv_emu::machine::Machine;
+use riscv_emu::trace::{
+    BinarySink, SpikeCommitLogSink, SymbolizedSink, TextSink, TraceCategory, TraceFilter,
+    COMMIT_LOG_CATEGORIES,
+};
 
+use riscv_emu_desktop::gdb_server::wait_for_gdb;
+use riscv_emu_desktop::remote_bitbang::RemoteBitbang;
 use riscv_emu_desktop::tty::Tty;
 
 use getopts::Options;
+use std::io::{self, BufWriter};
 use std::path::PathBuf;
 use std::{env, process};
 
+/// Number of the cycles run between the polls of the remote_bitbang server.
+const REMOTE_BITBANG_POLL_CYCLES: u64 = 1000;
+
+/// Number of the checkpoints for reverse execution unless `--reverse-checkpoints` is given.
+const DEFAULT_REVERSE_CHECKPOINTS: usize = 32;
+
+/// Number of the hot functions reported unless `--profile-top` is given.
+const DEFAULT_PROFILE_TOP: usize = 20;
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
@@ -36,7 +54,137 @@ fn main() {
         "Target machine (SiFive_e|SiFive_u|Qemu_virt)",
         "SiFive_e",
     );
+    opts.optopt(
+        "",
+        "entropy-seed",
+        "Seed of the entropy source (seed CSR) for reproducible runs",
+        "0",
+    );
+    opts.optopt(
+        "",
+        "cache-block-size",
+        "Cache block size of the cbo.* instructions in bytes",
+        "64",
+    );
+    opts.optopt(
+        "g",
+        "gdb",
+        "Wait for GDB on the local TCP port or the Unix socket (unix:PATH)",
+        "1234",
+    );
+    opts.optopt(
+        "",
+        "remote-bitbang",
+        "Serve JTAG for OpenOCD remote_bitbang on the local TCP port",
+        "9824",
+    );
+    opts.optopt(
+        "",
+        "trace-events",
+        "Trace the events of the categories (all|instruction,register,memory,trap,interrupt,mmio,pagewalk,csr,branch,call)",
+        "instruction,trap",
+    );
+    opts.optopt(
+        "",
+        "trace-file",
+        "Write the trace events to the file in the binary form instead of stdout",
+        "trace.bin",
+    );
+    opts.optopt(
+        "",
+        "trace-pc",
+        "Trace only the events of the instructions in the PC range (hex, end exclusive)",
+        "80000000:80001000",
+    );
+    opts.optflag(
+        "",
+        "log-commits",
+        "Write the retired instructions to stderr in the format of Spike's --log-commits",
+    );
+    opts.optopt(
+        "",
+        "snapshot-in",
+        "Restore the machine from the snapshot file before running",
+        "xv6.snap",
+    );
+    opts.optopt(
+        "",
+        "snapshot-out",
+        "Save the machine to the snapshot file when the run stops",
+        "xv6.snap",
+    );
+    opts.optopt(
+        "",
+        "snapshot-cycles",
+        "Stop the run after the number of cycles to save the snapshot",
+        "100000000",
+    );
+    opts.optopt(
+        "",
+        "cycle-limit",
+        "Stop the run after the number of cycles, and save the snapshot and the reports",
+        "100000000",
+    );
+    opts.optopt(
+        "",
+        "record-inputs",
+        "Record the console input and the entropy seed to the file",
+        "inputs.log",
+    );
+    opts.optopt(
+        "",
+        "replay-inputs",
+        "Replay the inputs recorded to the file instead of the console input",
+        "inputs.log",
+    );
+    opts.optopt(
+        "",
+        "reverse-interval",
+        "Keep checkpoints at the interval of cycles for reverse execution in GDB",
+        "10000000",
+    );
+    opts.optopt(
+        "",
+        "reverse-checkpoints",
+        "Maximum number of the checkpoints for reverse execution (default: 32)",
+        "32",
+    );
+    opts.optopt(
+        "",
+        "coverage-drcov",
+        "Save the executed basic blocks to the drcov file when the run stops",
+        "coverage.drcov",
+    );
+    opts.optopt(
+        "",
+        "coverage-lcov",
+        "Save the line coverage to the lcov file when the run stops",
+        "coverage.info",
+    );
+    opts.optopt(
+        "",
+        "profile",
+        "Save the folded call stacks of the retired instructions to the file for flamegraph.pl when the run stops",
+        "profile.folded",
+    );
+    opts.optopt(
+        "",
+        "profile-top",
+        "Number of the hot functions reported when the run stops (default: 20)",
+        "20",
+    );
+    opts.optflag(
+        "",
+        "stats",
+        "Print the instruction mix and the execution statistics when the run stops, e.g. at --cycle-limit",
+    );
+    opts.optflag("e", "rve", "Use the RV32E/RV64E base ISA (16 registers)");
     opts.optflag("t", "testmode", "Testmode is enabled");
+    opts.optflag(
+        "",
+        "trace",
+        "Trace the instructions with the symbols, and the calls and returns",
+    );
     opts.optflag("h", "help", "Help message");
 
     if args.len() < 2 {
@@ -62,6 +210,81 @@ fn main() {
     let fs_path = matches.opt_str("f");
     let dtb_path = matches.opt_str("d");
     let testmode = matches.opt_present("t");
+    let rve = matches.opt_present("e");
+    let trace = matches.opt_present("trace");
+    let log_commits = matches.opt_present("log-commits");
+    let cache_block_size = matches.opt_str("cache-block-size").map(|size| {
+        size.parse::<u64>()
+            .unwrap_or_else(|_| panic!("Invalid cache block size: {}", size))
+    });
+    let entropy_seed = matches.opt_str("entropy-seed").map(|seed| {
+        seed.parse::<u64>()
+            .unwrap_or_else(|_| panic!("Invalid entropy seed: {}", seed))
+    });
+    let trace_categories = matches.opt_str("trace-events").map(|names| match &*names {
+        "all" => TraceCategory::ALL.to_vec(),
+        _ => names
+            .split(',')
+            .map(|name| {
+                TraceCategory::from_name(name)
+                    .unwrap_or_else(|| panic!("Invalid trace category: {}", name))
+            })
+            .collect::<Vec<_>>(),
+    });
+    let trace_file = matches.opt_str("trace-file");
+    let trace_pc_range = matches.opt_str("trace-pc").map(|range| {
+        let parse = |address: &str| {
+            u64::from_str_radix(address.trim_start_matches("0x"), 16)
+                .unwrap_or_else(|_| panic!("Invalid PC range: {}", range))
+        };
+        match range.split_once(':') {
+            Some((start, end)) => (parse(start), parse(end)),
+            None => panic!("Invalid PC range: {}", range),
+        }
+    });
+    let snapshot_in = matches.opt_str("snapshot-in");
+    let snapshot_out = matches.opt_str("snapshot-out");
+    let snapshot_cycles = matches.opt_str("snapshot-cycles").map(|cycles| {
+        cycles
+            .parse::<u64>()
+            .unwrap_or_else(|_| panic!("Invalid number of cycles: {}", cycles))
+    });
+    let cycle_limit = matches.opt_str("cycle-limit").map(|cycles| {
+        cycles
+            .parse::<u64>()
+            .unwrap_or_else(|_| panic!("Invalid number of cycles: {}", cycles))
+    });
+    let record_inputs = matches.opt_str("record-inputs");
+    let replay_inputs = matches.opt_str("replay-inputs");
+    let reverse_interval = matches.opt_str("reverse-interval").map(|cycles| {
+        cycles
+            .parse::<u64>()
+            .unwrap_or_else(|_| panic!("Invalid number of cycles: {}", cycles))
+    });
+    let reverse_checkpoints = matches
+        .opt_str("reverse-checkpoints")
+        .map(|count| {
+            count
+                .parse::<usize>()
+                .unwrap_or_else(|_| panic!("Invalid number of checkpoints: {}", count))
+        })
+        .unwrap_or(DEFAULT_REVERSE_CHECKPOINTS);
+    let reports = Reports {
+        coverage_drcov: matches.opt_str("coverage-drcov"),
+        coverage_lcov: matches.opt_str("coverage-lcov"),
+        profile: matches.opt_str("profile"),
+        profile_top: matches.opt_str("profile-top").map(|count| {
+            count
+                .parse::<usize>()
+                .unwrap_or_else(|_| panic!("Invalid number of functions: {}", count))
+        }),
+        stats: matches.opt_present("stats"),
+    };
+    let gdb_address = matches.opt_str("g");
+    let remote_bitbang_port = matches.opt_str("remote-bitbang").map(|port| {
+        port.parse::<u16>()
+            .unwrap_or_else(|_| panic!("Invalid port: {}", port))
+    });
     let machine = match matches.opt_str("m") {
         Some(machine_name) => match &*machine_name {
             "Qemu_virt" => Machine::QemuVirt,
@@ -80,6 +303,56 @@ fn main() {
         emu = Emulator::new(machine, tty, testmode);
     }
 
+    if let Some(seed) = entropy_seed {
+        emu.set_entropy_seed(seed);
+    }
+    if rve {
+        emu.set_rve(true);
+    }
+    if let Some(size) = cache_block_size {
+        emu.set_cache_block_size(size);
+    }
+    if trace_categories.is_some() || log_commits || trace {
+        let tracer = emu.get_tracer();
+        tracer.clear_sinks();
+        let mut categories = vec![];
+        if let Some(trace_categories) = trace_categories {
+            match trace_file {
+                Some(path) => tracer.add_sink(Box::new(
+                    BinarySink::create(&PathBuf::from(&path))
+                        .unwrap_or_else(|why| panic!("Failed to create {}: {}", path, why)),
+                )),
+                None => tracer.add_sink(Box::new(TextSink::stdout())),
+            }
+            categories.extend(trace_categories);
+        }
+        if log_commits {
+            tracer.add_sink(Box::new(SpikeCommitLogSink::new(BufWriter::new(
+                io::stderr(),
+            ))));
+            categories.extend(COMMIT_LOG_CATEGORIES.iter());
+        }
+        if trace {
+            tracer.add_sink(Box::new(SymbolizedSink::stdout()));
+            categories.extend(&[TraceCategory::Instruction, TraceCategory::Call]);
+        }
+        tracer.set_categories(&categories);
+        tracer.set_filter(TraceFilter {
+            pc_range: trace_pc_range,
+            ..Default::default()
+        });
+    }
+
+    if reports.coverage_drcov.is_some() || reports.coverage_lcov.is_some() {
+        emu.set_coverage(true);
+    }
+    if reports.profile.is_some() || reports.profile_top.is_some() {
+        emu.set_profiling(true);
+    }
+    if reports.stats {
+        emu.set_statistics(true);
+    }
+
     /*
     let data = vec![
         0x13, 0x85, 0x87, 0xfd // addi a0,a5,-40
@@ -113,12 +386,193 @@ fn main() {
         None => {}
     }
 
-    // run emulator.
-    let result = match emu.run() {
-        Ok(ret) => ret,
-        Err(ret) => ret,
+    // restore the machine, which replaces the program, the disk image and the dtb.
+    if let Some(path) = snapshot_in {
+        if let Err(why) = emu.load_snapshot(&PathBuf::from(&path)) {
+            panic!("Failed to load {}: {}", path, why);
+        }
+    }
+
+    // record or replay the inputs from the current state.
+    if let Some(path) = record_inputs {
+        if let Err(why) = emu.record_inputs_to_file(&PathBuf::from(&path)) {
+            panic!("Failed to create {}: {}", path, why);
+        }
+    }
+    if let Some(path) = replay_inputs {
+        if let Err(why) = emu.replay_inputs_from_file(&PathBuf::from(&path)) {
+            panic!("Failed to replay {}: {}", path, why);
+        }
+    }
+
+    // run the given cycles, and save the machine.
+    if let (Some(path), Some(cycles)) = (&snapshot_out, snapshot_cycles) {
+        emu.run_until(&StopConditions {
+            cycle_limit: Some(cycles),
+            ..Default::default()
+        });
+        save_snapshot(&emu, path);
+        reports.save(&emu);
+        process::exit(0);
+    }
+
+    // debug the program with GDB, and run the rest after GDB detaches.
+    if let Some(address) = gdb_address {
+        if let Some(interval) = reverse_interval {
+            emu.enable_reverse_execution(interval, reverse_checkpoints);
+        }
+        let mut stub = GdbStub::new(wait_for_gdb(&address));
+        if !stub.serve(&mut emu) {
+            reports.save(&emu);
+            process::exit(0);
+        }
+        emu.disable_reverse_execution();
+    }
+
+    // run emulator, which stops at the cycle limit if it is given.
+    let result = match (remote_bitbang_port, cycle_limit) {
+        (Some(port), _) => run_remote_bitbang(&mut emu, port, cycle_limit),
+        (None, Some(cycles)) => run_to_limit(&mut emu, cycles),
+        (None, None) => match emu.run() {
+            Ok(ret) | Err(RunError::ToHost(ret)) => Ok(Some(ret)),
+            Err(RunError::Divergence(divergence)) => Err(divergence),
+        },
     };
-    println!("Result: {}", result);
+    if let Some(path) = snapshot_out {
+        save_snapshot(&emu, &path);
+    }
+    reports.save(&emu);
+    match result {
+        Ok(Some(result)) => println!("Result: {}", result),
+        Ok(None) => println!("Stopped at cycle {}", emu.get_cycle()),
+        Err(divergence) => {
+            eprint!("{}", divergence);
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the emulator until the program writes tohost or the number of cycles
+/// has passed, and returns the value written to tohost.
+fn run_to_limit(emu: &mut Emulator, cycles: u64) -> Result<Option<u32>, Box<Divergence>> {
+    let end = emu.get_cycle() + cycles;
+    while emu.get_cycle() < end {
+        match emu.run_until(&StopConditions {
+            cycle_limit: Some(end - emu.get_cycle()),
+            ..Default::default()
+        }) {
+            StopReason::ToHost(value) => return Ok(Some(value)),
+            StopReason::CycleLimit => return Ok(None),
+            StopReason::Divergence(divergence) => return Err(divergence),
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Runs the emulator with the JTAG server, which is polled between the runs of
+/// some cycles, until the program writes tohost, the client quits or the
+/// number of cycles has passed. Returns the value written to tohost.
+fn run_remote_bitbang(
+    emu: &mut Emulator,
+    port: u16,
+    cycles: Option<u64>,
+) -> Result<Option<u32>, Box<Divergence>> {
+    let mut server = RemoteBitbang::new(port);
+    let end = cycles.map(|cycles| emu.get_cycle() + cycles);
+    loop {
+        if server.poll(emu) {
+            return Ok(None);
+        }
+        let cycles = match end {
+            Some(end) => end
+                .saturating_sub(emu.get_cycle())
+                .min(REMOTE_BITBANG_POLL_CYCLES),
+            None => REMOTE_BITBANG_POLL_CYCLES,
+        };
+        if cycles == 0 {
+            return Ok(None);
+        }
+        match emu.run_until(&StopConditions {
+            cycle_limit: Some(cycles),
+            ..Default::default()
+        }) {
+            StopReason::ToHost(value) => return Ok(Some(value)),
+            StopReason::Divergence(divergence) => return Err(divergence),
+            _ => {}
+        }
+    }
+}
+
+fn save_snapshot(emu: &Emulator, path: &str) {
+    if let Err(why) = emu.save_snapshot(&PathBuf::from(path)) {
+        panic!("Failed to save {}: {}", path, why);
+    }
+}
+
+/// Outputs of the coverage, the profiler and the statistics, which are saved
+/// or printed when the run stops.
+struct Reports {
+    coverage_drcov: Option<String>,
+    coverage_lcov: Option<String>,
+    profile: Option<String>,
+    profile_top: Option<usize>,
+    stats: bool,
+}
+
+impl Reports {
+    fn save(&self, emu: &Emulator) {
+        save_coverage(emu, &self.coverage_drcov, &self.coverage_lcov);
+        save_profile(emu, &self.profile, self.profile_top);
+        if self.stats {
+            println!("{}", emu.get_statistics());
+        }
+    }
+}
+
+fn save_coverage(emu: &Emulator, drcov: &Option<String>, lcov: &Option<String>) {
+    if let Some(path) = drcov {
+        if let Err(why) = emu.save_drcov(&PathBuf::from(path)) {
+            panic!("Failed to save {}: {}", path, why);
+        }
+    }
+    if let Some(path) = lcov {
+        if let Err(why) = emu.save_lcov(&PathBuf::from(path)) {
+            panic!("Failed to save {}: {}", path, why);
+        }
+    }
+}
+
+/// Saves the folded stacks, and prints the hot functions.
+fn save_profile(emu: &Emulator, path: &Option<String>, top: Option<usize>) {
+    if !emu.get_profiler().is_enabled() {
+        return;
+    }
+    if let Some(path) = path {
+        if let Err(why) = emu.save_profile(&PathBuf::from(path)) {
+            panic!("Failed to save {}: {}", path, why);
+        }
+    }
+    let count = emu.get_profiler().get_instruction_count();
+    println!("Retired instructions: {}", count);
+    let instructions = count.max(1) as f64;
+    println!(
+        "{:>8} {:>12} {:>8}  function",
+        "self", "self count", "total"
+    );
+    for function in emu
+        .get_hot_functions()
+        .iter()
+        .take(top.unwrap_or(DEFAULT_PROFILE_TOP))
+    {
+        println!(
+            "{:>7.2}% {:>12} {:>7.2}%  {}",
+            function.self_count as f64 * 100.0 / instructions,
+            function.self_count,
+            function.total_count as f64 * 100.0 / instructions,
+            function.name
+        );
+    }
 }
 
 fn print_usage(program: &str, opts: &Options) {
@@ -0,0 +1,86 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use riscv_emu::emulator::Emulator;
+
+/// Server of the OpenOCD remote_bitbang protocol, which drives the JTAG TAP of
+/// the emulator. It only listens on localhost, and never blocks the emulator.
+pub struct RemoteBitbang {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+}
+
+impl RemoteBitbang {
+    pub fn new(port: u16) -> Self {
+        let listener = match TcpListener::bind(("127.0.0.1", port)) {
+            Ok(listener) => listener,
+            Err(why) => panic!("Failed to listen on port {}: {}", port, why),
+        };
+        listener
+            .set_nonblocking(true)
+            .expect("Failed to set the listener non-blocking");
+        RemoteBitbang {
+            listener,
+            client: None,
+        }
+    }
+
+    /// Accepts a client, and processes the commands received from it. Returns
+    /// whether the client has quit the session, which it does when OpenOCD
+    /// shuts down.
+    pub fn poll(&mut self, emu: &mut Emulator) -> bool {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream
+                        .set_nonblocking(true)
+                        .expect("Failed to set the stream non-blocking");
+                    let _ = stream.set_nodelay(true);
+                    self.client = Some(stream);
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return false,
+                Err(why) => panic!("Failed to accept a client: {}", why),
+            }
+        }
+
+        let mut buffer = [0; 4096];
+        let mut response = vec![];
+        let mut quit = false;
+        let mut quit_command = false;
+        if let Some(client) = self.client.as_mut() {
+            match client.read(&mut buffer) {
+                Ok(0) => quit = true,
+                Ok(length) => {
+                    for command in buffer[..length].iter() {
+                        match command {
+                            // blink on/off
+                            b'B' | b'b' => {}
+                            b'R' => response.push(match emu.jtag_get_tdo() {
+                                true => b'1',
+                                false => b'0',
+                            }),
+                            b'Q' => quit_command = true,
+                            b'0'..=b'7' => {
+                                let pins = command - b'0';
+                                emu.jtag_set_pins(pins & 4 != 0, pins & 2 != 0, pins & 1 != 0);
+                            }
+                            // trst and srst. srst is not connected.
+                            b'r' | b's' => {}
+                            b't' | b'u' => emu.jtag_reset(),
+                            _ => {}
+                        }
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(_) => quit = true,
+            }
+            if !response.is_empty() && client.write_all(&response).is_err() {
+                quit = true;
+            }
+        }
+        if quit || quit_command {
+            self.client = None;
+        }
+        quit_command
+    }
+}
@@ -0,0 +1,165 @@
+// Fuzz target running the inputs in a guest program, which is given by the
+// environment variables:
+//
+//   RISCV_FUZZ_KERNEL      ELF file of the program
+//   RISCV_FUZZ_MACHINE     SiFive_e|SiFive_u|Qemu_virt (default: Qemu_virt)
+//   RISCV_FUZZ_FILESYSTEM  disk image (optional)
+//   RISCV_FUZZ_START       symbol or address where the checkpoint is taken
+//                          (default: the entry point)
+//   RISCV_FUZZ_INPUT       BUFFER:SIZE[:LENGTH] to write the input to the
+//                          buffer and its length to the 32-bit variable, or
+//                          disk:OFFSET:SIZE to write it to the disk image
+//   RISCV_FUZZ_END         symbols or addresses where a run ends (comma separated)
+//   RISCV_FUZZ_CYCLES      cycle limit of a run (default: 10000000)
+//   RISCV_FUZZ_CRASH       crash conditions (comma separated, default: fault:m):
+//                          fault (any mode), fault:m|s|u, write:SENTINEL
+//
+// The symbols and the addresses (hex) are physical, except that the sentinel
+// is the effective address of the store. The coverage of the guest is given
+// to libFuzzer as its extra counters.
+
+#![no_main]
+
+use std::cell::RefCell;
+use std::env;
+use std::path::Path;
+
+use libfuzzer_sys::fuzz_target;
+use riscv_emu::bus::bus::Device;
+use riscv_emu::console::TtyDummy;
+use riscv_emu::cpu::cpu::Privilege;
+use riscv_emu::emulator::{Breakpoint, Emulator, StopConditions, StopReason};
+use riscv_emu::machine::Machine;
+use riscv_emu_fuzz::{
+    CrashCondition, Harness, HarnessConfig, InputLocation, Outcome, FAULTS, MAP_SIZE,
+};
+
+const DEFAULT_CYCLE_LIMIT: u64 = 10_000_000;
+
+#[used]
+#[link_section = "__libfuzzer_extra_counters"]
+static mut COUNTERS: [u8; MAP_SIZE] = [0; MAP_SIZE];
+
+thread_local! {
+    static HARNESS: RefCell<Option<Harness>> = const { RefCell::new(None) };
+}
+
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Looks up the symbol, or parses a hex address.
+fn parse_address(emulator: &Emulator, value: &str) -> u64 {
+    if let Some(address) = emulator.get_symbols().find_symbol(value) {
+        return address;
+    }
+    match u64::from_str_radix(value.trim_start_matches("0x"), 16) {
+        Ok(address) => address,
+        Err(_) => panic!("Unknown symbol: {}", value),
+    }
+}
+
+fn parse_number(value: &str) -> u64 {
+    let result = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    match result {
+        Ok(number) => number,
+        Err(_) => panic!("Invalid number: {}", value),
+    }
+}
+
+fn parse_input(emulator: &Emulator, value: &str) -> InputLocation {
+    let fields: Vec<&str> = value.split(':').collect();
+    match fields[..] {
+        ["disk", offset, size] => InputLocation::Disk {
+            offset: parse_number(offset),
+            size: parse_number(size) as usize,
+        },
+        [buffer, size] | [buffer, size, _] => InputLocation::Buffer {
+            address: parse_address(emulator, buffer),
+            size: parse_number(size) as usize,
+            length: fields.get(2).map(|length| parse_address(emulator, length)),
+        },
+        _ => panic!("Invalid RISCV_FUZZ_INPUT: {}", value),
+    }
+}
+
+fn parse_crash(emulator: &Emulator, value: &str) -> CrashCondition {
+    match value.split_once(':') {
+        None if value == "fault" => CrashCondition::Exception {
+            exceptions: FAULTS.to_vec(),
+            privilege: None,
+        },
+        Some(("fault", "m")) => CrashCondition::fault_in(Privilege::Machine),
+        Some(("fault", "s")) => CrashCondition::fault_in(Privilege::Supervisor),
+        Some(("fault", "u")) => CrashCondition::fault_in(Privilege::User),
+        Some(("write", address)) => CrashCondition::Write(parse_address(emulator, address)),
+        _ => panic!("Invalid RISCV_FUZZ_CRASH: {}", value),
+    }
+}
+
+/// Loads the program, and boots it to the start.
+fn create_harness() -> Harness {
+    let kernel = var("RISCV_FUZZ_KERNEL").expect("RISCV_FUZZ_KERNEL is not set");
+    let machine = match var("RISCV_FUZZ_MACHINE").as_deref() {
+        Some("SiFive_e") => Machine::SiFiveE,
+        Some("SiFive_u") => Machine::SiFiveU,
+        Some("Qemu_virt") | None => Machine::QemuVirt,
+        Some(machine) => panic!("Unknown machine: {}", machine),
+    };
+    let mut emulator = Emulator::new(machine, Box::new(TtyDummy::new()), false);
+    emulator.load_program_from_file(Path::new(&kernel));
+    if let Some(filesystem) = var("RISCV_FUZZ_FILESYSTEM") {
+        emulator.set_data_from_file(Device::Disk, Path::new(&filesystem));
+    }
+
+    if let Some(start) = var("RISCV_FUZZ_START") {
+        let start = parse_address(&emulator, &start);
+        match emulator.run_until(&StopConditions {
+            breakpoints: vec![Breakpoint::Physical(start)],
+            ..StopConditions::default()
+        }) {
+            StopReason::Breakpoint(_) => {}
+            reason => panic!("The program stopped before the start: {:?}", reason),
+        }
+    }
+
+    let input = var("RISCV_FUZZ_INPUT").expect("RISCV_FUZZ_INPUT is not set");
+    let config = HarnessConfig {
+        input: parse_input(&emulator, &input),
+        ends: var("RISCV_FUZZ_END")
+            .map(|ends| {
+                ends.split(',')
+                    .map(|end| Breakpoint::Physical(parse_address(&emulator, end)))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        cycle_limit: var("RISCV_FUZZ_CYCLES")
+            .map(|cycles| parse_number(&cycles))
+            .unwrap_or(DEFAULT_CYCLE_LIMIT),
+        crashes: var("RISCV_FUZZ_CRASH")
+            .unwrap_or_else(|| String::from("fault:m"))
+            .split(',')
+            .map(|crash| parse_crash(&emulator, crash))
+            .collect(),
+    };
+    Harness::new(emulator, config)
+}
+
+fuzz_target!(|data: &[u8]| {
+    HARNESS.with(|harness| {
+        let mut harness = harness.borrow_mut();
+        let harness = harness.get_or_insert_with(create_harness);
+        let outcome = harness.run(data);
+        let coverage = harness.get_coverage();
+        // libFuzzer reads the counters after the run.
+        unsafe {
+            (*std::ptr::addr_of_mut!(COUNTERS)).copy_from_slice(&coverage.borrow());
+        }
+        if let Outcome::Crashed(crash) = outcome {
+            panic!("Guest crash: {}", crash);
+        }
+    });
+});
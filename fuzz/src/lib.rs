@@ -0,0 +1,316 @@
+// Coverage-guided fuzzing of guest code.
+//
+// The harness takes a checkpoint of the booted machine, and runs each input
+// from it: the input is written to a guest buffer or to the disk image, and
+// the machine runs until the PC reaches an end, a crash condition is met or
+// the cycle limit runs out. The edges between the executed branches are
+// counted in a coverage map like AFL's, which the fuzzer is guided by.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use riscv_emu::bus::bus::Device;
+use riscv_emu::cpu::cpu::Privilege;
+use riscv_emu::cpu::mmu::Watchpoint;
+use riscv_emu::cpu::trap::Exception;
+use riscv_emu::emulator::{Breakpoint, Emulator, StopConditions, StopReason};
+use riscv_emu::trace::{TraceCategory, TraceEvent, TraceRecord, TraceSink};
+
+/// Number of the counters of the coverage map.
+pub const MAP_SIZE: usize = 1 << 16;
+
+/// Exceptions which are faults, i.e. not a breakpoint or an environment call.
+pub const FAULTS: [Exception; 10] = [
+    Exception::InstructionAddressMisaligned,
+    Exception::InstructionAccessFault,
+    Exception::IllegalInstruction,
+    Exception::LoadAddressMisaligned,
+    Exception::LoadAccessFault,
+    Exception::StoreAddressMisaligned,
+    Exception::StoreAccessFault,
+    Exception::InstructionPageFault,
+    Exception::LoadPageFault,
+    Exception::StorePageFault,
+];
+
+/// Where the input is written before each run.
+#[derive(Clone, Debug)]
+pub enum InputLocation {
+    /// buffer of `size` bytes at the physical address. A longer input is
+    /// truncated, and the length is written to the 32-bit variable at the
+    /// physical address `length` if any.
+    Buffer {
+        address: u64,
+        size: usize,
+        length: Option<u64>,
+    },
+    /// disk image from the offset, up to `size` bytes.
+    Disk { offset: u64, size: usize },
+}
+
+/// Condition of a run which is reported as a crash.
+#[derive(Clone, Debug)]
+pub enum CrashCondition {
+    /// one of the exceptions taken in the privilege mode, or in any mode if
+    /// it is `None`.
+    Exception {
+        exceptions: Vec<Exception>,
+        privilege: Option<Privilege>,
+    },
+    /// a store to the effective address, e.g. a sentinel which the guest
+    /// writes when it detects an error.
+    Write(u64),
+}
+
+impl CrashCondition {
+    /// Any fault in the privilege mode, e.g. in M-mode.
+    pub fn fault_in(privilege: Privilege) -> Self {
+        CrashCondition::Exception {
+            exceptions: FAULTS.to_vec(),
+            privilege: Some(privilege),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HarnessConfig {
+    pub input: InputLocation,
+    /// PCs where a run ends, e.g. where the guest has handled the input.
+    pub ends: Vec<Breakpoint>,
+    /// cycles of a run, after which it times out.
+    pub cycle_limit: u64,
+    pub crashes: Vec<CrashCondition>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Crash {
+    /// index of the condition in `HarnessConfig::crashes`.
+    pub condition: usize,
+    /// PC of the instruction which raised the exception, or the PC after the
+    /// store to the sentinel.
+    pub pc: u64,
+    pub privilege: Privilege,
+    pub reason: StopReason,
+}
+
+impl fmt::Display for Crash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.reason {
+            StopReason::Exception(trap) => write!(
+                f,
+                "{:?} ({:x}) at {:016x} in {:?}",
+                trap.exception, trap.value, self.pc, self.privilege
+            ),
+            StopReason::Watchpoint { address, .. } => write!(
+                f,
+                "Write to {:016x} before {:016x} in {:?}",
+                address, self.pc, self.privilege
+            ),
+            reason => write!(f, "{:?} at {:016x}", reason, self.pc),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    /// the PC reached one of the ends.
+    Completed,
+    /// the run did not end within the cycle limit.
+    Timeout,
+    Crashed(Crash),
+    /// the run stopped for another reason, e.g. the test program wrote `tohost`.
+    Stopped(StopReason),
+}
+
+/// Sink counting the edges between the branches and their targets in the
+/// coverage map, and keeping the last trap with the mode it was taken in.
+struct CoverageSink {
+    map: Rc<RefCell<Vec<u8>>>,
+    last_trap: Rc<RefCell<Option<(u64, Privilege)>>>,
+}
+
+/// Hashes an edge to an index of the coverage map.
+fn edge_index(from: u64, to: u64) -> usize {
+    let hash = (from ^ to.rotate_left(17)).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    (hash >> 48) as usize % MAP_SIZE
+}
+
+impl TraceSink for CoverageSink {
+    fn record(&mut self, record: &TraceRecord) {
+        match record.event {
+            TraceEvent::Branch { target, .. } => {
+                let mut map = self.map.borrow_mut();
+                let counter = &mut map[edge_index(record.pc, target)];
+                *counter = counter.saturating_add(1);
+            }
+            TraceEvent::Trap { .. } => {
+                *self.last_trap.borrow_mut() = Some((record.pc, record.privilege.clone()));
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct Harness {
+    emulator: Emulator,
+    config: HarnessConfig,
+    /// conditions of `run_until`, which are the ends and the crash conditions.
+    conditions: StopConditions,
+    map: Rc<RefCell<Vec<u8>>>,
+    last_trap: Rc<RefCell<Option<(u64, Privilege)>>>,
+}
+
+impl Harness {
+    /// Takes the checkpoint of the emulator, which every input runs from.
+    /// The emulator should have booted to the point where the guest reads the
+    /// input, e.g. by `Emulator::run_until` with a breakpoint there. Its
+    /// tracer is taken over for the coverage.
+    pub fn new(mut emulator: Emulator, config: HarnessConfig) -> Self {
+        let map = Rc::new(RefCell::new(vec![0; MAP_SIZE]));
+        let last_trap = Rc::new(RefCell::new(None));
+        let tracer = emulator.get_tracer();
+        tracer.clear_sinks();
+        tracer.set_categories(&[TraceCategory::Branch, TraceCategory::Trap]);
+        tracer.add_sink(Box::new(CoverageSink {
+            map: map.clone(),
+            last_trap: last_trap.clone(),
+        }));
+        emulator.take_checkpoint();
+
+        let mut conditions = StopConditions {
+            breakpoints: config.ends.clone(),
+            ..StopConditions::default()
+        };
+        for crash in config.crashes.iter() {
+            match crash {
+                CrashCondition::Exception { exceptions, .. } => {
+                    for exception in exceptions {
+                        if !conditions.exceptions.contains(exception) {
+                            conditions.exceptions.push(*exception);
+                        }
+                    }
+                }
+                CrashCondition::Write(address) => conditions.watchpoints.push(Watchpoint {
+                    address: *address,
+                    size: 1,
+                    read: false,
+                    write: true,
+                }),
+            }
+        }
+        Harness {
+            emulator,
+            config,
+            conditions,
+            map,
+            last_trap,
+        }
+    }
+
+    /// Runs the input from the checkpoint. The coverage map holds the edges
+    /// of the run after it.
+    pub fn run(&mut self, input: &[u8]) -> Outcome {
+        self.emulator.restore_checkpoint();
+        for counter in self.map.borrow_mut().iter_mut() {
+            *counter = 0;
+        }
+        self.write_input(input);
+
+        let start = self.emulator.get_cycle();
+        loop {
+            let elapsed = self.emulator.get_cycle() - start;
+            if elapsed >= self.config.cycle_limit {
+                return Outcome::Timeout;
+            }
+            self.conditions.cycle_limit = Some(self.config.cycle_limit - elapsed);
+            let reason = self.emulator.run_until(&self.conditions);
+            match reason {
+                StopReason::Breakpoint(_) => return Outcome::Completed,
+                StopReason::CycleLimit => return Outcome::Timeout,
+                StopReason::Exception(_) | StopReason::Watchpoint { .. } => {
+                    if let Some(crash) = self.check_crash(reason) {
+                        return Outcome::Crashed(crash);
+                    }
+                }
+                reason => return Outcome::Stopped(reason),
+            }
+        }
+    }
+
+    fn write_input(&mut self, input: &[u8]) {
+        match self.config.input {
+            InputLocation::Buffer {
+                address,
+                size,
+                length,
+            } => {
+                let input = &input[..input.len().min(size)];
+                for (i, byte) in input.iter().enumerate() {
+                    let _ =
+                        self.emulator
+                            .write_physical_memory(address + i as u64, 1, *byte as u64);
+                }
+                if let Some(length) = length {
+                    let _ = self
+                        .emulator
+                        .write_physical_memory(length, 4, input.len() as u64);
+                }
+            }
+            InputLocation::Disk { offset, size } => {
+                let input = &input[..input.len().min(size)];
+                self.emulator.write_device_data(Device::Disk, offset, input);
+            }
+        }
+    }
+
+    /// Returns the crash if the stop meets a crash condition. An exception
+    /// in another mode, e.g. a page fault of a user process, is not one.
+    fn check_crash(&mut self, reason: StopReason) -> Option<Crash> {
+        let (pc, privilege) = match &reason {
+            StopReason::Exception(_) => self.last_trap.borrow_mut().take()?,
+            _ => (self.emulator.get_pc(), self.emulator.get_privilege()),
+        };
+        let condition = self
+            .config
+            .crashes
+            .iter()
+            .position(|crash| match (crash, &reason) {
+                (
+                    CrashCondition::Exception {
+                        exceptions,
+                        privilege: mode,
+                    },
+                    StopReason::Exception(trap),
+                ) => {
+                    exceptions.contains(&trap.exception)
+                        && mode.as_ref().is_none_or(|mode| *mode == privilege)
+                }
+                // the watchpoint reports the start of the store, which is up to 8 bytes.
+                (
+                    CrashCondition::Write(address),
+                    StopReason::Watchpoint {
+                        address: start,
+                        write: true,
+                    },
+                ) => address.wrapping_sub(*start) < 8,
+                _ => false,
+            })?;
+        Some(Crash {
+            condition,
+            pc,
+            privilege,
+            reason,
+        })
+    }
+
+    /// Returns the coverage map, whose counters are the number of the times
+    /// each edge was executed in the last run, saturated at 255.
+    pub fn get_coverage(&self) -> Rc<RefCell<Vec<u8>>> {
+        self.map.clone()
+    }
+
+    pub fn get_emulator(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+}
@@ -0,0 +1,112 @@
+extern crate riscv_emu;
+extern crate riscv_emu_fuzz;
+
+use riscv_emu::console::TtyDummy;
+use riscv_emu::cpu::cpu::Privilege;
+use riscv_emu::cpu::trap::Exception;
+use riscv_emu::emulator::{Breakpoint, Emulator, StopReason};
+use riscv_emu::machine::Machine;
+use riscv_emu_fuzz::{CrashCondition, Harness, HarnessConfig, InputLocation, Outcome};
+
+const DRAM_BASE: u64 = 0x8000_0000;
+const BUFFER: u64 = DRAM_BASE + 0x1000;
+const LENGTH: u64 = BUFFER + 0x100;
+const S0: usize = 8;
+
+/// Crashes if the input starts with 'c', hangs if it starts with 't', and
+/// reaches the end otherwise.
+const PROGRAM: [u32; 9] = [
+    0x00044283, // lbu t0,0(s0)
+    0x06300313, // li t1,99
+    0x00628863, // beq t0,t1,0x18
+    0x07400313, // li t1,116
+    0x00628663, // beq t0,t1,0x1c
+    0x00c0006f, // j 0x20
+    0x00003383, // ld t2,0(zero)
+    0x0000006f, // j .
+    0x00000013, // nop
+];
+const CRASH: u64 = DRAM_BASE + 0x18;
+const END: u64 = DRAM_BASE + 0x20;
+
+fn harness() -> Harness {
+    let mut emu = Emulator::new(Machine::QemuVirt, Box::new(TtyDummy::new()), false);
+    let program = PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect();
+    emu.set_dram_data(program);
+    emu.set_pc(DRAM_BASE);
+    emu.write_register(S0, BUFFER);
+    Harness::new(
+        emu,
+        HarnessConfig {
+            input: InputLocation::Buffer {
+                address: BUFFER,
+                size: 16,
+                length: Some(LENGTH),
+            },
+            ends: vec![Breakpoint::Physical(END)],
+            cycle_limit: 1000,
+            crashes: vec![CrashCondition::fault_in(Privilege::Machine)],
+        },
+    )
+}
+
+/// Returns the counters of the edges which the last run executed.
+fn edges(harness: &Harness) -> Vec<(usize, u8)> {
+    let map = harness.get_coverage();
+    let map = map.borrow();
+    map.iter()
+        .enumerate()
+        .filter(|(_, counter)| **counter != 0)
+        .map(|(index, counter)| (index, *counter))
+        .collect()
+}
+
+#[test]
+fn outcomes_and_edges() {
+    let mut harness = harness();
+
+    match harness.run(b"abc") {
+        Outcome::Completed => {}
+        outcome => panic!("{:?}", outcome),
+    }
+    // two branches not taken and the jump to the end.
+    let completed = edges(&harness);
+    assert_eq!(3, completed.len(), "{:?}", completed);
+    assert_eq!(END, harness.get_emulator().get_pc());
+    assert_eq!(
+        3,
+        harness
+            .get_emulator()
+            .read_physical_memory(LENGTH, 4)
+            .unwrap()
+    );
+
+    let crash = match harness.run(b"c") {
+        Outcome::Crashed(crash) => crash,
+        outcome => panic!("{:?}", outcome),
+    };
+    assert_eq!(0, crash.condition);
+    assert_eq!(CRASH, crash.pc);
+    assert_eq!(Privilege::Machine, crash.privilege);
+    match crash.reason {
+        StopReason::Exception(trap) => assert_eq!(Exception::LoadPageFault, trap.exception),
+        reason => panic!("{:?}", reason),
+    }
+    let crashed = edges(&harness);
+    assert!(!crashed.is_empty());
+    assert_ne!(completed, crashed);
+
+    match harness.run(b"t") {
+        Outcome::Timeout => {}
+        outcome => panic!("{:?}", outcome),
+    }
+    // the loop saturates its counter.
+    assert!(edges(&harness).iter().any(|(_, counter)| *counter == 255));
+
+    // each run starts from the checkpoint with a cleared map.
+    match harness.run(b"abc") {
+        Outcome::Completed => {}
+        outcome => panic!("{:?}", outcome),
+    }
+    assert_eq!(completed, edges(&harness));
+}
@@ -1,4 +1,5 @@
 use crate::console::Console;
+use crate::snapshot::Snapshot;
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -9,9 +10,21 @@ pub enum Device {
     DTB = 3,
 }
 
-pub trait Bus {
+/// The state of the devices is saved in snapshots.
+pub trait Bus: Snapshot {
     fn set_device_data(&mut self, device: Device, data: Vec<u8>);
+    /// Overwrites the data of the device from the offset, e.g. a part of the disk image.
+    fn write_device_data(&mut self, device: Device, offset: u64, data: &[u8]);
+    /// Takes a checkpoint of the memories and the disk image, which
+    /// `restore_memory_checkpoint` restores by copying back the pages written
+    /// since then. The snapshots without the memories leave them to it.
+    fn take_memory_checkpoint(&mut self);
+    fn restore_memory_checkpoint(&mut self);
     fn get_base_address(&mut self, device: Device) -> u64;
+    /// The address is in the registers of a device rather than in a memory.
+    fn is_mmio(&self, addr: u64) -> bool;
+    /// Returns the name of the memory or the device at the address, e.g. "dram".
+    fn get_region_name(&self, addr: u64) -> &'static str;
     fn get_console(&mut self) -> &mut Box<dyn Console>;
     fn tick(&mut self) -> Vec<bool>;
     fn is_pending_software_interrupt(&mut self, core: usize) -> bool;
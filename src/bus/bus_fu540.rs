@@ -11,6 +11,7 @@ use crate::peripherals::fu540_c000::plic::Plic;
 use crate::peripherals::intc::Intc;
 use crate::peripherals::memory::Memory;
 use crate::peripherals::timer::Timer;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
 
 const _DEBUG_ADDRESS_START: u64 = 0x0000_0000;
 const _DEBUG_ADDRESS_END: u64 = 0x0000_0FFF;
@@ -91,6 +92,25 @@ impl Bus for BusFu540 {
         }
     }
 
+    fn write_device_data(&mut self, device: Device, offset: u64, data: &[u8]) {
+        match device {
+            Device::SpiFlash => self.flash.write_bytes(offset, data),
+            _ => panic!("Unexpected device: {:?}", device),
+        }
+    }
+
+    fn take_memory_checkpoint(&mut self) {
+        self.dtim.take_checkpoint();
+        self.flash.take_checkpoint();
+        self.dram.take_checkpoint();
+    }
+
+    fn restore_memory_checkpoint(&mut self) {
+        self.dtim.restore_checkpoint();
+        self.flash.restore_checkpoint();
+        self.dram.restore_checkpoint();
+    }
+
     fn get_console(&mut self) -> &mut Box<dyn Console> {
         self.uart0.get_console()
     }
@@ -122,6 +142,27 @@ impl Bus for BusFu540 {
         self.timer.is_pending_timer_interrupt(core)
     }
 
+    fn is_mmio(&self, addr: u64) -> bool {
+        (TIMER_ADDRESS_START..=GPIO_ADDRESS_END).contains(&addr)
+    }
+
+    fn get_region_name(&self, addr: u64) -> &'static str {
+        if DRAM_ADDRESS_START <= addr {
+            return "dram";
+        }
+        match addr {
+            DTIM_ADDRESS_START..=DTIM_ADDRESS_END => "dtim",
+            TIMER_ADDRESS_START..=TIMER_ADDRESS_END => "timer",
+            INTC_ADDRESS_START..=INTC_ADDRESS_END => "intc",
+            PRCI_ADDRESS_START..=PRCI_ADDRESS_END => "prci",
+            UART0_ADDRESS_START..=UART0_ADDRESS_END => "uart0",
+            UART1_ADDRESS_START..=UART1_ADDRESS_END => "uart1",
+            GPIO_ADDRESS_START..=GPIO_ADDRESS_END => "gpio",
+            SPIFLASH_ADDRESS_START..=SPIFLASH_ADDRESS_END => "spiflash",
+            _ => "unmapped",
+        }
+    }
+
     fn get_base_address(&mut self, device: Device) -> u64 {
         match device {
             Device::SpiFlash => SPIFLASH_ADDRESS_START,
@@ -393,3 +434,40 @@ impl Bus for BusFu540 {
         }
     }
 }
+
+impl Snapshot for BusFu540 {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("bus_fu540");
+        writer.write_u64(self.clock);
+        writer.write_tag("dtim");
+        self.dtim.save(writer);
+        writer.write_tag("flash");
+        self.flash.save(writer);
+        writer.write_tag("dram");
+        self.dram.save(writer);
+        self.timer.save(writer);
+        self.intc.save(writer);
+        self.prci.save(writer);
+        self.uart0.save(writer);
+        self.uart1.save(writer);
+        self.gpio.save(writer);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("bus_fu540")?;
+        self.clock = reader.read_u64()?;
+        reader.expect_tag("dtim")?;
+        self.dtim.restore(reader)?;
+        reader.expect_tag("flash")?;
+        self.flash.restore(reader)?;
+        reader.expect_tag("dram")?;
+        self.dram.restore(reader)?;
+        self.timer.restore(reader)?;
+        self.intc.restore(reader)?;
+        self.prci.restore(reader)?;
+        self.uart0.restore(reader)?;
+        self.uart1.restore(reader)?;
+        self.gpio.restore(reader)?;
+        Ok(())
+    }
+}
@@ -9,6 +9,7 @@ use crate::peripherals::memory::Memory;
 use crate::peripherals::timer::Timer;
 use crate::peripherals::uart::Uart;
 use crate::peripherals::virtio::Virtio;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
 
 const DTB_ADDRESS_START: u64 = 0x0000_1020;
 const DTB_ADDRESS_END: u64 = 0x0000_1FFF;
@@ -76,6 +77,26 @@ impl Bus for BusQemuVirt {
         }
     }
 
+    fn write_device_data(&mut self, device: Device, offset: u64, data: &[u8]) {
+        match device {
+            Device::Dram => self.dram.write_bytes(offset, data),
+            Device::Disk => self.virtio.write_disk(offset, data),
+            _ => panic!("Unexpected device: {:?}", device),
+        }
+    }
+
+    fn take_memory_checkpoint(&mut self) {
+        self.mrom.take_checkpoint();
+        self.dram.take_checkpoint();
+        self.virtio.take_checkpoint();
+    }
+
+    fn restore_memory_checkpoint(&mut self) {
+        self.mrom.restore_checkpoint();
+        self.dram.restore_checkpoint();
+        self.virtio.restore_checkpoint();
+    }
+
     fn get_console(&mut self) -> &mut Box<dyn Console> {
         self.uart.get_console()
     }    
@@ -106,6 +127,25 @@ impl Bus for BusQemuVirt {
         self.timer.is_pending_timer_interrupt(core)
     }
 
+    fn is_mmio(&self, addr: u64) -> bool {
+        (TIMER_ADDRESS_START..=VIRTIO_ADDRESS_END).contains(&addr)
+    }
+
+    fn get_region_name(&self, addr: u64) -> &'static str {
+        if DRAM_ADDRESS_START <= addr {
+            return "dram";
+        }
+        match addr {
+            DTB_ADDRESS_START..=DTB_ADDRESS_END => "dtb",
+            MROM_ADDRESS_START..=MROM_ADDRESS_END => "mrom",
+            TIMER_ADDRESS_START..=TIMER_ADDRESS_END => "timer",
+            INTC_ADDRESS_START..=INTC_ADDRESS_END => "intc",
+            UART_ADDRESS_START..=UART_ADDRESS_END => "uart",
+            VIRTIO_ADDRESS_START..=VIRTIO_ADDRESS_END => "virtio",
+            _ => "unmapped",
+        }
+    }
+
     fn get_base_address(&mut self, device: Device) -> u64 {
         match device {
             Device::Dram => DRAM_ADDRESS_START,
@@ -389,3 +429,38 @@ impl Bus for BusQemuVirt {
         }
     }
 }
+
+impl Snapshot for BusQemuVirt {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("bus_qemu_virt");
+        writer.write_u64(self.clock);
+        writer.write_bytes(&self.dtb);
+        writer.write_tag("mrom");
+        self.mrom.save(writer);
+        writer.write_tag("dram");
+        self.dram.save(writer);
+        self.timer.save(writer);
+        self.intc.save(writer);
+        self.uart.save(writer);
+        self.virtio.save(writer);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("bus_qemu_virt")?;
+        self.clock = reader.read_u64()?;
+        let dtb = reader.read_bytes()?;
+        if dtb.len() != self.dtb.len() {
+            return Err(reader.corrupted());
+        }
+        self.dtb = dtb;
+        reader.expect_tag("mrom")?;
+        self.mrom.restore(reader)?;
+        reader.expect_tag("dram")?;
+        self.dram.restore(reader)?;
+        self.timer.restore(reader)?;
+        self.intc.restore(reader)?;
+        self.uart.restore(reader)?;
+        self.virtio.restore(reader)?;
+        Ok(())
+    }
+}
@@ -1,8 +1,20 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
 pub trait Console {
     fn putchar(&mut self, c: u8);
     fn getchar(&mut self) -> u8;
     fn set_input(&mut self, c: u8);
     fn get_output(&mut self) -> u8;
+
+    /// Returns the input which has not been read and the output which has not
+    /// been taken, which are saved in snapshots. Consoles without buffers,
+    /// e.g. terminals, have none.
+    fn get_buffers(&self) -> (Vec<u8>, Vec<u8>) {
+        (vec![], vec![])
+    }
+
+    fn set_buffers(&mut self, _input: Vec<u8>, _output: Vec<u8>) {}
 }
 
 pub struct TtyDummy {}
@@ -26,3 +38,45 @@ impl Console for TtyDummy {
         0
     }
 }
+
+/// Console which passes everything through to another console, and copies the
+/// output into a shared buffer while the buffer is `Some`.
+pub struct CapturingConsole {
+    console: Box<dyn Console>,
+    capture: Rc<RefCell<Option<Vec<u8>>>>,
+}
+
+impl CapturingConsole {
+    pub fn new(console: Box<dyn Console>, capture: Rc<RefCell<Option<Vec<u8>>>>) -> Self {
+        CapturingConsole { console, capture }
+    }
+}
+
+impl Console for CapturingConsole {
+    fn putchar(&mut self, c: u8) {
+        if let Some(output) = self.capture.borrow_mut().as_mut() {
+            output.push(c);
+        }
+        self.console.putchar(c)
+    }
+
+    fn getchar(&mut self) -> u8 {
+        self.console.getchar()
+    }
+
+    fn set_input(&mut self, c: u8) {
+        self.console.set_input(c)
+    }
+
+    fn get_output(&mut self) -> u8 {
+        self.console.get_output()
+    }
+
+    fn get_buffers(&self) -> (Vec<u8>, Vec<u8>) {
+        self.console.get_buffers()
+    }
+
+    fn set_buffers(&mut self, input: Vec<u8>, output: Vec<u8>) {
+        self.console.set_buffers(input, output)
+    }
+}
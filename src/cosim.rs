@@ -0,0 +1,207 @@
+use std::fmt;
+
+use crate::cpu::cpu::{Cpu, ExecutedInstruction, Privilege, StepResult, Xlen};
+use crate::cpu::cpu_csr::*;
+use crate::cpu::mmu::MemoryWrite;
+use crate::cpu::trap::Interrupt;
+
+/// CSRs compared in lockstep. mip, the counters and the timer are left out
+/// because they follow the devices and the cycles rather than the program.
+pub const COMPARED_CSRS: [u16; 17] = [
+    CSR_MSTATUS,
+    CSR_MISA,
+    CSR_MEDELEG,
+    CSR_MIDELEG,
+    CSR_MIE,
+    CSR_MTVEC,
+    CSR_MSCRATCH,
+    CSR_MEPC,
+    CSR_MCAUSE,
+    CSR_MTVAL,
+    CSR_STVEC,
+    CSR_SSCRATCH,
+    CSR_SEPC,
+    CSR_SCAUSE,
+    CSR_STVAL,
+    CSR_SPTBR,
+    CSR_FCSR,
+];
+
+/// Architectural state compared after every step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchState {
+    pub pc: u64,
+    pub privilege: Privilege,
+    /// integer registers truncated to the XLEN.
+    pub x: [u64; 32],
+    /// bits of the floating-point registers.
+    pub f: [u64; 32],
+    /// values of `COMPARED_CSRS`.
+    pub csrs: Vec<(u16, u64)>,
+}
+
+impl ArchState {
+    pub fn from_cpu(cpu: &mut Cpu) -> Self {
+        let mut x = [0; 32];
+        for (value, register) in x.iter_mut().zip(cpu.x.iter()) {
+            *value = match cpu.xlen {
+                Xlen::X32 => *register as u64 & 0xffffffff,
+                Xlen::X64 => *register as u64,
+            };
+        }
+        let mut f = [0; 32];
+        for (value, register) in f.iter_mut().zip(cpu.f.iter()) {
+            *value = register.to_bits();
+        }
+        ArchState {
+            pc: cpu.pc,
+            privilege: cpu.privilege.clone(),
+            x,
+            f,
+            csrs: COMPARED_CSRS
+                .iter()
+                .map(|&csr| (csr, cpu.read_csr_direct(csr)))
+                .collect(),
+        }
+    }
+}
+
+/// Model which runs the same program in lockstep with the emulator, e.g.
+/// another `Emulator` with a different configuration.
+pub trait ReferenceModel {
+    /// Runs the step which the emulator has run. It takes `interrupt`, if any,
+    /// instead of its own pending interrupts, and then executes an instruction
+    /// unless the hart waits for an interrupt. Its loads from devices return
+    /// `mmio_reads` in order. Returns the stores of the step.
+    fn step(&mut self, interrupt: Option<Interrupt>, mmio_reads: Vec<u64>) -> Vec<MemoryWrite>;
+    fn get_arch_state(&mut self) -> ArchState;
+}
+
+/// Mismatch between the emulator and the reference model after a step.
+#[derive(Clone, Debug)]
+pub struct Divergence {
+    /// number of the steps since the reference model was set, including this one.
+    pub step: u64,
+    /// instruction executed in the step.
+    pub instruction: Option<ExecutedInstruction>,
+    /// name of the state, its value in the emulator and in the reference model.
+    pub differences: Vec<(String, String, String)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Divergence at step {}", self.step)?;
+        if let Some(instruction) = &self.instruction {
+            write!(
+                f,
+                " after {:016x}: {:08x} {}",
+                instruction.address, instruction.word, instruction.mnemonic
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "    {:<12} {:<24} reference", "", "emulator")?;
+        for (name, value, reference) in self.differences.iter() {
+            writeln!(f, "    {:<12} {:<24} {}", name, value, reference)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lockstep co-simulation, which steps the reference model after each step of
+/// the hart. The MMIO reads and the interrupts of the hart are forwarded to the
+/// reference so that device-dependent code stays in sync.
+pub struct Lockstep {
+    reference: Box<dyn ReferenceModel>,
+    steps: u64,
+}
+
+impl Lockstep {
+    pub fn new(reference: Box<dyn ReferenceModel>) -> Self {
+        Lockstep {
+            reference,
+            steps: 0,
+        }
+    }
+
+    /// Runs a step of the hart and of the reference model, and compares them.
+    /// The reference model does not follow the hart in debug mode. A mismatch
+    /// is returned in `divergence` of the result.
+    pub fn step(&mut self, cpu: &mut Cpu) -> StepResult {
+        if cpu.is_debug_mode() {
+            return cpu.step();
+        }
+        cpu.mmu.start_access_log();
+        let mut result = cpu.step();
+        let log = cpu.mmu.take_access_log();
+        if result.debug_halt {
+            return result;
+        }
+        let reference_writes = self.reference.step(result.interrupt, log.mmio_reads);
+        self.steps += 1;
+
+        let mut differences =
+            compare_states(&ArchState::from_cpu(cpu), &self.reference.get_arch_state());
+        if log.writes != reference_writes {
+            differences.push((
+                String::from("stores"),
+                format_writes(&log.writes),
+                format_writes(&reference_writes),
+            ));
+        }
+        if !differences.is_empty() {
+            result.divergence = Some(Box::new(Divergence {
+                step: self.steps,
+                instruction: result.instruction.clone(),
+                differences,
+            }));
+        }
+        result
+    }
+}
+
+fn compare_states(state: &ArchState, reference: &ArchState) -> Vec<(String, String, String)> {
+    let hex = |value: &u64| format!("0x{:x}", value);
+    let mut differences = vec![];
+    if state.pc != reference.pc {
+        differences.push((String::from("pc"), hex(&state.pc), hex(&reference.pc)));
+    }
+    if state.privilege != reference.privilege {
+        differences.push((
+            String::from("privilege"),
+            format!("{:?}", state.privilege),
+            format!("{:?}", reference.privilege),
+        ));
+    }
+    for (i, (value, reference)) in state.x.iter().zip(reference.x.iter()).enumerate() {
+        if value != reference {
+            differences.push((format!("x{}", i), hex(value), hex(reference)));
+        }
+    }
+    for (i, (value, reference)) in state.f.iter().zip(reference.f.iter()).enumerate() {
+        if value != reference {
+            differences.push((format!("f{}", i), hex(value), hex(reference)));
+        }
+    }
+    for ((csr, value), (_, reference)) in state.csrs.iter().zip(reference.csrs.iter()) {
+        if value != reference {
+            differences.push((csr_name(*csr).to_string(), hex(value), hex(reference)));
+        }
+    }
+    differences
+}
+
+fn format_writes(writes: &[MemoryWrite]) -> String {
+    let writes = writes
+        .iter()
+        .map(|write| {
+            format!(
+                "[0x{:x}]({})=0x{:x}",
+                write.address, write.size, write.value
+            )
+        })
+        .collect::<Vec<_>>();
+    match writes.is_empty() {
+        true => String::from("none"),
+        false => writes.join(", "),
+    }
+}
@@ -0,0 +1,260 @@
+// Code coverage of the guest programs.
+//
+// The executed basic blocks are counted while the coverage is enabled. A block
+// is a sequence of instructions at consecutive addresses, which ends at a
+// branch, a jump, a trap or any other control transfer. The blocks are
+// exported per loaded module in the drcov format of DynamoRIO, which
+// Lighthouse reads, and as the line coverage in the lcov tracefile format,
+// which genhtml reads, using the DWARF line number table of the module.
+// https://github.com/gaasedelen/lighthouse/tree/master/coverage
+// https://github.com/linux-test-project/lcov/blob/master/man/geninfo.1
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::cpu::cpu::StepResult;
+use crate::symbols::SymbolTable;
+
+/// Program loaded to the memory, which the blocks are attributed to.
+pub struct Module {
+    /// path of the ELF file, or a name if it was loaded from a buffer.
+    pub path: String,
+    /// start of the loaded sections, by their (virtual) addresses.
+    pub base: u64,
+    /// end of the loaded sections.
+    pub end: u64,
+    pub entry: u64,
+    pub symbols: Rc<SymbolTable>,
+}
+
+/// Executed basic block of `end - start` bytes, with the number of the times
+/// it was executed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub start: u64,
+    pub end: u64,
+    pub count: u64,
+}
+
+#[derive(Default)]
+pub struct Coverage {
+    enabled: bool,
+    modules: Vec<Module>,
+    /// executions of the blocks by the start and the end.
+    blocks: HashMap<(u64, u64), u64>,
+    /// start and end of the block being executed.
+    current: Option<(u64, u64)>,
+}
+
+/// Whether the instruction is a conditional branch, which ends a block even
+/// if it is not taken. The other control transfers change the PC.
+fn is_branch(word: u32) -> bool {
+    match word & 0x3 {
+        // c.beqz, c.bnez
+        0x1 => (word >> 13) & 0x6 == 0x6,
+        0x3 => word & 0x7f == 0x63,
+        _ => false,
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Starts or stops counting the blocks. The counted blocks are kept.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.end_block();
+    }
+
+    /// Forgets the counted blocks. The modules are kept.
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.current = None;
+    }
+
+    pub fn add_module(&mut self, module: Module) {
+        self.modules.push(module);
+    }
+
+    pub fn get_modules(&self) -> &[Module] {
+        &self.modules
+    }
+
+    /// Counts the instruction of the step. `pc` is the PC after the step.
+    pub fn record(&mut self, result: &StepResult, pc: u64) {
+        let instruction = match result.instruction.as_ref() {
+            Some(instruction) => instruction,
+            None => {
+                if result.exception.is_some() || result.interrupt.is_some() {
+                    self.end_block();
+                }
+                return;
+            }
+        };
+        let end = instruction.address + instruction.length as u64;
+        match self.current {
+            Some((start, current_end))
+                if current_end == instruction.address && result.interrupt.is_none() =>
+            {
+                self.current = Some((start, end));
+            }
+            _ => {
+                self.end_block();
+                self.current = Some((instruction.address, end));
+            }
+        }
+        if pc != end || result.exception.is_some() || is_branch(instruction.word) {
+            self.end_block();
+        }
+    }
+
+    fn end_block(&mut self) {
+        if let Some(block) = self.current.take() {
+            *self.blocks.entry(block).or_insert(0) += 1;
+        }
+    }
+
+    /// Returns the executed blocks sorted by the address, including the one
+    /// being executed.
+    pub fn get_blocks(&self) -> Vec<Block> {
+        let mut blocks = self.blocks.clone();
+        if let Some(block) = self.current {
+            *blocks.entry(block).or_insert(0) += 1;
+        }
+        let mut blocks: Vec<Block> = blocks
+            .into_iter()
+            .map(|((start, end), count)| Block { start, end, count })
+            .collect();
+        blocks.sort_by_key(|block| (block.start, block.end));
+        blocks
+    }
+
+    /// Returns the index of the module containing `address`. The modules
+    /// loaded later are preferred, since they have overwritten the earlier.
+    fn find_module(&self, address: u64) -> Option<usize> {
+        self.modules
+            .iter()
+            .rposition(|module| module.base <= address && address < module.end)
+    }
+
+    /// Writes the blocks in the modules in the drcov format (version 2). The
+    /// blocks outside the modules, e.g. of the boot ROM, are left out.
+    pub fn write_drcov(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let mut entries = vec![];
+        for block in self.get_blocks() {
+            let id = match self.find_module(block.start) {
+                Some(id) => id,
+                None => continue,
+            };
+            let offset = block.start - self.modules[id].base;
+            if offset > u32::MAX as u64 {
+                continue;
+            }
+            let size = (block.end - block.start).min(u16::MAX as u64);
+            // the blocks of the same start are merged into the longest one.
+            match entries.last_mut() {
+                Some((last, last_size, last_id)) if *last == offset && *last_id == id => {
+                    *last_size = size;
+                }
+                _ => entries.push((offset, size, id)),
+            }
+        }
+
+        writeln!(writer, "DRCOV VERSION: 2")?;
+        writeln!(writer, "DRCOV FLAVOR: riscv_emu")?;
+        writeln!(
+            writer,
+            "Module Table: version 2, count {}",
+            self.modules.len()
+        )?;
+        writeln!(
+            writer,
+            "Columns: id, base, end, entry, checksum, timestamp, path"
+        )?;
+        for (id, module) in self.modules.iter().enumerate() {
+            writeln!(
+                writer,
+                "{:3}, 0x{:016x}, 0x{:016x}, 0x{:016x}, 0x{:08x}, 0x{:08x}, {}",
+                id, module.base, module.end, module.entry, 0, 0, module.path
+            )?;
+        }
+        writeln!(writer, "BB Table: {} bbs", entries.len())?;
+        for (offset, size, id) in entries {
+            writer.write_all(&(offset as u32).to_le_bytes())?;
+            writer.write_all(&(size as u16).to_le_bytes())?;
+            writer.write_all(&(id as u16).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Writes the line coverage of the source files of the modules in the
+    /// lcov format. A line counts the executions of its instruction executed
+    /// most often, which is in all the blocks containing it.
+    pub fn write_lcov(&self, writer: &mut dyn Write) -> io::Result<()> {
+        // executions of the code from each address up to the next one.
+        let mut changes: BTreeMap<u64, i64> = BTreeMap::new();
+        for block in self.get_blocks() {
+            *changes.entry(block.start).or_insert(0) += block.count as i64;
+            *changes.entry(block.end).or_insert(0) -= block.count as i64;
+        }
+        let mut steps: Vec<(u64, u64)> = vec![];
+        let mut executions = 0;
+        for (address, change) in changes {
+            executions += change;
+            steps.push((address, executions as u64));
+        }
+        // executions of the code in [start, end).
+        let count = |start: u64, end: u64| {
+            let first = steps
+                .partition_point(|step| step.0 <= start)
+                .saturating_sub(1);
+            steps[first..]
+                .iter()
+                .take_while(|step| step.0 < end)
+                .map(|step| step.1)
+                .max()
+                .unwrap_or(0)
+        };
+
+        // line counts by the source file. A file may be in several units.
+        let mut files: BTreeMap<&str, BTreeMap<u64, u64>> = BTreeMap::new();
+        for module in self.modules.iter() {
+            let (names, rows) = module.symbols.get_line_table();
+            for (row, next) in rows.iter().zip(rows.iter().skip(1)) {
+                // line 0 is code which no source line is attributed to.
+                if row.end_sequence || row.line == 0 {
+                    continue;
+                }
+                let hits = files
+                    .entry(&names[row.file])
+                    .or_default()
+                    .entry(row.line)
+                    .or_insert(0);
+                *hits = (*hits).max(count(row.address, next.address));
+            }
+        }
+
+        for (file, lines) in files {
+            writeln!(writer, "TN:")?;
+            writeln!(writer, "SF:{}", file)?;
+            for (line, hits) in lines.iter() {
+                writeln!(writer, "DA:{},{}", line, hits)?;
+            }
+            writeln!(writer, "LF:{}", lines.len())?;
+            writeln!(
+                writer,
+                "LH:{}",
+                lines.values().filter(|hits| **hits > 0).count()
+            )?;
+            writeln!(writer, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
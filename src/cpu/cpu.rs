@@ -1,19 +1,74 @@
+use std::rc::Rc;
+
 use crate::bus::bus::Device;
 use crate::console::Console;
+use crate::cosim::Divergence;
 use crate::cpu::cpu_csr::*;
-use crate::cpu::cpu_instruction::{Opecode, OPECODES};
+use crate::cpu::cpu_instruction::{
+    destination_register, uses_rve_reserved_registers, Opecode, OPECODES,
+};
 use crate::cpu::cpu_instruction_comp::*;
 use crate::cpu::mmu::Mmu;
 use crate::cpu::trap::*;
 use crate::machine::Machine;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
+use crate::symbols::SymbolTable;
+use crate::trace::{TextSink, TraceCategory, TraceEvent};
 
-#[derive(Clone, Debug)]
+/// Number of cycles a `pause` hint stalls the hart.
+const PAUSE_STALL_CYCLES: u64 = 32;
+
+/// Timeout of `wrs.sto` (short duration wait on reservation set).
+const WRS_STO_STALL_CYCLES: u64 = 1024;
+
+/// Single-letter extensions of the ISA string and their misa bits, in
+/// canonical order.
+const ISA_LETTERS: [(char, u64); 7] = [
+    ('i', CSR_MISA_I),
+    ('e', CSR_MISA_E),
+    ('m', CSR_MISA_M),
+    ('a', CSR_MISA_A),
+    ('f', CSR_MISA_F),
+    ('d', CSR_MISA_D),
+    ('c', CSR_MISA_C),
+];
+
+/// Multi-letter extensions advertised in the ISA string, in canonical order,
+/// with the misa bits of the extensions which they depend on.
+const ISA_EXTENSIONS: [(&str, u64); 24] = [
+    ("zicbom", 0),
+    ("zicbop", 0),
+    ("zicboz", 0),
+    ("zicond", 0),
+    ("zihintpause", 0),
+    ("zacas", CSR_MISA_A),
+    ("zawrs", CSR_MISA_A),
+    ("zfa", CSR_MISA_F),
+    ("zfh", CSR_MISA_F),
+    ("zcb", CSR_MISA_C),
+    ("zcmp", CSR_MISA_C),
+    ("zbkb", 0),
+    ("zbkc", 0),
+    ("zbkx", 0),
+    ("zknd", 0),
+    ("zkne", 0),
+    ("zknh", 0),
+    ("zkr", 0),
+    ("zksed", 0),
+    ("zksh", 0),
+    ("sdext", 0),
+    ("sdtrig", 0),
+    ("svnapot", CSR_MISA_S),
+    ("svpbmt", CSR_MISA_S),
+];
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Xlen {
     X32 = 0,
     X64 = 1,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Privilege {
     User = 0,
     Supervisor = 1,
@@ -21,17 +76,100 @@ pub enum Privilege {
     Machine = 3,
 }
 
+/// Reasons to enter debug mode, which are saved in dcsr.cause.
+#[derive(Clone, Debug)]
+pub enum DebugCause {
+    Ebreak = 1,
+    Trigger = 2,
+    HaltRequest = 3,
+    Step = 4,
+    ResetHaltRequest = 5,
+}
+
+/// Abstract commands of the debug spec, which the host issues to a hart halted in debug mode.
+pub enum AbstractCommand {
+    /// Access Register. regno 0x0000-0x0fff are the CSRs, 0x1000-0x101f are the integer
+    /// registers and 0x1020-0x103f are the floating-point registers.
+    ReadRegister(u16),
+    WriteRegister(u16, u64),
+    /// Access Memory of 1, 2, 4 or 8 bytes. A virtual address is translated in the
+    /// privilege mode held in dcsr.prv.
+    ReadMemory {
+        address: u64,
+        size: u8,
+        virtual_address: bool,
+    },
+    WriteMemory {
+        address: u64,
+        size: u8,
+        virtual_address: bool,
+        data: u64,
+    },
+}
+
+/// Errors of abstract commands, which are reported in abstractcs.cmderr.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommandError {
+    NotSupported = 2,
+    Exception = 3,
+    HaltResume = 4,
+    Bus = 5,
+}
+
+/// Instruction executed in a step of the hart.
+#[derive(Clone, Debug)]
+pub struct ExecutedInstruction {
+    pub address: u64,
+    /// instruction bits as fetched. The upper half is 0 for compressed instructions.
+    pub word: u32,
+    /// instruction bits after a compressed instruction is expanded.
+    pub expanded_word: u32,
+    /// 2 for compressed instructions, otherwise 4.
+    pub length: u8,
+    pub mnemonic: &'static str,
+}
+
+/// What happened in a step of the hart (`Cpu::step`).
+#[derive(Clone, Debug, Default)]
+pub struct StepResult {
+    /// interrupt taken before the instruction.
+    pub interrupt: Option<Interrupt>,
+    /// instruction executed in the step. It is retired unless `exception` is set.
+    pub instruction: Option<ExecutedInstruction>,
+    /// exception taken in the step, including a fault of the instruction fetch.
+    pub exception: Option<Trap>,
+    /// the hart entered debug mode in the step.
+    pub debug_halt: bool,
+    /// the state differs from the reference model after the step, which is
+    /// only compared by `Emulator` in lockstep.
+    pub divergence: Option<Box<Divergence>>,
+}
+
 pub struct Cpu {
     cycle: u64,
     pub pc: u64,
     pub wfi: bool,
+    /// remaining cycles of a stall requested by `pause` or `wrs.sto`.
+    stall_timeout: Option<u64>,
+    /// `wrs.nto`/`wrs.sto` stall until the reservation set is invalidated.
+    stall_on_reservation: bool,
     pub xlen: Xlen,
+    /// RV32E/RV64E base ISA, which only has the integer registers x0-x15.
+    pub rve: bool,
     pub privilege: Privilege,
+    /// the hart is halted in debug mode (Sdext).
+    debug_mode: bool,
+    /// entering debug mode ended a stall, which changed the execution.
+    debug_wakeup: bool,
     pub x: [i64; 32],
     pub f: [f64; 32],
     pub csr: Csr,
     pub mmu: Mmu,
-    testmode: bool,
+    /// events of the current step.
+    step_result: StepResult,
+    /// interrupt which the next step takes instead of the pending ones.
+    next_interrupt: Option<Option<Interrupt>>,
+    symbols: Rc<SymbolTable>,
 }
 
 impl Cpu {
@@ -40,15 +178,38 @@ impl Cpu {
             cycle: 0,
             pc: 0,
             wfi: false,
+            stall_timeout: None,
+            stall_on_reservation: false,
             xlen: Xlen::X64,
+            rve: false,
             privilege: Privilege::Machine,
+            debug_mode: false,
+            debug_wakeup: false,
             x: [0; 32],
             f: [0.0; 32],
             csr: Csr::new(),
-            mmu: Mmu::new(Xlen::X64, machine_, console),
-            testmode: testmode_,
+            mmu: Mmu::new(Xlen::X64, machine_.clone(), console),
+            step_result: StepResult::default(),
+            next_interrupt: None,
+            symbols: Rc::new(SymbolTable::new()),
         };
 
+        // testmode prints the instructions and the traps.
+        if testmode_ {
+            cpu.mmu.tracer.add_sink(Box::new(TextSink::stdout()));
+            cpu.mmu.tracer.set_categories(&[
+                TraceCategory::Instruction,
+                TraceCategory::Trap,
+                TraceCategory::Interrupt,
+            ]);
+        }
+
+        // the FE310 is RV32IMAC with M-mode and U-mode.
+        if let Machine::SiFiveE = machine_ {
+            cpu.csr
+                .clear_extensions(CSR_MISA_F | CSR_MISA_D | CSR_MISA_S);
+        }
+
         // initial value for Linux booting (DTB start address).
         cpu.x[0xb] = cpu.mmu.get_bus().get_base_address(Device::DTB) as i64;
         cpu
@@ -58,8 +219,11 @@ impl Cpu {
         self.pc = 0;
         self.cycle = 0;
         self.privilege = Privilege::Machine;
-        self.wfi = false;
-        self.xlen = Xlen::X64;
+        self.resume();
+        self.debug_mode = false;
+        self.csr.leave_debug_mode();
+        self.mmu.triggers.set_debug_mode(false);
+        self.set_xlen(Xlen::X64);
         self.x = [0; 32];
         self.f = [0.0; 32];
     }
@@ -68,24 +232,191 @@ impl Cpu {
         self.pc = pc;
     }
 
+    /// Returns the number of the cycles since the reset.
+    pub fn get_cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Makes the next step take `interrupt`, or no interrupt, regardless of the
+    /// pending interrupts, e.g. for a hart following another model in lockstep.
+    pub fn set_next_interrupt(&mut self, interrupt: Option<Interrupt>) {
+        self.next_interrupt = Some(interrupt);
+    }
+
+    /// Sets the symbols of the loaded program, which the tracer is also given.
+    pub fn set_symbols(&mut self, symbols: Rc<SymbolTable>) {
+        self.mmu.tracer.set_symbols(symbols.clone());
+        self.symbols = symbols;
+    }
+
+    pub fn get_symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Sets the machine XLEN (misa.MXL). The XLEN of S-mode and U-mode follows it.
     pub fn set_xlen(&mut self, xlen: Xlen) {
-        self.xlen = xlen;
+        self.csr.set_mxl(&xlen);
+        self.update_xlen();
+    }
+
+    /// Updates the effective XLEN of the current privilege mode. This must be called
+    /// when misa.MXL, mstatus.SXL/UXL or the privilege mode changes.
+    pub fn update_xlen(&mut self) {
+        self.xlen = self.csr.get_xlen(&self.privilege);
         self.mmu.set_xlen(&self.xlen);
     }
 
-    pub fn tick(&mut self) {
-        match self.check_interrupts() {
-            Some(interrupt) => self.interrupt_handler(interrupt),
-            None => {}
+    /// Reads a CSR as the CSR instructions do. The trigger registers are held by
+    /// the trigger module in the MMU.
+    pub fn read_csr(&mut self, csr: u16, addr: u64) -> Result<u64, Trap> {
+        let data = self.csr.read(csr, addr, &self.privilege)?;
+        Ok(match csr {
+            CSR_TSELECT..=CSR_TINFO => self.mmu.triggers.read(csr, &self.csr.get_mxl()),
+            _ => data,
+        })
+    }
+
+    /// Writes a CSR as the CSR instructions do, and applies the new value to the
+    /// address translation and the effective XLEN.
+    pub fn write_csr(&mut self, csr: u16, data: u64, addr: u64) -> Result<(), Trap> {
+        let need_update_mmu_addressing_mode = self.csr.write(csr, data, addr, &self.privilege)?;
+        match csr {
+            CSR_TSELECT..=CSR_TINFO => self.mmu.triggers.write(csr, data, &self.csr.get_mxl()),
+            _ => {}
+        }
+        if need_update_mmu_addressing_mode {
+            self.mmu.update_addressing_mode(data);
         }
+        self.update_xlen();
+        self.update_pbmte();
+        if self.mmu.tracer.is_enabled(TraceCategory::CsrWrite) {
+            let value = self.read_csr_direct(csr);
+            self.mmu.tracer.record(TraceEvent::CsrWrite { csr, value });
+        }
+        Ok(())
+    }
 
-        if !self.wfi {
-            let instruction_addr = self.pc;
-            match self.tick_execute() {
-                Ok(()) => {}
-                Err(e) => self.catch_exception(e, instruction_addr),
+    /// Reads a CSR without the privilege check, e.g. for a debugger or a test harness.
+    pub fn read_csr_direct(&mut self, csr: u16) -> u64 {
+        match csr {
+            CSR_TSELECT..=CSR_TINFO => self.mmu.triggers.read(csr, &self.csr.get_mxl()),
+            _ => self.csr.read_direct(csr),
+        }
+    }
+
+    /// Writes a CSR without the privilege check, and applies the new value like `write_csr`.
+    pub fn write_csr_direct(&mut self, csr: u16, data: u64) {
+        match csr {
+            CSR_TSELECT..=CSR_TINFO => self.mmu.triggers.write(csr, data, &self.csr.get_mxl()),
+            _ => self.csr.write_direct(csr, data),
+        }
+        if csr == CSR_SPTBR {
+            self.mmu.update_addressing_mode(data);
+        }
+        self.update_xlen();
+        self.update_pbmte();
+    }
+
+    /// Updates whether the MMU accepts the PBMT field of PTEs (menvcfg.PBMTE).
+    pub fn update_pbmte(&mut self) {
+        let pbmte = self.csr.read_direct(CSR_MENVCFG) & CSR_ENVCFG_PBMTE != 0;
+        self.mmu.set_pbmte(pbmte);
+    }
+
+    /// Selects the RV32E/RV64E base ISA instead of RV32I/RV64I.
+    pub fn set_rve(&mut self, rve: bool) {
+        self.rve = rve;
+        self.csr.set_rve(rve);
+    }
+
+    /// ISA string of this hart (e.g. "rv64imafdc_zicond_zihintpause"), which
+    /// has the extensions in misa and the multi-letter extensions which they allow.
+    pub fn get_isa_string(&self) -> String {
+        let mut isa = match self.csr.get_mxl() {
+            Xlen::X32 => String::from("rv32"),
+            Xlen::X64 => String::from("rv64"),
+        };
+        let misa = self.csr.read_direct(CSR_MISA);
+        for (letter, bit) in ISA_LETTERS.iter() {
+            if misa & bit != 0 {
+                isa.push(*letter);
             }
         }
+        for (extension, requires) in ISA_EXTENSIONS.iter() {
+            if misa & requires == *requires {
+                isa += "_";
+                isa += extension;
+            }
+        }
+        isa
+    }
+
+    /// Stall the hart in the same way as WFI. The stall ends when an interrupt is taken,
+    /// when `timeout` cycles have elapsed, or (if `on_reservation` is set) when the
+    /// reservation set is invalidated or an enabled interrupt becomes pending.
+    fn stall(&mut self, timeout: Option<u64>, on_reservation: bool) {
+        self.wfi = true;
+        self.stall_timeout = timeout;
+        self.stall_on_reservation = on_reservation;
+    }
+
+    /// [pause] hint.
+    pub fn stall_pause(&mut self) {
+        self.stall(Some(PAUSE_STALL_CYCLES), false);
+    }
+
+    /// [wrs.nto]/[wrs.sto] hints. If no reservation set is registered, they do not stall.
+    pub fn stall_wrs(&mut self, short_timeout: bool) {
+        if !self.mmu.has_address_reserved() {
+            return;
+        }
+        self.stall(
+            match short_timeout {
+                true => Some(WRS_STO_STALL_CYCLES),
+                false => None,
+            },
+            true,
+        );
+    }
+
+    /// The hart waits for an interrupt or for the end of a stall.
+    pub fn is_stalled(&self) -> bool {
+        self.wfi || self.stall_timeout.is_some() || self.stall_on_reservation
+    }
+
+    fn resume(&mut self) {
+        self.wfi = false;
+        self.stall_timeout = None;
+        self.stall_on_reservation = false;
+    }
+
+    /// Runs a cycle like `tick`, and returns what the hart did in it.
+    pub fn step(&mut self) -> StepResult {
+        let debug_mode = self.debug_mode;
+        self.tick();
+        let mut result = std::mem::take(&mut self.step_result);
+        result.debug_halt = !debug_mode && self.debug_mode;
+        result
+    }
+
+    pub fn tick(&mut self) {
+        self.step_result = StepResult::default();
+
+        // the state at the start of the cycle, which the statistics count it in.
+        let state = match self.mmu.stats.is_counting() {
+            true => Some((self.privilege.clone(), self.debug_mode, self.is_stalled())),
+            false => None,
+        };
+
+        // a hart halted in debug mode executes nothing until the host resumes it.
+        if !self.debug_mode {
+            self.tick_hart();
+        }
+        if let Some((privilege, halted, stalled)) = state {
+            self.mmu
+                .stats
+                .record_cycle(&privilege, halted, stalled, &self.step_result);
+        }
 
         // run peripherals.
         let bus = self.mmu.get_bus();
@@ -99,44 +430,109 @@ impl Cpu {
         self.csr.tick();
     }
 
+    fn tick_hart(&mut self) {
+        let dcsr = self.csr.read_direct(CSR_DCSR);
+        let stepping = dcsr & CSR_DCSR_STEP != 0;
+
+        // interrupts are disabled while single stepping unless dcsr.stepie is set.
+        let next_interrupt = self.next_interrupt.take();
+        if !stepping || dcsr & CSR_DCSR_STEPIE != 0 {
+            let interrupt = match next_interrupt {
+                Some(interrupt) => interrupt,
+                None => self.check_interrupts(),
+            };
+            if let Some(interrupt) = interrupt {
+                self.step_result.interrupt = Some(interrupt);
+                self.interrupt_handler(interrupt);
+                // a step that takes an interrupt ends at the first instruction of the handler.
+                if stepping {
+                    self.enter_debug_mode(DebugCause::Step, self.pc);
+                    return;
+                }
+            }
+        }
+
+        if !self.wfi {
+            let instruction_addr = self.pc;
+            match self.tick_execute() {
+                Ok(()) => {}
+                Err(e) => self.catch_exception(e, instruction_addr),
+            }
+        } else {
+            self.tick_stall();
+        }
+
+        if stepping && !self.debug_mode {
+            self.enter_debug_mode(DebugCause::Step, self.pc);
+        }
+    }
+
     fn tick_execute(&mut self) -> Result<(), Trap> {
         let instruction_addr = self.pc;
-        let word = match self.fetch() {
+        if self.mmu.tracer.is_active() {
+            self.mmu
+                .tracer
+                .start_instruction(instruction_addr, &self.privilege);
+        }
+        let (raw_word, word) = match self.fetch() {
             Ok(_word) => _word,
             Err(e) => return Err(e),
         };
 
-        let mut debug_message = String::new();
-        if self.testmode {
-            debug_message += &format!("[PC]: {:016x}", instruction_addr);
-            debug_message += &format!(" [P]: {:?}", self.privilege);
-            debug_message += &format!(" [XLEN]: {:?} |", self.xlen);
-            debug_message += &format!("    {:08x}    ", word);
-            match self.pc.wrapping_sub(instruction_addr) {
-                0x2 => debug_message += "(C)",
-                _ => debug_message += "   ",
-            };
-        }
-
         // instruction decode.
-        let instruction = match self.decode(word) {
+        let instruction = match self.decode(instruction_addr, word) {
             Ok(opecode) => match (opecode.operation)(self, instruction_addr, word) {
                 Ok(_instruction) => _instruction,
-                Err(()) => panic!("Not found instruction: {:016x}", instruction_addr),
+                Err(()) => {
+                    return Err(Trap {
+                        exception: Exception::IllegalInstruction,
+                        value: instruction_addr,
+                    })
+                }
             },
             Err(e) => return Err(e),
         };
-
-        // instruction execute.
-        if self.testmode {
-            let dis = (instruction.disassemble)(self, instruction.mnemonic, word);
-            debug_message += &format!("{}", dis);
-            println!("{}", debug_message);
+        let length = self.pc.wrapping_sub(instruction_addr) as u8;
+        let disassembly = match self.mmu.tracer.is_enabled(TraceCategory::Instruction) {
+            true => Some((instruction.disassemble)(self, instruction.mnemonic, word)),
+            false => None,
+        };
+        let mnemonic = instruction.mnemonic;
+        let operation = instruction.operation;
+        if let Some(disassembly) = disassembly {
+            self.mmu.tracer.record(TraceEvent::Instruction {
+                word,
+                raw_word,
+                length,
+                xlen: self.xlen.clone(),
+                disassembly,
+            });
         }
-        match (instruction.operation)(self, instruction_addr, word) {
+        self.step_result.instruction = Some(ExecutedInstruction {
+            address: instruction_addr,
+            word: raw_word,
+            expanded_word: word,
+            length,
+            mnemonic,
+        });
+        // instruction execute.
+        let registers = match self.mmu.tracer.is_enabled(TraceCategory::RegisterWrite) {
+            true => Some((self.x, self.f)),
+            false => None,
+        };
+        match operation(self, instruction_addr, word) {
             Err(e) => return Err(e),
             _ => {}
         }
+        if let Some((x, f)) = registers {
+            self.trace_register_writes(word, &x, &f);
+        }
+        if self.mmu.tracer.is_enabled(TraceCategory::Branch) {
+            self.trace_branch(instruction_addr, word, length);
+        }
+        if self.mmu.tracer.is_enabled(TraceCategory::Call) {
+            self.trace_call(mnemonic, word);
+        }
 
         // x0 register is hardwired to the constant 0. To simplify the implementation,
         // I don't care that x0 is always zero in each instruction implementation.
@@ -145,6 +541,25 @@ impl Cpu {
         return Ok(());
     }
 
+    fn tick_stall(&mut self) {
+        if let Some(timeout) = self.stall_timeout {
+            match timeout {
+                0 => {
+                    self.resume();
+                    return;
+                }
+                n => self.stall_timeout = Some(n - 1),
+            }
+        }
+
+        if self.stall_on_reservation {
+            let pending = self.csr.read_direct(CSR_MIE) & self.csr.read_direct(CSR_MIP);
+            if pending != 0 || !self.mmu.has_address_reserved() {
+                self.resume();
+            }
+        }
+    }
+
     fn tick_interrupt(&mut self, irqs: &Vec<bool>) {
         let bus = self.mmu.get_bus();
 
@@ -188,7 +603,9 @@ impl Cpu {
         }
     }
 
-    fn fetch(&mut self) -> Result<u32, Trap> {
+    /// Fetches an instruction, and returns the fetched bits and the instruction
+    /// decompressed to 32 bits.
+    fn fetch(&mut self) -> Result<(u32, u32), Trap> {
         let fetch_word = match self.mmu.fetch32(self.pc) {
             Ok(word) => word,
             Err(e) => return Err(e),
@@ -198,13 +615,13 @@ impl Cpu {
             // 32bit instruction
             true => {
                 self.pc = self.pc.wrapping_add(4);
-                return Ok(fetch_word);
+                return Ok((fetch_word, fetch_word));
             }
             // 16bit compressed instruction
             false => {
                 self.pc = self.pc.wrapping_add(2);
                 return match instruction_decompress(self, self.pc.wrapping_sub(2), fetch_word) {
-                    Ok(word) => Ok(word),
+                    Ok(word) => Ok((fetch_word & 0xffff, word)),
                     Err(()) => Err(Trap {
                         exception: Exception::IllegalInstruction,
                         value: self.pc.wrapping_sub(2),
@@ -214,19 +631,95 @@ impl Cpu {
         };
     }
 
-    fn decode(&mut self, word: u32) -> Result<&Opecode, Trap> {
+    fn decode(&mut self, addr: u64, word: u32) -> Result<&Opecode, Trap> {
+        // Zcmp instructions have no 32-bit equivalent and are kept as 16-bit words.
+        if word & 0x3 != 0x3 {
+            return Ok(&COMPRESSED_MULTI_OP);
+        }
+        if self.rve && uses_rve_reserved_registers(word) {
+            return Err(Trap {
+                exception: Exception::IllegalInstruction,
+                value: addr,
+            });
+        }
         match OPECODES.get(&((word & 0x7f) as u8)) {
             Some(opecode) => return Ok(&opecode),
-            None => panic!("Not found opecode: {:016x}", word),
+            None => Err(Trap {
+                exception: Exception::IllegalInstruction,
+                value: addr,
+            }),
+        }
+    }
+
+    /// Records the registers which the instruction has changed, and its
+    /// destination register even if the value is unchanged.
+    fn trace_register_writes(&mut self, word: u32, x: &[i64; 32], f: &[f64; 32]) {
+        let destination = match word & 0x3 == 0x3 {
+            true => destination_register(word),
+            false => None,
+        };
+        for (i, (new, old)) in self.x.iter().zip(x.iter()).enumerate().skip(1) {
+            if new != old || destination == Some((i as u8, false)) {
+                self.mmu.tracer.record(TraceEvent::IntRegisterWrite {
+                    register: i as u8,
+                    value: *new as u64,
+                });
+            }
+        }
+        for (i, (new, old)) in self.f.iter().zip(f.iter()).enumerate() {
+            if new.to_bits() != old.to_bits() || destination == Some((i as u8, true)) {
+                self.mmu.tracer.record(TraceEvent::FpRegisterWrite {
+                    register: i as u8,
+                    value: new.to_bits(),
+                });
+            }
+        }
+    }
+
+    /// Records the control transfer of the instruction, and a conditional
+    /// branch even if it is not taken.
+    fn trace_branch(&mut self, addr: u64, word: u32, length: u8) {
+        let taken = self.pc != addr.wrapping_add(length as u64);
+        let conditional = word & 0x7f == 0x63;
+        if taken || conditional {
+            self.mmu.tracer.record(TraceEvent::Branch {
+                target: self.pc,
+                taken,
+            });
+        }
+    }
+
+    /// Records a call or a return, which are the jumps linking or restoring ra
+    /// (or t0 as the alternate link register).
+    fn trace_call(&mut self, mnemonic: &str, word: u32) {
+        let rd = (word >> 7) & 0x1f;
+        let rs1 = (word >> 15) & 0x1f;
+        let is_link = |register: u32| register == 1 || register == 5;
+        match mnemonic {
+            "jal" | "jalr" if is_link(rd) => {
+                self.mmu.tracer.record(TraceEvent::Call { target: self.pc })
+            }
+            "jalr" if rd == 0 && is_link(rs1) => self
+                .mmu
+                .tracer
+                .record(TraceEvent::Return { target: self.pc }),
+            _ => {}
         }
     }
 
     fn catch_exception(&mut self, trap: Trap, addr: u64) {
-        if self.testmode {
-            println!(
-                "  >> Exception: {:?} ({:016x}) {:?}, {:x}",
-                trap.exception, trap.value, self.privilege, self.pc
-            );
+        // a trigger with action=1 enters debug mode instead of raising the exception.
+        if self.mmu.triggers.take_debug_request() {
+            self.enter_debug_mode(DebugCause::Trigger, addr);
+            return;
+        }
+
+        self.step_result.exception = Some(trap.clone());
+        if self.mmu.tracer.is_enabled(TraceCategory::Trap) {
+            self.mmu.tracer.record(TraceEvent::Trap {
+                exception: trap.exception,
+                value: trap.value,
+            });
         }
 
         let trap_code = trap.exception as u8;
@@ -286,14 +779,9 @@ impl Cpu {
     }
 
     fn interrupt_handler(&mut self, interrupt: Interrupt) {
-        if self.testmode {
-            let mie = self.csr.read_direct(CSR_MIE);
-            let mip = self.csr.read_direct(CSR_MIP);
-            println!("mie = {:x}, mip = {:x}", mie, mip);
-            println!(
-                "  >> Interrupt: {:?} ({:x}, {:?})",
-                interrupt, self.pc, self.privilege
-            );
+        if self.mmu.tracer.is_enabled(TraceCategory::Interrupt) {
+            self.mmu.tracer.set_context(self.pc, &self.privilege);
+            self.mmu.tracer.record(TraceEvent::Interrupt { interrupt });
         }
 
         let trap_code = interrupt as u8;
@@ -304,7 +792,7 @@ impl Cpu {
         self.update_csr_trap_registers(self.pc, trap_code, self.pc, previous_privilege, true);
         self.pc = self.get_trap_next_pc();
 
-        self.wfi = false;
+        self.resume();
     }
 
     fn _clear_interrupt(&mut self, interrupt: Interrupt) {
@@ -549,8 +1037,278 @@ impl Cpu {
         }
     }
 
-    fn change_privilege(&mut self, next_privilege: Privilege) {
+    pub fn change_privilege(&mut self, next_privilege: Privilege) {
         self.privilege = next_privilege;
         self.mmu.set_privilege(&self.privilege);
+        self.update_xlen();
+    }
+
+    pub fn is_debug_mode(&self) -> bool {
+        self.debug_mode
+    }
+
+    /// Returns whether entering debug mode has ended a stall since the last call.
+    pub fn take_debug_wakeup(&mut self) -> bool {
+        std::mem::take(&mut self.debug_wakeup)
+    }
+
+    /// Halts the hart in debug mode. `pc` is saved in dpc, and the hart runs in
+    /// M-mode until it leaves debug mode.
+    pub fn enter_debug_mode(&mut self, cause: DebugCause, pc: u64) {
+        self.mmu.tracer.record(TraceEvent::DebugEntry {
+            cause: cause.clone(),
+            dpc: pc,
+        });
+        self.csr.enter_debug_mode(cause as u64, &self.privilege, pc);
+        self.debug_mode = true;
+        self.mmu.triggers.set_debug_mode(true);
+        self.debug_wakeup |= self.is_stalled();
+        self.resume();
+        self.change_privilege(Privilege::Machine);
+    }
+
+    /// Resumes the hart at dpc in the privilege mode held in dcsr.prv (`dret`).
+    pub fn leave_debug_mode(&mut self) {
+        let dcsr = self.csr.read_direct(CSR_DCSR);
+        let privilege = match dcsr & CSR_DCSR_PRV {
+            0 => Privilege::User,
+            1 => Privilege::Supervisor,
+            _ => Privilege::Machine,
+        };
+        // mstatus.MPRV is cleared when the hart resumes in a mode below M-mode.
+        if dcsr & CSR_DCSR_PRV != 3 {
+            self.csr
+                .read_modify_write_direct(CSR_MSTATUS, 0, CSR_STATUS_MPRV);
+        }
+        self.pc = self.csr.read_direct(CSR_DPC);
+        self.csr.leave_debug_mode();
+        self.debug_mode = false;
+        self.mmu.triggers.set_debug_mode(false);
+        self.change_privilege(privilege);
+    }
+
+    /// Halt request from the host (e.g. a debugger).
+    pub fn debug_halt(&mut self) {
+        if !self.debug_mode {
+            self.enter_debug_mode(DebugCause::HaltRequest, self.pc);
+        }
+    }
+
+    /// Resume request from the host. If dcsr.step is set, the hart executes a
+    /// single instruction and halts again.
+    pub fn debug_resume(&mut self) {
+        if self.debug_mode {
+            self.leave_debug_mode();
+        }
+    }
+
+    /// Executes an abstract command on the halted hart, and returns the value
+    /// read by the command (0 for writes).
+    pub fn execute_abstract_command(
+        &mut self,
+        command: &AbstractCommand,
+    ) -> Result<u64, CommandError> {
+        if !self.debug_mode {
+            return Err(CommandError::HaltResume);
+        }
+        let gprs = match self.rve {
+            true => 16,
+            false => 32,
+        };
+        match *command {
+            AbstractCommand::ReadRegister(regno) => match regno {
+                0x0000..=0x0fff => self
+                    .read_csr(regno, self.pc)
+                    .map_err(|_| CommandError::Exception),
+                0x1000..=0x101f if regno - 0x1000 < gprs => {
+                    Ok(self.x[(regno - 0x1000) as usize] as u64)
+                }
+                0x1020..=0x103f => Ok(self.f[(regno - 0x1020) as usize].to_bits()),
+                _ => Err(CommandError::NotSupported),
+            },
+            AbstractCommand::WriteRegister(regno, data) => match regno {
+                0x0000..=0x0fff => self
+                    .write_csr(regno, data, self.pc)
+                    .map(|_| 0)
+                    .map_err(|_| CommandError::Exception),
+                0x1000..=0x101f if regno - 0x1000 < gprs => {
+                    // x0 is hardwired to 0.
+                    if regno != 0x1000 {
+                        self.x[(regno - 0x1000) as usize] = match self.xlen {
+                            Xlen::X32 => data as i32 as i64,
+                            Xlen::X64 => data as i64,
+                        };
+                    }
+                    Ok(0)
+                }
+                0x1020..=0x103f => {
+                    self.f[(regno - 0x1020) as usize] = f64::from_bits(data);
+                    Ok(0)
+                }
+                _ => Err(CommandError::NotSupported),
+            },
+            AbstractCommand::ReadMemory {
+                address,
+                size,
+                virtual_address,
+            } => self.access_memory_for_debug(address, size, virtual_address, None),
+            AbstractCommand::WriteMemory {
+                address,
+                size,
+                virtual_address,
+                data,
+            } => self
+                .access_memory_for_debug(address, size, virtual_address, Some(data))
+                .map(|_| 0),
+        }
+    }
+
+    /// Executes the program buffer of the debug module on the halted hart. The
+    /// program ends at `ebreak`/`c.ebreak` or at the end of the buffer, and
+    /// control flow instructions do not move the execution in the buffer.
+    pub fn execute_program_buffer(&mut self, program: &[u32]) -> Result<(), CommandError> {
+        if !self.debug_mode {
+            return Err(CommandError::HaltResume);
+        }
+        let pc = self.pc;
+        let mut halfwords = vec![];
+        for word in program.iter() {
+            halfwords.push(*word & 0xffff);
+            halfwords.push(*word >> 16);
+        }
+
+        let mut result = Ok(());
+        let mut i = 0;
+        while i < halfwords.len() {
+            let (word, length) = match halfwords[i] & 0x3 {
+                0x3 if i + 1 < halfwords.len() => (halfwords[i] | halfwords[i + 1] << 16, 2),
+                0x3 => break,
+                _ => (halfwords[i], 1),
+            };
+            if word == 0x00100073 || word == 0x9002 {
+                break;
+            }
+            // the program buffer is regarded to be at the address 0.
+            let addr = i as u64 * 2;
+            self.pc = addr + length as u64 * 2;
+            if let Err(e) = self.execute_debug_instruction(addr, word, length) {
+                self.mmu.tracer.record(TraceEvent::ProgramBufferException {
+                    exception: e.exception,
+                    value: e.value,
+                });
+                result = Err(CommandError::Exception);
+                break;
+            }
+            i += length;
+        }
+        self.pc = pc;
+        result
+    }
+
+    fn execute_debug_instruction(
+        &mut self,
+        addr: u64,
+        word: u32,
+        length: usize,
+    ) -> Result<(), Trap> {
+        let word = match length {
+            1 => instruction_decompress(self, addr, word).map_err(|()| Trap {
+                exception: Exception::IllegalInstruction,
+                value: addr,
+            })?,
+            _ => word,
+        };
+        let instruction =
+            (self.decode(addr, word)?.operation)(self, addr, word).map_err(|()| Trap {
+                exception: Exception::IllegalInstruction,
+                value: addr,
+            })?;
+        (instruction.operation)(self, addr, word)?;
+        self.x[0] = 0;
+        Ok(())
+    }
+
+    fn access_memory_for_debug(
+        &mut self,
+        address: u64,
+        size: u8,
+        virtual_address: bool,
+        data: Option<u64>,
+    ) -> Result<u64, CommandError> {
+        match size {
+            1 | 2 | 4 | 8 => {}
+            _ => return Err(CommandError::NotSupported),
+        }
+        if !virtual_address {
+            return match data {
+                None => self.mmu.load_physical(address, size),
+                Some(d) => self.mmu.store_physical(address, size, d).map(|_| 0),
+            }
+            .map_err(|_| CommandError::Bus);
+        }
+
+        // translate the address in the privilege mode the hart resumes in.
+        let privilege = match self.csr.read_direct(CSR_DCSR) & CSR_DCSR_PRV {
+            0 => Privilege::User,
+            1 => Privilege::Supervisor,
+            _ => Privilege::Machine,
+        };
+        self.mmu.set_privilege(&privilege);
+        let result = match data {
+            None => self.mmu.load(address, size),
+            Some(d) => self.mmu.store(address, size, d).map(|_| 0),
+        };
+        self.mmu.set_privilege(&self.privilege);
+        result.map_err(|_| CommandError::Exception)
+    }
+}
+
+/// The pending events of a step and the symbols are not part of
+/// the machine, so they are not saved.
+impl Snapshot for Cpu {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("cpu");
+        writer.write_u64(self.cycle);
+        writer.write_u64(self.pc);
+        writer.write_bool(self.wfi);
+        writer.write_option_u64(self.stall_timeout);
+        writer.write_bool(self.stall_on_reservation);
+        writer.write_xlen(&self.xlen);
+        writer.write_bool(self.rve);
+        writer.write_privilege(&self.privilege);
+        writer.write_bool(self.debug_mode);
+        writer.write_u64s(&self.x.iter().map(|x| *x as u64).collect::<Vec<_>>());
+        writer.write_u64s(&self.f.iter().map(|f| f.to_bits()).collect::<Vec<_>>());
+        self.csr.save(writer);
+        self.mmu.save(writer);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("cpu")?;
+        self.cycle = reader.read_u64()?;
+        self.pc = reader.read_u64()?;
+        self.wfi = reader.read_bool()?;
+        self.stall_timeout = reader.read_option_u64()?;
+        self.stall_on_reservation = reader.read_bool()?;
+        self.xlen = reader.read_xlen()?;
+        self.rve = reader.read_bool()?;
+        self.privilege = reader.read_privilege()?;
+        self.debug_mode = reader.read_bool()?;
+        let mut x = [0; 32];
+        reader.read_u64_array(&mut x)?;
+        for (register, value) in self.x.iter_mut().zip(x.iter()) {
+            *register = *value as i64;
+        }
+        let mut f = [0; 32];
+        reader.read_u64_array(&mut f)?;
+        for (register, value) in self.f.iter_mut().zip(f.iter()) {
+            *register = f64::from_bits(*value);
+        }
+        self.csr.restore(reader)?;
+        self.mmu.restore(reader)?;
+        self.step_result = StepResult::default();
+        self.next_interrupt = None;
+        self.debug_wakeup = false;
+        Ok(())
     }
 }
@@ -1,5 +1,7 @@
-use crate::cpu::cpu::Privilege;
+use crate::cpu::cpu::{Privilege, Xlen};
+use crate::cpu::entropy::Entropy;
 use crate::cpu::trap::*;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
 
 pub const CSR_USTATUS: u16 = 0x000;
 pub const CSR_UIE: u16 = 0x004;
@@ -15,6 +17,8 @@ pub const CSR_FFLAGS: u16 = 0x001;
 pub const CSR_FRM: u16 = 0x002;
 pub const CSR_FCSR: u16 = 0x003;
 
+pub const CSR_SEED: u16 = 0x015;
+
 pub const CSR_CYCLE: u16 = 0xC00;
 pub const CSR_TIME: u16 = 0xC01;
 pub const CSR_INSTRET: u16 = 0xC02;
@@ -27,6 +31,7 @@ pub const CSR_SEDELEG: u16 = 0x102;
 pub const CSR_SIDELEG: u16 = 0x103;
 pub const CSR_SIE: u16 = 0x104;
 pub const CSR_STVEC: u16 = 0x105;
+pub const CSR_SENVCFG: u16 = 0x10A;
 
 pub const CSR_SSCRATCH: u16 = 0x140;
 pub const CSR_SEPC: u16 = 0x141;
@@ -72,6 +77,7 @@ pub const CSR_MEDELEG: u16 = 0x302;
 pub const CSR_MIDELEG: u16 = 0x303;
 pub const CSR_MIE: u16 = 0x304;
 pub const CSR_MTVEC: u16 = 0x305;
+pub const CSR_MENVCFG: u16 = 0x30A;
 
 pub const CSR_MSCRATCH: u16 = 0x340;
 pub const CSR_MEPC: u16 = 0x341;
@@ -97,6 +103,21 @@ pub const CSR_MUCONTEREN: u16 = 0x310;
 pub const CSR_MSCONTEREN: u16 = 0x311;
 pub const CSR_MHCONTEREN: u16 = 0x312;
 
+pub const CSR_MSECCFG: u16 = 0x747;
+
+// Trigger module (Sdtrig). The registers are held by the trigger module in the MMU.
+pub const CSR_TSELECT: u16 = 0x7A0;
+pub const CSR_TDATA1: u16 = 0x7A1;
+pub const CSR_TDATA2: u16 = 0x7A2;
+pub const CSR_TDATA3: u16 = 0x7A3;
+pub const CSR_TINFO: u16 = 0x7A4;
+
+// Debug mode registers (Sdext), which are only accessible in debug mode.
+pub const CSR_DCSR: u16 = 0x7B0;
+pub const CSR_DPC: u16 = 0x7B1;
+pub const CSR_DSCRATCH0: u16 = 0x7B2;
+pub const CSR_DSCRATCH1: u16 = 0x7B3;
+
 // register bit files
 pub const CSR_STATUS_UIE: u64 = 0x00000001;
 pub const CSR_STATUS_SIE: u64 = 0x00000002;
@@ -114,6 +135,9 @@ pub const CSR_STATUS_XS: u64 = 0x00018000;
 pub const CSR_STATUS_MPRV: u64 = 0x00020000;
 pub const CSR_STATUS_PUM: u64 = 0x00040000;
 pub const CSR_STATUS_MXR: u64 = 0x00080000;
+pub const CSR_STATUS_TW: u64 = 0x00200000;
+pub const CSR_STATUS_UXL: u64 = 0x00000003_00000000;
+pub const CSR_STATUS_SXL: u64 = 0x0000000c_00000000;
 
 pub const CSR_IP_USIP: u64 = 0x00000001;
 pub const CSR_IP_SSIP: u64 = 0x00000002;
@@ -141,19 +165,124 @@ pub const CSR_IE_SEIE: u64 = 0x00000200;
 pub const CSR_IE_HEIE: u64 = 0x00000400;
 pub const CSR_IE_MEIE: u64 = 0x00000800;
 
+// menvcfg/senvcfg: enables of cache-block instructions (Zicbom/Zicboz).
+pub const CSR_ENVCFG_CBIE: u64 = 0x00000030;
+pub const CSR_ENVCFG_CBCFE: u64 = 0x00000040;
+pub const CSR_ENVCFG_CBZE: u64 = 0x00000080;
+// menvcfg only: enables the PBMT field of page table entries (Svpbmt).
+pub const CSR_ENVCFG_PBMTE: u64 = 0x4000000000000000;
+
+// dcsr fields.
+pub const CSR_DCSR_PRV: u64 = 0x00000003;
+pub const CSR_DCSR_STEP: u64 = 0x00000004;
+pub const CSR_DCSR_MPRVEN: u64 = 0x00000010;
+pub const CSR_DCSR_CAUSE: u64 = 0x000001c0;
+pub const CSR_DCSR_STEPIE: u64 = 0x00000800;
+pub const CSR_DCSR_EBREAKU: u64 = 0x00001000;
+pub const CSR_DCSR_EBREAKS: u64 = 0x00002000;
+pub const CSR_DCSR_EBREAKM: u64 = 0x00008000;
+
+// misa extension bits.
+pub const CSR_MISA_A: u64 = 0x00000001;
+pub const CSR_MISA_C: u64 = 0x00000004;
+pub const CSR_MISA_D: u64 = 0x00000008;
+pub const CSR_MISA_E: u64 = 0x00000010;
+pub const CSR_MISA_F: u64 = 0x00000020;
+pub const CSR_MISA_I: u64 = 0x00000100;
+pub const CSR_MISA_M: u64 = 0x00001000;
+pub const CSR_MISA_S: u64 = 0x00040000;
+pub const CSR_MISA_U: u64 = 0x00100000;
+
+pub const CSR_MSECCFG_USEED: u64 = 0x00000100;
+pub const CSR_MSECCFG_SSEED: u64 = 0x00000200;
+
+// seed CSR operational status (OPST), bits [31:30].
+pub const CSR_SEED_OPST_ES16: u64 = 0x80000000;
+
+/// Returns the name of a CSR as Spike and the assembler spell it, e.g. "satp"
+/// for 0x180, or "unknown-csr" like Spike does.
+pub fn csr_name(csr: u16) -> &'static str {
+    match csr {
+        CSR_FFLAGS => "fflags",
+        CSR_FRM => "frm",
+        CSR_FCSR => "fcsr",
+        CSR_SEED => "seed",
+        CSR_CYCLE => "cycle",
+        CSR_TIME => "time",
+        CSR_INSTRET => "instret",
+        CSR_CYCLEH => "cycleh",
+        CSR_TIMEH => "timeh",
+        CSR_INSTRETH => "instreth",
+        CSR_SSTATUS => "sstatus",
+        CSR_SIE => "sie",
+        CSR_STVEC => "stvec",
+        0x106 => "scounteren",
+        CSR_SENVCFG => "senvcfg",
+        CSR_SSCRATCH => "sscratch",
+        CSR_SEPC => "sepc",
+        CSR_SCAUSE => "scause",
+        CSR_STVAL => "stval",
+        CSR_SIP => "sip",
+        CSR_SPTBR => "satp",
+        CSR_MVENDORID => "mvendorid",
+        CSR_MARCHID => "marchid",
+        CSR_MIMPID => "mimpid",
+        CSR_MHARTID => "mhartid",
+        CSR_MSTATUS => "mstatus",
+        CSR_MISA => "misa",
+        CSR_MEDELEG => "medeleg",
+        CSR_MIDELEG => "mideleg",
+        CSR_MIE => "mie",
+        CSR_MTVEC => "mtvec",
+        0x306 => "mcounteren",
+        CSR_MENVCFG => "menvcfg",
+        CSR_MSCRATCH => "mscratch",
+        CSR_MEPC => "mepc",
+        CSR_MCAUSE => "mcause",
+        CSR_MTVAL => "mtval",
+        CSR_MIP => "mip",
+        CSR_MSECCFG => "mseccfg",
+        CSR_TSELECT => "tselect",
+        CSR_TDATA1 => "tdata1",
+        CSR_TDATA2 => "tdata2",
+        CSR_TDATA3 => "tdata3",
+        CSR_TINFO => "tinfo",
+        CSR_DCSR => "dcsr",
+        CSR_DPC => "dpc",
+        CSR_DSCRATCH0 => "dscratch0",
+        CSR_DSCRATCH1 => "dscratch1",
+        _ => "unknown-csr",
+    }
+}
+
 pub struct Csr {
     csr: [u64; 4096],
+    entropy: Entropy,
+    /// machine XLEN (misa.MXL).
+    mxl: Xlen,
+    /// the debug mode registers are only accessible in debug mode.
+    debug_mode: bool,
 }
 
 impl Csr {
     pub fn new() -> Self {
-        let mut csr = Csr { csr: [0; 4096] };
-
-        // this is actived when release mode for passing 
-        // "rv32mi-p-csr" test scenario of riscv-tests.
-        if cfg!(not(debug_assertions)) {
-            csr.csr[CSR_MISA as usize] = 0x800000008014312f;
-        }
+        let mut csr = Csr {
+            csr: [0; 4096],
+            entropy: Entropy::new(),
+            mxl: Xlen::X64,
+            debug_mode: false,
+        };
+
+        // MXL is not held in the register, it is added on read.
+        csr.csr[CSR_MISA as usize] = CSR_MISA_A
+            | CSR_MISA_C
+            | CSR_MISA_D
+            | CSR_MISA_F
+            | CSR_MISA_I
+            | CSR_MISA_M
+            | CSR_MISA_S
+            | CSR_MISA_U;
+        csr.set_mxl(&Xlen::X64);
         csr
     }
 
@@ -161,16 +290,147 @@ impl Csr {
         self.csr[CSR_TIME as usize] = self.csr[CSR_TIME as usize].wrapping_add(1);
     }
 
+    /// Makes the values returned by the seed CSR reproducible.
+    pub fn set_entropy_seed(&mut self, seed: u64) {
+        self.entropy = Entropy::from_seed(seed);
+    }
+
+    pub fn get_entropy_seed(&self) -> u64 {
+        self.entropy.get_seed()
+    }
+
+    /// Sets the machine XLEN. SXL and UXL follow it.
+    pub fn set_mxl(&mut self, xlen: &Xlen) {
+        self.mxl = xlen.clone();
+        let xl = Csr::encode_xl(xlen);
+        self.csr[CSR_MSTATUS as usize] = (self.csr[CSR_MSTATUS as usize]
+            & !(CSR_STATUS_SXL | CSR_STATUS_UXL))
+            | xl << 34
+            | xl << 32;
+    }
+
+    /// Saves the state of the hart on entry to debug mode. dcsr.cause and dcsr.prv
+    /// are read-only to software, so they are only set here.
+    pub fn enter_debug_mode(&mut self, cause: u64, privilege: &Privilege, pc: u64) {
+        let dcsr = self.csr[CSR_DCSR as usize] & !(CSR_DCSR_CAUSE | CSR_DCSR_PRV);
+        self.csr[CSR_DCSR as usize] = dcsr | cause << 6 | privilege.clone() as u64;
+        self.csr[CSR_DPC as usize] = pc;
+        self.debug_mode = true;
+    }
+
+    pub fn leave_debug_mode(&mut self) {
+        self.debug_mode = false;
+    }
+
+    pub fn get_mxl(&self) -> Xlen {
+        self.mxl.clone()
+    }
+
+    /// Effective XLEN of the privilege mode, given by misa.MXL, mstatus.SXL and mstatus.UXL.
+    pub fn get_xlen(&self, privilege: &Privilege) -> Xlen {
+        let xl = match (&self.mxl, privilege) {
+            (Xlen::X32, _) | (_, Privilege::Machine) => return self.mxl.clone(),
+            (_, Privilege::User) => (self.csr[CSR_MSTATUS as usize] & CSR_STATUS_UXL) >> 32,
+            _ => (self.csr[CSR_MSTATUS as usize] & CSR_STATUS_SXL) >> 34,
+        };
+        match xl {
+            1 => Xlen::X32,
+            _ => Xlen::X64,
+        }
+    }
+
+    /// Removes the extensions which the hart does not have from misa.
+    pub fn clear_extensions(&mut self, extensions: u64) {
+        self.csr[CSR_MISA as usize] &= !extensions;
+    }
+
+    /// Selects E (RV32E/RV64E) instead of I as the base ISA in misa.
+    pub fn set_rve(&mut self, rve: bool) {
+        let (set, clear) = match rve {
+            true => (CSR_MISA_E, CSR_MISA_I),
+            false => (CSR_MISA_I, CSR_MISA_E),
+        };
+        self.csr[CSR_MISA as usize] = (self.csr[CSR_MISA as usize] & !clear) | set;
+    }
+
+    fn encode_xl(xlen: &Xlen) -> u64 {
+        match xlen {
+            Xlen::X32 => 1,
+            Xlen::X64 => 2,
+        }
+    }
+
+    /// MXL, SXL and UXL are WARL fields which only hold 1 (32-bit) or 2 (64-bit).
+    fn write_xl_field(old: u64, data: u64, mask: u64) -> u64 {
+        let shift = mask.trailing_zeros();
+        match (data & mask) >> shift {
+            1 | 2 => (old & !mask) | (data & mask),
+            _ => old,
+        }
+    }
+
+    fn is_accessible(&self, addr: u16, cur_privilege: &Privilege) -> bool {
+        match addr {
+            0x7B0..=0x7BF => self.debug_mode,
+            // Access to seed from S/U-mode is granted by mseccfg.SSEED/USEED.
+            CSR_SEED => match cur_privilege {
+                Privilege::Machine => true,
+                Privilege::Supervisor => self.csr[CSR_MSECCFG as usize] & CSR_MSECCFG_SSEED != 0,
+                Privilege::User => self.csr[CSR_MSECCFG as usize] & CSR_MSECCFG_USEED != 0,
+                _ => false,
+            },
+            // The floating-point CSRs are not accessible while mstatus.FS is Off.
+            CSR_FFLAGS | CSR_FRM | CSR_FCSR => self.is_fp_enabled(),
+            _ => {
+                let privilege = ((addr >> 8) & 0x3) as u8;
+                privilege <= cur_privilege.clone() as u8
+            }
+        }
+    }
+
+    /// Returns whether the floating-point unit is enabled (mstatus.FS is not Off).
+    pub fn is_fp_enabled(&self) -> bool {
+        self.csr[CSR_MSTATUS as usize] & CSR_STATUS_FS != 0
+    }
+
+    /// Sets mstatus.FS to Dirty, after the floating-point state is modified.
+    pub fn set_fp_dirty(&mut self) {
+        self.csr[CSR_MSTATUS as usize] |= CSR_STATUS_FS;
+    }
+
+    /// Returns the bit of mstatus.SD, at the top of the register.
+    fn status_sd_bit(&self) -> u64 {
+        match self.mxl {
+            Xlen::X32 => 1 << 31,
+            Xlen::X64 => 1 << 63,
+        }
+    }
+
+    /// Returns mstatus.SD, the read-only bit summarizing whether FS or XS is Dirty.
+    fn status_sd(&self) -> u64 {
+        let mstatus = self.csr[CSR_MSTATUS as usize];
+        match mstatus & CSR_STATUS_FS == CSR_STATUS_FS || mstatus & CSR_STATUS_XS == CSR_STATUS_XS {
+            true => self.status_sd_bit(),
+            false => 0,
+        }
+    }
+
     pub fn read(
         &mut self,
         addr: u16,
         instruction_addr: u64,
         cur_privilege: &Privilege,
     ) -> Result<u64, Trap> {
-        let privilege = ((addr >> 8) & 0x3) as u8;
-        let cur_level = cur_privilege.clone() as u8;
-        match privilege <= cur_level {
-            true => Ok(self.read_direct(addr)),
+        match self.is_accessible(addr, cur_privilege) {
+            true => {
+                // Entropy Source (Zkr): every read by an instruction takes 16 fresh bits,
+                // which `read_direct` returns until the next one.
+                if addr == CSR_SEED {
+                    self.csr[CSR_SEED as usize] =
+                        CSR_SEED_OPST_ES16 | self.entropy.next_u16() as u64;
+                }
+                Ok(self.read_direct(addr))
+            }
             _ => Err(Trap {
                 exception: Exception::IllegalInstruction,
                 value: instruction_addr,
@@ -178,17 +438,29 @@ impl Csr {
         }
     }
 
-    pub fn read_direct(&mut self, addr: u16) -> u64 {
+    /// Reads a CSR without the privilege check and the side effects of the read, e.g.
+    /// for a debugger.
+    pub fn read_direct(&self, addr: u16) -> u64 {
         match addr {
             // User Floating-Point (FFLAGS/FRM/FCSR)
             CSR_FFLAGS => self.csr[CSR_FCSR as usize] & 0x1f,
             CSR_FRM => (self.csr[CSR_FCSR as usize] >> 5) & 0x7,
 
+            // misa.MXL is placed in the two most-significant bits of MXLEN.
+            CSR_MISA => match self.mxl {
+                Xlen::X32 => self.csr[CSR_MISA as usize] | 1 << 30,
+                Xlen::X64 => self.csr[CSR_MISA as usize] | 2 << 62,
+            },
+
+            // debugver=4: external debug support as described in the debug spec 1.0.
+            CSR_DCSR => self.csr[CSR_DCSR as usize] | 4 << 28,
+
             // Restricted views of the mstatus register appear as the hstatus and
             // sstatus registers in the H and S privilege-level ISAs respectively.
             CSR_HSTATUS => panic!("TODO: HSTATUS"),
             CSR_SSTATUS => {
-                let mask = CSR_STATUS_PUM
+                let mask = CSR_STATUS_UXL
+                    | CSR_STATUS_PUM
                     | CSR_STATUS_XS
                     | CSR_STATUS_FS
                     | CSR_STATUS_SPP
@@ -196,8 +468,9 @@ impl Csr {
                     | CSR_STATUS_UPIE
                     | CSR_STATUS_SIE
                     | CSR_STATUS_UIE;
-                self.csr[CSR_MSTATUS as usize] & mask
+                (self.csr[CSR_MSTATUS as usize] & mask) | self.status_sd()
             }
+            CSR_MSTATUS => self.csr[CSR_MSTATUS as usize] | self.status_sd(),
 
             // Restricted views of the mip and mie registers appear as the hip/hie,
             // sip/sie, and uip/uie registers in H-mode, S-mode, and U-mode respectively.
@@ -250,9 +523,9 @@ impl Csr {
         instruction_addr: u64,
         cur_privilege: &Privilege,
     ) -> Result<bool, Trap> {
-        let privilege = ((addr >> 8) & 0x3) as u8;
-        let cur_level = cur_privilege.clone() as u8;
-        match privilege <= cur_level {
+        // CSRs whose top two address bits are 0b11 are read-only.
+        let read_only = (addr >> 10) & 0x3 == 0x3;
+        match self.is_accessible(addr, cur_privilege) && !read_only {
             true => {
                 self.write_direct(addr, data);
                 Ok(match addr {
@@ -273,11 +546,54 @@ impl Csr {
             CSR_FFLAGS => {
                 self.csr[CSR_FCSR as usize] &= !0x1f;
                 self.csr[CSR_FCSR as usize] |= data & 0x1f;
+                self.set_fp_dirty();
             }
             CSR_FRM => {
                 self.csr[CSR_FCSR as usize] &= !0xe0;
                 self.csr[CSR_FCSR as usize] |= (data << 5) & 0xe0;
+                self.set_fp_dirty();
+            }
+            CSR_FCSR => {
+                self.csr[CSR_FCSR as usize] = data & 0xff;
+                self.set_fp_dirty();
+            }
+
+            // Entropy Source (Zkr): writes are ignored.
+            CSR_SEED => {}
+            // CBIE=10 is reserved, and the field keeps the previous value.
+            CSR_MENVCFG | CSR_SENVCFG => {
+                let old = self.csr[addr as usize];
+                let mask = match addr {
+                    CSR_MENVCFG => CSR_ENVCFG_PBMTE,
+                    _ => 0,
+                };
+                let mut envcfg =
+                    data & (CSR_ENVCFG_CBIE | CSR_ENVCFG_CBCFE | CSR_ENVCFG_CBZE | mask);
+                if envcfg & CSR_ENVCFG_CBIE == 0x20 {
+                    envcfg = (envcfg & !CSR_ENVCFG_CBIE) | (old & CSR_ENVCFG_CBIE);
+                }
+                self.csr[addr as usize] = envcfg;
+            }
+            CSR_MSECCFG => {
+                self.csr[CSR_MSECCFG as usize] = data & (CSR_MSECCFG_SSEED | CSR_MSECCFG_USEED);
+            }
+            // prv is WARL and does not hold the reserved H-mode (2).
+            CSR_DCSR => {
+                let mask = CSR_DCSR_EBREAKM
+                    | CSR_DCSR_EBREAKS
+                    | CSR_DCSR_EBREAKU
+                    | CSR_DCSR_STEPIE
+                    | CSR_DCSR_MPRVEN
+                    | CSR_DCSR_STEP;
+                let old = self.csr[CSR_DCSR as usize];
+                let prv = match data & CSR_DCSR_PRV {
+                    2 => old & CSR_DCSR_PRV,
+                    prv => prv,
+                };
+                self.csr[CSR_DCSR as usize] = (old & CSR_DCSR_CAUSE) | (data & mask) | prv;
             }
+            // held by the trigger module in the MMU.
+            CSR_TSELECT..=CSR_TINFO => {}
 
             // Restricted views of the mstatus register appear as the hstatus and
             // sstatus registers in the H and S privilege-level ISAs respectively.
@@ -291,8 +607,37 @@ impl Csr {
                     | CSR_STATUS_UPIE
                     | CSR_STATUS_SIE
                     | CSR_STATUS_UIE;
-                self.csr[CSR_MSTATUS as usize] =
-                    (self.csr[CSR_MSTATUS as usize] & !mask) | (data & mask);
+                let mstatus = (self.csr[CSR_MSTATUS as usize] & !mask) | (data & mask);
+                self.csr[CSR_MSTATUS as usize] = match self.mxl {
+                    Xlen::X32 => mstatus,
+                    Xlen::X64 => Csr::write_xl_field(mstatus, data, CSR_STATUS_UXL),
+                };
+            }
+
+            // Extensions can not be disabled, so only MXL is writable.
+            CSR_MISA => {
+                let mxl = match self.mxl {
+                    Xlen::X32 => (data >> 30) & 0x3,
+                    Xlen::X64 => (data >> 62) & 0x3,
+                };
+                match mxl {
+                    1 => self.mxl = Xlen::X32,
+                    2 => self.mxl = Xlen::X64,
+                    _ => {}
+                };
+            }
+            CSR_MSTATUS => {
+                let old = self.csr[CSR_MSTATUS as usize];
+                let mask = CSR_STATUS_SXL | CSR_STATUS_UXL;
+                let mstatus = (old & mask) | (data & !(mask | self.status_sd_bit()));
+                self.csr[CSR_MSTATUS as usize] = match self.mxl {
+                    // mstatus of RV32 does not have SXL and UXL.
+                    Xlen::X32 => mstatus,
+                    Xlen::X64 => {
+                        let mstatus = Csr::write_xl_field(mstatus, data, CSR_STATUS_SXL);
+                        Csr::write_xl_field(mstatus, data, CSR_STATUS_UXL)
+                    }
+                };
             }
 
             // Restricted views of the mip and mie registers appear as the hip/hie,
@@ -332,3 +677,22 @@ impl Csr {
         }
     }
 }
+
+impl Snapshot for Csr {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("csr");
+        writer.write_u64s(&self.csr);
+        self.entropy.save(writer);
+        writer.write_xlen(&self.mxl);
+        writer.write_bool(self.debug_mode);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("csr")?;
+        reader.read_u64_array(&mut self.csr)?;
+        self.entropy.restore(reader)?;
+        self.mxl = reader.read_xlen()?;
+        self.debug_mode = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 
-use crate::cpu::cpu::{Cpu, Privilege, Xlen};
+use crate::cpu::cpu::{Cpu, DebugCause, Privilege, Xlen};
 use crate::cpu::cpu_csr::*;
+use crate::cpu::crypto::*;
+use crate::cpu::softfloat::{load_immediate, Format, RoundingMode, SoftFloat, DOUBLE, HALF, SINGLE};
 use crate::cpu::trap::*;
 
 pub struct Opecode {
@@ -107,6 +109,10 @@ lazy_static! {
         m.insert(0x33, Opecode {operation: opecode_33});
         m.insert(0x37, Opecode {operation: opecode_37});
         m.insert(0x3b, Opecode {operation: opecode_3b});
+        m.insert(0x43, Opecode {operation: opecode_fused});
+        m.insert(0x47, Opecode {operation: opecode_fused});
+        m.insert(0x4b, Opecode {operation: opecode_fused});
+        m.insert(0x4f, Opecode {operation: opecode_fused});
         m.insert(0x53, Opecode {operation: opecode_53});
         m.insert(0x63, Opecode {operation: opecode_63});
         m.insert(0x67, Opecode {operation: opecode_67});
@@ -159,11 +165,16 @@ lazy_static! {
     // RV32F/RV64F Single/Double-Precision Load Instructions.
     static ref INSTRUCTIONS_GROUP07: HashMap<u8, Instruction> = {
         let mut m = HashMap::new();
-        m.insert(0, Instruction{
+        m.insert(2, Instruction{
             mnemonic: "flw",
             operation: flw,
             disassemble: disassemble_precision_load,
         });
+        m.insert(1, Instruction{
+            mnemonic: "flh",
+            operation: flh,
+            disassemble: disassemble_precision_load,
+        });
         m.insert(3, Instruction{
             mnemonic: "fld",
             operation: fld,
@@ -180,6 +191,11 @@ lazy_static! {
     };
     static ref INSTRUCTIONS_GROUP27: HashMap<u8, Instruction> = {
         let mut m = HashMap::new();
+        m.insert(1, Instruction{
+            mnemonic: "fsh",
+            operation: fsh,
+            disassemble: disassemble_s,
+        });
         m.insert(2, Instruction{
             mnemonic: "fsw",
             operation: fsw,
@@ -209,6 +225,32 @@ lazy_static! {
         m
     };
 
+    // Cache-Block Management Instructions (funct3=2), keyed by imm[11:0].
+    static ref INSTRUCTIONS_GROUP0F_CBO: HashMap<u16, Instruction> = {
+        let mut m = HashMap::new();
+        m.insert(0, Instruction{
+            mnemonic: "cbo.inval",
+            operation: cbo_inval,
+            disassemble: disassemble_cbo,
+        });
+        m.insert(1, Instruction{
+            mnemonic: "cbo.clean",
+            operation: cbo_clean,
+            disassemble: disassemble_cbo,
+        });
+        m.insert(2, Instruction{
+            mnemonic: "cbo.flush",
+            operation: cbo_flush,
+            disassemble: disassemble_cbo,
+        });
+        m.insert(4, Instruction{
+            mnemonic: "cbo.zero",
+            operation: cbo_zero,
+            disassemble: disassemble_cbo,
+        });
+        m
+    };
+
     // RV32I/RV64I Integer Register-Immediate Instructions.
     static ref INSTRUCTIONS_GROUP13: HashMap<u8, Instruction> = {
         let mut m = HashMap::new();
@@ -261,6 +303,135 @@ lazy_static! {
             operation: srai,
             disassemble: disassemble_computation_shamt,
         });
+        m.insert((48, 5), Instruction{
+            mnemonic: "rori",
+            operation: rori,
+            disassemble: disassemble_computation_shamt,
+        });
+        m
+    };
+
+    // Prefetch hints encoded as ORI with rd=x0, keyed by imm[4:0].
+    static ref INSTRUCTIONS_GROUP13_PREFETCH: HashMap<u8, Instruction> = {
+        let mut m = HashMap::new();
+        m.insert(0, Instruction{
+            mnemonic: "prefetch.i",
+            operation: prefetch,
+            disassemble: disassemble_prefetch,
+        });
+        m.insert(1, Instruction{
+            mnemonic: "prefetch.r",
+            operation: prefetch,
+            disassemble: disassemble_prefetch,
+        });
+        m.insert(3, Instruction{
+            mnemonic: "prefetch.w",
+            operation: prefetch,
+            disassemble: disassemble_prefetch,
+        });
+        m
+    };
+
+    // Zbkb/Zkn/Zks instructions with a single source operand, keyed by (imm[11:0], funct3).
+    static ref INSTRUCTIONS_GROUP13_UNARY: HashMap<(u16, u8), Instruction> = {
+        let mut m = HashMap::new();
+        m.insert((0x100, 1), Instruction{
+            mnemonic: "sha256sum0",
+            operation: sha256sum0,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x101, 1), Instruction{
+            mnemonic: "sha256sum1",
+            operation: sha256sum1,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x102, 1), Instruction{
+            mnemonic: "sha256sig0",
+            operation: sha256sig0,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x103, 1), Instruction{
+            mnemonic: "sha256sig1",
+            operation: sha256sig1,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x104, 1), Instruction{
+            mnemonic: "sha512sum0",
+            operation: sha512sum0,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x105, 1), Instruction{
+            mnemonic: "sha512sum1",
+            operation: sha512sum1,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x106, 1), Instruction{
+            mnemonic: "sha512sig0",
+            operation: sha512sig0,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x107, 1), Instruction{
+            mnemonic: "sha512sig1",
+            operation: sha512sig1,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x108, 1), Instruction{
+            mnemonic: "sm3p0",
+            operation: sm3p0,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x109, 1), Instruction{
+            mnemonic: "sm3p1",
+            operation: sm3p1,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x300, 1), Instruction{
+            mnemonic: "aes64im",
+            operation: aes64im,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x08f, 1), Instruction{
+            mnemonic: "zip",
+            operation: zip,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x08f, 5), Instruction{
+            mnemonic: "unzip",
+            operation: unzip,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x687, 5), Instruction{
+            mnemonic: "brev8",
+            operation: brev8,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x698, 5), Instruction{
+            mnemonic: "rev8",
+            operation: rev8,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x6b8, 5), Instruction{
+            mnemonic: "rev8",
+            operation: rev8,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x604, 1), Instruction{
+            mnemonic: "sext.b",
+            operation: sext_b,
+            disassemble: disassemble_unary,
+        });
+        m.insert((0x605, 1), Instruction{
+            mnemonic: "sext.h",
+            operation: sext_h,
+            disassemble: disassemble_unary,
+        });
+        for rnum in 0..0xf {
+            m.insert((0x310 | rnum, 1), Instruction{
+                mnemonic: "aes64ks1i",
+                operation: aes64ks1i,
+                disassemble: disassemble_rnum,
+            });
+        }
         m
     };
 
@@ -302,6 +473,11 @@ lazy_static! {
             operation: sraiw,
             disassemble: disassemble_precision_load,
         });
+        m.insert((48, 5), Instruction{
+            mnemonic: "roriw",
+            operation: roriw,
+            disassemble: disassemble_computation_shamt,
+        });
         m
     };
 
@@ -443,6 +619,21 @@ lazy_static! {
             operation: amomaxu_d,
             disassemble: disassemble_r,
         });
+        m.insert((5, 2), Instruction{
+            mnemonic: "amocas.w",
+            operation: amocas_w,
+            disassemble: disassemble_r,
+        });
+        m.insert((5, 3), Instruction{
+            mnemonic: "amocas.d",
+            operation: amocas_d,
+            disassemble: disassemble_r,
+        });
+        m.insert((5, 4), Instruction{
+            mnemonic: "amocas.q",
+            operation: amocas_q,
+            disassemble: disassemble_r,
+        });
         m
     };
 
@@ -538,6 +729,159 @@ lazy_static! {
             operation: remu,
             disassemble: disassemble_r,
         });
+        m.insert((7, 5), Instruction{
+            mnemonic: "czero.eqz",
+            operation: czero_eqz,
+            disassemble: disassemble_r,
+        });
+        m.insert((7, 7), Instruction{
+            mnemonic: "czero.nez",
+            operation: czero_nez,
+            disassemble: disassemble_r,
+        });
+        m.insert((32, 7), Instruction{
+            mnemonic: "andn",
+            operation: andn,
+            disassemble: disassemble_r,
+        });
+        m.insert((32, 6), Instruction{
+            mnemonic: "orn",
+            operation: orn,
+            disassemble: disassemble_r,
+        });
+        m.insert((32, 4), Instruction{
+            mnemonic: "xnor",
+            operation: xnor,
+            disassemble: disassemble_r,
+        });
+        m.insert((48, 1), Instruction{
+            mnemonic: "rol",
+            operation: rol,
+            disassemble: disassemble_r,
+        });
+        m.insert((48, 5), Instruction{
+            mnemonic: "ror",
+            operation: ror,
+            disassemble: disassemble_r,
+        });
+        m.insert((4, 4), Instruction{
+            mnemonic: "pack",
+            operation: pack,
+            disassemble: disassemble_r,
+        });
+        m.insert((4, 7), Instruction{
+            mnemonic: "packh",
+            operation: packh,
+            disassemble: disassemble_r,
+        });
+        m.insert((5, 1), Instruction{
+            mnemonic: "clmul",
+            operation: clmul,
+            disassemble: disassemble_r,
+        });
+        m.insert((5, 3), Instruction{
+            mnemonic: "clmulh",
+            operation: clmulh,
+            disassemble: disassemble_r,
+        });
+        m.insert((20, 2), Instruction{
+            mnemonic: "xperm4",
+            operation: xperm4,
+            disassemble: disassemble_r,
+        });
+        m.insert((20, 4), Instruction{
+            mnemonic: "xperm8",
+            operation: xperm8,
+            disassemble: disassemble_r,
+        });
+        m.insert((0x19, 0), Instruction{
+            mnemonic: "aes64es",
+            operation: aes64es,
+            disassemble: disassemble_r,
+        });
+        m.insert((0x1b, 0), Instruction{
+            mnemonic: "aes64esm",
+            operation: aes64esm,
+            disassemble: disassemble_r,
+        });
+        m.insert((0x1d, 0), Instruction{
+            mnemonic: "aes64ds",
+            operation: aes64ds,
+            disassemble: disassemble_r,
+        });
+        m.insert((0x1f, 0), Instruction{
+            mnemonic: "aes64dsm",
+            operation: aes64dsm,
+            disassemble: disassemble_r,
+        });
+        m.insert((0x3f, 0), Instruction{
+            mnemonic: "aes64ks2",
+            operation: aes64ks2,
+            disassemble: disassemble_r,
+        });
+        m.insert((0x28, 0), Instruction{
+            mnemonic: "sha512sum0r",
+            operation: sha512sum0r,
+            disassemble: disassemble_r,
+        });
+        m.insert((0x29, 0), Instruction{
+            mnemonic: "sha512sum1r",
+            operation: sha512sum1r,
+            disassemble: disassemble_r,
+        });
+        m.insert((0x2a, 0), Instruction{
+            mnemonic: "sha512sig0l",
+            operation: sha512sig0l,
+            disassemble: disassemble_r,
+        });
+        m.insert((0x2b, 0), Instruction{
+            mnemonic: "sha512sig1l",
+            operation: sha512sig1l,
+            disassemble: disassemble_r,
+        });
+        m.insert((0x2e, 0), Instruction{
+            mnemonic: "sha512sig0h",
+            operation: sha512sig0h,
+            disassemble: disassemble_r,
+        });
+        m.insert((0x2f, 0), Instruction{
+            mnemonic: "sha512sig1h",
+            operation: sha512sig1h,
+            disassemble: disassemble_r,
+        });
+        // bs (byte select) is encoded in funct7[6:5].
+        for bs in 0..4 {
+            m.insert(((bs << 5) | 0x11, 0), Instruction{
+                mnemonic: "aes32esi",
+                operation: aes32esi,
+                disassemble: disassemble_r_bs,
+            });
+            m.insert(((bs << 5) | 0x13, 0), Instruction{
+                mnemonic: "aes32esmi",
+                operation: aes32esmi,
+                disassemble: disassemble_r_bs,
+            });
+            m.insert(((bs << 5) | 0x15, 0), Instruction{
+                mnemonic: "aes32dsi",
+                operation: aes32dsi,
+                disassemble: disassemble_r_bs,
+            });
+            m.insert(((bs << 5) | 0x17, 0), Instruction{
+                mnemonic: "aes32dsmi",
+                operation: aes32dsmi,
+                disassemble: disassemble_r_bs,
+            });
+            m.insert(((bs << 5) | 0x18, 0), Instruction{
+                mnemonic: "sm4ed",
+                operation: sm4ed,
+                disassemble: disassemble_r_bs,
+            });
+            m.insert(((bs << 5) | 0x1a, 0), Instruction{
+                mnemonic: "sm4ks",
+                operation: sm4ks,
+                disassemble: disassemble_r_bs,
+            });
+        }
         m
     };
 
@@ -593,16 +937,610 @@ lazy_static! {
             operation: remuw,
             disassemble: disassemble_r,
         });
+        m.insert((4, 0), Instruction{
+            mnemonic: "add.uw",
+            operation: add_uw,
+            disassemble: disassemble_r,
+        });
+        m.insert((4, 4), Instruction{
+            mnemonic: "packw",
+            operation: packw,
+            disassemble: disassemble_r,
+        });
+        m.insert((48, 1), Instruction{
+            mnemonic: "rolw",
+            operation: rolw,
+            disassemble: disassemble_r,
+        });
+        m.insert((48, 5), Instruction{
+            mnemonic: "rorw",
+            operation: rorw,
+            disassemble: disassemble_r,
+        });
         m
     };
 
-    static ref INSTRUCTIONS_GROUP53: HashMap<(u8, u8), Instruction> = {
+    // Floating-Point Computational, Conversion and Move Instructions, keyed by
+    // (funct7, funct3, rs2). funct3 is ANY for the instructions with the rm field, and rs2
+    // is ANY for the ones with two source registers.
+    static ref INSTRUCTIONS_GROUP53: HashMap<(u8, u8, u8), Instruction> = {
         let mut m = HashMap::new();
-        m.insert((0x78, 0), Instruction{
-            mnemonic: "fmv.w.x",
-            operation: fmv_w_x,
+        m.insert((0x00, ANY, ANY), Instruction{
+            mnemonic: "fadd.s",
+            operation: fadd,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x01, ANY, ANY), Instruction{
+            mnemonic: "fadd.d",
+            operation: fadd,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x02, ANY, ANY), Instruction{
+            mnemonic: "fadd.h",
+            operation: fadd,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x04, ANY, ANY), Instruction{
+            mnemonic: "fsub.s",
+            operation: fsub,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x05, ANY, ANY), Instruction{
+            mnemonic: "fsub.d",
+            operation: fsub,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x06, ANY, ANY), Instruction{
+            mnemonic: "fsub.h",
+            operation: fsub,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x08, ANY, ANY), Instruction{
+            mnemonic: "fmul.s",
+            operation: fmul,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x09, ANY, ANY), Instruction{
+            mnemonic: "fmul.d",
+            operation: fmul,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x0a, ANY, ANY), Instruction{
+            mnemonic: "fmul.h",
+            operation: fmul,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x0c, ANY, ANY), Instruction{
+            mnemonic: "fdiv.s",
+            operation: fdiv,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x0d, ANY, ANY), Instruction{
+            mnemonic: "fdiv.d",
+            operation: fdiv,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x0e, ANY, ANY), Instruction{
+            mnemonic: "fdiv.h",
+            operation: fdiv,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x10, 0, ANY), Instruction{
+            mnemonic: "fsgnj.s",
+            operation: fsgnj,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x10, 1, ANY), Instruction{
+            mnemonic: "fsgnjn.s",
+            operation: fsgnjn,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x10, 2, ANY), Instruction{
+            mnemonic: "fsgnjx.s",
+            operation: fsgnjx,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x11, 0, ANY), Instruction{
+            mnemonic: "fsgnj.d",
+            operation: fsgnj,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x11, 1, ANY), Instruction{
+            mnemonic: "fsgnjn.d",
+            operation: fsgnjn,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x11, 2, ANY), Instruction{
+            mnemonic: "fsgnjx.d",
+            operation: fsgnjx,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x12, 0, ANY), Instruction{
+            mnemonic: "fsgnj.h",
+            operation: fsgnj,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x12, 1, ANY), Instruction{
+            mnemonic: "fsgnjn.h",
+            operation: fsgnjn,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x12, 2, ANY), Instruction{
+            mnemonic: "fsgnjx.h",
+            operation: fsgnjx,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x14, 0, ANY), Instruction{
+            mnemonic: "fmin.s",
+            operation: fmin,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x14, 1, ANY), Instruction{
+            mnemonic: "fmax.s",
+            operation: fmax,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x14, 2, ANY), Instruction{
+            mnemonic: "fminm.s",
+            operation: fminm,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x14, 3, ANY), Instruction{
+            mnemonic: "fmaxm.s",
+            operation: fmaxm,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x15, 0, ANY), Instruction{
+            mnemonic: "fmin.d",
+            operation: fmin,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x15, 1, ANY), Instruction{
+            mnemonic: "fmax.d",
+            operation: fmax,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x15, 2, ANY), Instruction{
+            mnemonic: "fminm.d",
+            operation: fminm,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x15, 3, ANY), Instruction{
+            mnemonic: "fmaxm.d",
+            operation: fmaxm,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x16, 0, ANY), Instruction{
+            mnemonic: "fmin.h",
+            operation: fmin,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x16, 1, ANY), Instruction{
+            mnemonic: "fmax.h",
+            operation: fmax,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x16, 2, ANY), Instruction{
+            mnemonic: "fminm.h",
+            operation: fminm,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x16, 3, ANY), Instruction{
+            mnemonic: "fmaxm.h",
+            operation: fmaxm,
+            disassemble: disassemble_fp_r,
+        });
+        m.insert((0x20, ANY, 1), Instruction{
+            mnemonic: "fcvt.s.d",
+            operation: fcvt_fp_fp,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x20, ANY, 2), Instruction{
+            mnemonic: "fcvt.s.h",
+            operation: fcvt_fp_fp,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x20, ANY, 4), Instruction{
+            mnemonic: "fround.s",
+            operation: fround,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x20, ANY, 5), Instruction{
+            mnemonic: "froundnx.s",
+            operation: froundnx,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x21, ANY, 0), Instruction{
+            mnemonic: "fcvt.d.s",
+            operation: fcvt_fp_fp,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x21, ANY, 2), Instruction{
+            mnemonic: "fcvt.d.h",
+            operation: fcvt_fp_fp,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x21, ANY, 4), Instruction{
+            mnemonic: "fround.d",
+            operation: fround,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x21, ANY, 5), Instruction{
+            mnemonic: "froundnx.d",
+            operation: froundnx,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x22, ANY, 0), Instruction{
+            mnemonic: "fcvt.h.s",
+            operation: fcvt_fp_fp,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x22, ANY, 1), Instruction{
+            mnemonic: "fcvt.h.d",
+            operation: fcvt_fp_fp,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x22, ANY, 4), Instruction{
+            mnemonic: "fround.h",
+            operation: fround,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x22, ANY, 5), Instruction{
+            mnemonic: "froundnx.h",
+            operation: froundnx,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x2c, ANY, 0), Instruction{
+            mnemonic: "fsqrt.s",
+            operation: fsqrt,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x2d, ANY, 0), Instruction{
+            mnemonic: "fsqrt.d",
+            operation: fsqrt,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x2e, ANY, 0), Instruction{
+            mnemonic: "fsqrt.h",
+            operation: fsqrt,
+            disassemble: disassemble_fp_unary,
+        });
+        m.insert((0x50, 0, ANY), Instruction{
+            mnemonic: "fle.s",
+            operation: fle,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x50, 1, ANY), Instruction{
+            mnemonic: "flt.s",
+            operation: flt,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x50, 2, ANY), Instruction{
+            mnemonic: "feq.s",
+            operation: feq,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x50, 4, ANY), Instruction{
+            mnemonic: "fleq.s",
+            operation: fleq,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x50, 5, ANY), Instruction{
+            mnemonic: "fltq.s",
+            operation: fltq,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x51, 0, ANY), Instruction{
+            mnemonic: "fle.d",
+            operation: fle,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x51, 1, ANY), Instruction{
+            mnemonic: "flt.d",
+            operation: flt,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x51, 2, ANY), Instruction{
+            mnemonic: "feq.d",
+            operation: feq,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x51, 4, ANY), Instruction{
+            mnemonic: "fleq.d",
+            operation: fleq,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x51, 5, ANY), Instruction{
+            mnemonic: "fltq.d",
+            operation: fltq,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x52, 0, ANY), Instruction{
+            mnemonic: "fle.h",
+            operation: fle,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x52, 1, ANY), Instruction{
+            mnemonic: "flt.h",
+            operation: flt,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x52, 2, ANY), Instruction{
+            mnemonic: "feq.h",
+            operation: feq,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x52, 4, ANY), Instruction{
+            mnemonic: "fleq.h",
+            operation: fleq,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x52, 5, ANY), Instruction{
+            mnemonic: "fltq.h",
+            operation: fltq,
+            disassemble: disassemble_fp_compare,
+        });
+        m.insert((0x59, 0, ANY), Instruction{
+            mnemonic: "fmvp.d.x",
+            operation: fmvp_d_x,
             disassemble: disassemble_r,
         });
+        m.insert((0x60, ANY, 0), Instruction{
+            mnemonic: "fcvt.w.s",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x60, ANY, 1), Instruction{
+            mnemonic: "fcvt.wu.s",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x60, ANY, 2), Instruction{
+            mnemonic: "fcvt.l.s",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x60, ANY, 3), Instruction{
+            mnemonic: "fcvt.lu.s",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x61, ANY, 0), Instruction{
+            mnemonic: "fcvt.w.d",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x61, ANY, 1), Instruction{
+            mnemonic: "fcvt.wu.d",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x61, ANY, 2), Instruction{
+            mnemonic: "fcvt.l.d",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x61, ANY, 3), Instruction{
+            mnemonic: "fcvt.lu.d",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x61, 1, 8), Instruction{
+            mnemonic: "fcvtmod.w.d",
+            operation: fcvtmod_w_d,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x62, ANY, 0), Instruction{
+            mnemonic: "fcvt.w.h",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x62, ANY, 1), Instruction{
+            mnemonic: "fcvt.wu.h",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x62, ANY, 2), Instruction{
+            mnemonic: "fcvt.l.h",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x62, ANY, 3), Instruction{
+            mnemonic: "fcvt.lu.h",
+            operation: fcvt_int_fp,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x68, ANY, 0), Instruction{
+            mnemonic: "fcvt.s.w",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x68, ANY, 1), Instruction{
+            mnemonic: "fcvt.s.wu",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x68, ANY, 2), Instruction{
+            mnemonic: "fcvt.s.l",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x68, ANY, 3), Instruction{
+            mnemonic: "fcvt.s.lu",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x69, ANY, 0), Instruction{
+            mnemonic: "fcvt.d.w",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x69, ANY, 1), Instruction{
+            mnemonic: "fcvt.d.wu",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x69, ANY, 2), Instruction{
+            mnemonic: "fcvt.d.l",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x69, ANY, 3), Instruction{
+            mnemonic: "fcvt.d.lu",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x6a, ANY, 0), Instruction{
+            mnemonic: "fcvt.h.w",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x6a, ANY, 1), Instruction{
+            mnemonic: "fcvt.h.wu",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x6a, ANY, 2), Instruction{
+            mnemonic: "fcvt.h.l",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x6a, ANY, 3), Instruction{
+            mnemonic: "fcvt.h.lu",
+            operation: fcvt_fp_int,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x70, 0, 0), Instruction{
+            mnemonic: "fmv.x.w",
+            operation: fmv_x_f,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x70, 1, 0), Instruction{
+            mnemonic: "fclass.s",
+            operation: fclass,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x71, 0, 0), Instruction{
+            mnemonic: "fmv.x.d",
+            operation: fmv_x_f,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x71, 0, 1), Instruction{
+            mnemonic: "fmvh.x.d",
+            operation: fmvh_x_d,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x71, 1, 0), Instruction{
+            mnemonic: "fclass.d",
+            operation: fclass,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x72, 0, 0), Instruction{
+            mnemonic: "fmv.x.h",
+            operation: fmv_x_f,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x72, 1, 0), Instruction{
+            mnemonic: "fclass.h",
+            operation: fclass,
+            disassemble: disassemble_fp_to_x,
+        });
+        m.insert((0x78, 0, 0), Instruction{
+            mnemonic: "fmv.w.x",
+            operation: fmv_f_x,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x78, 0, 1), Instruction{
+            mnemonic: "fli.s",
+            operation: fli,
+            disassemble: disassemble_fli,
+        });
+        m.insert((0x79, 0, 0), Instruction{
+            mnemonic: "fmv.d.x",
+            operation: fmv_f_x,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x79, 0, 1), Instruction{
+            mnemonic: "fli.d",
+            operation: fli,
+            disassemble: disassemble_fli,
+        });
+        m.insert((0x7a, 0, 0), Instruction{
+            mnemonic: "fmv.h.x",
+            operation: fmv_f_x,
+            disassemble: disassemble_fp_from_x,
+        });
+        m.insert((0x7a, 0, 1), Instruction{
+            mnemonic: "fli.h",
+            operation: fli,
+            disassemble: disassemble_fli,
+        });
+        m
+    };
+
+    // Fused Multiply-Add Instructions, keyed by (opcode, fmt).
+    static ref INSTRUCTIONS_FUSED: HashMap<(u8, u8), Instruction> = {
+        let mut m = HashMap::new();
+        m.insert((0x43, 0), Instruction{
+            mnemonic: "fmadd.s",
+            operation: fmadd,
+            disassemble: disassemble_fp_r4,
+        });
+        m.insert((0x43, 1), Instruction{
+            mnemonic: "fmadd.d",
+            operation: fmadd,
+            disassemble: disassemble_fp_r4,
+        });
+        m.insert((0x43, 2), Instruction{
+            mnemonic: "fmadd.h",
+            operation: fmadd,
+            disassemble: disassemble_fp_r4,
+        });
+        m.insert((0x47, 0), Instruction{
+            mnemonic: "fmsub.s",
+            operation: fmsub,
+            disassemble: disassemble_fp_r4,
+        });
+        m.insert((0x47, 1), Instruction{
+            mnemonic: "fmsub.d",
+            operation: fmsub,
+            disassemble: disassemble_fp_r4,
+        });
+        m.insert((0x47, 2), Instruction{
+            mnemonic: "fmsub.h",
+            operation: fmsub,
+            disassemble: disassemble_fp_r4,
+        });
+        m.insert((0x4b, 0), Instruction{
+            mnemonic: "fnmsub.s",
+            operation: fnmsub,
+            disassemble: disassemble_fp_r4,
+        });
+        m.insert((0x4b, 1), Instruction{
+            mnemonic: "fnmsub.d",
+            operation: fnmsub,
+            disassemble: disassemble_fp_r4,
+        });
+        m.insert((0x4b, 2), Instruction{
+            mnemonic: "fnmsub.h",
+            operation: fnmsub,
+            disassemble: disassemble_fp_r4,
+        });
+        m.insert((0x4f, 0), Instruction{
+            mnemonic: "fnmadd.s",
+            operation: fnmadd,
+            disassemble: disassemble_fp_r4,
+        });
+        m.insert((0x4f, 1), Instruction{
+            mnemonic: "fnmadd.d",
+            operation: fnmadd,
+            disassemble: disassemble_fp_r4,
+        });
+        m.insert((0x4f, 2), Instruction{
+            mnemonic: "fnmadd.h",
+            operation: fnmadd,
+            disassemble: disassemble_fp_r4,
+        });
         m
     };
 
@@ -704,20 +1642,39 @@ lazy_static! {
             operation: mret,
             disassemble: disassemble_mnemonic,
         });
+        m.insert(0x7b2, Instruction{
+            mnemonic: "dret",
+            operation: dret,
+            disassemble: disassemble_mnemonic,
+        });
         m.insert(0x105, Instruction{
             mnemonic: "wfi",
             operation: wfi,
             disassemble: disassemble_mnemonic,
         });
+        m.insert(0x00d, Instruction{
+            mnemonic: "wrs.nto",
+            operation: wrs_nto,
+            disassemble: disassemble_mnemonic,
+        });
+        m.insert(0x01d, Instruction{
+            mnemonic: "wrs.sto",
+            operation: wrs_sto,
+            disassemble: disassemble_mnemonic,
+        });
         m
     };
 }
 
-fn opecode_03(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
+fn opecode_03(cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
     let funct3 = ((word & 0x00007000) >> 12) as u8;
+    // ld and lwu are RV64I only.
+    if cpu.xlen == Xlen::X32 && (funct3 == 3 || funct3 == 6) {
+        return Err(());
+    }
     match INSTRUCTIONS_GROUP03.get(&funct3) {
         Some(instruction) => Ok(&instruction),
-        None => panic!("Not found instruction!"),
+        None => Err(()),
     }
 }
 
@@ -725,31 +1682,55 @@ fn opecode_07(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
     let funct3 = ((word & 0x00007000) >> 12) as u8;
     match INSTRUCTIONS_GROUP07.get(&funct3) {
         Some(instruction) => Ok(&instruction),
-        None => panic!("Not found instruction!"),
+        None => Err(()),
     }
 }
 
 fn opecode_0f(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
+    // PAUSE is encoded as FENCE with pred=W, succ=0, fm=0, rd=x0 and rs1=x0.
+    if word == 0x0100000f {
+        return Ok(&Instruction {
+            mnemonic: "pause",
+            operation: pause,
+            disassemble: disassemble_mnemonic,
+        });
+    }
     let funct3 = ((word & 0x00007000) >> 12) as u8;
+    if funct3 == 2 {
+        let imm = ((word & 0xfff00000) >> 20) as u16;
+        return match INSTRUCTIONS_GROUP0F_CBO.get(&imm) {
+            Some(instruction) => Ok(instruction),
+            None => Err(()),
+        };
+    }
     match INSTRUCTIONS_GROUP0F.get(&funct3) {
         Some(instruction) => Ok(&instruction),
-        None => panic!("Not found instruction!"),
+        None => Err(()),
     }
 }
 
 fn opecode_13(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
     let funct3 = ((word & 0x00007000) >> 12) as u8;
+    let imm = ((word & 0xfff00000) >> 20) as u16;
+    if funct3 == 6 && (word & 0x00000f80) == 0 {
+        if let Some(instruction) = INSTRUCTIONS_GROUP13_PREFETCH.get(&((imm & 0x1f) as u8)) {
+            return Ok(instruction);
+        }
+    }
+    if let Some(instruction) = INSTRUCTIONS_GROUP13_UNARY.get(&(imm, funct3)) {
+        return Ok(instruction);
+    }
     match funct3 {
         5 => {
             let funct7 = ((word & 0xfc000000) >> 25) as u8;
             match INSTRUCTIONS_GROUP13_SUB.get(&(funct7, funct3)) {
                 Some(instruction) => Ok(&instruction),
-                None => panic!("Not found instruction!",),
+                None => Err(()),
             }
         }
         _ => match INSTRUCTIONS_GROUP13.get(&funct3) {
             Some(instruction) => Ok(&instruction),
-            None => panic!("Not found instruction!"),
+            None => Err(()),
         },
     }
 }
@@ -758,32 +1739,40 @@ fn opecode_17(_cpu: &Cpu, _addr: u64, _word: u32) -> Result<&Instruction, ()> {
     let idx = 0;
     match INSTRUCTIONS_GROUP17.get(&idx) {
         Some(instruction) => Ok(&instruction),
-        None => panic!("Not found instruction!"),
+        None => Err(()),
     }
 }
 
-fn opecode_1b(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
+fn opecode_1b(cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
+    // the word operations are RV64 only.
+    if cpu.xlen == Xlen::X32 {
+        return Err(());
+    }
     let funct3 = ((word & 0x00007000) >> 12) as u8;
     match funct3 {
         5 => {
             let funct7 = ((word & 0xfe000000) >> 25) as u8;
             match INSTRUCTIONS_GROUP1B_SUB.get(&(funct7, funct3)) {
                 Some(instruction) => Ok(&instruction),
-                None => panic!("Not found instruction!"),
+                None => Err(()),
             }
         }
         _ => match INSTRUCTIONS_GROUP1B.get(&funct3) {
             Some(instruction) => Ok(&instruction),
-            None => panic!("Not found instruction!"),
+            None => Err(()),
         },
     }
 }
 
-fn opecode_23(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
+fn opecode_23(cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
     let funct3 = ((word & 0x00007000) >> 12) as u8;
+    // sd is RV64I only.
+    if cpu.xlen == Xlen::X32 && funct3 == 3 {
+        return Err(());
+    }
     match INSTRUCTIONS_GROUP23.get(&funct3) {
         Some(instruction) => Ok(&instruction),
-        None => panic!("Not found instruction!"),
+        None => Err(()),
     }
 }
 
@@ -791,7 +1780,7 @@ fn opecode_27(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
     let funct3 = ((word & 0x00007000) >> 12) as u8;
     match INSTRUCTIONS_GROUP27.get(&funct3) {
         Some(instruction) => Ok(&instruction),
-        None => panic!("Not found instruction!"),
+        None => Err(()),
     }
 }
 
@@ -800,7 +1789,7 @@ fn opecode_2f(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
     let funct7 = ((word & 0xf8000000) >> 27) as u8;
     match INSTRUCTIONS_GROUP2F.get(&(funct7, funct3)) {
         Some(instruction) => Ok(&instruction),
-        None => panic!("Not found instruction!"),
+        None => Err(()),
     }
 }
 
@@ -809,7 +1798,7 @@ fn opecode_33(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
     let funct7 = ((word & 0xfe000000) >> 25) as u8;
     match INSTRUCTIONS_GROUP33.get(&(funct7, funct3)) {
         Some(instruction) => Ok(&instruction),
-        None => panic!("Not found instruction!"),
+        None => Err(()),
     }
 }
 
@@ -821,21 +1810,41 @@ fn opecode_37(_cpu: &Cpu, _addr: u64, _word: u32) -> Result<&Instruction, ()> {
     })
 }
 
-fn opecode_3b(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
+fn opecode_3b(cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
+    // the word operations are RV64 only.
+    if cpu.xlen == Xlen::X32 {
+        return Err(());
+    }
     let funct3 = ((word & 0x00007000) >> 12) as u8;
     let funct7 = ((word & 0xfe000000) >> 25) as u8;
     match INSTRUCTIONS_GROUP3B.get(&(funct7, funct3)) {
         Some(instruction) => Ok(&instruction),
-        None => panic!("Not found instruction!"),
+        None => Err(()),
+    }
+}
+
+fn opecode_fused(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
+    let opcode = (word & 0x0000007f) as u8;
+    let fmt = ((word & 0x06000000) >> 25) as u8;
+    match INSTRUCTIONS_FUSED.get(&(opcode, fmt)) {
+        Some(instruction) => Ok(instruction),
+        None => Err(()),
     }
 }
 
 fn opecode_53(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
     let funct3 = ((word & 0x00007000) >> 12) as u8;
     let funct7 = ((word & 0xfe000000) >> 25) as u8;
-    match INSTRUCTIONS_GROUP53.get(&(funct7, funct3)) {
-        Some(instruction) => Ok(&instruction),
-        None => panic!("Not found instruction!"),
+    let rs2 = ((word & 0x01f00000) >> 20) as u8;
+    let keys = [
+        (funct7, funct3, rs2),
+        (funct7, funct3, ANY),
+        (funct7, ANY, rs2),
+        (funct7, ANY, ANY),
+    ];
+    match keys.iter().find_map(|key| INSTRUCTIONS_GROUP53.get(key)) {
+        Some(instruction) => Ok(instruction),
+        None => Err(()),
     }
 }
 
@@ -843,7 +1852,7 @@ fn opecode_63(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
     let funct3 = ((word & 0x00007000) >> 12) as u8;
     match INSTRUCTIONS_GROUP63.get(&funct3) {
         Some(instruction) => Ok(&instruction),
-        None => panic!("Not found instruction!"),
+        None => Err(()),
     }
 }
 
@@ -868,21 +1877,21 @@ fn opecode_73(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
     match funct3 {
         0 => {
             let funct12 = ((word & 0xfff00000) >> 20) as u16;
-            match funct12 & 0x120 {
-                0x120 => Ok(&Instruction {
+            match funct12 >> 5 {
+                0x09 => Ok(&Instruction {
                     mnemonic: "sfence.vma",
                     operation: sfence,
                     disassemble: disassemble_mnemonic,
                 }),
                 _ => match INSTRUCTIONS_GROUP73_EXTEND.get(&funct12) {
                     Some(instruction) => Ok(&instruction),
-                    None => panic!("Not found instruction!"),
+                    None => Err(()),
                 },
             }
         }
         _ => match INSTRUCTIONS_GROUP73.get(&funct3) {
             Some(instruction) => Ok(&instruction),
-            None => panic!("Not found instruction!"),
+            None => Err(()),
         },
     }
 }
@@ -973,6 +1982,71 @@ pub fn signed(cpu: &Cpu, data: i64) -> i64 {
     }
 }
 
+/// Returns true if a 32-bit instruction uses any of the integer registers x16-x31,
+/// which do not exist in the RV32E/RV64E base ISA.
+pub fn uses_rve_reserved_registers(word: u32) -> bool {
+    let o = parse_type_r(word);
+    let funct3 = (word >> 12) & 0x7;
+    let funct7 = word >> 25;
+    let (rd, rs1, rs2) = match word & 0x7f {
+        // LUI, AUIPC, JAL
+        0x37 | 0x17 | 0x6f => (true, false, false),
+        // LOAD, MISC-MEM, OP-IMM, OP-IMM-32, JALR
+        0x03 | 0x0f | 0x13 | 0x1b | 0x67 => (true, true, false),
+        // LOAD-FP, STORE-FP
+        0x07 | 0x27 => (false, true, false),
+        // STORE, BRANCH
+        0x23 | 0x63 => (false, true, true),
+        // AMO, OP, OP-32
+        0x2f | 0x33 | 0x3b => (true, true, true),
+        // OP-FP: only compares, conversions and moves use integer registers.
+        0x53 => match funct7 >> 2 {
+            0x14 | 0x18 | 0x1c => (true, false, false),
+            // fli holds an immediate in rs1.
+            0x1e if o.rs2 == 1 => (false, false, false),
+            0x1a | 0x1e => (false, true, false),
+            // fmvp.d.x
+            0x16 => (false, true, true),
+            _ => (false, false, false),
+        },
+        // SYSTEM
+        0x73 => match funct3 {
+            0 => match funct7 {
+                // sfence.vma
+                0x09 => (false, true, true),
+                _ => (false, false, false),
+            },
+            1..=3 => (true, true, false),
+            5..=7 => (true, false, false),
+            _ => (false, false, false),
+        },
+        _ => (false, false, false),
+    };
+    (rd && o.rd >= 16) || (rs1 && o.rs1 >= 16) || (rs2 && o.rs2 >= 16)
+}
+
+/// Returns the destination register of a 32-bit instruction, and whether it is
+/// a floating-point register. The register is returned even if it is x0.
+pub fn destination_register(word: u32) -> Option<(u8, bool)> {
+    let rd = parse_type_r(word).rd;
+    let funct3 = (word >> 12) & 0x7;
+    let funct7 = word >> 25;
+    match word & 0x7f {
+        // LUI, AUIPC, JAL, JALR, LOAD, OP-IMM, OP-IMM-32, AMO, OP, OP-32
+        0x37 | 0x17 | 0x6f | 0x67 | 0x03 | 0x13 | 0x1b | 0x2f | 0x33 | 0x3b => Some((rd, false)),
+        // LOAD-FP, MADD, MSUB, NMSUB, NMADD
+        0x07 | 0x43 | 0x47 | 0x4b | 0x4f => Some((rd, true)),
+        // OP-FP: compares, conversions to integers and moves to integers write x registers.
+        0x53 => match funct7 >> 2 {
+            0x14 | 0x18 | 0x1c => Some((rd, false)),
+            _ => Some((rd, true)),
+        },
+        // SYSTEM: Zicsr
+        0x73 if funct3 != 0 && funct3 != 4 => Some((rd, false)),
+        _ => None,
+    }
+}
+
 //==============================================================================
 // DisAssembling functions for debug.
 //==============================================================================
@@ -1069,6 +2143,124 @@ fn disassemble_r(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
     s
 }
 
+fn disassemble_unary(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let o = parse_type_r(word);
+    let mut s = String::new();
+    s += &format!("{0: <10} ", mnemonic);
+    s += REGISTERS.get(&o.rd).unwrap();
+    s += &format!(":{:x}", _cpu.x[o.rd as usize]);
+    s += &format!(",{:}", REGISTERS.get(&o.rs1).unwrap());
+    s += &format!(":{:x}", _cpu.x[o.rs1 as usize]);
+    s
+}
+
+fn disassemble_cbo(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let o = parse_type_r(word);
+    let mut s = String::new();
+    s += &format!("{0: <10} ", mnemonic);
+    s += &format!("({:}", REGISTERS.get(&o.rs1).unwrap());
+    s += &format!(":{:x})", _cpu.x[o.rs1 as usize]);
+    s
+}
+
+fn disassemble_prefetch(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let o = parse_type_r(word);
+    let mut s = String::new();
+    s += &format!("{0: <10} ", mnemonic);
+    s += &format!("{:x}", (word as i32 >> 20) & !0x1f);
+    s += &format!("({:}", REGISTERS.get(&o.rs1).unwrap());
+    s += &format!(":{:x})", _cpu.x[o.rs1 as usize]);
+    s
+}
+
+fn disassemble_rnum(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let o = parse_type_r(word);
+    let mut s = String::new();
+    s += &format!("{0: <10} ", mnemonic);
+    s += REGISTERS.get(&o.rd).unwrap();
+    s += &format!(":{:x}", _cpu.x[o.rd as usize]);
+    s += &format!(",{:}", REGISTERS.get(&o.rs1).unwrap());
+    s += &format!(":{:x}", _cpu.x[o.rs1 as usize]);
+    s += &format!(",{:x}", (word >> 20) & 0xf);
+    s
+}
+
+fn disassemble_r_bs(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let mut s = disassemble_r(_cpu, mnemonic, word);
+    s += &format!(",{:x}", word >> 30);
+    s
+}
+
+/// Returns an f register and its bits, e.g. "f1:3ff0000000000000".
+fn fp_register(cpu: &Cpu, register: u8) -> String {
+    format!("f{}:{:x}", register, cpu.f[register as usize].to_bits())
+}
+
+fn disassemble_fp_r(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let o = parse_type_r(word);
+    let mut s = String::new();
+    s += &format!("{0: <10} ", mnemonic);
+    s += &fp_register(_cpu, o.rd);
+    s += &format!(",{}", fp_register(_cpu, o.rs1));
+    s += &format!(",{}", fp_register(_cpu, o.rs2));
+    s
+}
+
+fn disassemble_fp_r4(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let mut s = disassemble_fp_r(_cpu, mnemonic, word);
+    s += &format!(",{}", fp_register(_cpu, (word >> 27) as u8));
+    s
+}
+
+fn disassemble_fp_unary(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let o = parse_type_r(word);
+    let mut s = String::new();
+    s += &format!("{0: <10} ", mnemonic);
+    s += &fp_register(_cpu, o.rd);
+    s += &format!(",{}", fp_register(_cpu, o.rs1));
+    s
+}
+
+fn disassemble_fli(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let o = parse_type_r(word);
+    let mut s = String::new();
+    s += &format!("{0: <10} ", mnemonic);
+    s += &fp_register(_cpu, o.rd);
+    s += &format!(",{:x}", o.rs1);
+    s
+}
+
+fn disassemble_fp_compare(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let o = parse_type_r(word);
+    let mut s = String::new();
+    s += &format!("{0: <10} ", mnemonic);
+    s += REGISTERS.get(&o.rd).unwrap();
+    s += &format!(":{:x}", _cpu.x[o.rd as usize]);
+    s += &format!(",{}", fp_register(_cpu, o.rs1));
+    s += &format!(",{}", fp_register(_cpu, o.rs2));
+    s
+}
+
+fn disassemble_fp_to_x(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let o = parse_type_r(word);
+    let mut s = String::new();
+    s += &format!("{0: <10} ", mnemonic);
+    s += REGISTERS.get(&o.rd).unwrap();
+    s += &format!(":{:x}", _cpu.x[o.rd as usize]);
+    s += &format!(",{}", fp_register(_cpu, o.rs1));
+    s
+}
+
+fn disassemble_fp_from_x(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let o = parse_type_r(word);
+    let mut s = String::new();
+    s += &format!("{0: <10} ", mnemonic);
+    s += &fp_register(_cpu, o.rd);
+    s += &format!(",{:}", REGISTERS.get(&o.rs1).unwrap());
+    s += &format!(":{:x}", _cpu.x[o.rs1 as usize]);
+    s
+}
+
 fn disassemble_j(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
     let o = parse_type_j(word);
     let mut s = String::new();
@@ -1252,37 +2444,55 @@ fn sd(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
 /// [flw rd,offset(rs1)]
 /// The FLW instruction loads a single-precision floating-point value
 /// from memory into floating-point register rd.
-fn flw(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+fn flw(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
     let o = parse_type_i(word);
-    let data = match cpu
+    let data = cpu
         .mmu
-        .read32(cpu.x[o.rs1 as usize].wrapping_add(o.imm) as u64)
-    {
-        Ok(d) => f64::from_bits(d as i32 as i64 as u64),
-        Err(e) => return Err(e),
-    };
-    cpu.f[o.rd as usize] = data;
+        .read32(cpu.x[o.rs1 as usize].wrapping_add(o.imm) as u64)?;
+    write_fp(cpu, o.rd, SINGLE, data as u64);
     Ok(())
 }
 
 /// [fld rd,rs1,offset]
 /// The FLD instruction loads a double-precision floating-point value
 /// from memory into floating-point register rd.
-fn fld(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+fn fld(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
     let o = parse_type_i(word);
-    let data = match cpu
+    let data = cpu
         .mmu
-        .read64(cpu.x[o.rs1 as usize].wrapping_add(o.imm) as u64)
-    {
-        Ok(d) => f64::from_bits(d),
-        Err(e) => return Err(e),
-    };
-    cpu.f[o.rd as usize] = data;
+        .read64(cpu.x[o.rs1 as usize].wrapping_add(o.imm) as u64)?;
+    write_fp(cpu, o.rd, DOUBLE, data);
+    Ok(())
+}
+
+/// [flh rd,offset(rs1)]
+/// The FLH instruction loads a half-precision floating-point value from memory
+/// into floating-point register rd, NaN-boxed to the register width.
+fn flh(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_i(word);
+    let data = cpu
+        .mmu
+        .read16(cpu.x[o.rs1 as usize].wrapping_add(o.imm) as u64)?;
+    write_fp(cpu, o.rd, HALF, data as u64);
     Ok(())
 }
 
+/// [fsh rs2,offset(rs1)]
+/// The stores write the low bits of the register, which are not NaN-box checked.
+fn fsh(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_s(word);
+    let addr = cpu.x[o.rs1 as usize].wrapping_add(o.imm) as u64;
+    cpu.mmu
+        .write16(addr, cpu.f[o.rs2 as usize].to_bits() as u16)
+}
+
 /// [fsw rs2,offset(rs1)]
-fn fsw(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+fn fsw(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
     let o = parse_type_s(word);
     let addr = cpu.x[o.rs1 as usize].wrapping_add(o.imm) as u64;
     cpu.mmu
@@ -1290,7 +2500,8 @@ fn fsw(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
 }
 
 /// [fsd rs2,offset(rs1)]
-fn fsd(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+fn fsd(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
     let o = parse_type_s(word);
     let addr = cpu.x[o.rs1 as usize].wrapping_add(o.imm) as u64;
     cpu.mmu.write64(addr, cpu.f[o.rs2 as usize].to_bits())
@@ -1307,6 +2518,79 @@ fn fence(_cpu: &mut Cpu, _addr: u64, _word: u32) -> Result<(), Trap> {
     Ok(())
 }
 
+/// [pause]
+/// The PAUSE instruction is a HINT that indicates the current hart's rate of instruction
+/// retirement should be temporarily reduced or paused. It is used in spin-wait loops.
+fn pause(cpu: &mut Cpu, _addr: u64, _word: u32) -> Result<(), Trap> {
+    cpu.stall_pause();
+    Ok(())
+}
+
+//==============================================================================
+// Cache-Block Management Instructions (Zicbom/Zicboz/Zicbop)
+//==============================================================================
+// Below M-mode, the instructions are enabled by menvcfg (and by senvcfg in U-mode).
+// Caches are not modeled, so only cbo.zero changes the memory.
+
+/// raise an illegal instruction exception unless the envcfg field is non-zero for the privilege.
+fn check_cbo_enabled(cpu: &mut Cpu, addr: u64, field: u64) -> Result<(), Trap> {
+    let enabled = match cpu.privilege {
+        Privilege::Machine => true,
+        Privilege::User => {
+            cpu.csr.read_direct(CSR_MENVCFG) & field != 0
+                && cpu.csr.read_direct(CSR_SENVCFG) & field != 0
+        }
+        _ => cpu.csr.read_direct(CSR_MENVCFG) & field != 0,
+    };
+    match enabled {
+        true => Ok(()),
+        false => Err(Trap {
+            exception: Exception::IllegalInstruction,
+            value: addr,
+        }),
+    }
+}
+
+/// [cbo.clean (rs1)]
+/// CBO.CLEAN writes back the cache block containing the address in rs1 to memory.
+fn cbo_clean(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_cbo_enabled(cpu, addr, CSR_ENVCFG_CBCFE)?;
+    cpu.mmu.check_cache_block(cpu.x[o.rs1 as usize] as u64)
+}
+
+/// [cbo.flush (rs1)]
+/// CBO.FLUSH writes back and invalidates the cache block containing the address in rs1.
+fn cbo_flush(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_cbo_enabled(cpu, addr, CSR_ENVCFG_CBCFE)?;
+    cpu.mmu.check_cache_block(cpu.x[o.rs1 as usize] as u64)
+}
+
+/// [cbo.inval (rs1)]
+/// CBO.INVAL invalidates the cache block containing the address in rs1. When CBIE=01,
+/// it performs a flush instead, which is the same for this emulator.
+fn cbo_inval(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_cbo_enabled(cpu, addr, CSR_ENVCFG_CBIE)?;
+    cpu.mmu.check_cache_block(cpu.x[o.rs1 as usize] as u64)
+}
+
+/// [cbo.zero (rs1)]
+/// CBO.ZERO stores zeros to the full set of bytes of the cache block containing the address in rs1.
+fn cbo_zero(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_cbo_enabled(cpu, addr, CSR_ENVCFG_CBZE)?;
+    cpu.mmu.zero_cache_block(cpu.x[o.rs1 as usize] as u64)
+}
+
+/// [prefetch.i offset(rs1)], [prefetch.r offset(rs1)], [prefetch.w offset(rs1)]
+/// The prefetch instructions are HINTs which never raise exceptions.
+fn prefetch(_cpu: &mut Cpu, _addr: u64, _word: u32) -> Result<(), Trap> {
+    // do nothing.
+    Ok(())
+}
+
 //==============================================================================
 // Integer Register-Immediate Instructions (RV32I/RV64I)
 //==============================================================================
@@ -1680,6 +2964,18 @@ fn bgeu(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
 //==============================================================================
 // Control and Status Register (CSR) Instructions.
 //==============================================================================
+/// The seed CSR (Zkr) must be accessed with a read-write instruction. CSRRS/CSRRC
+/// with rs1=x0 and CSRRSI/CSRRCI with uimm=0 raise an illegal instruction exception.
+fn check_seed_read_only_access(o: &InstructionTypeCSR, addr: u64) -> Result<(), Trap> {
+    match o.csr == CSR_SEED && o.rs1 == 0 {
+        true => Err(Trap {
+            exception: Exception::IllegalInstruction,
+            value: addr,
+        }),
+        false => Ok(()),
+    }
+}
+
 /// [csrrw rd,offset,rs1]
 /// The CSRRW (Atomic Read/Write CSR) instruction atomically swaps values in the
 /// CSRs and integer registers. CSRRW reads the old value of the CSR, zero-extends
@@ -1688,40 +2984,20 @@ fn bgeu(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
 /// read the CSR and shall not cause any of the side effects that might occur on a CSR read.
 fn csrrw(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
     let o = parse_type_csr(word);
-    let t = match cpu.csr.read(o.csr, addr, &cpu.privilege) {
-        Ok(data) => data as i64,
-        Err(e) => return Err(e),
-    };
+    let t = cpu.read_csr(o.csr, addr)? as i64;
     let data = unsigned(cpu, cpu.x[o.rs1 as usize]);
-    match cpu.csr.write(o.csr, data, addr, &cpu.privilege) {
-        Ok(need_update_mmu_addressing_mode) => {
-            if need_update_mmu_addressing_mode {
-                cpu.mmu.update_addressing_mode(data);
-            }
-            cpu.x[o.rd as usize] = signed(cpu, t);
-            Ok(())
-        }
-        Err(e) => Err(e),
-    }
+    cpu.write_csr(o.csr, data, addr)?;
+    cpu.x[o.rd as usize] = signed(cpu, t);
+    Ok(())
 }
 
 /// [csrrwi rd,offset,uimm]
 fn csrrwi(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
     let o = parse_type_csr(word);
     let t = o.rs1 as u64; // uimm field
-    match cpu.csr.read(o.csr, addr, &cpu.privilege) {
-        Ok(data) => cpu.x[o.rd as usize] = signed(cpu, data as i64),
-        Err(e) => return Err(e),
-    };
-    match cpu.csr.write(o.csr, t, addr, &cpu.privilege) {
-        Ok(need_update_mmu_addressing_mode) => {
-            if need_update_mmu_addressing_mode {
-                cpu.mmu.update_addressing_mode(t);
-            }
-            Ok(())
-        }
-        Err(e) => Err(e),
-    }
+    let data = cpu.read_csr(o.csr, addr)?;
+    cpu.x[o.rd as usize] = signed(cpu, data as i64);
+    cpu.write_csr(o.csr, t, addr)
 }
 
 /// [csrrs rd,offset,rs1]
@@ -1733,41 +3009,33 @@ fn csrrwi(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
 /// Other bits in the CSR are unaffected (though CSRs might have side effects when written).
 fn csrrs(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
     let o = parse_type_csr(word);
-    let t = match cpu.csr.read(o.csr, addr, &cpu.privilege) {
-        Ok(data) => data as i64,
-        Err(e) => return Err(e),
-    };
-    let data = unsigned(cpu, t | cpu.x[o.rs1 as usize]);
-    match cpu.csr.write(o.csr, data, addr, &cpu.privilege) {
-        Ok(need_update_mmu_addressing_mode) => {
-            if need_update_mmu_addressing_mode {
-                cpu.mmu.update_addressing_mode(data);
-            }
-            cpu.x[o.rd as usize] = signed(cpu, t);
-            Ok(())
-        }
-        Err(e) => Err(e),
+    check_seed_read_only_access(&o, addr)?;
+    let t = cpu.read_csr(o.csr, addr)? as i64;
+    // the CSR is not written if rs1=x0 (uimm=0).
+    if o.rs1 == 0 {
+        cpu.x[o.rd as usize] = signed(cpu, t);
+        return Ok(());
     }
+    let data = unsigned(cpu, t | cpu.x[o.rs1 as usize]);
+    cpu.write_csr(o.csr, data, addr)?;
+    cpu.x[o.rd as usize] = signed(cpu, t);
+    Ok(())
 }
 
 /// [csrrsi rd,offset,uimm]
 fn csrrsi(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
     let o = parse_type_csr(word);
-    let t = match cpu.csr.read(o.csr, addr, &cpu.privilege) {
-        Ok(data) => data as i64,
-        Err(e) => return Err(e),
-    };
-    let data = unsigned(cpu, t | o.rs1 as i64);
-    match cpu.csr.write(o.csr, data, addr, &cpu.privilege) {
-        Ok(need_update_mmu_addressing_mode) => {
-            if need_update_mmu_addressing_mode {
-                cpu.mmu.update_addressing_mode(data);
-            }
-            cpu.x[o.rd as usize] = signed(cpu, t);
-            Ok(())
-        }
-        Err(e) => Err(e),
+    check_seed_read_only_access(&o, addr)?;
+    let t = cpu.read_csr(o.csr, addr)? as i64;
+    // the CSR is not written if rs1=x0 (uimm=0).
+    if o.rs1 == 0 {
+        cpu.x[o.rd as usize] = signed(cpu, t);
+        return Ok(());
     }
+    let data = unsigned(cpu, t | o.rs1 as i64);
+    cpu.write_csr(o.csr, data, addr)?;
+    cpu.x[o.rd as usize] = signed(cpu, t);
+    Ok(())
 }
 
 /// [csrrc rd,offset,rs1]
@@ -1778,41 +3046,33 @@ fn csrrsi(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
 /// be cleared in the CSR, if that CSR bit is writable. Other bits in the CSR are unaffected.
 fn csrrc(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
     let o = parse_type_csr(word);
-    let t = match cpu.csr.read(o.csr, addr, &cpu.privilege) {
-        Ok(data) => data as i64,
-        Err(e) => return Err(e),
-    };
-    let data = (signed(cpu, t) & !cpu.x[o.rs1 as usize]) as u64;
-    match cpu.csr.write(o.csr, data, addr, &cpu.privilege) {
-        Ok(need_update_mmu_addressing_mode) => {
-            if need_update_mmu_addressing_mode {
-                cpu.mmu.update_addressing_mode(data);
-            }
-            cpu.x[o.rd as usize] = signed(cpu, t);
-            Ok(())
-        }
-        Err(e) => Err(e),
+    check_seed_read_only_access(&o, addr)?;
+    let t = cpu.read_csr(o.csr, addr)? as i64;
+    // the CSR is not written if rs1=x0 (uimm=0).
+    if o.rs1 == 0 {
+        cpu.x[o.rd as usize] = signed(cpu, t);
+        return Ok(());
     }
+    let data = (signed(cpu, t) & !cpu.x[o.rs1 as usize]) as u64;
+    cpu.write_csr(o.csr, data, addr)?;
+    cpu.x[o.rd as usize] = signed(cpu, t);
+    Ok(())
 }
 
 /// [csrrci rd,offset,uimm]
 fn csrrci(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
     let o = parse_type_csr(word);
-    let t = match cpu.csr.read(o.csr, addr, &cpu.privilege) {
-        Ok(data) => data as i64,
-        Err(e) => return Err(e),
-    };
-    let data = (signed(cpu, t) & !(o.rs1 as i64)) as u64;
-    match cpu.csr.write(o.csr, data, addr, &cpu.privilege) {
-        Ok(need_update_mmu_addressing_mode) => {
-            if need_update_mmu_addressing_mode {
-                cpu.mmu.update_addressing_mode(data);
-            }
-            cpu.x[o.rd as usize] = signed(cpu, t);
-            Ok(())
-        }
-        Err(e) => Err(e),
+    check_seed_read_only_access(&o, addr)?;
+    let t = cpu.read_csr(o.csr, addr)? as i64;
+    // the CSR is not written if rs1=x0 (uimm=0).
+    if o.rs1 == 0 {
+        cpu.x[o.rd as usize] = signed(cpu, t);
+        return Ok(());
     }
+    let data = (signed(cpu, t) & !(o.rs1 as i64)) as u64;
+    cpu.write_csr(o.csr, data, addr)?;
+    cpu.x[o.rd as usize] = signed(cpu, t);
+    Ok(())
 }
 
 //==============================================================================
@@ -1832,7 +3092,19 @@ fn ecall(cpu: &mut Cpu, addr: u64, _word: u32) -> Result<(), Trap> {
 }
 
 /// [ebreak]
-fn ebreak(_cpu: &mut Cpu, addr: u64, _word: u32) -> Result<(), Trap> {
+/// If dcsr.ebreakm/ebreaks/ebreaku is set for the current privilege mode, EBREAK
+/// enters debug mode instead of raising a breakpoint exception.
+fn ebreak(cpu: &mut Cpu, addr: u64, _word: u32) -> Result<(), Trap> {
+    let enter_debug_mode = cpu.csr.read_direct(CSR_DCSR)
+        & match cpu.privilege {
+            Privilege::User => CSR_DCSR_EBREAKU,
+            Privilege::Supervisor => CSR_DCSR_EBREAKS,
+            _ => CSR_DCSR_EBREAKM,
+        };
+    if enter_debug_mode != 0 {
+        cpu.enter_debug_mode(DebugCause::Ebreak, addr);
+        return Ok(());
+    }
     Err(Trap {
         exception: Exception::Breakpoint,
         value: addr,
@@ -1866,13 +3138,11 @@ fn sret(cpu: &mut Cpu, addr: u64, _word: u32) -> Result<(), Trap> {
     );
 
     // update privilege by SPP.
-    // TODO: refactoring.
-    cpu.privilege = match spp {
+    cpu.change_privilege(match spp {
         0 => Privilege::User,
         1 => Privilege::Supervisor,
         _ => panic!("Unexpected Error!!"),
-    };
-    cpu.mmu.set_privilege(&cpu.privilege);
+    });
     Ok(())
 }
 
@@ -1895,18 +3165,32 @@ fn mret(cpu: &mut Cpu, addr: u64, _word: u32) -> Result<(), Trap> {
     );
 
     // update privilege by MPP.
-    // TODO: refactoring.
-    cpu.privilege = match mpp {
+    cpu.change_privilege(match mpp {
         0 => Privilege::User,
         1 => Privilege::Supervisor,
         2 => Privilege::Hypervisor,
         3 => Privilege::Machine,
         _ => panic!("Unexpected Error!!"),
-    };
-    cpu.mmu.set_privilege(&cpu.privilege);
+    });
     Ok(())
 }
 
+/// [dret]
+/// DRET returns from debug mode to dpc in the privilege mode held in dcsr.prv.
+/// It is an illegal instruction outside of debug mode.
+fn dret(cpu: &mut Cpu, addr: u64, _word: u32) -> Result<(), Trap> {
+    match cpu.is_debug_mode() {
+        true => {
+            cpu.leave_debug_mode();
+            Ok(())
+        }
+        false => Err(Trap {
+            exception: Exception::IllegalInstruction,
+            value: addr,
+        }),
+    }
+}
+
 /// [wfi]
 /// The Wait for Interrupt instruction (WFI) provides a hint to the implementation that the current
 /// hart can be stalled until an interrupt might need servicing. Execution of the WFI instruction
@@ -1918,11 +3202,61 @@ fn wfi(cpu: &mut Cpu, _addr: u64, _word: u32) -> Result<(), Trap> {
     Ok(())
 }
 
+/// [wrs.nto]
+/// WRS.NTO (Wait-on-Reservation-Set with No TimeOut) stalls the hart until the reservation
+/// set registered by a preceding LR is invalidated or an interrupt becomes pending.
+/// As the stall has no time limit, it is an illegal instruction outside of M-mode when
+/// mstatus.TW is set, like WFI.
+fn wrs_nto(cpu: &mut Cpu, addr: u64, _word: u32) -> Result<(), Trap> {
+    if cpu.privilege != Privilege::Machine
+        && cpu.csr.read_direct(CSR_MSTATUS) & CSR_STATUS_TW != 0
+    {
+        return Err(Trap {
+            exception: Exception::IllegalInstruction,
+            value: addr,
+        });
+    }
+    cpu.stall_wrs(false);
+    Ok(())
+}
+
+/// [wrs.sto]
+/// WRS.STO is the same as WRS.NTO, except that the stall is limited to a short timeout.
+fn wrs_sto(cpu: &mut Cpu, _addr: u64, _word: u32) -> Result<(), Trap> {
+    cpu.stall_wrs(true);
+    Ok(())
+}
+
 /// [sfence.vma]
 fn sfence(_cpu: &mut Cpu, _addr: u64, _word: u32) -> Result<(), Trap> {
     Ok(())
 }
 
+//==============================================================================
+// Integer Conditional Operations (Zicond)
+//==============================================================================
+/// [czero.eqz rd,rs1,rs2]
+/// CZERO.EQZ moves zero to rd if rs2 is equal to zero, otherwise moves rs1 to rd.
+fn czero_eqz(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    cpu.x[o.rd as usize] = match cpu.x[o.rs2 as usize] {
+        0 => 0,
+        _ => cpu.x[o.rs1 as usize],
+    };
+    Ok(())
+}
+
+/// [czero.nez rd,rs1,rs2]
+/// CZERO.NEZ moves zero to rd if rs2 is nonzero, otherwise moves rs1 to rd.
+fn czero_nez(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    cpu.x[o.rd as usize] = match cpu.x[o.rs2 as usize] {
+        0 => cpu.x[o.rs1 as usize],
+        _ => 0,
+    };
+    Ok(())
+}
+
 //==============================================================================
 // Multiplication Instructions (RV32M/RV64M)
 //==============================================================================
@@ -2494,16 +3828,1172 @@ fn amomaxu_d(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
 }
 
 //==============================================================================
-// Single-Precision Load and Store Instructions (RV32F/RV64D)
+// Atomic Compare-and-Swap Instructions (Zacas)
 //==============================================================================
+// AMOCAS.D on RV32 and AMOCAS.Q on RV64 operate on even/odd register pairs.
+// A pair starting at x0 reads as zero and is never written.
+
+/// read the register pair (rs, rs+1) as a 2*XLEN value.
+fn read_register_pair(cpu: &Cpu, rs: u8) -> u128 {
+    match rs {
+        0 => 0,
+        _ => match cpu.xlen {
+            Xlen::X32 => {
+                (cpu.x[rs as usize] as u32 as u128) | (cpu.x[rs as usize + 1] as u32 as u128) << 32
+            }
+            Xlen::X64 => {
+                (cpu.x[rs as usize] as u64 as u128) | (cpu.x[rs as usize + 1] as u64 as u128) << 64
+            }
+        },
+    }
+}
+
+/// write a 2*XLEN value to the register pair (rd, rd+1).
+fn write_register_pair(cpu: &mut Cpu, rd: u8, data: u128) {
+    if rd == 0 {
+        return;
+    }
+    match cpu.xlen {
+        Xlen::X32 => {
+            cpu.x[rd as usize] = data as u32 as i32 as i64;
+            cpu.x[rd as usize + 1] = (data >> 32) as u32 as i32 as i64;
+        }
+        Xlen::X64 => {
+            cpu.x[rd as usize] = data as u64 as i64;
+            cpu.x[rd as usize + 1] = (data >> 64) as u64 as i64;
+        }
+    }
+}
+
+/// [amocas.w rd,rs2,(rs1)]
+/// AMOCAS.W atomically loads a 32-bit word from the address in rs1 and compares it with
+/// the value in rd. Only if they are equal, the value in rs2 is stored to memory.
+/// The loaded value is sign-extended and written to rd in either case.
+fn amocas_w(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let addr = cpu.x[o.rs1 as usize] as u64;
+    let t = cpu.mmu.read32(addr)?;
+    if t == cpu.x[o.rd as usize] as u32 {
+        cpu.mmu.write32(addr, cpu.x[o.rs2 as usize] as u32)?;
+    }
+    cpu.x[o.rd as usize] = t as i32 as i64;
+    Ok(())
+}
 
-/// [fmv.w.x rd,rs1]
-/// FMV.W.X moves the single-precision value encoded in IEEE 754-2008 standard encoding
-/// from the lower 32 bits of integer register rs1 to the floating-point register rd.
-/// The bits are not modified in the transfer, and in particular, the payloads of
-/// non-canonical NaNs are preserved.
-fn fmv_w_x(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+/// [amocas.d rd,rs2,(rs1)]
+fn amocas_d(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let mem_addr = cpu.x[o.rs1 as usize] as u64;
+    match cpu.xlen {
+        Xlen::X64 => {
+            let t = cpu.mmu.read64(mem_addr)?;
+            if t == cpu.x[o.rd as usize] as u64 {
+                cpu.mmu.write64(mem_addr, cpu.x[o.rs2 as usize] as u64)?;
+            }
+            cpu.x[o.rd as usize] = t as i64;
+        }
+        Xlen::X32 => {
+            // rd and rs2 must be even registers.
+            if o.rd & 1 != 0 || o.rs2 & 1 != 0 {
+                return Err(Trap {
+                    exception: Exception::IllegalInstruction,
+                    value: addr,
+                });
+            }
+            let t = cpu.mmu.read64(mem_addr)?;
+            if t as u128 == read_register_pair(cpu, o.rd) {
+                let data = read_register_pair(cpu, o.rs2) as u64;
+                cpu.mmu.write64(mem_addr, data)?;
+            }
+            write_register_pair(cpu, o.rd, t as u128);
+        }
+    }
+    Ok(())
+}
+
+/// [amocas.q rd,rs2,(rs1)]
+fn amocas_q(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    // AMOCAS.Q is RV64 only, and rd and rs2 must be even registers.
+    if let Xlen::X32 = cpu.xlen {
+        return Err(Trap {
+            exception: Exception::IllegalInstruction,
+            value: addr,
+        });
+    }
+    if o.rd & 1 != 0 || o.rs2 & 1 != 0 {
+        return Err(Trap {
+            exception: Exception::IllegalInstruction,
+            value: addr,
+        });
+    }
+    let mem_addr = cpu.x[o.rs1 as usize] as u64;
+    let mut t = 0_u128;
+    for i in 0..2 {
+        match cpu.mmu.read64(mem_addr.wrapping_add(i * 8)) {
+            Ok(data) => t |= (data as u128) << (i * 64),
+            Err(e) => return Err(e),
+        };
+    }
+    if t == read_register_pair(cpu, o.rd) {
+        let data = read_register_pair(cpu, o.rs2);
+        for i in 0..2 {
+            cpu.mmu
+                .write64(mem_addr.wrapping_add(i * 8), (data >> (i * 64)) as u64)?;
+        }
+    }
+    write_register_pair(cpu, o.rd, t);
+    Ok(())
+}
+
+//==============================================================================
+// Bit-manipulation Instructions for Cryptography (Zbkb/Zbkc/Zbkx)
+//==============================================================================
+/// raise an illegal instruction exception unless executed with the given XLEN.
+fn check_xlen(cpu: &Cpu, xlen: Xlen, addr: u64) -> Result<(), Trap> {
+    match cpu.xlen == xlen {
+        true => Ok(()),
+        false => Err(Trap {
+            exception: Exception::IllegalInstruction,
+            value: addr,
+        }),
+    }
+}
+
+/// [andn rd,rs1,rs2]
+/// ANDN performs the bitwise logical AND operation between rs1 and the bitwise
+/// inversion of rs2.
+fn andn(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    cpu.x[o.rd as usize] = cpu.x[o.rs1 as usize] & !cpu.x[o.rs2 as usize];
+    Ok(())
+}
+
+/// [orn rd,rs1,rs2]
+fn orn(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    cpu.x[o.rd as usize] = cpu.x[o.rs1 as usize] | !cpu.x[o.rs2 as usize];
+    Ok(())
+}
+
+/// [xnor rd,rs1,rs2]
+fn xnor(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    cpu.x[o.rd as usize] = !(cpu.x[o.rs1 as usize] ^ cpu.x[o.rs2 as usize]);
+    Ok(())
+}
+
+/// [rol rd,rs1,rs2]
+/// ROL performs a rotate left of rs1 by the amount in least-significant log2(XLEN)
+/// bits of rs2.
+fn rol(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let shamt = cpu.x[o.rs2 as usize] as u32;
+    cpu.x[o.rd as usize] = match cpu.xlen {
+        Xlen::X32 => (cpu.x[o.rs1 as usize] as u32).rotate_left(shamt & 0x1f) as i32 as i64,
+        Xlen::X64 => (cpu.x[o.rs1 as usize] as u64).rotate_left(shamt & 0x3f) as i64,
+    };
+    Ok(())
+}
+
+/// [ror rd,rs1,rs2]
+fn ror(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let shamt = cpu.x[o.rs2 as usize] as u32;
+    cpu.x[o.rd as usize] = match cpu.xlen {
+        Xlen::X32 => (cpu.x[o.rs1 as usize] as u32).rotate_right(shamt & 0x1f) as i32 as i64,
+        Xlen::X64 => (cpu.x[o.rs1 as usize] as u64).rotate_right(shamt & 0x3f) as i64,
+    };
+    Ok(())
+}
+
+/// [rori rd,rs1,shamt]
+fn rori(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_i(word);
+    cpu.x[o.rd as usize] = match cpu.xlen {
+        Xlen::X32 => (cpu.x[o.rs1 as usize] as u32).rotate_right((word >> 20) & 0x1f) as i32 as i64,
+        Xlen::X64 => (cpu.x[o.rs1 as usize] as u64).rotate_right((word >> 20) & 0x3f) as i64,
+    };
+    Ok(())
+}
+
+/// [rolw rd,rs1,rs2]
+/// ROLW, RORW and RORIW are RV64-only instructions that rotate the least-significant
+/// word of rs1 and sign-extend the 32-bit result.
+fn rolw(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let shamt = cpu.x[o.rs2 as usize] as u32 & 0x1f;
+    cpu.x[o.rd as usize] = (cpu.x[o.rs1 as usize] as u32).rotate_left(shamt) as i32 as i64;
+    Ok(())
+}
+
+/// [rorw rd,rs1,rs2]
+fn rorw(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let shamt = cpu.x[o.rs2 as usize] as u32 & 0x1f;
+    cpu.x[o.rd as usize] = (cpu.x[o.rs1 as usize] as u32).rotate_right(shamt) as i32 as i64;
+    Ok(())
+}
+
+/// [roriw rd,rs1,shamt]
+fn roriw(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_i(word);
+    let shamt = (word >> 20) & 0x1f;
+    cpu.x[o.rd as usize] = (cpu.x[o.rs1 as usize] as u32).rotate_right(shamt) as i32 as i64;
+    Ok(())
+}
+
+/// [pack rd,rs1,rs2]
+/// PACK packs the XLEN/2-bit lower halves of rs1 and rs2 into rd, with rs1 in
+/// the lower half and rs2 in the upper half.
+fn pack(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize], cpu.x[o.rs2 as usize]);
+    cpu.x[o.rd as usize] = match cpu.xlen {
+        Xlen::X32 => ((rs1 as u32 & 0xffff) | (rs2 as u32) << 16) as i32 as i64,
+        Xlen::X64 => (rs1 as u64 & 0xffffffff | (rs2 as u64) << 32) as i64,
+    };
+    Ok(())
+}
+
+/// [packh rd,rs1,rs2]
+/// PACKH packs the least-significant bytes of rs1 and rs2 into the 16 least-significant
+/// bits of rd, zero extending the rest of rd.
+fn packh(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    cpu.x[o.rd as usize] = (cpu.x[o.rs1 as usize] & 0xff) | (cpu.x[o.rs2 as usize] & 0xff) << 8;
+    Ok(())
+}
+
+/// [packw rd,rs1,rs2]
+fn packw(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize], cpu.x[o.rs2 as usize]);
+    cpu.x[o.rd as usize] = ((rs1 as u32 & 0xffff) | (rs2 as u32) << 16) as i32 as i64;
+    Ok(())
+}
+
+/// [brev8 rd,rs]
+/// BREV8 reverses the order of the bits in every byte of a register.
+fn brev8(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let mut bytes = (cpu.x[o.rs1 as usize] as u64).to_le_bytes();
+    for b in bytes.iter_mut() {
+        *b = b.reverse_bits();
+    }
+    cpu.x[o.rd as usize] = signed(cpu, u64::from_le_bytes(bytes) as i64);
+    Ok(())
+}
+
+/// [rev8 rd,rs]
+/// REV8 reverses the order of the bytes in rs. The RV32 and RV64 encodings differ
+/// in the immediate, and each is reserved for the other XLEN.
+fn rev8(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    match word >> 20 {
+        0x698 => check_xlen(cpu, Xlen::X32, addr)?,
+        _ => check_xlen(cpu, Xlen::X64, addr)?,
+    };
+    cpu.x[o.rd as usize] = match cpu.xlen {
+        Xlen::X32 => (cpu.x[o.rs1 as usize] as u32).swap_bytes() as i32 as i64,
+        Xlen::X64 => cpu.x[o.rs1 as usize].swap_bytes(),
+    };
+    Ok(())
+}
+
+/// [zip rd,rs]
+/// ZIP (RV32 only) scatters the bits of the lower half of rs to the even bit
+/// positions of rd, and the bits of the upper half to the odd bit positions.
+fn zip(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X32, addr)?;
+    let rs1 = cpu.x[o.rs1 as usize] as u32;
+    let t = (0..16).fold(0_u32, |acc, i| {
+        acc | ((rs1 >> i) & 1) << (2 * i) | ((rs1 >> (i + 16)) & 1) << (2 * i + 1)
+    });
+    cpu.x[o.rd as usize] = t as i32 as i64;
+    Ok(())
+}
+
+/// [unzip rd,rs]
+/// UNZIP (RV32 only) is the inverse of ZIP.
+fn unzip(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X32, addr)?;
+    let rs1 = cpu.x[o.rs1 as usize] as u32;
+    let t = (0..16).fold(0_u32, |acc, i| {
+        acc | ((rs1 >> (2 * i)) & 1) << i | ((rs1 >> (2 * i + 1)) & 1) << (i + 16)
+    });
+    cpu.x[o.rd as usize] = t as i32 as i64;
+    Ok(())
+}
+
+/// carry-less multiplication of two XLEN-bit values.
+fn carryless_multiply(a: u64, b: u64) -> u128 {
+    (0..64)
+        .filter(|i| (b >> i) & 1 != 0)
+        .fold(0, |acc, i| acc ^ (a as u128) << i)
+}
+
+/// [clmul rd,rs1,rs2]
+/// CLMUL produces the lower half of the 2*XLEN carry-less product.
+fn clmul(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let t = carryless_multiply(
+        unsigned(cpu, cpu.x[o.rs1 as usize]),
+        unsigned(cpu, cpu.x[o.rs2 as usize]),
+    );
+    cpu.x[o.rd as usize] = signed(cpu, t as i64);
+    Ok(())
+}
+
+/// [clmulh rd,rs1,rs2]
+/// CLMULH produces the upper half of the 2*XLEN carry-less product.
+fn clmulh(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let t = carryless_multiply(
+        unsigned(cpu, cpu.x[o.rs1 as usize]),
+        unsigned(cpu, cpu.x[o.rs2 as usize]),
+    );
+    cpu.x[o.rd as usize] = match cpu.xlen {
+        Xlen::X32 => (t >> 32) as i32 as i64,
+        Xlen::X64 => (t >> 64) as i64,
+    };
+    Ok(())
+}
+
+/// look up each width-bit element of rs2 as an index into the elements of rs1.
+/// Out of range indices yield zero.
+fn crossbar_permute(cpu: &Cpu, rs1: u64, rs2: u64, width: u32) -> u64 {
+    let xlen = match cpu.xlen {
+        Xlen::X32 => 32,
+        Xlen::X64 => 64,
+    };
+    let mask = (1_u64 << width) - 1;
+    (0..xlen).step_by(width as usize).fold(0, |acc, i| {
+        let index = ((rs2 >> i) & mask) * width as u64;
+        match index < xlen {
+            true => acc | ((rs1 >> index) & mask) << i,
+            false => acc,
+        }
+    })
+}
+
+/// [xperm4 rd,rs1,rs2]
+/// XPERM4 performs a nibble-wise lookup of the indices in rs2 into the vector of
+/// nibbles in rs1.
+fn xperm4(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let rs1 = unsigned(cpu, cpu.x[o.rs1 as usize]);
+    let rs2 = unsigned(cpu, cpu.x[o.rs2 as usize]);
+    cpu.x[o.rd as usize] = signed(cpu, crossbar_permute(cpu, rs1, rs2, 4) as i64);
+    Ok(())
+}
+
+/// [xperm8 rd,rs1,rs2]
+/// XPERM8 performs a byte-wise lookup of the indices in rs2 into the vector of
+/// bytes in rs1.
+fn xperm8(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let rs1 = unsigned(cpu, cpu.x[o.rs1 as usize]);
+    let rs2 = unsigned(cpu, cpu.x[o.rs2 as usize]);
+    cpu.x[o.rd as usize] = signed(cpu, crossbar_permute(cpu, rs1, rs2, 8) as i64);
+    Ok(())
+}
+
+//==============================================================================
+// Bit-manipulation Instructions used by Zcb (Zba/Zbb subset)
+//==============================================================================
+
+/// [sext.b rd,rs]
+/// SEXT.B sign-extends the least-significant byte in rs to XLEN.
+fn sext_b(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    cpu.x[o.rd as usize] = cpu.x[o.rs1 as usize] as i8 as i64;
+    Ok(())
+}
+
+/// [sext.h rd,rs]
+/// SEXT.H sign-extends the least-significant halfword in rs to XLEN.
+fn sext_h(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    cpu.x[o.rd as usize] = cpu.x[o.rs1 as usize] as i16 as i64;
+    Ok(())
+}
+
+/// [add.uw rd,rs1,rs2]
+/// ADD.UW (RV64 only) adds the zero-extended lower 32 bits of rs1 to rs2.
+fn add_uw(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X64, addr)?;
+    let rs1 = cpu.x[o.rs1 as usize] as u32 as i64;
+    cpu.x[o.rd as usize] = rs1.wrapping_add(cpu.x[o.rs2 as usize]);
+    Ok(())
+}
+
+//==============================================================================
+// AES Instructions (Zkne/Zknd)
+//==============================================================================
+// On RV64 the 128-bit AES state is held in two registers, rs1 holding columns 0-1
+// and rs2 columns 2-3. Each instruction produces one half of the next round state;
+// software obtains the other half by swapping rs1 and rs2.
+// On RV32 each instruction processes the single byte of rs2 selected by bs.
+
+/// rd = sign-extend(rs1[31:0] ^ rotate-left(f(rs2.byte[bs]), 8 * bs))
+fn byte_select(cpu: &mut Cpu, word: u32, f: fn(u8) -> u32) {
+    let o = parse_type_r(word);
+    let shamt = (word >> 30) * 8;
+    let t = f((cpu.x[o.rs2 as usize] >> shamt) as u8);
+    cpu.x[o.rd as usize] = (cpu.x[o.rs1 as usize] as u32 ^ t.rotate_left(shamt)) as i32 as i64;
+}
+
+/// [aes32esi rd,rs1,rs2,bs]
+/// AES final round encryption instruction for RV32.
+fn aes32esi(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_xlen(cpu, Xlen::X32, addr)?;
+    byte_select(cpu, word, |b| aes_sbox_fwd(b) as u32);
+    Ok(())
+}
+
+/// [aes32esmi rd,rs1,rs2,bs]
+/// AES middle round encryption instruction for RV32.
+fn aes32esmi(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_xlen(cpu, Xlen::X32, addr)?;
+    byte_select(cpu, word, |b| aes_mixcolumn_byte_fwd(aes_sbox_fwd(b)));
+    Ok(())
+}
+
+/// [aes32dsi rd,rs1,rs2,bs]
+/// AES final round decryption instruction for RV32.
+fn aes32dsi(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_xlen(cpu, Xlen::X32, addr)?;
+    byte_select(cpu, word, |b| aes_sbox_inv(b) as u32);
+    Ok(())
+}
+
+/// [aes32dsmi rd,rs1,rs2,bs]
+/// AES middle round decryption instruction for RV32.
+fn aes32dsmi(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_xlen(cpu, Xlen::X32, addr)?;
+    byte_select(cpu, word, |b| aes_mixcolumn_byte_inv(aes_sbox_inv(b)));
+    Ok(())
+}
+
+/// [aes64es rd,rs1,rs2]
+/// AES final round encryption instruction for RV64: ShiftRows and SubBytes.
+fn aes64es(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X64, addr)?;
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize] as u64, cpu.x[o.rs2 as usize] as u64);
+    cpu.x[o.rd as usize] = aes_subbytes_fwd(aes_shiftrows_fwd(rs1, rs2)) as i64;
+    Ok(())
+}
+
+/// [aes64esm rd,rs1,rs2]
+/// AES middle round encryption instruction for RV64: ShiftRows, SubBytes and MixColumns.
+fn aes64esm(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X64, addr)?;
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize] as u64, cpu.x[o.rs2 as usize] as u64);
+    let t = aes_subbytes_fwd(aes_shiftrows_fwd(rs1, rs2));
+    cpu.x[o.rd as usize] = aes_mixcolumns_fwd(t) as i64;
+    Ok(())
+}
+
+/// [aes64ds rd,rs1,rs2]
+/// AES final round decryption instruction for RV64: InvShiftRows and InvSubBytes.
+fn aes64ds(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X64, addr)?;
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize] as u64, cpu.x[o.rs2 as usize] as u64);
+    cpu.x[o.rd as usize] = aes_subbytes_inv(aes_shiftrows_inv(rs1, rs2)) as i64;
+    Ok(())
+}
+
+/// [aes64dsm rd,rs1,rs2]
+/// AES middle round decryption instruction for RV64: InvShiftRows, InvSubBytes and
+/// InvMixColumns.
+fn aes64dsm(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X64, addr)?;
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize] as u64, cpu.x[o.rs2 as usize] as u64);
+    let t = aes_subbytes_inv(aes_shiftrows_inv(rs1, rs2));
+    cpu.x[o.rd as usize] = aes_mixcolumns_inv(t) as i64;
+    Ok(())
+}
+
+/// [aes64im rd,rs1]
+/// AES decryption key schedule helper: InvMixColumns applied to a round key.
+fn aes64im(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X64, addr)?;
+    cpu.x[o.rd as usize] = aes_mixcolumns_inv(cpu.x[o.rs1 as usize] as u64) as i64;
+    Ok(())
+}
+
+/// [aes64ks1i rd,rs1,rnum]
+/// AES key schedule instruction 1: rotates the upper word of rs1 (unless rnum=0xA),
+/// applies SubWord and adds the round constant. rnum values above 0xA are reserved.
+fn aes64ks1i(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let rnum = ((word >> 20) & 0xf) as u8;
+    check_xlen(cpu, Xlen::X64, addr)?;
+    if rnum > 0xa {
+        return Err(Trap {
+            exception: Exception::IllegalInstruction,
+            value: addr,
+        });
+    }
+    let t = (cpu.x[o.rs1 as usize] as u64 >> 32) as u32;
+    let t = match rnum {
+        0xa => t,
+        _ => t.rotate_right(8),
+    };
+    let t = (aes_subword_fwd(t) ^ aes_rcon(rnum)) as u64;
+    cpu.x[o.rd as usize] = (t << 32 | t) as i64;
+    Ok(())
+}
+
+/// [aes64ks2 rd,rs1,rs2]
+/// AES key schedule instruction 2.
+fn aes64ks2(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X64, addr)?;
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize] as u64, cpu.x[o.rs2 as usize] as u64);
+    let w0 = (rs1 >> 32) ^ (rs2 & 0xffffffff);
+    let w1 = w0 ^ (rs2 >> 32);
+    cpu.x[o.rd as usize] = (w1 << 32 | w0) as i64;
+    Ok(())
+}
+
+//==============================================================================
+// SHA2 Instructions (Zknh)
+//==============================================================================
+/// [sha256sig0 rd,rs1]
+/// The SHA-256 functions operate on the low 32 bits of rs1 and sign-extend the result.
+fn sha256sig0(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let x = cpu.x[o.rs1 as usize] as u32;
+    cpu.x[o.rd as usize] = (x.rotate_right(7) ^ x.rotate_right(18) ^ (x >> 3)) as i32 as i64;
+    Ok(())
+}
+
+/// [sha256sig1 rd,rs1]
+fn sha256sig1(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let x = cpu.x[o.rs1 as usize] as u32;
+    cpu.x[o.rd as usize] = (x.rotate_right(17) ^ x.rotate_right(19) ^ (x >> 10)) as i32 as i64;
+    Ok(())
+}
+
+/// [sha256sum0 rd,rs1]
+fn sha256sum0(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let x = cpu.x[o.rs1 as usize] as u32;
+    cpu.x[o.rd as usize] =
+        (x.rotate_right(2) ^ x.rotate_right(13) ^ x.rotate_right(22)) as i32 as i64;
+    Ok(())
+}
+
+/// [sha256sum1 rd,rs1]
+fn sha256sum1(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let x = cpu.x[o.rs1 as usize] as u32;
+    cpu.x[o.rd as usize] =
+        (x.rotate_right(6) ^ x.rotate_right(11) ^ x.rotate_right(25)) as i32 as i64;
+    Ok(())
+}
+
+/// [sha512sig0 rd,rs1]
+/// The SHA-512 functions with a single source operand are RV64 only.
+fn sha512sig0(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X64, addr)?;
+    let x = cpu.x[o.rs1 as usize] as u64;
+    cpu.x[o.rd as usize] = (x.rotate_right(1) ^ x.rotate_right(8) ^ (x >> 7)) as i64;
+    Ok(())
+}
+
+/// [sha512sig1 rd,rs1]
+fn sha512sig1(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X64, addr)?;
+    let x = cpu.x[o.rs1 as usize] as u64;
+    cpu.x[o.rd as usize] = (x.rotate_right(19) ^ x.rotate_right(61) ^ (x >> 6)) as i64;
+    Ok(())
+}
+
+/// [sha512sum0 rd,rs1]
+fn sha512sum0(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X64, addr)?;
+    let x = cpu.x[o.rs1 as usize] as u64;
+    cpu.x[o.rd as usize] = (x.rotate_right(28) ^ x.rotate_right(34) ^ x.rotate_right(39)) as i64;
+    Ok(())
+}
+
+/// [sha512sum1 rd,rs1]
+fn sha512sum1(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X64, addr)?;
+    let x = cpu.x[o.rs1 as usize] as u64;
+    cpu.x[o.rd as usize] = (x.rotate_right(14) ^ x.rotate_right(18) ^ x.rotate_right(41)) as i64;
+    Ok(())
+}
+
+// On RV32 the SHA-512 functions take the two halves of a 64-bit operand in rs1
+// and rs2, and return one half of the result.
+
+/// [sha512sig0l rd,rs1,rs2]
+fn sha512sig0l(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X32, addr)?;
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize] as u32, cpu.x[o.rs2 as usize] as u32);
+    let t = (rs1 >> 1) ^ (rs1 >> 7) ^ (rs1 >> 8) ^ (rs2 << 31) ^ (rs2 << 25) ^ (rs2 << 24);
+    cpu.x[o.rd as usize] = t as i32 as i64;
+    Ok(())
+}
+
+/// [sha512sig0h rd,rs1,rs2]
+fn sha512sig0h(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X32, addr)?;
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize] as u32, cpu.x[o.rs2 as usize] as u32);
+    let t = (rs1 >> 1) ^ (rs1 >> 7) ^ (rs1 >> 8) ^ (rs2 << 31) ^ (rs2 << 24);
+    cpu.x[o.rd as usize] = t as i32 as i64;
+    Ok(())
+}
+
+/// [sha512sig1l rd,rs1,rs2]
+fn sha512sig1l(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X32, addr)?;
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize] as u32, cpu.x[o.rs2 as usize] as u32);
+    let t = (rs1 << 3) ^ (rs1 >> 6) ^ (rs1 >> 19) ^ (rs2 >> 29) ^ (rs2 << 26) ^ (rs2 << 13);
+    cpu.x[o.rd as usize] = t as i32 as i64;
+    Ok(())
+}
+
+/// [sha512sig1h rd,rs1,rs2]
+fn sha512sig1h(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X32, addr)?;
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize] as u32, cpu.x[o.rs2 as usize] as u32);
+    let t = (rs1 << 3) ^ (rs1 >> 6) ^ (rs1 >> 19) ^ (rs2 >> 29) ^ (rs2 << 13);
+    cpu.x[o.rd as usize] = t as i32 as i64;
+    Ok(())
+}
+
+/// [sha512sum0r rd,rs1,rs2]
+fn sha512sum0r(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X32, addr)?;
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize] as u32, cpu.x[o.rs2 as usize] as u32);
+    let t = (rs1 << 25) ^ (rs1 << 30) ^ (rs1 >> 28) ^ (rs2 >> 7) ^ (rs2 >> 2) ^ (rs2 << 4);
+    cpu.x[o.rd as usize] = t as i32 as i64;
+    Ok(())
+}
+
+/// [sha512sum1r rd,rs1,rs2]
+fn sha512sum1r(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    check_xlen(cpu, Xlen::X32, addr)?;
+    let (rs1, rs2) = (cpu.x[o.rs1 as usize] as u32, cpu.x[o.rs2 as usize] as u32);
+    let t = (rs1 << 23) ^ (rs1 >> 14) ^ (rs1 >> 18) ^ (rs2 >> 9) ^ (rs2 << 18) ^ (rs2 << 14);
+    cpu.x[o.rd as usize] = t as i32 as i64;
+    Ok(())
+}
+
+//==============================================================================
+// ShangMi Instructions (Zksed/Zksh)
+//==============================================================================
+/// [sm4ed rd,rs1,rs2,bs]
+/// SM4 encrypt/decrypt round function: S-box substitution of the selected byte of
+/// rs2, followed by the linear transformation L, accumulated into rs1.
+fn sm4ed(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    byte_select(cpu, word, |b| sm4_linear_ed(sm4_sbox(b) as u32));
+    Ok(())
+}
+
+/// [sm4ks rd,rs1,rs2,bs]
+/// SM4 key schedule round function, using the linear transformation L'.
+fn sm4ks(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    byte_select(cpu, word, |b| sm4_linear_ks(sm4_sbox(b) as u32));
+    Ok(())
+}
+
+/// [sm3p0 rd,rs1]
+/// SM3 permutation function P0 used in the compression function.
+fn sm3p0(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let x = cpu.x[o.rs1 as usize] as u32;
+    cpu.x[o.rd as usize] = (x ^ x.rotate_left(9) ^ x.rotate_left(17)) as i32 as i64;
+    Ok(())
+}
+
+/// [sm3p1 rd,rs1]
+/// SM3 permutation function P1 used in the message expansion.
+fn sm3p1(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let o = parse_type_r(word);
+    let x = cpu.x[o.rs1 as usize] as u32;
+    cpu.x[o.rd as usize] = (x ^ x.rotate_left(15) ^ x.rotate_left(23)) as i32 as i64;
+    Ok(())
+}
+
+//==============================================================================
+// Floating-Point Instructions (RV32F/RV64F, RV32D/RV64D, Zfh)
+//==============================================================================
+// The f registers are FLEN=64 bits wide. Narrower values are NaN-boxed, i.e. the upper bits are
+// all ones, and an operand that is not a properly NaN-boxed value is read as the canonical NaN.
+// The arithmetic is computed by the soft-float unit in the rounding mode of the rm field or frm,
+// and the exception flags are accrued to fflags. All of the instructions are illegal while
+// mstatus.FS is Off, and the ones writing an f register or fflags set it to Dirty.
+
+/// Wildcard of funct3 and rs2 in the keys of `INSTRUCTIONS_GROUP53`.
+const ANY: u8 = 0xff;
+
+/// Returns the format in the fmt field (bits 26:25) of the instruction.
+fn fp_format(word: u32) -> Format {
+    match (word >> 25) & 0x3 {
+        0 => SINGLE,
+        1 => DOUBLE,
+        _ => HALF,
+    }
+}
+
+fn illegal_instruction(addr: u64) -> Trap {
+    Trap {
+        exception: Exception::IllegalInstruction,
+        value: addr,
+    }
+}
+
+fn check_fp_enabled(cpu: &Cpu, addr: u64) -> Result<(), Trap> {
+    match cpu.csr.is_fp_enabled() {
+        true => Ok(()),
+        false => Err(illegal_instruction(addr)),
+    }
+}
+
+/// Returns the rounding mode in the rm field, or in frm if it is dynamic (7). The
+/// reserved rounding modes are illegal.
+fn fp_rounding_mode(cpu: &Cpu, addr: u64, word: u32) -> Result<RoundingMode, Trap> {
+    let rm = match (word >> 12) & 0x7 {
+        7 => cpu.csr.read_direct(CSR_FRM),
+        rm => rm as u64,
+    };
+    RoundingMode::from_bits(rm).ok_or_else(|| illegal_instruction(addr))
+}
+
+/// Reads a value of the format from an f register.
+fn read_fp(cpu: &Cpu, register: u8, format: Format) -> u64 {
+    let bits = cpu.f[register as usize].to_bits();
+    if format.width() == 64 {
+        return bits;
+    }
+    let upper = !0u64 << format.width();
+    match bits & upper == upper {
+        true => bits & !upper,
+        false => format.canonical_nan(),
+    }
+}
+
+/// Writes a value of the format to an f register, NaN-boxing it.
+fn write_fp(cpu: &mut Cpu, register: u8, format: Format, bits: u64) {
+    let upper = match format.width() {
+        64 => 0,
+        width => !0u64 << width,
+    };
+    cpu.f[register as usize] = f64::from_bits(upper | bits);
+    cpu.csr.set_fp_dirty();
+}
+
+fn accrue_fp_flags(cpu: &mut Cpu, flags: u64) {
+    if flags != 0 {
+        cpu.csr.read_modify_write_direct(CSR_FFLAGS, flags, 0);
+    }
+}
+
+/// Computes an operation of two f registers, rounded in the rounding mode.
+fn fp_binary(
+    cpu: &mut Cpu,
+    addr: u64,
+    word: u32,
+    operation: impl Fn(&mut SoftFloat, Format, u64, u64) -> u64,
+) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let format = fp_format(word);
+    let mut fpu = SoftFloat::new(fp_rounding_mode(cpu, addr, word)?);
+    let a = read_fp(cpu, o.rs1, format);
+    let b = read_fp(cpu, o.rs2, format);
+    let result = operation(&mut fpu, format, a, b);
+    accrue_fp_flags(cpu, fpu.flags);
+    write_fp(cpu, o.rd, format, result);
+    Ok(())
+}
+
+/// Compares two f registers, and writes 1 to the x register if the comparison is true.
+fn fp_compare(
+    cpu: &mut Cpu,
+    addr: u64,
+    word: u32,
+    comparison: impl Fn(&mut SoftFloat, Format, u64, u64) -> bool,
+) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let format = fp_format(word);
+    let mut fpu = SoftFloat::new(RoundingMode::NearestEven);
+    let a = read_fp(cpu, o.rs1, format);
+    let b = read_fp(cpu, o.rs2, format);
+    let result = comparison(&mut fpu, format, a, b);
+    accrue_fp_flags(cpu, fpu.flags);
+    cpu.x[o.rd as usize] = result as i64;
+    Ok(())
+}
+
+/// Injects the sign bit computed from the signs of two f registers.
+fn fp_sign_injection(
+    cpu: &mut Cpu,
+    addr: u64,
+    word: u32,
+    sign: impl Fn(bool, bool) -> bool,
+) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let format = fp_format(word);
+    let a = read_fp(cpu, o.rs1, format);
+    let b = read_fp(cpu, o.rs2, format);
+    let sign_bit = 1 << (format.width() - 1);
+    let negative = sign(format.is_negative(a), format.is_negative(b));
+    write_fp(cpu, o.rd, format, (a & !sign_bit) | (negative as u64 * sign_bit));
+    Ok(())
+}
+
+/// Computes `±(rs1 * rs2) ± rs3` with a single rounding.
+fn fp_fused_multiply_add(
+    cpu: &mut Cpu,
+    addr: u64,
+    word: u32,
+    negate_product: bool,
+    negate_addend: bool,
+) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let rs3 = (word >> 27) as u8;
+    let format = fp_format(word);
+    let mut fpu = SoftFloat::new(fp_rounding_mode(cpu, addr, word)?);
+    let a = read_fp(cpu, o.rs1, format);
+    let b = read_fp(cpu, o.rs2, format);
+    let c = read_fp(cpu, rs3, format);
+    let result = fpu.fused_multiply_add(format, a, b, c, negate_product, negate_addend);
+    accrue_fp_flags(cpu, fpu.flags);
+    write_fp(cpu, o.rd, format, result);
+    Ok(())
+}
+
+/// [fadd.s rd,rs1,rs2], [fadd.d rd,rs1,rs2]
+fn fadd(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_binary(cpu, addr, word, |fpu, format, a, b| fpu.add(format, a, b))
+}
+
+/// [fsub.s rd,rs1,rs2], [fsub.d rd,rs1,rs2]
+fn fsub(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_binary(cpu, addr, word, |fpu, format, a, b| fpu.sub(format, a, b))
+}
+
+/// [fmul.s rd,rs1,rs2], [fmul.d rd,rs1,rs2]
+fn fmul(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_binary(cpu, addr, word, |fpu, format, a, b| fpu.mul(format, a, b))
+}
+
+/// [fdiv.s rd,rs1,rs2], [fdiv.d rd,rs1,rs2]
+fn fdiv(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_binary(cpu, addr, word, |fpu, format, a, b| fpu.div(format, a, b))
+}
+
+/// [fsqrt.s rd,rs1], [fsqrt.d rd,rs1]
+fn fsqrt(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_binary(cpu, addr, word, |fpu, format, a, _| fpu.sqrt(format, a))
+}
+
+/// [fmin.s rd,rs1,rs2], [fmin.d rd,rs1,rs2]
+/// If only one of the operands is a NaN, the result is the other operand.
+fn fmin(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_binary(cpu, addr, word, |fpu, format, a, b| {
+        fpu.min_max(format, a, b, false, false)
+    })
+}
+
+/// [fmax.s rd,rs1,rs2], [fmax.d rd,rs1,rs2]
+fn fmax(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_binary(cpu, addr, word, |fpu, format, a, b| {
+        fpu.min_max(format, a, b, true, false)
+    })
+}
+
+/// [fmadd.s rd,rs1,rs2,rs3], [fmadd.d rd,rs1,rs2,rs3]
+fn fmadd(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_fused_multiply_add(cpu, addr, word, false, false)
+}
+
+/// [fmsub.s rd,rs1,rs2,rs3], [fmsub.d rd,rs1,rs2,rs3]
+fn fmsub(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_fused_multiply_add(cpu, addr, word, false, true)
+}
+
+/// [fnmsub.s rd,rs1,rs2,rs3], [fnmsub.d rd,rs1,rs2,rs3]
+fn fnmsub(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_fused_multiply_add(cpu, addr, word, true, false)
+}
+
+/// [fnmadd.s rd,rs1,rs2,rs3], [fnmadd.d rd,rs1,rs2,rs3]
+fn fnmadd(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_fused_multiply_add(cpu, addr, word, true, true)
+}
+
+/// [fsgnj.s rd,rs1,rs2], [fsgnj.d rd,rs1,rs2]
+fn fsgnj(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_sign_injection(cpu, addr, word, |_, b| b)
+}
+
+/// [fsgnjn.s rd,rs1,rs2], [fsgnjn.d rd,rs1,rs2]
+fn fsgnjn(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_sign_injection(cpu, addr, word, |_, b| !b)
+}
+
+/// [fsgnjx.s rd,rs1,rs2], [fsgnjx.d rd,rs1,rs2]
+fn fsgnjx(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_sign_injection(cpu, addr, word, |a, b| a != b)
+}
+
+/// [feq.s rd,rs1,rs2], [feq.d rd,rs1,rs2]
+/// FEQ is a quiet comparison, which only signals the invalid operation for signaling NaNs.
+fn feq(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_compare(cpu, addr, word, |fpu, format, a, b| fpu.equal(format, a, b))
+}
+
+/// [flt.s rd,rs1,rs2], [flt.d rd,rs1,rs2]
+/// FLT and FLE are signaling comparisons, which signal the invalid operation for any NaN.
+fn flt(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_compare(cpu, addr, word, |fpu, format, a, b| {
+        fpu.less(format, a, b, false, true)
+    })
+}
+
+/// [fle.s rd,rs1,rs2], [fle.d rd,rs1,rs2]
+fn fle(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_compare(cpu, addr, word, |fpu, format, a, b| {
+        fpu.less(format, a, b, true, true)
+    })
+}
+
+/// [fclass.s rd,rs1], [fclass.d rd,rs1]
+/// FCLASS writes the 10-bit mask of the class of the value to integer register rd.
+fn fclass(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let format = fp_format(word);
+    cpu.x[o.rd as usize] = format.classify(read_fp(cpu, o.rs1, format)) as i64;
+    Ok(())
+}
+
+/// [fcvt.w.s rd,rs1], [fcvt.wu.s rd,rs1], [fcvt.l.s rd,rs1], [fcvt.lu.s rd,rs1] and the same
+/// conversions of double-precision values. rs2 selects the integer type. Out of range values
+/// and NaNs are saturated and signal the invalid operation, and the 32-bit results are
+/// sign-extended.
+fn fcvt_int_fp(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let format = fp_format(word);
+    let (width, signed) = match (o.rs2, &cpu.xlen) {
+        (0, _) => (32, true),
+        (1, _) => (32, false),
+        (2, Xlen::X64) => (64, true),
+        (3, Xlen::X64) => (64, false),
+        _ => return Err(illegal_instruction(addr)),
+    };
+    let mut fpu = SoftFloat::new(fp_rounding_mode(cpu, addr, word)?);
+    let value = fpu.to_integer(format, read_fp(cpu, o.rs1, format), width, signed);
+    accrue_fp_flags(cpu, fpu.flags);
+    cpu.x[o.rd as usize] = match width {
+        32 => value as i32 as i64,
+        _ => value as i64,
+    };
+    Ok(())
+}
+
+/// [fcvt.s.w rd,rs1], [fcvt.s.wu rd,rs1], [fcvt.s.l rd,rs1], [fcvt.s.lu rd,rs1] and the same
+/// conversions to double-precision values. rs2 selects the integer type.
+fn fcvt_fp_int(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let format = fp_format(word);
+    let x = cpu.x[o.rs1 as usize];
+    let (value, signed) = match (o.rs2, &cpu.xlen) {
+        (0, _) => (x as i32 as u64, true),
+        (1, _) => (x as u32 as u64, false),
+        (2, Xlen::X64) => (x as u64, true),
+        (3, Xlen::X64) => (x as u64, false),
+        _ => return Err(illegal_instruction(addr)),
+    };
+    let mut fpu = SoftFloat::new(fp_rounding_mode(cpu, addr, word)?);
+    let result = fpu.from_integer(format, value, signed);
+    accrue_fp_flags(cpu, fpu.flags);
+    write_fp(cpu, o.rd, format, result);
+    Ok(())
+}
+
+/// [fcvt.s.d rd,rs1], [fcvt.d.s rd,rs1]
+/// rs2 holds the format of the source, which is rounded to the format of the destination.
+fn fcvt_fp_fp(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let format = fp_format(word);
+    let source = fp_format((o.rs2 as u32) << 25);
+    let mut fpu = SoftFloat::new(fp_rounding_mode(cpu, addr, word)?);
+    let result = fpu.convert(source, format, read_fp(cpu, o.rs1, source));
+    accrue_fp_flags(cpu, fpu.flags);
+    write_fp(cpu, o.rd, format, result);
+    Ok(())
+}
+
+/// [fmv.x.w rd,rs1], [fmv.x.d rd,rs1], [fmv.x.h rd,rs1]
+/// FMV.X.W and FMV.X.H move the bits of the value in the low bits of floating-point register
+/// rs1 to integer register rd, which are not NaN-box checked, and fill the upper bits with
+/// copies of the sign bit. FMV.X.D is RV64 only.
+fn fmv_x_f(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let bits = cpu.f[o.rs1 as usize].to_bits();
+    cpu.x[o.rd as usize] = match (fp_format(word).width(), &cpu.xlen) {
+        (16, _) => bits as i16 as i64,
+        (32, _) => bits as i32 as i64,
+        (_, Xlen::X64) => bits as i64,
+        _ => return Err(illegal_instruction(addr)),
+    };
+    Ok(())
+}
+
+/// [fmv.w.x rd,rs1], [fmv.d.x rd,rs1], [fmv.h.x rd,rs1]
+/// FMV.W.X and FMV.H.X move the value encoded in the low bits of integer register rs1 to
+/// floating-point register rd, NaN-boxing it. The bits are not modified in the transfer, and
+/// in particular, the payloads of non-canonical NaNs are preserved. FMV.D.X is RV64 only.
+fn fmv_f_x(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let format = fp_format(word);
+    let x = cpu.x[o.rs1 as usize] as u64;
+    let bits = match (format.width(), &cpu.xlen) {
+        (64, Xlen::X32) => return Err(illegal_instruction(addr)),
+        (64, _) => x,
+        (width, _) => x & ((1 << width) - 1),
+    };
+    write_fp(cpu, o.rd, format, bits);
+    Ok(())
+}
+
+//==============================================================================
+// Additional Floating-Point Instructions (Zfa)
+//==============================================================================
+
+/// [fli.s rd,imm], [fli.d rd,imm], [fli.h rd,imm]
+/// FLI loads one of the 32 constants of the table, selected by the rs1 field, to
+/// floating-point register rd.
+fn fli(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let format = fp_format(word);
+    write_fp(cpu, o.rd, format, load_immediate(format, o.rs1));
+    Ok(())
+}
+
+/// [fminm.s rd,rs1,rs2], [fminm.d rd,rs1,rs2], [fminm.h rd,rs1,rs2]
+/// FMINM and FMAXM are FMIN and FMAX, except that the result is the canonical NaN if
+/// either operand is a NaN.
+fn fminm(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_binary(cpu, addr, word, |fpu, format, a, b| {
+        fpu.min_max(format, a, b, false, true)
+    })
+}
+
+/// [fmaxm.s rd,rs1,rs2], [fmaxm.d rd,rs1,rs2], [fmaxm.h rd,rs1,rs2]
+fn fmaxm(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_binary(cpu, addr, word, |fpu, format, a, b| {
+        fpu.min_max(format, a, b, true, true)
+    })
+}
+
+/// [fround.s rd,rs1], [fround.d rd,rs1], [fround.h rd,rs1]
+/// FROUND rounds the value to an integer in the rounding mode, and does not raise the
+/// inexact flag.
+fn fround(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_binary(cpu, addr, word, |fpu, format, a, _| {
+        fpu.round_to_integral(format, a, false)
+    })
+}
+
+/// [froundnx.s rd,rs1], [froundnx.d rd,rs1], [froundnx.h rd,rs1]
+/// FROUNDNX is FROUND, except that the inexact flag is raised if the result differs.
+fn froundnx(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_binary(cpu, addr, word, |fpu, format, a, _| {
+        fpu.round_to_integral(format, a, true)
+    })
+}
+
+/// [fcvtmod.w.d rd,rs1,rtz]
+/// FCVTMOD.W.D converts the double-precision value to an integer rounding towards zero,
+/// and writes the low 32 bits sign-extended, as the conversion of Java and JavaScript.
+/// The flags are raised as FCVT.W.D does.
+fn fcvtmod_w_d(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    let o = parse_type_r(word);
+    let mut fpu = SoftFloat::new(RoundingMode::TowardZero);
+    let value = fpu.to_int32_modular(read_fp(cpu, o.rs1, DOUBLE));
+    accrue_fp_flags(cpu, fpu.flags);
+    cpu.x[o.rd as usize] = value as i64;
+    Ok(())
+}
+
+/// [fleq.s rd,rs1,rs2], [fleq.d rd,rs1,rs2], [fleq.h rd,rs1,rs2]
+/// FLEQ and FLTQ are FLE and FLT, except that they are quiet comparisons.
+fn fleq(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_compare(cpu, addr, word, |fpu, format, a, b| {
+        fpu.less(format, a, b, true, false)
+    })
+}
+
+/// [fltq.s rd,rs1,rs2], [fltq.d rd,rs1,rs2], [fltq.h rd,rs1,rs2]
+fn fltq(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    fp_compare(cpu, addr, word, |fpu, format, a, b| {
+        fpu.less(format, a, b, false, false)
+    })
+}
+
+/// [fmvh.x.d rd,rs1]
+/// FMVH.X.D moves the upper 32 bits of the double-precision value in floating-point
+/// register rs1 to integer register rd. RV32 only.
+fn fmvh_x_d(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    if cpu.xlen != Xlen::X32 {
+        return Err(illegal_instruction(addr));
+    }
+    let o = parse_type_r(word);
+    cpu.x[o.rd as usize] = (cpu.f[o.rs1 as usize].to_bits() >> 32) as i32 as i64;
+    Ok(())
+}
+
+/// [fmvp.d.x rd,rs1,rs2]
+/// FMVP.D.X moves the double-precision value with the lower 32 bits in integer register rs1
+/// and the upper 32 bits in rs2 to floating-point register rd. RV32 only.
+fn fmvp_d_x(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    check_fp_enabled(cpu, addr)?;
+    if cpu.xlen != Xlen::X32 {
+        return Err(illegal_instruction(addr));
+    }
     let o = parse_type_r(word);
-    cpu.f[o.rd as usize] = f64::from_bits(cpu.x[o.rs1 as usize] as u32 as u64);
+    let low = cpu.x[o.rs1 as usize] as u32 as u64;
+    let high = cpu.x[o.rs2 as usize] as u32 as u64;
+    write_fp(cpu, o.rd, DOUBLE, (high << 32) | low);
     Ok(())
 }
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 
 use crate::cpu::cpu::{Cpu, Xlen};
+use crate::cpu::cpu_instruction::{signed, uses_rve_reserved_registers, Instruction, Opecode};
+use crate::cpu::trap::*;
 
 pub struct CompressedOpecode {
     pub operation: fn(cpu: &Cpu, word: u16) -> Result<&'static CompressedInstruction, ()>,
@@ -135,11 +137,41 @@ fn opecode_0(cpu: &Cpu, word: u16) -> Result<&'static CompressedInstruction, ()>
     match funct3 {
         3 | 7 => match COMPRESSED_INSTRUCTIONS_GROUP0_SUB.get(&(cpu.xlen.clone() as u8, funct3)) {
             Some(instruction) => Ok(&instruction),
-            None => panic!("Not found instruction!"),
+            None => Err(()),
+        },
+        4 => match (word >> 10) & 0x7 {
+            0 => Ok(&CompressedInstruction {
+                mnemonic: "c.lbu",
+                decompress: c_lbu,
+                disassemble: disassemble_mnemonic,
+            }),
+            1 => match (word >> 6) & 0x1 {
+                0 => Ok(&CompressedInstruction {
+                    mnemonic: "c.lhu",
+                    decompress: c_lhu,
+                    disassemble: disassemble_mnemonic,
+                }),
+                _ => Ok(&CompressedInstruction {
+                    mnemonic: "c.lh",
+                    decompress: c_lh,
+                    disassemble: disassemble_mnemonic,
+                }),
+            },
+            2 => Ok(&CompressedInstruction {
+                mnemonic: "c.sb",
+                decompress: c_sb,
+                disassemble: disassemble_mnemonic,
+            }),
+            3 => Ok(&CompressedInstruction {
+                mnemonic: "c.sh",
+                decompress: c_sh,
+                disassemble: disassemble_mnemonic,
+            }),
+            _ => Err(()),
         },
         _ => match COMPRESSED_INSTRUCTIONS_GROUP0.get(&funct3) {
             Some(instruction) => Ok(&instruction),
-            None => panic!("Not found instruction!"),
+            None => Err(()),
         },
     }
 }
@@ -200,6 +232,8 @@ fn opecode_1(cpu: &Cpu, word: u16) -> Result<&'static CompressedInstruction, ()>
                 disassemble: disassemble_mnemonic,
             }),
             _ => match (word >> 5) & 0x3 {
+                // c.subw and c.addw are RV64C only.
+                0 | 1 if (word >> 12) & 0x1 == 1 && cpu.xlen == Xlen::X32 => Err(()),
                 0 => match (word >> 12) & 0x1 {
                     0 => Ok(&CompressedInstruction {
                         mnemonic: "c.sub",
@@ -224,25 +258,87 @@ fn opecode_1(cpu: &Cpu, word: u16) -> Result<&'static CompressedInstruction, ()>
                         disassemble: disassemble_mnemonic,
                     }),
                 },
-                2 => Ok(&CompressedInstruction {
-                    mnemonic: "c.or",
-                    decompress: c_or,
-                    disassemble: disassemble_mnemonic,
-                }),
-                _ => Ok(&CompressedInstruction {
-                    mnemonic: "c.and",
-                    decompress: c_and,
-                    disassemble: disassemble_mnemonic,
-                }),
+                2 => match (word >> 12) & 0x1 {
+                    0 => Ok(&CompressedInstruction {
+                        mnemonic: "c.or",
+                        decompress: c_or,
+                        disassemble: disassemble_mnemonic,
+                    }),
+                    _ => Ok(&CompressedInstruction {
+                        mnemonic: "c.mul",
+                        decompress: c_mul,
+                        disassemble: disassemble_mnemonic,
+                    }),
+                },
+                _ => match (word >> 12) & 0x1 {
+                    0 => Ok(&CompressedInstruction {
+                        mnemonic: "c.and",
+                        decompress: c_and,
+                        disassemble: disassemble_mnemonic,
+                    }),
+                    _ => opecode_1_unary(cpu, word),
+                },
             },
         },
         _ => match COMPRESSED_INSTRUCTIONS_GROUP1.get(&funct3) {
             Some(instruction) => Ok(&instruction),
-            None => panic!("Not found instruction!"),
+            None => Err(()),
         },
     }
 }
 
+/// Zcb instructions with a single register operand (rd'/rs1').
+fn opecode_1_unary(cpu: &Cpu, word: u16) -> Result<&'static CompressedInstruction, ()> {
+    match (word >> 2) & 0x7 {
+        0 => Ok(&CompressedInstruction {
+            mnemonic: "c.zext.b",
+            decompress: c_zext_b,
+            disassemble: disassemble_mnemonic,
+        }),
+        1 => Ok(&CompressedInstruction {
+            mnemonic: "c.sext.b",
+            decompress: c_sext_b,
+            disassemble: disassemble_mnemonic,
+        }),
+        2 => match cpu.xlen {
+            Xlen::X32 => Ok(&CompressedInstruction {
+                mnemonic: "c.zext.h",
+                decompress: c_zext_h,
+                disassemble: disassemble_mnemonic,
+            }),
+            _ => Ok(&CompressedInstruction {
+                mnemonic: "c.zext.h",
+                decompress: c_zext_hw,
+                disassemble: disassemble_mnemonic,
+            }),
+        },
+        3 => Ok(&CompressedInstruction {
+            mnemonic: "c.sext.h",
+            decompress: c_sext_h,
+            disassemble: disassemble_mnemonic,
+        }),
+        4 => match cpu.xlen {
+            Xlen::X32 => Ok(&CompressedInstruction {
+                // RV64 only.
+                mnemonic: "c.zext.w",
+                decompress: c_reserved,
+                disassemble: disassemble_mnemonic,
+            }),
+            _ => Ok(&CompressedInstruction {
+                mnemonic: "c.zext.w",
+                decompress: c_zext_w,
+                disassemble: disassemble_mnemonic,
+            }),
+        },
+        5 => Ok(&CompressedInstruction {
+            mnemonic: "c.not",
+            decompress: c_not,
+            disassemble: disassemble_mnemonic,
+        }),
+        _ => Err(()),
+    }
+}
+
 fn opecode_2(cpu: &Cpu, word: u16) -> Result<&'static CompressedInstruction, ()> {
     let funct3 = ((word >> 13) & 0x7) as u8;
     match funct3 {
@@ -293,6 +389,43 @@ fn opecode_2(cpu: &Cpu, word: u16) -> Result<&'static CompressedInstruction, ()>
                 },
             },
         },
+        // Zcmp reuses the encodings of c.fsdsp (Zcd), which is not supported.
+        5 => match ((word >> 8) & 0x1f, (word >> 10) & 0x7, (word >> 5) & 0x3) {
+            (0x18, _, _) => Ok(&CompressedInstruction {
+                mnemonic: "cm.push",
+                decompress: c_multi_op,
+                disassemble: disassemble_mnemonic,
+            }),
+            (0x1a, _, _) => Ok(&CompressedInstruction {
+                mnemonic: "cm.pop",
+                decompress: c_multi_op,
+                disassemble: disassemble_mnemonic,
+            }),
+            (0x1c, _, _) => Ok(&CompressedInstruction {
+                mnemonic: "cm.popretz",
+                decompress: c_multi_op,
+                disassemble: disassemble_mnemonic,
+            }),
+            (0x1e, _, _) => Ok(&CompressedInstruction {
+                mnemonic: "cm.popret",
+                decompress: c_multi_op,
+                disassemble: disassemble_mnemonic,
+            }),
+            (_, 3, 1) => Ok(&CompressedInstruction {
+                mnemonic: "cm.mvsa01",
+                decompress: c_multi_op,
+                disassemble: disassemble_mnemonic,
+            }),
+            (_, 3, 3) => Ok(&CompressedInstruction {
+                mnemonic: "cm.mva01s",
+                decompress: c_multi_op,
+                disassemble: disassemble_mnemonic,
+            }),
+            _ => match COMPRESSED_INSTRUCTIONS_GROUP2.get(&funct3) {
+                Some(instruction) => Ok(instruction),
+                None => Err(()),
+            },
+        },
         7 => match cpu.xlen {
             Xlen::X32 => Ok(&CompressedInstruction {
                 // RV32FC only.
@@ -309,12 +442,12 @@ fn opecode_2(cpu: &Cpu, word: u16) -> Result<&'static CompressedInstruction, ()>
         },
         _ => match COMPRESSED_INSTRUCTIONS_GROUP2.get(&funct3) {
             Some(instruction) => Ok(&instruction),
-            None => panic!("Not found instruction!"),
+            None => Err(()),
         },
     }
 }
 
-pub fn instruction_decompress(cpu: &Cpu, instruction_addr: u64, word: u32) -> Result<u32, ()> {
+pub fn instruction_decompress(cpu: &Cpu, _instruction_addr: u64, word: u32) -> Result<u32, ()> {
     let compressed_word = (word & 0xffff) as u16;
 
     let opecodes = match COMPRESSED_OPECODES.get(&((word & 0x3) as u8)) {
@@ -322,9 +455,17 @@ pub fn instruction_decompress(cpu: &Cpu, instruction_addr: u64, word: u32) -> Re
         None => panic!("Not found opecode: {:016x}", word),
     };
 
-    match (opecodes.operation)(cpu, compressed_word) {
-        Ok(instruction) => (instruction.decompress)(compressed_word),
-        Err(()) => panic!("Not found instruction: {:016x}", instruction_addr),
+    let instruction = (opecodes.operation)(cpu, compressed_word)?;
+    let decompressed_word = (instruction.decompress)(compressed_word)?;
+
+    // RV32E/RV64E do not have the registers x16-x31.
+    let reserved = match decompressed_word & 0x3 {
+        0x3 => uses_rve_reserved_registers(decompressed_word),
+        _ => multi_op_uses_rve_reserved_registers(compressed_word),
+    };
+    match cpu.rve && reserved {
+        true => Err(()),
+        false => Ok(decompressed_word),
     }
 }
 
@@ -402,11 +543,11 @@ fn c_flw(word: u16) -> Result<u32, ()> {
     let uimm = (((word >> 7) & 0x38) | ((word >> 4) & 0x4) | ((word << 1) & 0x40)) as u32;
 
     // flw rd,uimm(rs1)
-    let op = 0x3 as u32;
+    let op = 0x7 as u32;
     let rd = (rd_ + 8) << 7;
     let rs1 = (rs1_ + 8) << 15;
     let offset = uimm << 20;
-    Ok(offset | rs1 | 3 << 12 | rd | op)
+    Ok(offset | rs1 | 2 << 12 | rd | op)
 }
 
 /// [c.sd rd’,uimm(rs1’)]
@@ -461,12 +602,82 @@ fn c_fsw(word: u16) -> Result<u32, ()> {
     let uimm = (((word >> 7) & 0x38) | ((word >> 4) & 0x4) | ((word << 1) & 0x40)) as u32;
 
     // fsw rd2,uimm(rs1)
-    let op = 0x23 as u32;
+    let op = 0x27 as u32;
     let rs1 = (rs1_ + 8) << 15;
     let rs2 = (rs2_ + 8) << 20;
     let offset_h = ((uimm >> 5) & 0x7f) << 25;
     let offset_l = (uimm & 0x1f) << 7;
-    Ok(offset_h | rs2 | rs1 | 3 << 12 | offset_l | op)
+    Ok(offset_h | rs2 | rs1 | 2 << 12 | offset_l | op)
+}
+
+/// [c.lbu rd’,uimm(rs1’)]
+fn c_lbu(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 2) & 0x7) as u32;
+    let rs1_ = ((word >> 7) & 0x7) as u32;
+    let uimm = (((word >> 6) & 0x1) | ((word >> 4) & 0x2)) as u32;
+
+    // lbu rd,offset(rs1)
+    let op = 0x3;
+    let rd = (rd_ + 8) << 7;
+    let rs1 = (rs1_ + 8) << 15;
+    let offset = uimm << 20;
+    Ok(offset | rs1 | 4 << 12 | rd | op)
+}
+
+/// [c.lhu rd’,uimm(rs1’)]
+fn c_lhu(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 2) & 0x7) as u32;
+    let rs1_ = ((word >> 7) & 0x7) as u32;
+    let uimm = ((word >> 4) & 0x2) as u32;
+
+    // lhu rd,offset(rs1)
+    let op = 0x3;
+    let rd = (rd_ + 8) << 7;
+    let rs1 = (rs1_ + 8) << 15;
+    let offset = uimm << 20;
+    Ok(offset | rs1 | 5 << 12 | rd | op)
+}
+
+/// [c.lh rd’,uimm(rs1’)]
+fn c_lh(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 2) & 0x7) as u32;
+    let rs1_ = ((word >> 7) & 0x7) as u32;
+    let uimm = ((word >> 4) & 0x2) as u32;
+
+    // lh rd,offset(rs1)
+    let op = 0x3;
+    let rd = (rd_ + 8) << 7;
+    let rs1 = (rs1_ + 8) << 15;
+    let offset = uimm << 20;
+    Ok(offset | rs1 | 1 << 12 | rd | op)
+}
+
+/// [c.sb rs2’,uimm(rs1’)]
+fn c_sb(word: u16) -> Result<u32, ()> {
+    let rs2_ = ((word >> 2) & 0x7) as u32;
+    let rs1_ = ((word >> 7) & 0x7) as u32;
+    let uimm = (((word >> 6) & 0x1) | ((word >> 4) & 0x2)) as u32;
+
+    // sb rs2,offset(rs1)
+    let op = 0x23;
+    let rs2 = (rs2_ + 8) << 20;
+    let rs1 = (rs1_ + 8) << 15;
+    let offset_l = uimm << 7;
+    Ok(rs2 | rs1 | offset_l | op)
+}
+
+/// [c.sh rs2’,uimm(rs1’)]
+fn c_sh(word: u16) -> Result<u32, ()> {
+    let rs2_ = ((word >> 2) & 0x7) as u32;
+    let rs1_ = ((word >> 7) & 0x7) as u32;
+    let uimm = ((word >> 4) & 0x2) as u32;
+
+    // sh rs2,offset(rs1)
+    let op = 0x23;
+    let rs2 = (rs2_ + 8) << 20;
+    let rs1 = (rs1_ + 8) << 15;
+    let offset_l = uimm << 7;
+    Ok(rs2 | rs1 | 1 << 12 | offset_l | op)
 }
 
 /// [c.nop]
@@ -720,6 +931,101 @@ fn c_addw(word: u16) -> Result<u32, ()> {
     Ok(rs2 | rs1 | rd | op)
 }
 
+/// [c.mul rd’,rs2’]
+fn c_mul(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 7) & 0x7) as u32;
+    let rs2_ = ((word >> 2) & 0x7) as u32;
+
+    // mul rd,rs1,rs2
+    let op = 0x33;
+    let rd = (rd_ + 8) << 7;
+    let rs1 = (rd_ + 8) << 15;
+    let rs2 = (rs2_ + 8) << 20;
+    Ok(1 << 25 | rs2 | rs1 | rd | op)
+}
+
+/// [c.zext.b rd’]
+fn c_zext_b(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 7) & 0x7) as u32;
+
+    // andi rd,rs1,0xff
+    let op = 0x13;
+    let rd = (rd_ + 8) << 7;
+    let rs1 = (rd_ + 8) << 15;
+    Ok(0xff << 20 | rs1 | 7 << 12 | rd | op)
+}
+
+/// [c.sext.b rd’]
+fn c_sext_b(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 7) & 0x7) as u32;
+
+    // sext.b rd,rs1
+    let op = 0x13;
+    let rd = (rd_ + 8) << 7;
+    let rs1 = (rd_ + 8) << 15;
+    Ok(0x604 << 20 | rs1 | 1 << 12 | rd | op)
+}
+
+/// [c.zext.h rd’] (RV32)
+fn c_zext_h(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 7) & 0x7) as u32;
+
+    // pack rd,rs1,x0
+    let op = 0x33;
+    let rd = (rd_ + 8) << 7;
+    let rs1 = (rd_ + 8) << 15;
+    Ok(0x04 << 25 | rs1 | 4 << 12 | rd | op)
+}
+
+/// [c.zext.h rd’] (RV64)
+fn c_zext_hw(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 7) & 0x7) as u32;
+
+    // packw rd,rs1,x0
+    let op = 0x3b;
+    let rd = (rd_ + 8) << 7;
+    let rs1 = (rd_ + 8) << 15;
+    Ok(0x04 << 25 | rs1 | 4 << 12 | rd | op)
+}
+
+/// [c.sext.h rd’]
+fn c_sext_h(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 7) & 0x7) as u32;
+
+    // sext.h rd,rs1
+    let op = 0x13;
+    let rd = (rd_ + 8) << 7;
+    let rs1 = (rd_ + 8) << 15;
+    Ok(0x605 << 20 | rs1 | 1 << 12 | rd | op)
+}
+
+/// [c.zext.w rd’]
+fn c_zext_w(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 7) & 0x7) as u32;
+
+    // add.uw rd,rs1,x0
+    let op = 0x3b;
+    let rd = (rd_ + 8) << 7;
+    let rs1 = (rd_ + 8) << 15;
+    Ok(0x04 << 25 | rs1 | rd | op)
+}
+
+/// [c.not rd’]
+fn c_not(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 7) & 0x7) as u32;
+
+    // xori rd,rs1,-1
+    let op = 0x13;
+    let rd = (rd_ + 8) << 7;
+    let rs1 = (rd_ + 8) << 15;
+    Ok(0xfff << 20 | rs1 | 4 << 12 | rd | op)
+}
+
+/// reserved encodings raise an illegal instruction exception.
+fn c_reserved(_word: u16) -> Result<u32, ()> {
+    Err(())
+}
+
 /// [c.j offset]
 fn c_j(word: u16) -> Result<u32, ()> {
     let offset = match word & 0x1000 {
@@ -801,8 +1107,16 @@ fn c_slli(word: u16) -> Result<u32, ()> {
 }
 
 /// [c.fldsp rd,uimm(x2)]
-fn c_fldsp(_word: u16) -> Result<u32, ()> {
-    panic!("TODO");
+fn c_fldsp(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 7) & 0x1f) as u32;
+    let uimm = (((word >> 7) & 0x20) | ((word >> 2) & 0x18) | ((word << 4) & 0x1c0)) as u32;
+
+    // fld rd,offset(rs1)
+    let op = 0x7;
+    let rd = rd_ << 7;
+    let rs1 = 2/* x2 */ << 15;
+    let offset = uimm << 20;
+    Ok(offset | rs1 | 3 << 12 | rd | op)
 }
 
 /// [c.lwsp rd,uimm(x2)]
@@ -826,8 +1140,16 @@ fn c_lwsp(word: u16) -> Result<u32, ()> {
 }
 
 /// [c.flwsp rd,uimm(x2)]
-fn c_flwsp(_word: u16) -> Result<u32, ()> {
-    panic!("TODO");
+fn c_flwsp(word: u16) -> Result<u32, ()> {
+    let rd_ = ((word >> 7) & 0x1f) as u32;
+    let uimm = (((word >> 7) & 0x20) | ((word >> 2) & 0x1c) | ((word << 4) & 0xc0)) as u32;
+
+    // flw rd,offset(rs1)
+    let op = 0x7;
+    let rd = rd_ << 7;
+    let rs1 = 2/* x2 */ << 15;
+    let offset = uimm << 20;
+    Ok(offset | rs1 | 2 << 12 | rd | op)
 }
 
 /// [c.ldsp rd,uimm(x2)]
@@ -943,8 +1265,17 @@ fn c_swsp(word: u16) -> Result<u32, ()> {
 }
 
 /// [c.fswsp rs2,uimm(rs2)]
-fn c_fswsp(_word: u16) -> Result<u32, ()> {
-    panic!("TODO");
+fn c_fswsp(word: u16) -> Result<u32, ()> {
+    let rs2_ = ((word >> 2) & 0x1f) as u32;
+    let uimm = (((word >> 7) & 0x3c) | ((word >> 1) & 0xc0)) as u32;
+
+    // fsw rs2,offset(rs1)
+    let op = 0x27;
+    let rs2 = rs2_ << 20;
+    let rs1 = 2/* x2 */ << 15;
+    let offset_h = ((uimm >> 5) & 0x7f) << 25;
+    let offset_l = (uimm & 0x1f) << 7;
+    Ok(offset_h | rs2 | rs1 | 2 << 12 | offset_l | op)
 }
 
 /// [c.sdsp rs2,uimm(x2)]
@@ -960,3 +1291,199 @@ fn c_sdsp(word: u16) -> Result<u32, ()> {
     let offset_l = (uimm & 0x1f) << 7;
     Ok(offset_h | rs2 | rs1 | 3 << 12 | offset_l | op)
 }
+
+//==============================================================================
+// Push/Pop and Register Move Instructions (Zcmp)
+//==============================================================================
+// These instructions perform several operations and have no 32-bit equivalent.
+// instruction_decompress passes them through as 16-bit words, and the CPU
+// dispatches such words to COMPRESSED_MULTI_OP instead of OPECODES.
+
+pub static COMPRESSED_MULTI_OP: Opecode = Opecode {
+    operation: opecode_multi_op,
+};
+
+/// [cm.push, cm.pop, cm.popretz, cm.popret, cm.mvsa01, cm.mva01s]
+fn c_multi_op(word: u16) -> Result<u32, ()> {
+    Ok(word as u32)
+}
+
+/// Only {ra, s0-s1} can be pushed/popped and only s0-s1 can be moved on RV32E/RV64E.
+fn multi_op_uses_rve_reserved_registers(word: u16) -> bool {
+    match (word >> 8) & 0x1f {
+        0x18 | 0x1a | 0x1c | 0x1e => (word >> 4) & 0xf > 6,
+        _ => (word >> 7) & 0x7 > 1 || (word >> 2) & 0x7 > 1,
+    }
+}
+
+fn opecode_multi_op(_cpu: &Cpu, _addr: u64, word: u32) -> Result<&Instruction, ()> {
+    match ((word >> 8) & 0x1f, (word >> 10) & 0x7, (word >> 5) & 0x3) {
+        (0x18, _, _) => Ok(&Instruction {
+            mnemonic: "cm.push",
+            operation: cm_push,
+            disassemble: disassemble_multi_op,
+        }),
+        (0x1a, _, _) => Ok(&Instruction {
+            mnemonic: "cm.pop",
+            operation: cm_pop,
+            disassemble: disassemble_multi_op,
+        }),
+        (0x1c, _, _) => Ok(&Instruction {
+            mnemonic: "cm.popretz",
+            operation: cm_popretz,
+            disassemble: disassemble_multi_op,
+        }),
+        (0x1e, _, _) => Ok(&Instruction {
+            mnemonic: "cm.popret",
+            operation: cm_popret,
+            disassemble: disassemble_multi_op,
+        }),
+        (_, 3, 1) => Ok(&Instruction {
+            mnemonic: "cm.mvsa01",
+            operation: cm_mvsa01,
+            disassemble: disassemble_multi_op,
+        }),
+        (_, 3, 3) => Ok(&Instruction {
+            mnemonic: "cm.mva01s",
+            operation: cm_mva01s,
+            disassemble: disassemble_multi_op,
+        }),
+        _ => Err(()),
+    }
+}
+
+fn disassemble_multi_op(_cpu: &Cpu, mnemonic: &str, word: u32) -> String {
+    let mut s = String::new();
+    s += &format!("{0: <10} ", mnemonic);
+    s += &format!("{:04x}", word);
+    s
+}
+
+/// map the 3-bit sreg field of cm.mvsa01/cm.mva01s to s0-s7.
+fn zcmp_sreg(sreg: u32) -> usize {
+    match sreg {
+        0 => 8,
+        1 => 9,
+        _ => 16 + sreg as usize,
+    }
+}
+
+/// decode rlist and spimm of cm.push/cm.pop into the saved registers
+/// {ra, s0-sN} and the total stack adjustment.
+fn zcmp_register_list(cpu: &Cpu, addr: u64, word: u32) -> Result<(Vec<usize>, u64), Trap> {
+    let rlist = (word >> 4) & 0xf;
+    let spimm = ((word >> 2) & 0x3) as u64;
+    let saved_sregs = match rlist {
+        // reserved.
+        0..=3 => {
+            return Err(Trap {
+                exception: Exception::IllegalInstruction,
+                value: addr,
+            })
+        }
+        // {ra, s0-s11}, as {ra, s0-s10} is not encodable.
+        15 => 12,
+        _ => rlist - 4,
+    };
+    let mut registers = vec![1];
+    registers.extend((0..saved_sregs).map(zcmp_sreg));
+    let bytes = match cpu.xlen {
+        Xlen::X32 => 4,
+        Xlen::X64 => 8,
+    };
+    let stack_adj_base = (registers.len() as u64 * bytes + 15) & !15;
+    Ok((registers, stack_adj_base + spimm * 16))
+}
+
+/// [cm.push {reg_list},-stack_adj]
+/// CM.PUSH stores the registers in reg_list to the memory below the stack pointer,
+/// and then allocates the stack frame by decrementing sp by stack_adj.
+fn cm_push(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let (registers, stack_adj) = zcmp_register_list(cpu, addr, word)?;
+    let sp = cpu.x[2] as u64;
+    let mut mem_addr = sp;
+    for r in registers.iter().rev() {
+        match cpu.xlen {
+            Xlen::X32 => {
+                mem_addr = mem_addr.wrapping_sub(4);
+                cpu.mmu.write32(mem_addr, cpu.x[*r] as u32)?;
+            }
+            Xlen::X64 => {
+                mem_addr = mem_addr.wrapping_sub(8);
+                cpu.mmu.write64(mem_addr, cpu.x[*r] as u64)?;
+            }
+        }
+    }
+    cpu.x[2] = signed(cpu, sp.wrapping_sub(stack_adj) as i64);
+    Ok(())
+}
+
+/// load the registers in reg_list from the top of the stack frame and deallocate it.
+fn zcmp_pop(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let (registers, stack_adj) = zcmp_register_list(cpu, addr, word)?;
+    let sp = (cpu.x[2] as u64).wrapping_add(stack_adj);
+    let mut mem_addr = sp;
+    for r in registers.iter().rev() {
+        match cpu.xlen {
+            Xlen::X32 => {
+                mem_addr = mem_addr.wrapping_sub(4);
+                cpu.x[*r] = cpu.mmu.read32(mem_addr)? as i32 as i64;
+            }
+            Xlen::X64 => {
+                mem_addr = mem_addr.wrapping_sub(8);
+                cpu.x[*r] = cpu.mmu.read64(mem_addr)? as i64;
+            }
+        }
+    }
+    cpu.x[2] = signed(cpu, sp as i64);
+    Ok(())
+}
+
+/// [cm.pop {reg_list},stack_adj]
+fn cm_pop(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    zcmp_pop(cpu, addr, word)
+}
+
+/// [cm.popretz {reg_list},stack_adj]
+/// CM.POPRETZ is CM.POP followed by moving zero to a0 and returning to ra.
+fn cm_popretz(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    zcmp_pop(cpu, addr, word)?;
+    cpu.x[10] = 0;
+    cpu.pc = cpu.x[1] as u64;
+    Ok(())
+}
+
+/// [cm.popret {reg_list},stack_adj]
+/// CM.POPRET is CM.POP followed by returning to ra.
+fn cm_popret(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    zcmp_pop(cpu, addr, word)?;
+    cpu.pc = cpu.x[1] as u64;
+    Ok(())
+}
+
+/// [cm.mvsa01 r1s’,r2s’]
+/// CM.MVSA01 moves a0 to r1s' and a1 to r2s'. r1s' and r2s' must be different.
+fn cm_mvsa01(cpu: &mut Cpu, addr: u64, word: u32) -> Result<(), Trap> {
+    let r1s = zcmp_sreg((word >> 7) & 0x7);
+    let r2s = zcmp_sreg((word >> 2) & 0x7);
+    if r1s == r2s {
+        return Err(Trap {
+            exception: Exception::IllegalInstruction,
+            value: addr,
+        });
+    }
+    cpu.x[r1s] = cpu.x[10];
+    cpu.x[r2s] = cpu.x[11];
+    Ok(())
+}
+
+/// [cm.mva01s r1s’,r2s’]
+/// CM.MVA01S moves r1s' to a0 and r2s' to a1.
+fn cm_mva01s(cpu: &mut Cpu, _addr: u64, word: u32) -> Result<(), Trap> {
+    let r1s = zcmp_sreg((word >> 7) & 0x7);
+    let r2s = zcmp_sreg((word >> 2) & 0x7);
+    let (a0, a1) = (cpu.x[r1s], cpu.x[r2s]);
+    cpu.x[10] = a0;
+    cpu.x[11] = a1;
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// Entropy source backing the Zkr `seed` CSR.
+/// A splitmix64 generator is used so that a fixed seed yields a reproducible
+/// sequence; the default seed is taken from the host's hash randomness.
+pub struct Entropy {
+    state: u64,
+}
+
+impl Entropy {
+    pub fn new() -> Self {
+        let seed = RandomState::new().build_hasher().finish();
+        Entropy::from_seed(seed)
+    }
+
+    pub fn from_seed(seed: u64) -> Self {
+        Entropy { state: seed }
+    }
+
+    /// Returns the seed which continues the sequence from here.
+    pub fn get_seed(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u16(&mut self) -> u16 {
+        (self.next_u64() >> 48) as u16
+    }
+}
+
+impl Default for Entropy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for Entropy {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_u64(self.state);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.state = reader.read_u64()?;
+        Ok(())
+    }
+}
@@ -4,12 +4,19 @@ use crate::bus::bus_fu540::BusFu540;
 use crate::bus::bus_qemu_virt::BusQemuVirt;
 use crate::console::Console;
 use crate::cpu::cpu::{Privilege, Xlen};
+use crate::cpu::stats::Stats;
 use crate::cpu::trap::*;
+use crate::cpu::trigger::{TriggerAccess, Triggers};
 use crate::machine::Machine;
-use std::collections::HashMap;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
+use crate::trace::{TraceCategory, TraceEvent, Tracer};
+use std::collections::{HashMap, VecDeque};
 
 const PAGE_SIZE: u64 = 4096;
 
+/// Default size of the cache block operated by Zicbom/Zicboz/Zicbop instructions.
+const CACHE_BLOCK_SIZE: u64 = 64;
+
 #[derive(Debug)]
 pub enum AddressingMode {
     Bare,
@@ -27,10 +34,28 @@ pub struct Mmu {
     addressing_mode: AddressingMode,
     privilege: Privilege,
     reserved_address: HashMap<u64, bool>,
+    cache_block_size: u64,
+    /// menvcfg.PBMTE. The PBMT field of PTEs is reserved when it is disabled.
+    pbmte: bool,
+    pub triggers: Triggers,
+    pub tracer: Tracer,
+    /// execution statistics, which the hart also counts in.
+    pub stats: Stats,
+    watchpoints: Vec<Watchpoint>,
+    /// address and direction (true for a store) of the first watched access.
+    watchpoint_hit: Option<(u64, bool)>,
+    /// accesses recorded for lockstep co-simulation.
+    access_log: Option<AccessLog>,
+    /// values returned to the device reads instead of the devices' own, when
+    /// the hart follows another model in lockstep.
+    mmio_replay: Option<VecDeque<u64>>,
 }
 
 struct Pte {
-    ppn: u64, // physical page number
+    reserved: u8, // reserved for future standard use (bits 60:54)
+    pbmt: u8,     // page-based memory type (Svpbmt)
+    n: u8,        // NAPOT translation contiguity (Svnapot)
+    ppn: u64,     // physical page number
     ppns: [u64; 3],
     _rsw: u8, // reserved for use by supervisor software
     d: u8,    // dirty
@@ -43,7 +68,33 @@ struct Pte {
     v: u8,    // PTE is valid
 }
 
-enum MemoryAccessType {
+/// Range of virtual addresses whose loads and/or stores are reported to the
+/// run loop, e.g. by `Emulator::run_until`.
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    pub address: u64,
+    pub size: u64,
+    pub read: bool,
+    pub write: bool,
+}
+
+/// Store of an instruction at an effective virtual address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryWrite {
+    pub address: u64,
+    pub size: u8,
+    pub value: u64,
+}
+
+/// Accesses of the instructions while the log is recorded.
+#[derive(Clone, Debug, Default)]
+pub struct AccessLog {
+    /// values which the devices returned to the loads, in order.
+    pub mmio_reads: Vec<u64>,
+    pub writes: Vec<MemoryWrite>,
+}
+
+pub enum MemoryAccessType {
     Fetch,
     Read,
     Write,
@@ -63,6 +114,15 @@ impl Mmu {
             addressing_mode: AddressingMode::Bare,
             privilege: Privilege::Machine,
             reserved_address: HashMap::new(),
+            cache_block_size: CACHE_BLOCK_SIZE,
+            pbmte: false,
+            triggers: Triggers::new(),
+            tracer: Tracer::new(),
+            stats: Stats::new(),
+            watchpoints: vec![],
+            watchpoint_hit: None,
+            access_log: None,
+            mmio_replay: None,
         }
     }
 
@@ -109,15 +169,357 @@ impl Mmu {
         }
     }
 
+    pub fn has_address_reserved(&self) -> bool {
+        !self.reserved_address.is_empty()
+    }
+
+    /// Sets the cache block size, which must be a power of two between 8 bytes and the page size.
+    pub fn set_cache_block_size(&mut self, size: u64) {
+        if !size.is_power_of_two() || !(8..=PAGE_SIZE).contains(&size) {
+            panic!("Invalid cache block size: {}", size);
+        }
+        self.cache_block_size = size;
+    }
+
+    /// Accepts the PBMT field of the PTEs when menvcfg.PBMTE is set.
+    pub fn set_pbmte(&mut self, pbmte: bool) {
+        self.pbmte = pbmte;
+    }
+
+    pub fn get_cache_block_size(&self) -> u64 {
+        self.cache_block_size
+    }
+
+    /// Checks the access to the cache block of cbo.clean, cbo.flush and cbo.inval.
+    /// Caches are not modeled, so the memory is not changed.
+    pub fn check_cache_block(&mut self, v_addr: u64) -> Result<(), Trap> {
+        let ev_addr = self.to_effective_address(v_addr);
+        match self.to_physical_address(ev_addr, MemoryAccessType::Read) {
+            Ok(_p_addr) => Ok(()),
+            Err(()) => Err(Trap {
+                exception: Exception::StorePageFault,
+                value: ev_addr,
+            }),
+        }
+    }
+
+    /// Writes zeros to the whole cache block which contains v_addr (cbo.zero).
+    pub fn zero_cache_block(&mut self, v_addr: u64) -> Result<(), Trap> {
+        let ev_addr = self.to_effective_address(v_addr);
+        let block_addr = ev_addr & !(self.cache_block_size - 1);
+        let p_addr = match self.to_physical_address(block_addr, MemoryAccessType::Write) {
+            Ok(p_addr) => p_addr,
+            Err(()) => {
+                return Err(Trap {
+                    exception: Exception::StorePageFault,
+                    value: ev_addr,
+                })
+            }
+        };
+        // The block doesn't cross a page, so it is translated once and stored
+        // in 8-byte pieces as write64 does.
+        for offset in (0..self.cache_block_size).step_by(8) {
+            let piece_addr = block_addr.wrapping_add(offset);
+            if self.bus.write64(p_addr.wrapping_add(offset), 0).is_err() {
+                return Err(Trap {
+                    exception: Exception::StoreAccessFault,
+                    value: ev_addr,
+                });
+            }
+            self.record_physical_access(p_addr.wrapping_add(offset), 8, 0, true);
+            self.check_watchpoints(piece_addr, 8, true);
+            self.log_memory_write(piece_addr, 8, 0);
+            self.trace_memory_access(piece_addr, 8, 0, true);
+        }
+        Ok(())
+    }
+
     pub fn get_bus(&mut self) -> &mut Box<dyn Bus> {
         &mut self.bus
     }
 
+    /// Checks the access against the triggers (Sdtrig). A fired trigger raises a
+    /// breakpoint exception, which the hart may turn into an entry to debug mode.
+    fn check_triggers(
+        &mut self,
+        access: TriggerAccess,
+        v_addr: u64,
+        size: u64,
+        data: Option<u64>,
+    ) -> Result<(), Trap> {
+        let ev_addr = self.to_effective_address(v_addr);
+        match self
+            .triggers
+            .check(access, ev_addr, size, data, &self.privilege, &self.xlen)
+        {
+            true => Err(Trap {
+                exception: Exception::Breakpoint,
+                value: ev_addr,
+            }),
+            false => Ok(()),
+        }
+    }
+
+    /// Sets the ranges watched for the run loop, and clears the last hit.
+    pub fn set_watchpoints(&mut self, watchpoints: Vec<Watchpoint>) {
+        self.watchpoints = watchpoints;
+        self.watchpoint_hit = None;
+    }
+
+    /// Returns the first watched access since the last call, as the address
+    /// and whether it was a store.
+    pub fn take_watchpoint_hit(&mut self) -> Option<(u64, bool)> {
+        self.watchpoint_hit.take()
+    }
+
+    fn check_watchpoints(&mut self, v_addr: u64, size: u64, write: bool) {
+        if self.watchpoints.is_empty() || self.watchpoint_hit.is_some() {
+            return;
+        }
+        let ev_addr = self.to_effective_address(v_addr);
+        let hit = self.watchpoints.iter().any(|watchpoint| {
+            (match write {
+                true => watchpoint.write,
+                false => watchpoint.read,
+            }) && ev_addr < watchpoint.address.wrapping_add(watchpoint.size)
+                && watchpoint.address < ev_addr.wrapping_add(size)
+        });
+        if hit {
+            self.watchpoint_hit = Some((ev_addr, write));
+        }
+    }
+
+    /// Starts recording the MMIO reads and the stores of the instructions.
+    pub fn start_access_log(&mut self) {
+        self.access_log = Some(AccessLog::default());
+    }
+
+    /// Stops recording, and returns the accesses since `start_access_log`.
+    pub fn take_access_log(&mut self) -> AccessLog {
+        self.access_log.take().unwrap_or_default()
+    }
+
+    /// Makes the MMIO reads return `values` in order instead of the values of
+    /// the devices, which are still accessed. The devices' values are returned
+    /// again after `values` run out or `None` is set.
+    pub fn set_mmio_replay(&mut self, values: Option<Vec<u64>>) {
+        self.mmio_replay = values.map(VecDeque::from);
+    }
+
+    fn log_memory_write(&mut self, v_addr: u64, size: u8, value: u64) {
+        if self.access_log.is_some() {
+            let address = self.to_effective_address(v_addr);
+            if let Some(log) = self.access_log.as_mut() {
+                log.writes.push(MemoryWrite {
+                    address,
+                    size,
+                    value,
+                });
+            }
+        }
+    }
+
+    /// Records or replaces the value of a device read, and returns the value
+    /// which the load gets.
+    fn mmio_read(&mut self, p_addr: u64, size: u8, mut data: u64) -> u64 {
+        if (self.access_log.is_some() || self.mmio_replay.is_some()) && self.bus.is_mmio(p_addr) {
+            if let Some(value) = self
+                .mmio_replay
+                .as_mut()
+                .and_then(|values| values.pop_front())
+            {
+                data = value;
+            }
+            if let Some(log) = self.access_log.as_mut() {
+                log.mmio_reads.push(data);
+            }
+        }
+        self.record_physical_access(p_addr, size, data, false);
+        data
+    }
+
+    fn trace_memory_access(&mut self, v_addr: u64, size: u8, value: u64, write: bool) {
+        if self.tracer.is_enabled(TraceCategory::MemoryAccess) {
+            let address = self.to_effective_address(v_addr);
+            self.tracer.record(TraceEvent::MemoryAccess {
+                address,
+                size,
+                value,
+                write,
+            });
+        }
+    }
+
+    /// Counts a load or a store by the region, and traces it if it accesses
+    /// the registers of a device.
+    fn record_physical_access(&mut self, p_addr: u64, size: u8, value: u64, write: bool) {
+        if self.stats.is_counting() {
+            let region = self.bus.get_region_name(p_addr);
+            self.stats.record_access(region, write);
+        }
+        if self.tracer.is_enabled(TraceCategory::Mmio) && self.bus.is_mmio(p_addr) {
+            self.tracer.record(TraceEvent::Mmio {
+                address: p_addr,
+                size,
+                value,
+                write,
+            });
+        }
+    }
+
     pub fn read8(&mut self, v_addr: u64) -> Result<u8, Trap> {
+        self.check_triggers(TriggerAccess::Load, v_addr, 1, None)?;
+        let data = self.load8(v_addr)?;
+        self.check_triggers(TriggerAccess::Load, v_addr, 1, Some(data as u64))?;
+        self.check_watchpoints(v_addr, 1, false);
+        self.trace_memory_access(v_addr, 1, data as u64, false);
+        Ok(data)
+    }
+
+    pub fn read16(&mut self, v_addr: u64) -> Result<u16, Trap> {
+        self.check_triggers(TriggerAccess::Load, v_addr, 2, None)?;
+        let data = self.load16(v_addr)?;
+        self.check_triggers(TriggerAccess::Load, v_addr, 2, Some(data as u64))?;
+        self.check_watchpoints(v_addr, 2, false);
+        self.trace_memory_access(v_addr, 2, data as u64, false);
+        Ok(data)
+    }
+
+    pub fn read32(&mut self, v_addr: u64) -> Result<u32, Trap> {
+        self.check_triggers(TriggerAccess::Load, v_addr, 4, None)?;
+        let data = self.load32(v_addr)?;
+        self.check_triggers(TriggerAccess::Load, v_addr, 4, Some(data as u64))?;
+        self.check_watchpoints(v_addr, 4, false);
+        self.trace_memory_access(v_addr, 4, data as u64, false);
+        Ok(data)
+    }
+
+    pub fn read64(&mut self, v_addr: u64) -> Result<u64, Trap> {
+        self.check_triggers(TriggerAccess::Load, v_addr, 8, None)?;
+        let data = self.load64(v_addr)?;
+        self.check_triggers(TriggerAccess::Load, v_addr, 8, Some(data))?;
+        self.check_watchpoints(v_addr, 8, false);
+        self.trace_memory_access(v_addr, 8, data, false);
+        Ok(data)
+    }
+
+    pub fn write8(&mut self, v_addr: u64, data: u8) -> Result<(), Trap> {
+        self.check_triggers(TriggerAccess::Store, v_addr, 1, None)?;
+        self.check_triggers(TriggerAccess::Store, v_addr, 1, Some(data as u64))?;
+        self.store8(v_addr, data)?;
+        self.check_watchpoints(v_addr, 1, true);
+        self.log_memory_write(v_addr, 1, data as u64);
+        self.trace_memory_access(v_addr, 1, data as u64, true);
+        Ok(())
+    }
+
+    pub fn write16(&mut self, v_addr: u64, data: u16) -> Result<(), Trap> {
+        self.check_triggers(TriggerAccess::Store, v_addr, 2, None)?;
+        self.check_triggers(TriggerAccess::Store, v_addr, 2, Some(data as u64))?;
+        self.store16(v_addr, data)?;
+        self.check_watchpoints(v_addr, 2, true);
+        self.log_memory_write(v_addr, 2, data as u64);
+        self.trace_memory_access(v_addr, 2, data as u64, true);
+        Ok(())
+    }
+
+    pub fn write32(&mut self, v_addr: u64, data: u32) -> Result<(), Trap> {
+        self.check_triggers(TriggerAccess::Store, v_addr, 4, None)?;
+        self.check_triggers(TriggerAccess::Store, v_addr, 4, Some(data as u64))?;
+        self.store32(v_addr, data)?;
+        self.check_watchpoints(v_addr, 4, true);
+        self.log_memory_write(v_addr, 4, data as u64);
+        self.trace_memory_access(v_addr, 4, data as u64, true);
+        Ok(())
+    }
+
+    pub fn write64(&mut self, v_addr: u64, data: u64) -> Result<(), Trap> {
+        self.check_triggers(TriggerAccess::Store, v_addr, 8, None)?;
+        self.check_triggers(TriggerAccess::Store, v_addr, 8, Some(data))?;
+        self.store64(v_addr, data)?;
+        self.check_watchpoints(v_addr, 8, true);
+        self.log_memory_write(v_addr, 8, data);
+        self.trace_memory_access(v_addr, 8, data, true);
+        Ok(())
+    }
+
+    /// Reads 1, 2, 4 or 8 bytes of the virtual memory without checking the
+    /// triggers, e.g. for a debugger.
+    pub fn load(&mut self, v_addr: u64, size: u8) -> Result<u64, Trap> {
+        match size {
+            1 => self.load8(v_addr).map(|d| d as u64),
+            2 => self.load16(v_addr).map(|d| d as u64),
+            4 => self.load32(v_addr).map(|d| d as u64),
+            8 => self.load64(v_addr),
+            _ => panic!("Unexpected size access: {}", size),
+        }
+    }
+
+    /// Writes 1, 2, 4 or 8 bytes of the virtual memory without checking the triggers.
+    pub fn store(&mut self, v_addr: u64, size: u8, data: u64) -> Result<(), Trap> {
+        match size {
+            1 => self.store8(v_addr, data as u8),
+            2 => self.store16(v_addr, data as u16),
+            4 => self.store32(v_addr, data as u32),
+            8 => self.store64(v_addr, data),
+            _ => panic!("Unexpected size access: {}", size),
+        }
+    }
+
+    /// Reads the physical memory through the bus. An unmapped address is an access fault.
+    pub fn load_physical(&mut self, p_addr: u64, size: u8) -> Result<u64, Trap> {
+        let result = match size {
+            1 => self.bus.read8(p_addr).map(|d| d as u64),
+            2 => self.bus.read16(p_addr).map(|d| d as u64),
+            4 => self.bus.read32(p_addr).map(|d| d as u64),
+            8 => self.bus.read64(p_addr),
+            _ => panic!("Unexpected size access: {}", size),
+        };
+        result.map_err(|()| Trap {
+            exception: Exception::LoadAccessFault,
+            value: p_addr,
+        })
+    }
+
+    pub fn store_physical(&mut self, p_addr: u64, size: u8, data: u64) -> Result<(), Trap> {
+        let result = match size {
+            1 => self.bus.write8(p_addr, data as u8),
+            2 => self.bus.write16(p_addr, data as u16),
+            4 => self.bus.write32(p_addr, data as u32),
+            8 => self.bus.write64(p_addr, data),
+            _ => panic!("Unexpected size access: {}", size),
+        };
+        result.map_err(|()| Trap {
+            exception: Exception::StoreAccessFault,
+            value: p_addr,
+        })
+    }
+
+    /// Translates a virtual address in the current privilege mode as an access of
+    /// `access_type` does, including the update of the A/D bits.
+    pub fn translate_address(
+        &mut self,
+        v_addr: u64,
+        access_type: MemoryAccessType,
+    ) -> Result<u64, Trap> {
+        let ev_addr = self.to_effective_address(v_addr);
+        let exception = match access_type {
+            MemoryAccessType::Fetch => Exception::InstructionPageFault,
+            MemoryAccessType::Read => Exception::LoadPageFault,
+            MemoryAccessType::Write => Exception::StorePageFault,
+        };
+        self.to_physical_address(ev_addr, access_type)
+            .map_err(|()| Trap {
+                exception,
+                value: ev_addr,
+            })
+    }
+
+    fn load8(&mut self, v_addr: u64) -> Result<u8, Trap> {
         let ev_addr = self.to_effective_address(v_addr);
         match self.to_physical_address(ev_addr, MemoryAccessType::Read) {
             Ok(p_addr) => match self.bus.read8(p_addr) {
-                Ok(data) => Ok(data),
+                Ok(data) => Ok(self.mmio_read(p_addr, 1, data as u64) as u8),
                 Err(()) => Err(Trap {
                     exception: Exception::LoadPageFault,
                     value: ev_addr,
@@ -130,7 +532,7 @@ impl Mmu {
         }
     }
 
-    pub fn read16(&mut self, v_addr: u64) -> Result<u16, Trap> {
+    fn load16(&mut self, v_addr: u64) -> Result<u16, Trap> {
         // sometimes access to unaliggned acccess.
         // If it exceeds the page size, it is necessary to refer to another page table.
         match v_addr & (PAGE_SIZE - 1) <= (PAGE_SIZE - 2) {
@@ -138,7 +540,7 @@ impl Mmu {
                 let ev_addr = self.to_effective_address(v_addr);
                 match self.to_physical_address(ev_addr, MemoryAccessType::Read) {
                     Ok(p_addr) => match self.bus.read16(p_addr) {
-                        Ok(data) => Ok(data),
+                        Ok(data) => Ok(self.mmio_read(p_addr, 2, data as u64) as u16),
                         Err(()) => Err(Trap {
                             exception: Exception::LoadPageFault,
                             value: ev_addr,
@@ -153,7 +555,7 @@ impl Mmu {
             _ => {
                 let mut data = 0 as u16;
                 for i in 0..2 {
-                    match self.read8(v_addr.wrapping_add(i)) {
+                    match self.load8(v_addr.wrapping_add(i)) {
                         Ok(d) => data |= (d as u16) << (i * 8),
                         Err(e) => return Err(e),
                     }
@@ -163,7 +565,7 @@ impl Mmu {
         }
     }
 
-    pub fn read32(&mut self, v_addr: u64) -> Result<u32, Trap> {
+    fn load32(&mut self, v_addr: u64) -> Result<u32, Trap> {
         // sometimes access to unaliggned acccess.
         // If it exceeds the page size, it is necessary to refer to another page table.
         match v_addr & (PAGE_SIZE - 1) <= (PAGE_SIZE - 4) {
@@ -171,7 +573,7 @@ impl Mmu {
                 let ev_addr = self.to_effective_address(v_addr);
                 match self.to_physical_address(ev_addr, MemoryAccessType::Read) {
                     Ok(p_addr) => match self.bus.read32(p_addr) {
-                        Ok(data) => Ok(data),
+                        Ok(data) => Ok(self.mmio_read(p_addr, 4, data as u64) as u32),
                         Err(()) => Err(Trap {
                             exception: Exception::LoadPageFault,
                             value: ev_addr,
@@ -186,7 +588,7 @@ impl Mmu {
             _ => {
                 let mut data = 0 as u32;
                 for i in 0..4 {
-                    match self.read8(v_addr.wrapping_add(i)) {
+                    match self.load8(v_addr.wrapping_add(i)) {
                         Ok(d) => data |= (d as u32) << (i * 8),
                         Err(e) => return Err(e),
                     }
@@ -207,7 +609,7 @@ impl Mmu {
         }
     }
 
-    pub fn read64(&mut self, v_addr: u64) -> Result<u64, Trap> {
+    fn load64(&mut self, v_addr: u64) -> Result<u64, Trap> {
         // sometimes access to unaliggned acccess.
         // If it exceeds the page size, it is necessary to refer to another page table.
         match v_addr & (PAGE_SIZE - 1) <= (PAGE_SIZE - 8) {
@@ -215,7 +617,7 @@ impl Mmu {
                 let ev_addr = self.to_effective_address(v_addr);
                 match self.to_physical_address(ev_addr, MemoryAccessType::Read) {
                     Ok(p_addr) => match self.bus.read64(p_addr) {
-                        Ok(data) => Ok(data),
+                        Ok(data) => Ok(self.mmio_read(p_addr, 8, data)),
                         Err(()) => Err(Trap {
                             exception: Exception::LoadPageFault,
                             value: ev_addr,
@@ -230,7 +632,7 @@ impl Mmu {
             _ => {
                 let mut data = 0 as u64;
                 for i in 0..8 {
-                    match self.read8(v_addr.wrapping_add(i)) {
+                    match self.load8(v_addr.wrapping_add(i)) {
                         Ok(d) => data |= (d as u64) << (i * 8),
                         Err(e) => return Err(e),
                     }
@@ -240,11 +642,14 @@ impl Mmu {
         }
     }
 
-    pub fn write8(&mut self, v_addr: u64, val: u8) -> Result<(), Trap> {
+    fn store8(&mut self, v_addr: u64, val: u8) -> Result<(), Trap> {
         let ev_addr = self.to_effective_address(v_addr);
         match self.to_physical_address(ev_addr, MemoryAccessType::Write) {
             Ok(p_addr) => match self.bus.write8(p_addr, val) {
-                Ok(()) => Ok(()),
+                Ok(()) => {
+                    self.record_physical_access(p_addr, 1, val as u64, true);
+                    Ok(())
+                }
                 Err(()) => Err(Trap {
                     exception: Exception::StorePageFault,
                     value: ev_addr,
@@ -257,7 +662,7 @@ impl Mmu {
         }
     }
 
-    pub fn write16(&mut self, v_addr: u64, data: u16) -> Result<(), Trap> {
+    fn store16(&mut self, v_addr: u64, data: u16) -> Result<(), Trap> {
         // sometimes access to unaliggned acccess.
         // If it exceeds the page size, it is necessary to refer to another page table.
         match v_addr & (PAGE_SIZE - 1) <= (PAGE_SIZE - 2) {
@@ -265,7 +670,10 @@ impl Mmu {
                 let ev_addr = self.to_effective_address(v_addr);
                 match self.to_physical_address(ev_addr, MemoryAccessType::Write) {
                     Ok(p_addr) => match self.bus.write16(p_addr, data) {
-                        Ok(()) => Ok(()),
+                        Ok(()) => {
+                            self.record_physical_access(p_addr, 2, data as u64, true);
+                            Ok(())
+                        }
                         Err(()) => Err(Trap {
                             exception: Exception::StorePageFault,
                             value: ev_addr,
@@ -279,7 +687,7 @@ impl Mmu {
             }
             _ => {
                 for i in 0..2 {
-                    match self.write8(v_addr.wrapping_add(i), ((data >> (i * 8)) & 0xff) as u8) {
+                    match self.store8(v_addr.wrapping_add(i), ((data >> (i * 8)) & 0xff) as u8) {
                         Err(e) => return Err(e),
                         _ => {}
                     }
@@ -289,7 +697,7 @@ impl Mmu {
         }
     }
 
-    pub fn write32(&mut self, v_addr: u64, data: u32) -> Result<(), Trap> {
+    fn store32(&mut self, v_addr: u64, data: u32) -> Result<(), Trap> {
         // sometimes access to unaliggned acccess.
         // If it exceeds the page size, it is necessary to refer to another page table.
         match v_addr & (PAGE_SIZE - 1) <= (PAGE_SIZE - 4) {
@@ -297,7 +705,10 @@ impl Mmu {
                 let ev_addr = self.to_effective_address(v_addr);
                 match self.to_physical_address(ev_addr, MemoryAccessType::Write) {
                     Ok(p_addr) => match self.bus.write32(p_addr, data) {
-                        Ok(()) => Ok(()),
+                        Ok(()) => {
+                            self.record_physical_access(p_addr, 4, data as u64, true);
+                            Ok(())
+                        }
                         Err(()) => Err(Trap {
                             exception: Exception::StorePageFault,
                             value: ev_addr,
@@ -311,7 +722,7 @@ impl Mmu {
             }
             _ => {
                 for i in 0..4 {
-                    match self.write8(v_addr.wrapping_add(i), ((data >> (i * 8)) & 0xff) as u8) {
+                    match self.store8(v_addr.wrapping_add(i), ((data >> (i * 8)) & 0xff) as u8) {
                         Err(e) => return Err(e),
                         _ => {}
                     }
@@ -321,7 +732,7 @@ impl Mmu {
         }
     }
 
-    pub fn write64(&mut self, v_addr: u64, data: u64) -> Result<(), Trap> {
+    fn store64(&mut self, v_addr: u64, data: u64) -> Result<(), Trap> {
         // sometimes access to unaliggned acccess.
         // If it exceeds the page size, it is necessary to refer to another page table.
         match v_addr & (PAGE_SIZE - 1) <= (PAGE_SIZE - 8) {
@@ -329,7 +740,10 @@ impl Mmu {
                 let ev_addr = self.to_effective_address(v_addr);
                 match self.to_physical_address(ev_addr, MemoryAccessType::Write) {
                     Ok(p_addr) => match self.bus.write64(p_addr, data) {
-                        Ok(()) => Ok(()),
+                        Ok(()) => {
+                            self.record_physical_access(p_addr, 8, data, true);
+                            Ok(())
+                        }
                         Err(()) => Err(Trap {
                             exception: Exception::StorePageFault,
                             value: ev_addr,
@@ -343,7 +757,7 @@ impl Mmu {
             }
             _ => {
                 for i in 0..8 {
-                    match self.write8(v_addr.wrapping_add(i), ((data >> (i * 8)) & 0xff) as u8) {
+                    match self.store8(v_addr.wrapping_add(i), ((data >> (i * 8)) & 0xff) as u8) {
                         Err(e) => return Err(e),
                         _ => {}
                     }
@@ -354,6 +768,7 @@ impl Mmu {
     }
 
     pub fn fetch32(&mut self, v_addr: u64) -> Result<u32, Trap> {
+        self.check_triggers(TriggerAccess::Execute, v_addr, 0, None)?;
         // sometimes access to unaliggned acccess.
         // If it exceeds the page size, it is necessary to refer to another page table.
         match v_addr & (PAGE_SIZE - 1) <= (PAGE_SIZE - 4) {
@@ -415,6 +830,9 @@ impl Mmu {
             AddressingMode::Sv32 => match self.privilege {
                 Privilege::User | Privilege::Supervisor => {
                     let vpns = [(v_addr >> 12) & 0x3ff, (v_addr >> 22) & 0x3ff];
+                    if self.stats.is_counting() {
+                        self.stats.page_walks += 1;
+                    }
                     self.page_waking(v_addr, 1, self.ppn, &vpns, &access_type)
                 }
                 _ => Ok(v_addr),
@@ -426,6 +844,9 @@ impl Mmu {
                         (v_addr >> 21) & 0x1ff,
                         (v_addr >> 30) & 0x1ff,
                     ];
+                    if self.stats.is_counting() {
+                        self.stats.page_walks += 1;
+                    }
                     self.page_waking(v_addr, 2, self.ppn, &vpns, &access_type)
                 }
                 _ => Ok(v_addr),
@@ -456,6 +877,9 @@ impl Mmu {
             _ => 8,
         };
         let pte_addr = parent_ppn * PAGE_SIZE + vpns[level as usize] * pte_size;
+        if self.stats.is_counting() {
+            self.stats.pte_reads += 1;
+        }
 
         // 2. get PTE (Page Table Entry).
         let pte = match self.addressing_mode {
@@ -463,6 +887,15 @@ impl Mmu {
             _ => self.pte_read64(pte_addr),
         };
 
+        if self.tracer.is_enabled(TraceCategory::PageWalk) {
+            self.tracer.record(TraceEvent::PageWalk {
+                address: v_addr,
+                level,
+                pte_address: pte_addr,
+                pte,
+            });
+        }
+
         // 3. check PTE.
         let pte_d = self.parse_pte(pte);
 
@@ -471,8 +904,20 @@ impl Mmu {
             return Err(());
         }
 
+        // 4.1 validate reserved bits and encodings (Svpbmt / Svnapot).
+        // PBMT=3 is reserved, and PBMT is reserved as a whole unless menvcfg.PBMTE is set.
+        // The memory types (PMA/NC/IO) themselves need no handling because caches
+        // are not modeled and every access is performed in order.
+        if pte_d.reserved != 0 || pte_d.pbmt == 3 || (pte_d.pbmt != 0 && !self.pbmte) {
+            return Err(());
+        }
+
         // 5. check last entry or not.
         if pte_d.r == 0 && pte_d.x == 0 {
+            // N and PBMT are reserved for non-leaf PTEs.
+            if pte_d.n != 0 || pte_d.pbmt != 0 {
+                return Err(());
+            }
             return match level {
                 0 => Err(()),
                 _ => self.page_waking(v_addr, level - 1, pte_d.ppn, vpns, access_type),
@@ -518,7 +963,10 @@ impl Mmu {
             }
         };
 
-        // 8. calculate physical address.
+        // 8. calculate physical address. NAPOT PTEs are only defined for 4 KiB pages.
+        if pte_d.n != 0 && level != 0 {
+            return Err(());
+        }
         let offset = v_addr & 0xfff;
         let p_addr = match self.addressing_mode {
             AddressingMode::Sv32 => match level {
@@ -544,6 +992,13 @@ impl Mmu {
                     }
                     (pte_d.ppns[2] << 30) | (pte_d.ppns[1] << 21) | (vpns[0] << 12) | offset
                 }
+                // Svnapot: a 64 KiB NAPOT range replaces PPN[0][3:0] with VPN[0][3:0].
+                0 if pte_d.n != 0 => {
+                    if pte_d.ppns[0] & 0xf != 0x8 {
+                        return Err(());
+                    }
+                    ((pte_d.ppn & !0xf) << 12) | ((vpns[0] & 0xf) << 12) | offset
+                }
                 0 => (pte_d.ppn << 12) | offset,
                 _ => panic!(),
             },
@@ -564,7 +1019,15 @@ impl Mmu {
                 (pte >> 28) & 0x3ffffff,
             ],
         };
+        // Sv32 PTEs have no N, PBMT and reserved bits.
+        let (_reserved, _pbmt, _n) = match self.addressing_mode {
+            AddressingMode::Sv32 => (0, 0, 0),
+            _ => ((pte >> 54) & 0x7f, (pte >> 61) & 0x3, (pte >> 63) & 1),
+        };
         Pte {
+            reserved: _reserved as u8,
+            pbmt: _pbmt as u8,
+            n: _n as u8,
             ppn: _ppn,
             ppns: _ppns,
             _rsw: ((pte >> 8) & 0x3) as u8,
@@ -579,38 +1042,37 @@ impl Mmu {
         }
     }
 
+    // Page table entries are accessed by physical addresses, which are not limited
+    // by the effective XLEN (e.g. Sv39 page tables of RV32 user processes).
     fn pte_read32(&mut self, addr: u64) -> u32 {
-        let effective_addr = self.to_effective_address(addr);
-        match self.bus.read32(effective_addr) {
+        match self.bus.read32(addr) {
             Ok(data) => data,
             Err(e) => panic!(e),
         }
     }
 
     fn pte_read64(&mut self, addr: u64) -> u64 {
-        let effective_addr = self.to_effective_address(addr);
-        match self.bus.read64(effective_addr) {
+        match self.bus.read64(addr) {
             Ok(data) => data,
             Err(e) => panic!(e),
         }
     }
 
     fn pte_write32(&mut self, addr: u64, data: u32) {
-        let effective_addr = self.to_effective_address(addr);
-        match self.bus.write32(effective_addr, data) {
+        match self.bus.write32(addr, data) {
             Ok(()) => (),
             Err(e) => panic!(e),
         }
     }
 
     fn pte_write64(&mut self, addr: u64, data: u64) {
-        let effective_addr = self.to_effective_address(addr);
-        match self.bus.write64(effective_addr, data) {
+        match self.bus.write64(addr, data) {
             Ok(()) => (),
             Err(e) => panic!(e),
         }
     }
 
+    /// Virtual addresses are truncated to the effective XLEN of the current privilege mode.
     fn to_effective_address(&self, addr: u64) -> u64 {
         match self.xlen {
             Xlen::X32 => addr & 0xffffffff,
@@ -618,3 +1080,54 @@ impl Mmu {
         }
     }
 }
+
+/// The bus is saved with the state of the MMU. The watchpoints, the tracer and
+/// the lockstep logs belong to the debugger rather than to the machine.
+impl Snapshot for Mmu {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("mmu");
+        writer.write_xlen(&self.xlen);
+        writer.write_u64(self.ppn);
+        writer.write_u8(match self.addressing_mode {
+            AddressingMode::Bare => 0,
+            AddressingMode::Sv32 => 1,
+            AddressingMode::Sv39 => 8,
+            AddressingMode::Sv48 => 9,
+            AddressingMode::Sv57 => 10,
+            AddressingMode::Sv64 => 11,
+        });
+        writer.write_privilege(&self.privilege);
+        let mut reserved_address = self.reserved_address.keys().cloned().collect::<Vec<_>>();
+        reserved_address.sort_unstable();
+        writer.write_u64s(&reserved_address);
+        writer.write_u64(self.cache_block_size);
+        writer.write_bool(self.pbmte);
+        self.triggers.save(writer);
+        self.bus.save(writer);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("mmu")?;
+        self.xlen = reader.read_xlen()?;
+        self.ppn = reader.read_u64()?;
+        self.addressing_mode = match reader.read_u8()? {
+            0 => AddressingMode::Bare,
+            1 => AddressingMode::Sv32,
+            8 => AddressingMode::Sv39,
+            9 => AddressingMode::Sv48,
+            10 => AddressingMode::Sv57,
+            11 => AddressingMode::Sv64,
+            _ => return Err(reader.corrupted()),
+        };
+        self.privilege = reader.read_privilege()?;
+        self.reserved_address = reader
+            .read_u64s()?
+            .into_iter()
+            .map(|address| (address, true))
+            .collect();
+        self.cache_block_size = reader.read_u64()?;
+        self.pbmte = reader.read_bool()?;
+        self.triggers.restore(reader)?;
+        self.bus.restore(reader)
+    }
+}
@@ -2,5 +2,10 @@ pub mod cpu;
 pub mod cpu_instruction;
 pub mod cpu_instruction_comp;
 pub mod cpu_csr;
+pub mod crypto;
+pub mod entropy;
+pub mod stats;
 pub mod trap;
+pub mod trigger;
 pub mod mmu;
+pub mod softfloat;
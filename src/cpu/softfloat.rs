@@ -0,0 +1,815 @@
+// IEEE 754-2008 binary floating-point arithmetic in software.
+//
+// The operations of the F, D and Zfh extensions are computed exactly on the
+// integer significands and rounded once in the rounding mode of the
+// instruction, so that the results and the accrued exception flags match the
+// RISC-V hardware regardless of the host FPU. Tininess is detected after
+// rounding, and every NaN result is the canonical NaN of the format.
+
+/// Rounding modes, encoded as in the rm field and frm.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoundingMode {
+    /// round to nearest, ties to even (RNE).
+    NearestEven,
+    /// round towards zero (RTZ).
+    TowardZero,
+    /// round down, towards -infinity (RDN).
+    Down,
+    /// round up, towards +infinity (RUP).
+    Up,
+    /// round to nearest, ties to max magnitude (RMM).
+    NearestMaxMagnitude,
+}
+
+impl RoundingMode {
+    /// Decodes the rm field or frm. The reserved encodings are `None`.
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0 => Some(RoundingMode::NearestEven),
+            1 => Some(RoundingMode::TowardZero),
+            2 => Some(RoundingMode::Down),
+            3 => Some(RoundingMode::Up),
+            4 => Some(RoundingMode::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+// Accrued exception flags (fflags).
+pub const FLAG_NX: u64 = 0x01;
+pub const FLAG_UF: u64 = 0x02;
+pub const FLAG_OF: u64 = 0x04;
+pub const FLAG_DZ: u64 = 0x08;
+pub const FLAG_NV: u64 = 0x10;
+
+/// Binary interchange format, given by the widths of the fields.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Format {
+    pub exponent_bits: u32,
+    pub fraction_bits: u32,
+}
+
+pub const HALF: Format = Format {
+    exponent_bits: 5,
+    fraction_bits: 10,
+};
+pub const SINGLE: Format = Format {
+    exponent_bits: 8,
+    fraction_bits: 23,
+};
+pub const DOUBLE: Format = Format {
+    exponent_bits: 11,
+    fraction_bits: 52,
+};
+
+impl Format {
+    pub fn width(self) -> u32 {
+        1 + self.exponent_bits + self.fraction_bits
+    }
+
+    fn bias(self) -> i32 {
+        (1 << (self.exponent_bits - 1)) - 1
+    }
+
+    fn sign_bit(self) -> u64 {
+        1 << (self.exponent_bits + self.fraction_bits)
+    }
+
+    fn exponent_field(self, bits: u64) -> u64 {
+        (bits >> self.fraction_bits) & ((1 << self.exponent_bits) - 1)
+    }
+
+    fn fraction_field(self, bits: u64) -> u64 {
+        bits & ((1 << self.fraction_bits) - 1)
+    }
+
+    fn max_exponent_field(self) -> u64 {
+        (1 << self.exponent_bits) - 1
+    }
+
+    pub fn is_negative(self, bits: u64) -> bool {
+        bits & self.sign_bit() != 0
+    }
+
+    pub fn is_nan(self, bits: u64) -> bool {
+        self.exponent_field(bits) == self.max_exponent_field() && self.fraction_field(bits) != 0
+    }
+
+    pub fn is_signaling_nan(self, bits: u64) -> bool {
+        self.is_nan(bits) && bits & (1 << (self.fraction_bits - 1)) == 0
+    }
+
+    pub fn is_infinite(self, bits: u64) -> bool {
+        self.exponent_field(bits) == self.max_exponent_field() && self.fraction_field(bits) == 0
+    }
+
+    pub fn is_zero(self, bits: u64) -> bool {
+        bits & !self.sign_bit() == 0
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        (self.max_exponent_field() << self.fraction_bits) | (1 << (self.fraction_bits - 1))
+    }
+
+    pub fn infinity(self, negative: bool) -> u64 {
+        self.sign(negative) | (self.max_exponent_field() << self.fraction_bits)
+    }
+
+    pub fn zero(self, negative: bool) -> u64 {
+        self.sign(negative)
+    }
+
+    fn max_finite(self, negative: bool) -> u64 {
+        self.infinity(negative) - 1
+    }
+
+    /// Returns the smallest positive normal number.
+    pub fn min_normal(self) -> u64 {
+        1 << self.fraction_bits
+    }
+
+    fn sign(self, negative: bool) -> u64 {
+        match negative {
+            true => self.sign_bit(),
+            false => 0,
+        }
+    }
+
+    /// Returns the significand and the exponent of a finite nonzero number,
+    /// whose magnitude is `significand * 2^exponent`.
+    fn unpack(self, bits: u64) -> (u128, i32) {
+        let exponent = self.exponent_field(bits) as i32;
+        let fraction = self.fraction_field(bits) as u128;
+        match exponent {
+            0 => (fraction, 1 - self.bias() - self.fraction_bits as i32),
+            _ => (
+                fraction | (1 << self.fraction_bits),
+                exponent - self.bias() - self.fraction_bits as i32,
+            ),
+        }
+    }
+
+    /// Returns the class of the number as FCLASS does.
+    pub fn classify(self, bits: u64) -> u64 {
+        let negative = self.is_negative(bits);
+        let exponent = self.exponent_field(bits);
+        let index = match () {
+            _ if self.is_signaling_nan(bits) => 8,
+            _ if self.is_nan(bits) => 9,
+            _ if self.is_infinite(bits) => 0,
+            _ if self.is_zero(bits) => 3,
+            _ if exponent == 0 => 2,
+            _ => 1,
+        };
+        match (index, negative) {
+            (8, _) | (9, _) => 1 << index,
+            (index, true) => 1 << index,
+            (index, false) => 1 << (7 - index),
+        }
+    }
+}
+
+/// Shifts right with the bits shifted out ORed into the lowest bit, which
+/// keeps whether the value was exact for the rounding.
+fn shift_right_jamming(value: u128, shift: u32) -> u128 {
+    match shift {
+        0 => value,
+        1..=127 => (value >> shift) | (value & ((1 << shift) - 1) != 0) as u128,
+        _ => (value != 0) as u128,
+    }
+}
+
+/// Shifts the magnitude right by `shift` bits, and rounds it to an integer in
+/// the rounding mode. Returns the result and whether it is inexact.
+fn shift_round(value: u128, shift: i32, negative: bool, mode: RoundingMode) -> (u128, bool) {
+    if shift <= 0 {
+        return (value << -shift, false);
+    }
+    let (quotient, round, sticky) = match shift {
+        1..=127 => (
+            value >> shift,
+            (value >> (shift - 1)) & 1 != 0,
+            value & ((1 << (shift - 1)) - 1) != 0,
+        ),
+        128 => (0, value >> 127 != 0, value & (u128::MAX >> 1) != 0),
+        _ => (0, false, value != 0),
+    };
+    let inexact = round || sticky;
+    let increment = match mode {
+        RoundingMode::NearestEven => round && (sticky || quotient & 1 != 0),
+        RoundingMode::NearestMaxMagnitude => round,
+        RoundingMode::TowardZero => false,
+        RoundingMode::Down => inexact && negative,
+        RoundingMode::Up => inexact && !negative,
+    };
+    (quotient + increment as u128, inexact)
+}
+
+/// Integer square root, rounded down.
+fn isqrt(value: u128) -> u128 {
+    if value == 0 {
+        return 0;
+    }
+    let mut root = (value as f64).sqrt() as u128;
+    // Newton's method from the estimate, which the host computes with 53 bits.
+    for _ in 0..2 {
+        root = (root + value / root.max(1)) / 2;
+    }
+    while root * root > value {
+        root -= 1;
+    }
+    while (root + 1) * (root + 1) <= value {
+        root += 1;
+    }
+    root
+}
+
+/// Position of the leading bit of the significands while adding them, which
+/// leaves a bit for the carry and many bits below the precision.
+const ALIGNED_LEADING_BIT: u32 = 125;
+
+/// Floating-point unit with the rounding mode of an operation and the exception
+/// flags it raised.
+pub struct SoftFloat {
+    pub rounding_mode: RoundingMode,
+    pub flags: u64,
+}
+
+impl SoftFloat {
+    pub fn new(rounding_mode: RoundingMode) -> Self {
+        SoftFloat {
+            rounding_mode,
+            flags: 0,
+        }
+    }
+
+    /// Returns the canonical NaN for an operation with a NaN operand, raising
+    /// the invalid operation flag if any of the operands is a signaling NaN.
+    fn propagate_nan(&mut self, format: Format, operands: &[u64]) -> u64 {
+        if operands.iter().any(|bits| format.is_signaling_nan(*bits)) {
+            self.flags |= FLAG_NV;
+        }
+        format.canonical_nan()
+    }
+
+    fn invalid(&mut self, format: Format) -> u64 {
+        self.flags |= FLAG_NV;
+        format.canonical_nan()
+    }
+
+    /// Rounds `significand * 2^exponent` to the format. The significand must
+    /// not be zero.
+    fn round_pack(
+        &mut self,
+        format: Format,
+        negative: bool,
+        exponent: i32,
+        significand: u128,
+    ) -> u64 {
+        let fraction_bits = format.fraction_bits as i32;
+        let min_exponent = 1 - format.bias();
+        let mode = self.rounding_mode;
+        // exponent of the leading bit.
+        let top = exponent + 127 - significand.leading_zeros() as i32;
+
+        // A tiny result rounds below the smallest normal number with an unbounded
+        // exponent range.
+        let tiny = match top {
+            _ if top >= min_exponent => false,
+            _ if top < min_exponent - 1 => true,
+            _ => {
+                let shift = top - fraction_bits - exponent;
+                let (rounded, _) = shift_round(significand, shift, negative, mode);
+                rounded >> (fraction_bits + 1) == 0
+            }
+        };
+
+        let mut lsb = top.max(min_exponent) - fraction_bits;
+        let (mut rounded, inexact) = shift_round(significand, lsb - exponent, negative, mode);
+        if rounded >> (fraction_bits + 1) != 0 {
+            rounded >>= 1;
+            lsb += 1;
+        }
+        if inexact {
+            self.flags |= FLAG_NX;
+            if tiny {
+                self.flags |= FLAG_UF;
+            }
+        }
+
+        let sign = format.sign(negative);
+        if rounded >> fraction_bits == 0 {
+            // subnormal or zero
+            return sign | rounded as u64;
+        }
+        let biased = lsb + fraction_bits + format.bias();
+        if biased as u64 >= format.max_exponent_field() {
+            self.flags |= FLAG_OF | FLAG_NX;
+            let infinite = match mode {
+                RoundingMode::NearestEven | RoundingMode::NearestMaxMagnitude => true,
+                RoundingMode::TowardZero => false,
+                RoundingMode::Down => negative,
+                RoundingMode::Up => !negative,
+            };
+            return match infinite {
+                true => format.infinity(negative),
+                false => format.max_finite(negative),
+            };
+        }
+        sign | ((biased as u64) << fraction_bits) | format.fraction_field(rounded as u64)
+    }
+
+    /// Adds two finite nonzero numbers exactly aligned, and rounds the sum.
+    #[allow(clippy::too_many_arguments)]
+    fn add_finite(
+        &mut self,
+        format: Format,
+        negative_a: bool,
+        exponent_a: i32,
+        significand_a: u128,
+        negative_b: bool,
+        exponent_b: i32,
+        significand_b: u128,
+    ) -> u64 {
+        let align = |exponent: i32, significand: u128| {
+            let shift = ALIGNED_LEADING_BIT - (127 - significand.leading_zeros());
+            (exponent - shift as i32, significand << shift)
+        };
+        let (exponent_a, significand_a) = align(exponent_a, significand_a);
+        let (exponent_b, significand_b) = align(exponent_b, significand_b);
+        let (exponent, significand_a, significand_b) = match exponent_a >= exponent_b {
+            true => (
+                exponent_a,
+                significand_a,
+                shift_right_jamming(significand_b, (exponent_a - exponent_b) as u32),
+            ),
+            false => (
+                exponent_b,
+                shift_right_jamming(significand_a, (exponent_b - exponent_a) as u32),
+                significand_b,
+            ),
+        };
+        let (negative, significand) = match negative_a == negative_b {
+            true => (negative_a, significand_a + significand_b),
+            false => match significand_a >= significand_b {
+                true => (negative_a, significand_a - significand_b),
+                false => (negative_b, significand_b - significand_a),
+            },
+        };
+        if significand == 0 {
+            // an exact zero sum is -0 only when rounding down.
+            return format.zero(self.rounding_mode == RoundingMode::Down);
+        }
+        self.round_pack(format, negative, exponent, significand)
+    }
+
+    /// Returns the sign of the exact zero sum of two zeros.
+    fn zero_sum_sign(&self, negative_a: bool, negative_b: bool) -> bool {
+        match self.rounding_mode {
+            RoundingMode::Down => negative_a || negative_b,
+            _ => negative_a && negative_b,
+        }
+    }
+
+    pub fn add(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        if format.is_nan(a) || format.is_nan(b) {
+            return self.propagate_nan(format, &[a, b]);
+        }
+        let (negative_a, negative_b) = (format.is_negative(a), format.is_negative(b));
+        match (format.is_infinite(a), format.is_infinite(b)) {
+            (true, true) if negative_a != negative_b => return self.invalid(format),
+            (true, _) => return a,
+            (_, true) => return b,
+            _ => {}
+        }
+        match (format.is_zero(a), format.is_zero(b)) {
+            (true, true) => return format.zero(self.zero_sum_sign(negative_a, negative_b)),
+            (true, false) => return b,
+            (false, true) => return a,
+            _ => {}
+        }
+        let (significand_a, exponent_a) = format.unpack(a);
+        let (significand_b, exponent_b) = format.unpack(b);
+        self.add_finite(
+            format,
+            negative_a,
+            exponent_a,
+            significand_a,
+            negative_b,
+            exponent_b,
+            significand_b,
+        )
+    }
+
+    pub fn sub(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        match format.is_nan(b) {
+            true => self.add(format, a, b),
+            false => self.add(format, a, b ^ format.sign_bit()),
+        }
+    }
+
+    pub fn mul(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        if format.is_nan(a) || format.is_nan(b) {
+            return self.propagate_nan(format, &[a, b]);
+        }
+        let negative = format.is_negative(a) != format.is_negative(b);
+        let (infinite, zero) = (
+            format.is_infinite(a) || format.is_infinite(b),
+            format.is_zero(a) || format.is_zero(b),
+        );
+        match (infinite, zero) {
+            (true, true) => return self.invalid(format),
+            (true, false) => return format.infinity(negative),
+            (false, true) => return format.zero(negative),
+            _ => {}
+        }
+        let (significand_a, exponent_a) = format.unpack(a);
+        let (significand_b, exponent_b) = format.unpack(b);
+        self.round_pack(
+            format,
+            negative,
+            exponent_a + exponent_b,
+            significand_a * significand_b,
+        )
+    }
+
+    pub fn div(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        if format.is_nan(a) || format.is_nan(b) {
+            return self.propagate_nan(format, &[a, b]);
+        }
+        let negative = format.is_negative(a) != format.is_negative(b);
+        match (format.is_infinite(a), format.is_infinite(b)) {
+            (true, true) => return self.invalid(format),
+            (true, false) => return format.infinity(negative),
+            (false, true) => return format.zero(negative),
+            _ => {}
+        }
+        match (format.is_zero(a), format.is_zero(b)) {
+            (true, true) => return self.invalid(format),
+            (true, false) => return format.zero(negative),
+            (false, true) => {
+                self.flags |= FLAG_DZ;
+                return format.infinity(negative);
+            }
+            _ => {}
+        }
+        let (significand_a, exponent_a) = format.unpack(a);
+        let (significand_b, exponent_b) = format.unpack(b);
+        // the quotient has more than 60 bits, the lowest of which keeps the remainder.
+        let shift_a = ALIGNED_LEADING_BIT - (127 - significand_a.leading_zeros());
+        let shift_b = 63 - (127 - significand_b.leading_zeros());
+        let dividend = significand_a << shift_a;
+        let divisor = significand_b << shift_b;
+        let remainder = dividend % divisor;
+        let quotient = (dividend / divisor) | (remainder != 0) as u128;
+        let exponent = (exponent_a - shift_a as i32) - (exponent_b - shift_b as i32);
+        self.round_pack(format, negative, exponent, quotient)
+    }
+
+    pub fn sqrt(&mut self, format: Format, a: u64) -> u64 {
+        if format.is_nan(a) {
+            return self.propagate_nan(format, &[a]);
+        }
+        if format.is_zero(a) {
+            return a;
+        }
+        if format.is_negative(a) {
+            return self.invalid(format);
+        }
+        if format.is_infinite(a) {
+            return a;
+        }
+        let (significand, exponent) = format.unpack(a);
+        // the exponent is made even, and the root has more than 60 bits.
+        let mut shift = 124 - (127 - significand.leading_zeros()) as i32;
+        if (exponent - shift) & 1 != 0 {
+            shift -= 1;
+        }
+        let radicand = significand << shift;
+        let root = isqrt(radicand);
+        let root = root | (root * root != radicand) as u128;
+        self.round_pack(format, false, (exponent - shift) / 2, root)
+    }
+
+    /// Computes `(a * b) + c` with a single rounding. The product and the
+    /// addend are negated by `negate_product` and `negate_addend`.
+    pub fn fused_multiply_add(
+        &mut self,
+        format: Format,
+        a: u64,
+        b: u64,
+        c: u64,
+        negate_product: bool,
+        negate_addend: bool,
+    ) -> u64 {
+        let invalid_product = (format.is_infinite(a) && format.is_zero(b))
+            || (format.is_zero(a) && format.is_infinite(b));
+        // the invalid product raises the flag even if the addend is a quiet NaN.
+        if invalid_product {
+            self.flags |= FLAG_NV;
+        }
+        if format.is_nan(a) || format.is_nan(b) || format.is_nan(c) {
+            return self.propagate_nan(format, &[a, b, c]);
+        }
+        if invalid_product {
+            return format.canonical_nan();
+        }
+        let negative_product = (format.is_negative(a) != format.is_negative(b)) != negate_product;
+        let negative_addend = format.is_negative(c) != negate_addend;
+        let infinite_product = format.is_infinite(a) || format.is_infinite(b);
+        match (infinite_product, format.is_infinite(c)) {
+            (true, true) if negative_product != negative_addend => return self.invalid(format),
+            (true, _) => return format.infinity(negative_product),
+            (_, true) => return format.infinity(negative_addend),
+            _ => {}
+        }
+        let zero_product = format.is_zero(a) || format.is_zero(b);
+        match (zero_product, format.is_zero(c)) {
+            (true, true) => {
+                return format.zero(self.zero_sum_sign(negative_product, negative_addend))
+            }
+            (true, false) => return c ^ format.sign(negate_addend),
+            _ => {}
+        }
+        let (significand_a, exponent_a) = format.unpack(a);
+        let (significand_b, exponent_b) = format.unpack(b);
+        let (significand, exponent) = (significand_a * significand_b, exponent_a + exponent_b);
+        if format.is_zero(c) {
+            return self.round_pack(format, negative_product, exponent, significand);
+        }
+        let (significand_c, exponent_c) = format.unpack(c);
+        self.add_finite(
+            format,
+            negative_product,
+            exponent,
+            significand,
+            negative_addend,
+            exponent_c,
+            significand_c,
+        )
+    }
+
+    /// Converts a number to another format.
+    pub fn convert(&mut self, from: Format, to: Format, a: u64) -> u64 {
+        if from.is_nan(a) {
+            return match from.is_signaling_nan(a) {
+                true => self.invalid(to),
+                false => to.canonical_nan(),
+            };
+        }
+        let negative = from.is_negative(a);
+        if from.is_infinite(a) {
+            return to.infinity(negative);
+        }
+        if from.is_zero(a) {
+            return to.zero(negative);
+        }
+        let (significand, exponent) = from.unpack(a);
+        self.round_pack(to, negative, exponent, significand)
+    }
+
+    /// Converts an integer to the format.
+    pub fn from_integer(&mut self, format: Format, value: u64, signed: bool) -> u64 {
+        let negative = signed && (value as i64) < 0;
+        let magnitude = match negative {
+            true => (value as i64).unsigned_abs(),
+            false => value,
+        };
+        match magnitude {
+            0 => format.zero(false),
+            _ => self.round_pack(format, negative, 0, magnitude as u128),
+        }
+    }
+
+    /// Converts a number to an integer of `width` bits, which is saturated and
+    /// raises the invalid operation flag if it is out of range. A NaN converts
+    /// to the largest integer.
+    pub fn to_integer(&mut self, format: Format, a: u64, width: u32, signed: bool) -> u64 {
+        let (max, min): (u128, u128) = match signed {
+            true => ((1 << (width - 1)) - 1, 1 << (width - 1)),
+            false => ((1 << width) - 1, 0),
+        };
+        let negative = format.is_negative(a);
+        let saturated = |negative: bool| match negative {
+            true => (min as u64).wrapping_neg(),
+            false => max as u64,
+        };
+        if format.is_nan(a) {
+            self.flags |= FLAG_NV;
+            return saturated(false);
+        }
+        if format.is_infinite(a) {
+            self.flags |= FLAG_NV;
+            return saturated(negative);
+        }
+        if format.is_zero(a) {
+            return 0;
+        }
+        let (significand, exponent) = format.unpack(a);
+        if exponent > width as i32 {
+            self.flags |= FLAG_NV;
+            return saturated(negative);
+        }
+        let (magnitude, inexact) =
+            shift_round(significand, -exponent, negative, self.rounding_mode);
+        let in_range = match negative {
+            true => magnitude <= min,
+            false => magnitude <= max,
+        };
+        if !in_range {
+            self.flags |= FLAG_NV;
+            return saturated(negative);
+        }
+        if inexact {
+            self.flags |= FLAG_NX;
+        }
+        match negative {
+            true => (magnitude as u64).wrapping_neg(),
+            false => magnitude as u64,
+        }
+    }
+
+    /// Returns whether `a < b`, or `a <= b` if `or_equal`. A signaling
+    /// comparison raises the invalid operation flag for any NaN operand, and a
+    /// quiet one only for a signaling NaN. NaNs are unordered.
+    pub fn less(
+        &mut self,
+        format: Format,
+        a: u64,
+        b: u64,
+        or_equal: bool,
+        signaling: bool,
+    ) -> bool {
+        if format.is_nan(a) || format.is_nan(b) {
+            if signaling || format.is_signaling_nan(a) || format.is_signaling_nan(b) {
+                self.flags |= FLAG_NV;
+            }
+            return false;
+        }
+        if format.is_zero(a) && format.is_zero(b) {
+            return or_equal;
+        }
+        if a == b {
+            return or_equal;
+        }
+        match (format.is_negative(a), format.is_negative(b)) {
+            (true, false) => true,
+            (false, true) => false,
+            (false, false) => a < b,
+            (true, true) => a > b,
+        }
+    }
+
+    /// Returns whether `a == b`, which is a quiet comparison.
+    pub fn equal(&mut self, format: Format, a: u64, b: u64) -> bool {
+        if format.is_nan(a) || format.is_nan(b) {
+            if format.is_signaling_nan(a) || format.is_signaling_nan(b) {
+                self.flags |= FLAG_NV;
+            }
+            return false;
+        }
+        a == b || (format.is_zero(a) && format.is_zero(b))
+    }
+
+    /// Returns the minimum or the maximum. -0 is less than +0. If one of the
+    /// operands is a NaN, the other is returned unless `propagate_nan`.
+    pub fn min_max(
+        &mut self,
+        format: Format,
+        a: u64,
+        b: u64,
+        max: bool,
+        propagate_nan: bool,
+    ) -> u64 {
+        let (nan_a, nan_b) = (format.is_nan(a), format.is_nan(b));
+        if nan_a || nan_b {
+            let nan = self.propagate_nan(format, &[a, b]);
+            return match (propagate_nan, nan_a, nan_b) {
+                (false, true, false) => b,
+                (false, false, true) => a,
+                _ => nan,
+            };
+        }
+        let a_less = match format.is_zero(a) && format.is_zero(b) {
+            true => format.is_negative(a),
+            false => self.less(format, a, b, false, false),
+        };
+        match a_less != max {
+            true => a,
+            false => b,
+        }
+    }
+
+    /// Rounds a number to an integral value in the format (Zfa FROUND and
+    /// FROUNDNX). The inexact flag is raised only if `signal_inexact`.
+    pub fn round_to_integral(&mut self, format: Format, a: u64, signal_inexact: bool) -> u64 {
+        if format.is_nan(a) {
+            return self.propagate_nan(format, &[a]);
+        }
+        if format.is_infinite(a) || format.is_zero(a) {
+            return a;
+        }
+        let (significand, exponent) = format.unpack(a);
+        if exponent >= 0 {
+            return a;
+        }
+        let negative = format.is_negative(a);
+        let (magnitude, inexact) =
+            shift_round(significand, -exponent, negative, self.rounding_mode);
+        if inexact && signal_inexact {
+            self.flags |= FLAG_NX;
+        }
+        match magnitude {
+            0 => format.zero(negative),
+            _ => self.round_pack(format, negative, 0, magnitude),
+        }
+    }
+
+    /// Converts a double-precision number to a 32-bit integer rounding
+    /// towards zero, and returns the low 32 bits of it modulo 2^32 (Zfa
+    /// FCVTMOD.W.D). The flags are raised as FCVT.W.D does.
+    pub fn to_int32_modular(&mut self, a: u64) -> u64 {
+        let format = DOUBLE;
+        if format.is_nan(a) || format.is_infinite(a) {
+            self.flags |= FLAG_NV;
+            return 0;
+        }
+        if format.is_zero(a) {
+            return 0;
+        }
+        let negative = format.is_negative(a);
+        let (significand, exponent) = format.unpack(a);
+        let (magnitude, inexact) = match exponent {
+            _ if exponent >= 64 => (0, false),
+            _ if exponent >= 0 => ((significand << exponent) & 0xffffffff, false),
+            _ => {
+                let (magnitude, inexact) =
+                    shift_round(significand, -exponent, negative, RoundingMode::TowardZero);
+                (magnitude & 0xffffffff, inexact)
+            }
+        };
+        let mut checker = SoftFloat::new(RoundingMode::TowardZero);
+        checker.to_integer(format, a, 32, true);
+        self.flags |= match checker.flags & FLAG_NV {
+            0 if inexact => FLAG_NX,
+            0 => 0,
+            _ => FLAG_NV,
+        };
+        let value = match negative {
+            true => (magnitude as u32).wrapping_neg(),
+            false => magnitude as u32,
+        };
+        value as i32 as i64 as u64
+    }
+}
+
+/// Returns the constant which FLI loads for the index in rs1 (Zfa).
+pub fn load_immediate(format: Format, index: u8) -> u64 {
+    // significand and exponent of 2..=29.
+    const VALUES: [(u128, i32); 28] = [
+        (1, -16),
+        (1, -15),
+        (1, -8),
+        (1, -7),
+        (1, -4),
+        (1, -3),
+        (1, -2),
+        (5, -4),
+        (3, -3),
+        (7, -4),
+        (1, -1),
+        (5, -3),
+        (3, -2),
+        (7, -3),
+        (1, 0),
+        (5, -2),
+        (3, -1),
+        (7, -2),
+        (1, 1),
+        (5, -1),
+        (3, 0),
+        (1, 2),
+        (1, 3),
+        (1, 4),
+        (1, 7),
+        (1, 8),
+        (1, 15),
+        (1, 16),
+    ];
+    match index {
+        0 => SoftFloat::new(RoundingMode::NearestEven).from_integer(format, -1i64 as u64, true),
+        1 => format.min_normal(),
+        30 => format.infinity(false),
+        31 => format.canonical_nan(),
+        _ => {
+            // 2^16 overflows to infinity in half precision.
+            let (significand, exponent) = VALUES[index as usize - 2];
+            SoftFloat::new(RoundingMode::NearestEven).round_pack(
+                format,
+                false,
+                exponent,
+                significand,
+            )
+        }
+    }
+}
@@ -0,0 +1,234 @@
+// Execution statistics of the hart.
+//
+// The counters are gathered while they are enabled: the cycles by the
+// privilege mode, the retired instructions by the mnemonic, the traps by the
+// cause, the loads and the stores by the memory or the device, and the page
+// table walks. The TLB is not modeled, so every address translation in Sv32 or
+// Sv39 walks the page table. The cycles which reverse execution executes again
+// are not counted again.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cpu::cpu::{Privilege, StepResult};
+use crate::cpu::trap::{Exception, Interrupt};
+
+/// Loads and stores of a memory or a device.
+#[derive(Clone, Debug, Default)]
+pub struct RegionAccesses {
+    pub loads: u64,
+    pub stores: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    enabled: bool,
+    /// the cycles are executed again for reverse execution.
+    replaying: bool,
+    pub cycles: u64,
+    /// cycles by the privilege mode, indexed by `Privilege as usize`.
+    pub privilege_cycles: [u64; 4],
+    /// cycles in which the hart waited for an interrupt or a reservation,
+    /// which are included in the cycles of the privilege mode.
+    pub stall_cycles: u64,
+    /// cycles in which the hart was halted in debug mode.
+    pub halted_cycles: u64,
+    pub instructions: u64,
+    pub compressed_instructions: u64,
+    /// retired instructions by the mnemonic. A compressed instruction counts
+    /// as the one it expands to.
+    pub mnemonics: HashMap<&'static str, u64>,
+    /// loads and stores by the name of the memory or the device.
+    pub regions: HashMap<&'static str, RegionAccesses>,
+    pub exceptions: Vec<(Exception, u64)>,
+    pub interrupts: Vec<(Interrupt, u64)>,
+    /// address translations which walked the page table.
+    pub page_walks: u64,
+    /// page table entries read by the walks.
+    pub pte_reads: u64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Starts or stops gathering the counters. The counters are kept.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Stops counting while the cycles counted before are executed again.
+    pub fn set_replaying(&mut self, replaying: bool) {
+        self.replaying = replaying;
+    }
+
+    /// Returns whether the current cycle is counted.
+    pub fn is_counting(&self) -> bool {
+        self.enabled && !self.replaying
+    }
+
+    /// Resets the counters.
+    pub fn clear(&mut self) {
+        *self = Stats {
+            enabled: self.enabled,
+            replaying: self.replaying,
+            ..Stats::default()
+        };
+    }
+
+    /// Counts a cycle of the hart, which started in the privilege mode.
+    pub fn record_cycle(
+        &mut self,
+        privilege: &Privilege,
+        halted: bool,
+        stalled: bool,
+        result: &StepResult,
+    ) {
+        self.cycles += 1;
+        if halted {
+            self.halted_cycles += 1;
+            return;
+        }
+        self.privilege_cycles[privilege.clone() as usize] += 1;
+        if stalled {
+            self.stall_cycles += 1;
+        }
+        if let Some(interrupt) = result.interrupt {
+            match self.interrupts.iter_mut().find(|(i, _)| *i == interrupt) {
+                Some((_, count)) => *count += 1,
+                None => self.interrupts.push((interrupt, 1)),
+            }
+        }
+        if let Some(trap) = result.exception.as_ref() {
+            match self
+                .exceptions
+                .iter_mut()
+                .find(|(e, _)| *e == trap.exception)
+            {
+                Some((_, count)) => *count += 1,
+                None => self.exceptions.push((trap.exception, 1)),
+            }
+        } else if let Some(instruction) = result.instruction.as_ref() {
+            self.instructions += 1;
+            if instruction.length == 2 {
+                self.compressed_instructions += 1;
+            }
+            *self.mnemonics.entry(instruction.mnemonic).or_insert(0) += 1;
+        }
+    }
+
+    /// Counts a load or a store performed on the region.
+    pub fn record_access(&mut self, region: &'static str, write: bool) {
+        let accesses = self.regions.entry(region).or_default();
+        match write {
+            true => accesses.stores += 1,
+            false => accesses.loads += 1,
+        }
+    }
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    count as f64 * 100.0 / total.max(1) as f64
+}
+
+/// Report of the counters, in the order of the most frequent ones.
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Cycles: {}", self.cycles)?;
+        for (name, privilege) in [
+            ("M-mode", Privilege::Machine),
+            ("S-mode", Privilege::Supervisor),
+            ("U-mode", Privilege::User),
+        ] {
+            let cycles = self.privilege_cycles[privilege as usize];
+            writeln!(
+                f,
+                "  {:<24} {:>14} {:>7.2}%",
+                name,
+                cycles,
+                percent(cycles, self.cycles)
+            )?;
+        }
+        writeln!(
+            f,
+            "  {:<24} {:>14} {:>7.2}%",
+            "stalled",
+            self.stall_cycles,
+            percent(self.stall_cycles, self.cycles)
+        )?;
+        writeln!(
+            f,
+            "  {:<24} {:>14} {:>7.2}%",
+            "halted",
+            self.halted_cycles,
+            percent(self.halted_cycles, self.cycles)
+        )?;
+
+        writeln!(
+            f,
+            "Retired instructions: {} (compressed {:.2}%, IPC {:.3})",
+            self.instructions,
+            percent(self.compressed_instructions, self.instructions),
+            self.instructions as f64 / self.cycles.max(1) as f64
+        )?;
+        let mut mnemonics: Vec<(&str, u64)> = self
+            .mnemonics
+            .iter()
+            .map(|(mnemonic, count)| (*mnemonic, *count))
+            .collect();
+        mnemonics.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        for (mnemonic, count) in mnemonics {
+            writeln!(
+                f,
+                "  {:<24} {:>14} {:>7.2}%",
+                mnemonic,
+                count,
+                percent(count, self.instructions)
+            )?;
+        }
+
+        writeln!(f, "Loads/stores:")?;
+        let mut regions: Vec<(&str, &RegionAccesses)> = self
+            .regions
+            .iter()
+            .map(|(region, accesses)| (*region, accesses))
+            .collect();
+        regions.sort_by(|a, b| {
+            (b.1.loads + b.1.stores)
+                .cmp(&(a.1.loads + a.1.stores))
+                .then(a.0.cmp(b.0))
+        });
+        for (region, accesses) in regions {
+            writeln!(
+                f,
+                "  {:<24} {:>14} loads {:>14} stores",
+                region, accesses.loads, accesses.stores
+            )?;
+        }
+
+        writeln!(f, "Exceptions:")?;
+        let mut exceptions = self.exceptions.clone();
+        exceptions.sort_by_key(|(_, count)| Reverse(*count));
+        for (exception, count) in exceptions {
+            writeln!(f, "  {:<24} {:>14}", format!("{:?}", exception), count)?;
+        }
+        writeln!(f, "Interrupts:")?;
+        let mut interrupts = self.interrupts.clone();
+        interrupts.sort_by_key(|(_, count)| Reverse(*count));
+        for (interrupt, count) in interrupts {
+            writeln!(f, "  {:<24} {:>14}", format!("{:?}", interrupt), count)?;
+        }
+
+        write!(
+            f,
+            "Page walks: {} ({} PTE reads, the TLB is not modeled)",
+            self.page_walks, self.pte_reads
+        )
+    }
+}
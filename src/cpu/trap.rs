@@ -1,10 +1,11 @@
+#[derive(Clone, Debug)]
 pub struct Trap {
     pub exception: Exception,
     pub value: u64,
 }
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Exception {
     InstructionAddressMisaligned = 0,
     InstructionAccessFault = 1,
@@ -25,7 +26,7 @@ pub enum Exception {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Interrupt {
     // Interrupts
     UserSoftware = 0,
@@ -0,0 +1,338 @@
+use crate::cpu::cpu::{Privilege, Xlen};
+use crate::cpu::cpu_csr::*;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
+
+/// Number of triggers implemented by the trigger module (Sdtrig).
+pub const TRIGGER_COUNT: usize = 4;
+
+// tdata1.type values.
+pub const TRIGGER_TYPE_MCONTROL6: u64 = 6;
+const TRIGGER_TYPE_DISABLED: u64 = 15;
+
+// mcontrol6 fields below tdata1.dmode.
+pub const MCONTROL6_LOAD: u64 = 0x00000001;
+pub const MCONTROL6_STORE: u64 = 0x00000002;
+pub const MCONTROL6_EXECUTE: u64 = 0x00000004;
+pub const MCONTROL6_U: u64 = 0x00000008;
+pub const MCONTROL6_S: u64 = 0x00000010;
+pub const MCONTROL6_M: u64 = 0x00000040;
+pub const MCONTROL6_MATCH: u64 = 0x00000780;
+pub const MCONTROL6_CHAIN: u64 = 0x00000800;
+pub const MCONTROL6_ACTION: u64 = 0x0000f000;
+pub const MCONTROL6_SIZE: u64 = 0x00070000;
+pub const MCONTROL6_SELECT: u64 = 0x00200000;
+pub const MCONTROL6_HIT0: u64 = 0x00400000;
+
+/// Access which is checked against the triggers.
+pub enum TriggerAccess {
+    Execute,
+    Load,
+    Store,
+}
+
+#[derive(Clone, Default)]
+struct Trigger {
+    /// type=6 (mcontrol6), otherwise type=15 (disabled).
+    enabled: bool,
+    /// only debug mode can write the trigger.
+    dmode: bool,
+    /// mcontrol6 fields below dmode.
+    control: u64,
+    tdata2: u64,
+}
+
+/// Trigger module, which implements address/data match triggers (mcontrol6)
+/// on instruction fetches, loads and stores. It is accessed through the
+/// tselect/tdata1/tdata2/tdata3/tinfo CSRs.
+#[derive(Clone)]
+pub struct Triggers {
+    triggers: [Trigger; TRIGGER_COUNT],
+    tselect: usize,
+    /// no trigger fires in debug mode.
+    debug_mode: bool,
+    /// a fired trigger requested to enter debug mode (action=1).
+    debug_request: bool,
+}
+
+impl Trigger {
+    fn read_tdata1(&self, xlen: &Xlen) -> u64 {
+        let shift = match xlen {
+            Xlen::X32 => 28,
+            Xlen::X64 => 60,
+        };
+        let trigger_type = match self.enabled {
+            true => TRIGGER_TYPE_MCONTROL6,
+            false => TRIGGER_TYPE_DISABLED,
+        };
+        trigger_type << shift | (self.dmode as u64) << (shift - 1) | self.control
+    }
+
+    /// tdata1 is WARL. Unsupported types disable the trigger, and unsupported
+    /// match, size and action values are replaced with 0.
+    fn write_tdata1(&mut self, data: u64, xlen: &Xlen, debug_mode: bool) {
+        let shift = match xlen {
+            Xlen::X32 => 28,
+            Xlen::X64 => 60,
+        };
+        let trigger_type = (data >> shift) & 0xf;
+        let dmode = (data >> (shift - 1)) & 1 != 0 && debug_mode;
+        if trigger_type != TRIGGER_TYPE_MCONTROL6 {
+            *self = Trigger {
+                dmode,
+                ..Default::default()
+            };
+            return;
+        }
+        let mut control = data
+            & (MCONTROL6_LOAD
+                | MCONTROL6_STORE
+                | MCONTROL6_EXECUTE
+                | MCONTROL6_U
+                | MCONTROL6_S
+                | MCONTROL6_M
+                | MCONTROL6_MATCH
+                | MCONTROL6_CHAIN
+                | MCONTROL6_ACTION
+                | MCONTROL6_SIZE
+                | MCONTROL6_SELECT
+                | MCONTROL6_HIT0);
+        match (control & MCONTROL6_MATCH) >> 7 {
+            0..=5 | 8 | 9 | 12 | 13 => {}
+            _ => control &= !MCONTROL6_MATCH,
+        }
+        match (control & MCONTROL6_SIZE) >> 16 {
+            0..=3 | 5 => {}
+            _ => control &= !MCONTROL6_SIZE,
+        }
+        // entering debug mode (action=1) is only allowed to debug-mode triggers.
+        match (control & MCONTROL6_ACTION) >> 12 {
+            0 => {}
+            1 if dmode => {}
+            _ => control &= !MCONTROL6_ACTION,
+        }
+        self.enabled = true;
+        self.dmode = dmode;
+        self.control = control;
+    }
+
+    fn matches(
+        &self,
+        access: &TriggerAccess,
+        address: u64,
+        size: u64,
+        data: Option<u64>,
+        privilege: &Privilege,
+        xlen: &Xlen,
+    ) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let mode = match privilege {
+            Privilege::User => MCONTROL6_U,
+            Privilege::Supervisor => MCONTROL6_S,
+            _ => MCONTROL6_M,
+        };
+        let access = match access {
+            TriggerAccess::Execute => MCONTROL6_EXECUTE,
+            TriggerAccess::Load => MCONTROL6_LOAD,
+            TriggerAccess::Store => MCONTROL6_STORE,
+        };
+        if self.control & mode == 0 || self.control & access == 0 {
+            return false;
+        }
+
+        // size=0 matches any access size. Instruction fetches match any size.
+        let trigger_size = match (self.control & MCONTROL6_SIZE) >> 16 {
+            1 => 1,
+            2 => 2,
+            3 => 4,
+            5 => 8,
+            _ => 0,
+        };
+        if trigger_size != 0 && size != 0 && trigger_size != size {
+            return false;
+        }
+
+        // select=0 compares the address before the access, and select=1 compares the data.
+        let value = match (self.control & MCONTROL6_SELECT != 0, data) {
+            (false, None) => address,
+            (true, Some(data)) => match size {
+                1 | 2 | 4 => data & ((1 << (size * 8)) - 1),
+                _ => data,
+            },
+            _ => return false,
+        };
+        let match_type = (self.control & MCONTROL6_MATCH) >> 7;
+        let matched = self.compare(match_type & 0x7, value, xlen);
+        match match_type & 0x8 {
+            0 => matched,
+            _ => !matched,
+        }
+    }
+
+    fn compare(&self, match_type: u64, value: u64, xlen: &Xlen) -> bool {
+        let half = match xlen {
+            Xlen::X32 => 16,
+            Xlen::X64 => 32,
+        };
+        let low_mask = (1u64 << half) - 1;
+        match match_type {
+            // equal
+            0 => value == self.tdata2,
+            // NAPOT: the trailing ones of tdata2 and the bit above them are ignored.
+            1 => {
+                let bits = self.tdata2.trailing_ones() + 1;
+                match bits {
+                    64..=65 => true,
+                    _ => (value >> bits) == (self.tdata2 >> bits),
+                }
+            }
+            // greater than or equal
+            2 => value >= self.tdata2,
+            // less than
+            3 => value < self.tdata2,
+            // the low/high half of the value, masked with the high half of tdata2,
+            // equals the low half of tdata2.
+            4 => value & (self.tdata2 >> half) & low_mask == self.tdata2 & low_mask,
+            5 => (value >> half) & (self.tdata2 >> half) & low_mask == self.tdata2 & low_mask,
+            _ => false,
+        }
+    }
+}
+
+impl Triggers {
+    pub fn new() -> Self {
+        Triggers {
+            triggers: Default::default(),
+            tselect: 0,
+            debug_mode: false,
+            debug_request: false,
+        }
+    }
+
+    pub fn set_debug_mode(&mut self, debug_mode: bool) {
+        self.debug_mode = debug_mode;
+    }
+
+    /// Returns and clears the request to enter debug mode made by a fired trigger.
+    pub fn take_debug_request(&mut self) -> bool {
+        let request = self.debug_request;
+        self.debug_request = false;
+        request
+    }
+
+    pub fn read(&self, addr: u16, xlen: &Xlen) -> u64 {
+        let trigger = &self.triggers[self.tselect];
+        match addr {
+            CSR_TSELECT => self.tselect as u64,
+            CSR_TDATA1 => trigger.read_tdata1(xlen),
+            CSR_TDATA2 => trigger.tdata2,
+            // info=mcontrol6|disabled, version=1 (Sdtrig 1.0)
+            CSR_TINFO => 1 << 24 | 1 << TRIGGER_TYPE_DISABLED | 1 << TRIGGER_TYPE_MCONTROL6,
+            // tdata3 (textra) is not implemented.
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u64, xlen: &Xlen) {
+        // tselect is WARL, so the number of triggers is detected by reading it back.
+        if addr == CSR_TSELECT {
+            if (data as usize) < TRIGGER_COUNT {
+                self.tselect = data as usize;
+            }
+            return;
+        }
+        let debug_mode = self.debug_mode;
+        let trigger = &mut self.triggers[self.tselect];
+        if trigger.dmode && !debug_mode {
+            return;
+        }
+        match addr {
+            CSR_TDATA1 => trigger.write_tdata1(data, xlen, debug_mode),
+            CSR_TDATA2 => {
+                trigger.tdata2 = match xlen {
+                    Xlen::X32 => data & 0xffffffff,
+                    Xlen::X64 => data,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks an access against the triggers. Chained triggers only fire when all
+    /// of them match. Returns true when a trigger fires; the hart then takes a
+    /// breakpoint exception, or enters debug mode if `take_debug_request` is true.
+    pub fn check(
+        &mut self,
+        access: TriggerAccess,
+        address: u64,
+        size: u64,
+        data: Option<u64>,
+        privilege: &Privilege,
+        xlen: &Xlen,
+    ) -> bool {
+        if self.debug_mode || !self.triggers.iter().any(|t| t.enabled) {
+            return false;
+        }
+        let mut fired = false;
+        let mut chain_start = 0;
+        let mut chain_matched = true;
+        for i in 0..TRIGGER_COUNT {
+            chain_matched &=
+                self.triggers[i].matches(&access, address, size, data, privilege, xlen);
+            if self.triggers[i].control & MCONTROL6_CHAIN != 0 && i + 1 < TRIGGER_COUNT {
+                continue;
+            }
+            if chain_matched {
+                for trigger in self.triggers[chain_start..=i].iter_mut() {
+                    trigger.control |= MCONTROL6_HIT0;
+                }
+                if (self.triggers[i].control & MCONTROL6_ACTION) >> 12 == 1 {
+                    self.debug_request = true;
+                }
+                fired = true;
+            }
+            chain_start = i + 1;
+            chain_matched = true;
+        }
+        fired
+    }
+}
+
+impl Default for Triggers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot for Triggers {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("triggers");
+        for trigger in self.triggers.iter() {
+            writer.write_bool(trigger.enabled);
+            writer.write_bool(trigger.dmode);
+            writer.write_u64(trigger.control);
+            writer.write_u64(trigger.tdata2);
+        }
+        writer.write_u64(self.tselect as u64);
+        writer.write_bool(self.debug_mode);
+        writer.write_bool(self.debug_request);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("triggers")?;
+        for trigger in self.triggers.iter_mut() {
+            trigger.enabled = reader.read_bool()?;
+            trigger.dmode = reader.read_bool()?;
+            trigger.control = reader.read_u64()?;
+            trigger.tdata2 = reader.read_u64()?;
+        }
+        self.tselect = reader.read_u64()? as usize;
+        if self.tselect >= TRIGGER_COUNT {
+            return Err(reader.corrupted());
+        }
+        self.debug_mode = reader.read_bool()?;
+        self.debug_request = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,435 @@
+use crate::cpu::cpu::{AbstractCommand, CommandError, Cpu, DebugCause, Xlen};
+
+// Debug Module registers, which are accessed through the DMI.
+pub const DM_DATA0: u32 = 0x04;
+pub const DM_DMCONTROL: u32 = 0x10;
+pub const DM_DMSTATUS: u32 = 0x11;
+pub const DM_HARTINFO: u32 = 0x12;
+pub const DM_ABSTRACTCS: u32 = 0x16;
+pub const DM_COMMAND: u32 = 0x17;
+pub const DM_ABSTRACTAUTO: u32 = 0x18;
+pub const DM_PROGBUF0: u32 = 0x20;
+pub const DM_SBCS: u32 = 0x38;
+pub const DM_SBADDRESS0: u32 = 0x39;
+pub const DM_SBADDRESS1: u32 = 0x3a;
+pub const DM_SBDATA0: u32 = 0x3c;
+pub const DM_SBDATA1: u32 = 0x3d;
+pub const DM_HALTSUM0: u32 = 0x40;
+
+const DATA_COUNT: usize = 4;
+const PROGBUF_SIZE: usize = 8;
+
+// dmcontrol fields.
+const DMCONTROL_DMACTIVE: u32 = 0x00000001;
+const DMCONTROL_NDMRESET: u32 = 0x00000002;
+const DMCONTROL_CLRRESETHALTREQ: u32 = 0x00000004;
+const DMCONTROL_SETRESETHALTREQ: u32 = 0x00000008;
+const DMCONTROL_ACKHAVERESET: u32 = 0x10000000;
+const DMCONTROL_RESUMEREQ: u32 = 0x40000000;
+const DMCONTROL_HALTREQ: u32 = 0x80000000;
+
+// sbcs fields.
+const SBCS_SBERROR: u32 = 0x00007000;
+const SBCS_SBREADONDATA: u32 = 0x00008000;
+const SBCS_SBAUTOINCREMENT: u32 = 0x00010000;
+const SBCS_SBACCESS: u32 = 0x000e0000;
+const SBCS_SBREADONADDR: u32 = 0x00100000;
+
+/// Debug Module of the debug spec 1.0, which controls the hart for an external
+/// debugger. The registers are accessed through the DMI (Debug Module Interface),
+/// e.g. by the JTAG DTM. It supports the Access Register and Access Memory
+/// abstract commands, a program buffer and System Bus Access.
+pub struct DebugModule {
+    dmactive: bool,
+    ndmreset: bool,
+    haltreq: bool,
+    resethaltreq: bool,
+    resumeack: bool,
+    havereset: bool,
+    data: [u32; DATA_COUNT],
+    progbuf: [u32; PROGBUF_SIZE],
+    /// the last command, which is executed again by abstractauto.
+    command: u32,
+    cmderr: u32,
+    abstractauto: u32,
+    sbcs: u32,
+    sbaddress: u64,
+    sbdata: u64,
+    /// hart reset by ndmreset starts from this address.
+    reset_pc: u64,
+}
+
+impl DebugModule {
+    pub fn new() -> Self {
+        DebugModule {
+            dmactive: false,
+            ndmreset: false,
+            haltreq: false,
+            resethaltreq: false,
+            resumeack: false,
+            havereset: true,
+            data: [0; DATA_COUNT],
+            progbuf: [0; PROGBUF_SIZE],
+            command: 0,
+            cmderr: 0,
+            abstractauto: 0,
+            sbcs: 0,
+            sbaddress: 0,
+            sbdata: 0,
+            reset_pc: 0,
+        }
+    }
+
+    pub fn set_reset_pc(&mut self, pc: u64) {
+        self.reset_pc = pc;
+    }
+
+    /// Resets the registers when dmactive is cleared.
+    fn deactivate(&mut self) {
+        *self = DebugModule {
+            havereset: self.havereset,
+            reset_pc: self.reset_pc,
+            ..DebugModule::new()
+        };
+    }
+
+    pub fn read(&mut self, cpu: &mut Cpu, addr: u32) -> u32 {
+        match addr {
+            0x04..=0x0f => {
+                let index = (addr - DM_DATA0) as usize;
+                // the command runs after the access, which returns the previous result.
+                let data = self.data.get(index).copied().unwrap_or(0);
+                self.auto_execute(cpu, self.abstractauto & (1 << index) != 0);
+                data
+            }
+            DM_DMCONTROL => {
+                (self.haltreq as u32) << 31 | (self.ndmreset as u32) << 1 | self.dmactive as u32
+            }
+            DM_DMSTATUS => {
+                let halted = cpu.is_debug_mode() as u32;
+                let running = !cpu.is_debug_mode() as u32;
+                // version=3 (1.0), authenticated, hasresethaltreq and impebreak.
+                3 | 1 << 5
+                    | 1 << 7
+                    | halted << 8
+                    | halted << 9
+                    | running << 10
+                    | running << 11
+                    | (self.resumeack as u32) << 16
+                    | (self.resumeack as u32) << 17
+                    | (self.havereset as u32) << 18
+                    | (self.havereset as u32) << 19
+                    | 1 << 22
+            }
+            // nscratch=2 (dscratch0 and dscratch1)
+            DM_HARTINFO => 2 << 20,
+            DM_ABSTRACTCS => (PROGBUF_SIZE as u32) << 24 | self.cmderr << 8 | DATA_COUNT as u32,
+            DM_COMMAND => 0,
+            DM_ABSTRACTAUTO => self.abstractauto,
+            0x20..=0x2f => {
+                let index = (addr - DM_PROGBUF0) as usize;
+                let data = self.progbuf.get(index).copied().unwrap_or(0);
+                self.auto_execute(cpu, self.abstractauto & (1 << (index + 16)) != 0);
+                data
+            }
+            DM_SBCS => {
+                let sbasize = match cpu.csr.get_mxl() {
+                    Xlen::X32 => 32,
+                    Xlen::X64 => 64,
+                };
+                // sbversion=1, and 8/16/32/64-bit accesses are supported.
+                1 << 29 | self.sbcs | sbasize << 5 | 0xf
+            }
+            DM_SBADDRESS0 => self.sbaddress as u32,
+            DM_SBADDRESS1 => (self.sbaddress >> 32) as u32,
+            DM_SBDATA0 => {
+                let data = self.sbdata as u32;
+                if self.sbcs & SBCS_SBREADONDATA != 0 {
+                    self.system_bus_read(cpu);
+                }
+                data
+            }
+            DM_SBDATA1 => (self.sbdata >> 32) as u32,
+            DM_HALTSUM0 => cpu.is_debug_mode() as u32,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, cpu: &mut Cpu, addr: u32, data: u32) {
+        if addr == DM_DMCONTROL {
+            self.write_dmcontrol(cpu, data);
+            return;
+        }
+        if !self.dmactive {
+            return;
+        }
+        match addr {
+            0x04..=0x0f => {
+                let index = (addr - DM_DATA0) as usize;
+                if index < DATA_COUNT {
+                    self.data[index] = data;
+                }
+                self.auto_execute(cpu, self.abstractauto & (1 << index) != 0);
+            }
+            DM_ABSTRACTCS => self.cmderr &= !((data >> 8) & 0x7),
+            // commands are ignored until cmderr is cleared.
+            DM_COMMAND if self.cmderr == 0 => {
+                self.command = data;
+                self.execute_command(cpu);
+            }
+            DM_ABSTRACTAUTO => {
+                self.abstractauto =
+                    data & (((1 << DATA_COUNT) - 1) | ((1 << PROGBUF_SIZE) - 1) << 16)
+            }
+            0x20..=0x2f => {
+                let index = (addr - DM_PROGBUF0) as usize;
+                if index < PROGBUF_SIZE {
+                    self.progbuf[index] = data;
+                }
+                self.auto_execute(cpu, self.abstractauto & (1 << (index + 16)) != 0);
+            }
+            DM_SBCS => {
+                let mask =
+                    SBCS_SBREADONDATA | SBCS_SBAUTOINCREMENT | SBCS_SBACCESS | SBCS_SBREADONADDR;
+                let sberror = (self.sbcs & SBCS_SBERROR) & !(data & SBCS_SBERROR);
+                self.sbcs = (data & mask) | sberror;
+            }
+            DM_SBADDRESS0 => {
+                self.sbaddress = (self.sbaddress & !0xffffffff) | data as u64;
+                if self.sbcs & SBCS_SBREADONADDR != 0 {
+                    self.system_bus_read(cpu);
+                }
+            }
+            DM_SBADDRESS1 => {
+                self.sbaddress = (self.sbaddress & 0xffffffff) | (data as u64) << 32;
+            }
+            DM_SBDATA0 => {
+                self.sbdata = (self.sbdata & !0xffffffff) | data as u64;
+                self.system_bus_write(cpu);
+            }
+            DM_SBDATA1 => {
+                self.sbdata = (self.sbdata & 0xffffffff) | (data as u64) << 32;
+            }
+            _ => {}
+        }
+    }
+
+    fn write_dmcontrol(&mut self, cpu: &mut Cpu, data: u32) {
+        if data & DMCONTROL_DMACTIVE == 0 {
+            self.deactivate();
+            return;
+        }
+        self.dmactive = true;
+        self.haltreq = data & DMCONTROL_HALTREQ != 0;
+        if data & DMCONTROL_ACKHAVERESET != 0 {
+            self.havereset = false;
+        }
+        if data & DMCONTROL_SETRESETHALTREQ != 0 {
+            self.resethaltreq = true;
+        }
+        if data & DMCONTROL_CLRRESETHALTREQ != 0 {
+            self.resethaltreq = false;
+        }
+
+        // the system is reset when ndmreset is deasserted.
+        let ndmreset = data & DMCONTROL_NDMRESET != 0;
+        if self.ndmreset && !ndmreset {
+            self.reset_hart(cpu);
+        }
+        self.ndmreset = ndmreset;
+
+        if self.haltreq {
+            cpu.debug_halt();
+        } else if data & DMCONTROL_RESUMEREQ != 0 && cpu.is_debug_mode() {
+            self.resumeack = false;
+            cpu.debug_resume();
+            self.resumeack = true;
+        }
+    }
+
+    fn reset_hart(&mut self, cpu: &mut Cpu) {
+        let xlen = cpu.csr.get_mxl();
+        cpu.reset();
+        cpu.set_xlen(xlen);
+        cpu.set_pc(self.reset_pc);
+        self.havereset = true;
+        if self.resethaltreq {
+            cpu.enter_debug_mode(DebugCause::ResetHaltRequest, self.reset_pc);
+        }
+    }
+
+    fn auto_execute(&mut self, cpu: &mut Cpu, enabled: bool) {
+        if enabled && self.cmderr == 0 {
+            self.execute_command(cpu);
+        }
+    }
+
+    fn execute_command(&mut self, cpu: &mut Cpu) {
+        let command = self.command;
+        let result = match command >> 24 {
+            0 => self.access_register(cpu, command),
+            2 => self.access_memory(cpu, command),
+            _ => Err(CommandError::NotSupported),
+        };
+        if let Err(e) = result {
+            self.cmderr = e as u32;
+        }
+    }
+
+    /// Reads the argument `index` of `size` bytes from the data registers.
+    fn get_argument(&self, index: usize, size: u64) -> u64 {
+        match size {
+            8 => self.data[index * 2] as u64 | (self.data[index * 2 + 1] as u64) << 32,
+            _ => self.data[index] as u64,
+        }
+    }
+
+    fn set_argument(&mut self, index: usize, size: u64, value: u64) {
+        match size {
+            8 => {
+                self.data[index * 2] = value as u32;
+                self.data[index * 2 + 1] = (value >> 32) as u32;
+            }
+            _ => self.data[index] = value as u32,
+        }
+    }
+
+    /// Access Register command. Only 32-bit and 64-bit accesses are supported,
+    /// and a 64-bit access is not supported for integer registers and CSRs of RV32.
+    fn access_register(&mut self, cpu: &mut Cpu, command: u32) -> Result<(), CommandError> {
+        let regno = (command & 0xffff) as u16;
+        let write = command & (1 << 16) != 0;
+        let transfer = command & (1 << 17) != 0;
+        let postexec = command & (1 << 18) != 0;
+        let postincrement = command & (1 << 19) != 0;
+        let size = match (command >> 20) & 0x7 {
+            2 => 4,
+            3 => 8,
+            _ => return Err(CommandError::NotSupported),
+        };
+        if transfer {
+            let fpr = (0x1020..=0x103f).contains(&regno);
+            if size == 8 && cpu.csr.get_mxl() == Xlen::X32 && !fpr {
+                return Err(CommandError::NotSupported);
+            }
+            match write {
+                true => {
+                    let value = self.get_argument(0, size);
+                    cpu.execute_abstract_command(&AbstractCommand::WriteRegister(regno, value))?;
+                }
+                false => {
+                    let value =
+                        cpu.execute_abstract_command(&AbstractCommand::ReadRegister(regno))?;
+                    self.set_argument(0, size, value);
+                }
+            }
+        }
+        if postincrement {
+            self.command = (command & !0xffff) | regno.wrapping_add(1) as u32;
+        }
+        if postexec {
+            cpu.execute_program_buffer(&self.progbuf)?;
+        }
+        Ok(())
+    }
+
+    /// Access Memory command. arg0 is the data and arg1 is the MXLEN-bit address.
+    fn access_memory(&mut self, cpu: &mut Cpu, command: u32) -> Result<(), CommandError> {
+        let virtual_address = command & (1 << 23) != 0;
+        let write = command & (1 << 16) != 0;
+        let postincrement = command & (1 << 19) != 0;
+        let size = match (command >> 20) & 0x7 {
+            0 => 1,
+            1 => 2,
+            2 => 4,
+            3 => 8,
+            _ => return Err(CommandError::NotSupported),
+        };
+        let address_size = match cpu.csr.get_mxl() {
+            Xlen::X32 => 4,
+            Xlen::X64 => 8,
+        };
+        let address = self.get_argument(1, address_size);
+        match write {
+            true => {
+                let data = self.get_argument(0, size);
+                cpu.execute_abstract_command(&AbstractCommand::WriteMemory {
+                    address,
+                    size: size as u8,
+                    virtual_address,
+                    data,
+                })?;
+            }
+            false => {
+                let data = cpu.execute_abstract_command(&AbstractCommand::ReadMemory {
+                    address,
+                    size: size as u8,
+                    virtual_address,
+                })?;
+                self.set_argument(0, size, data);
+            }
+        }
+        if postincrement {
+            self.set_argument(1, address_size, address.wrapping_add(size));
+        }
+        Ok(())
+    }
+
+    fn system_bus_size(&self) -> Option<u64> {
+        match (self.sbcs & SBCS_SBACCESS) >> 17 {
+            0 => Some(1),
+            1 => Some(2),
+            2 => Some(4),
+            3 => Some(8),
+            _ => None,
+        }
+    }
+
+    /// System Bus Access reads the physical memory without the hart.
+    fn system_bus_read(&mut self, cpu: &mut Cpu) {
+        let size = match self.system_bus_size() {
+            Some(size) => size,
+            None => return self.sbcs |= 4 << 12,
+        };
+        let bus = cpu.mmu.get_bus();
+        let result = match size {
+            1 => bus.read8(self.sbaddress).map(|d| d as u64),
+            2 => bus.read16(self.sbaddress).map(|d| d as u64),
+            4 => bus.read32(self.sbaddress).map(|d| d as u64),
+            _ => bus.read64(self.sbaddress),
+        };
+        match result {
+            Ok(data) => self.sbdata = data,
+            Err(()) => return self.sbcs |= 2 << 12,
+        }
+        if self.sbcs & SBCS_SBAUTOINCREMENT != 0 {
+            self.sbaddress = self.sbaddress.wrapping_add(size);
+        }
+    }
+
+    fn system_bus_write(&mut self, cpu: &mut Cpu) {
+        let size = match self.system_bus_size() {
+            Some(size) => size,
+            None => return self.sbcs |= 4 << 12,
+        };
+        let bus = cpu.mmu.get_bus();
+        let result = match size {
+            1 => bus.write8(self.sbaddress, self.sbdata as u8),
+            2 => bus.write16(self.sbaddress, self.sbdata as u16),
+            4 => bus.write32(self.sbaddress, self.sbdata as u32),
+            _ => bus.write64(self.sbaddress, self.sbdata),
+        };
+        if result.is_err() {
+            return self.sbcs |= 2 << 12;
+        }
+        if self.sbcs & SBCS_SBAUTOINCREMENT != 0 {
+            self.sbaddress = self.sbaddress.wrapping_add(size);
+        }
+    }
+}
+
+impl Default for DebugModule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
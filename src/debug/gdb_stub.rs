@@ -0,0 +1,817 @@
+use crate::cpu::cpu::{AbstractCommand, CommandError, Xlen};
+use crate::cpu::cpu_csr::*;
+use crate::cpu::mmu::Watchpoint;
+use crate::cpu::trigger::*;
+use crate::emulator::{Breakpoint, Emulator, StopConditions, StopReason};
+
+/// Number of cycles executed between the checks of Ctrl-C while the target runs.
+const POLL_CYCLES: u64 = 1000;
+
+const PACKET_SIZE: usize = 0x1000;
+
+// GDB register numbers of RISC-V.
+const REGNUM_PC: usize = 32;
+const REGNUM_FIRST_FPR: usize = 33;
+const REGNUM_LAST_FPR: usize = 64;
+const REGNUM_FIRST_CSR: usize = 65;
+const REGNUM_LAST_CSR: usize = REGNUM_FIRST_CSR + 0xfff;
+const REGNUM_PRIV: usize = REGNUM_FIRST_CSR + 0x1000;
+
+const GPR_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+const FPR_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+/// CSRs described in the target description. fflags, frm and fcsr are in the FPU feature.
+const CSRS: [(&str, u16); 31] = [
+    ("sstatus", CSR_SSTATUS),
+    ("sie", CSR_SIE),
+    ("stvec", CSR_STVEC),
+    ("senvcfg", CSR_SENVCFG),
+    ("sscratch", CSR_SSCRATCH),
+    ("sepc", CSR_SEPC),
+    ("scause", CSR_SCAUSE),
+    ("stval", CSR_STVAL),
+    ("sip", CSR_SIP),
+    ("satp", CSR_SPTBR),
+    ("mstatus", CSR_MSTATUS),
+    ("misa", CSR_MISA),
+    ("medeleg", CSR_MEDELEG),
+    ("mideleg", CSR_MIDELEG),
+    ("mie", CSR_MIE),
+    ("mtvec", CSR_MTVEC),
+    ("menvcfg", CSR_MENVCFG),
+    ("mscratch", CSR_MSCRATCH),
+    ("mepc", CSR_MEPC),
+    ("mcause", CSR_MCAUSE),
+    ("mtval", CSR_MTVAL),
+    ("mip", CSR_MIP),
+    ("tselect", CSR_TSELECT),
+    ("tdata1", CSR_TDATA1),
+    ("tdata2", CSR_TDATA2),
+    ("dcsr", CSR_DCSR),
+    ("dpc", CSR_DPC),
+    ("cycle", CSR_CYCLE),
+    ("time", CSR_TIME),
+    ("mvendorid", CSR_MVENDORID),
+    ("mhartid", CSR_MHARTID),
+];
+
+/// Byte stream to GDB, e.g. a TCP or Unix domain socket.
+pub trait GdbConnection {
+    /// Waits for a byte. Returns None when the connection is closed.
+    fn read(&mut self) -> Option<u8>;
+    /// Returns a byte if it has already arrived, without waiting.
+    fn poll(&mut self) -> Option<u8>;
+    fn write(&mut self, data: &[u8]);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum HwPointKind {
+    Breakpoint,
+    WriteWatchpoint,
+    ReadWatchpoint,
+    AccessWatchpoint,
+}
+
+/// Hardware breakpoint or watchpoint, which uses one trigger or two chained triggers.
+#[derive(Clone, Copy)]
+struct HwPoint {
+    kind: HwPointKind,
+    address: u64,
+    length: u64,
+}
+
+/// GDB Remote Serial Protocol stub. The hart is halted in debug mode while GDB
+/// inspects it, and the registers and memory are accessed with abstract commands.
+/// Software breakpoints are PC breakpoints of the emulator, which leave the
+/// memory intact, and hardware breakpoints and watchpoints are implemented with
+/// the triggers. If reverse execution is enabled, the hart also runs backwards
+/// for reverse-stepi and reverse-continue (bs/bc).
+pub struct GdbStub {
+    connection: Box<dyn GdbConnection>,
+    last_packet: Vec<u8>,
+    /// addresses of the software breakpoints.
+    breakpoints: Vec<u64>,
+    /// hardware breakpoint/watchpoint using each trigger.
+    triggers: [Option<HwPoint>; TRIGGER_COUNT],
+    /// dcsr.ebreak{m,s,u} before GDB is attached.
+    dcsr_ebreak: u64,
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<u64> {
+    u64::from_str_radix(text, 16).ok()
+}
+
+/// Parses "addr,length" of m/M/Z packets.
+fn parse_address_length(text: &str) -> Option<(u64, u64)> {
+    let mut fields = text.split(',');
+    let address = parse_hex(fields.next()?)?;
+    let length = parse_hex(fields.next()?)?;
+    Some((address, length))
+}
+
+fn xlen_bytes(xlen: &Xlen) -> usize {
+    match xlen {
+        Xlen::X32 => 4,
+        Xlen::X64 => 8,
+    }
+}
+
+impl GdbStub {
+    pub fn new(connection: Box<dyn GdbConnection>) -> Self {
+        GdbStub {
+            connection,
+            last_packet: vec![],
+            breakpoints: vec![],
+            triggers: [None; TRIGGER_COUNT],
+            dcsr_ebreak: 0,
+        }
+    }
+
+    /// Halts the hart and serves GDB until it detaches or kills the target.
+    /// Returns false if GDB killed the target.
+    pub fn serve(&mut self, emu: &mut Emulator) -> bool {
+        emu.debug_halt();
+        // ebreak enters debug mode, so that software breakpoints halt the hart.
+        let dcsr = self.read_csr(emu, CSR_DCSR);
+        let ebreak = CSR_DCSR_EBREAKM | CSR_DCSR_EBREAKS | CSR_DCSR_EBREAKU;
+        self.dcsr_ebreak = dcsr & ebreak;
+        self.write_csr(emu, CSR_DCSR, dcsr | ebreak);
+
+        loop {
+            let packet = match self.read_packet() {
+                Some(packet) => packet,
+                None => {
+                    self.detach(emu);
+                    return true;
+                }
+            };
+            match packet.chars().next() {
+                Some('D') => {
+                    self.send_packet("OK");
+                    self.detach(emu);
+                    return true;
+                }
+                Some('k') => return false,
+                _ => {
+                    let response = self.handle_packet(emu, &packet);
+                    self.send_packet(&response);
+                }
+            }
+        }
+    }
+
+    /// Removes the breakpoints and resumes the hart.
+    fn detach(&mut self, emu: &mut Emulator) {
+        self.breakpoints.clear();
+        for i in 0..TRIGGER_COUNT {
+            if self.triggers[i].take().is_some() {
+                self.write_trigger(emu, i, 0, 0);
+            }
+        }
+        let dcsr = self.read_csr(emu, CSR_DCSR);
+        let ebreak = CSR_DCSR_EBREAKM | CSR_DCSR_EBREAKS | CSR_DCSR_EBREAKU;
+        self.write_csr(
+            emu,
+            CSR_DCSR,
+            (dcsr & !(ebreak | CSR_DCSR_STEP)) | self.dcsr_ebreak,
+        );
+        emu.debug_resume();
+    }
+
+    fn read_packet(&mut self) -> Option<String> {
+        loop {
+            match self.connection.read()? {
+                b'$' => {}
+                b'-' => {
+                    let packet = self.last_packet.clone();
+                    self.connection.write(&packet);
+                    continue;
+                }
+                _ => continue,
+            }
+            let mut data = vec![];
+            loop {
+                match self.connection.read()? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [self.connection.read()?, self.connection.read()?];
+            let expected = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+            match std::str::from_utf8(&checksum).ok().and_then(parse_hex) {
+                Some(checksum) if checksum == expected as u64 => {
+                    self.connection.write(b"+");
+                    return Some(String::from_utf8_lossy(&data).into_owned());
+                }
+                _ => self.connection.write(b"-"),
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.last_packet = format!("${}#{:02x}", data, checksum).into_bytes();
+        let packet = self.last_packet.clone();
+        self.connection.write(&packet);
+    }
+
+    fn handle_packet(&mut self, emu: &mut Emulator, packet: &str) -> String {
+        let command = match packet.get(..1) {
+            Some(command) => command,
+            None => return String::new(),
+        };
+        let args = &packet[1..];
+        let response = match command {
+            "?" => Some(String::from("S05")),
+            "g" => self.read_registers(emu),
+            "G" => self.write_registers(emu, args),
+            "p" => parse_hex(args).and_then(|regnum| self.read_register(emu, regnum as usize)),
+            "P" => {
+                let mut fields = args.split('=');
+                match (fields.next().and_then(parse_hex), fields.next()) {
+                    (Some(regnum), Some(value)) => self.write_register(emu, regnum as usize, value),
+                    _ => None,
+                }
+            }
+            "m" => parse_address_length(args)
+                .and_then(|(address, length)| self.read_memory(emu, address, length))
+                .map(|data| to_hex(&data)),
+            "M" => {
+                let mut fields = args.split(':');
+                match (
+                    fields.next().and_then(parse_address_length),
+                    fields.next().and_then(from_hex),
+                ) {
+                    (Some((address, _)), Some(data)) => self
+                        .write_memory(emu, address, &data)
+                        .map(|_| String::from("OK")),
+                    _ => None,
+                }
+            }
+            "c" | "s" | "C" | "S" => {
+                // "addr" of c/s resumes at the address, and signals of C/S are ignored.
+                if let ("c", Some(pc)) | ("s", Some(pc)) = (command, parse_hex(args)) {
+                    self.write_csr(emu, CSR_DPC, pc);
+                }
+                Some(self.resume(emu, command == "s" || command == "S"))
+            }
+            "b" => match args {
+                "c" => Some(self.reverse(emu, false)),
+                "s" => Some(self.reverse(emu, true)),
+                _ => return String::new(),
+            },
+            "Z" | "z" => self.handle_breakpoint(emu, command == "Z", args),
+            "H" | "T" => Some(String::from("OK")),
+            "q" => return self.handle_query(emu, packet),
+            _ => return String::new(),
+        };
+        response.unwrap_or_else(|| String::from("E01"))
+    }
+
+    fn handle_query(&mut self, emu: &mut Emulator, packet: &str) -> String {
+        let mut fields = packet.splitn(2, ':');
+        match (fields.next(), fields.next()) {
+            (Some("qSupported"), _) => {
+                let mut features = format!(
+                    "PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+",
+                    PACKET_SIZE
+                );
+                if emu.is_reverse_execution_enabled() {
+                    features += ";ReverseStep+;ReverseContinue+";
+                }
+                features
+            }
+            (Some("qXfer"), Some(args)) => {
+                let fields: Vec<&str> = args.split(':').collect();
+                match (
+                    fields.as_slice(),
+                    fields.get(3).and_then(|f| parse_address_length(f)),
+                ) {
+                    (["features", "read", "target.xml", _], Some((offset, length))) => {
+                        let xml = self.target_description(emu);
+                        let start = (offset as usize).min(xml.len());
+                        let end = (start + length as usize).min(xml.len());
+                        match end == xml.len() {
+                            true => format!("l{}", &xml[start..end]),
+                            false => format!("m{}", &xml[start..end]),
+                        }
+                    }
+                    _ => String::from("E00"),
+                }
+            }
+            (Some("qAttached"), _) => String::from("1"),
+            (Some("qC"), _) => String::from("QC1"),
+            (Some("qfThreadInfo"), _) => String::from("m1"),
+            (Some("qsThreadInfo"), _) => String::from("l"),
+            (Some("qSymbol"), _) => String::from("OK"),
+            _ => String::new(),
+        }
+    }
+
+    fn target_description(&self, emu: &Emulator) -> String {
+        let (architecture, bitsize) = match emu.get_mxl() {
+            Xlen::X32 => ("riscv:rv32", 32),
+            Xlen::X64 => ("riscv:rv64", 64),
+        };
+        let mut xml = String::from("<?xml version=\"1.0\"?>");
+        xml += "<!DOCTYPE target SYSTEM \"gdb-target.dtd\"><target version=\"1.0\">";
+        xml += &format!("<architecture>{}</architecture>", architecture);
+
+        xml += "<feature name=\"org.gnu.gdb.riscv.cpu\">";
+        for (i, name) in GPR_NAMES.iter().enumerate() {
+            let reg_type = match i {
+                1 => "code_ptr",
+                2 | 3 | 4 | 8 => "data_ptr",
+                _ => "int",
+            };
+            xml += &format!(
+                "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>",
+                name, bitsize, reg_type, i
+            );
+        }
+        xml += &format!(
+            "<reg name=\"pc\" bitsize=\"{}\" type=\"code_ptr\" regnum=\"{}\"/>",
+            bitsize, REGNUM_PC
+        );
+        xml += "</feature>";
+
+        xml += "<feature name=\"org.gnu.gdb.riscv.fpu\">";
+        for (i, name) in FPR_NAMES.iter().enumerate() {
+            xml += &format!(
+                "<reg name=\"{}\" bitsize=\"64\" type=\"ieee_double\" regnum=\"{}\"/>",
+                name,
+                REGNUM_FIRST_FPR + i
+            );
+        }
+        for (name, csr) in [("fflags", CSR_FFLAGS), ("frm", CSR_FRM), ("fcsr", CSR_FCSR)].iter() {
+            xml += &format!(
+                "<reg name=\"{}\" bitsize=\"32\" type=\"int\" regnum=\"{}\"/>",
+                name,
+                REGNUM_FIRST_CSR + *csr as usize
+            );
+        }
+        xml += "</feature>";
+
+        xml += "<feature name=\"org.gnu.gdb.riscv.csr\">";
+        for (name, csr) in CSRS.iter() {
+            xml += &format!(
+                "<reg name=\"{}\" bitsize=\"{}\" type=\"int\" regnum=\"{}\"/>",
+                name,
+                bitsize,
+                REGNUM_FIRST_CSR + *csr as usize
+            );
+        }
+        xml += "</feature>";
+
+        xml += "<feature name=\"org.gnu.gdb.riscv.virtual\">";
+        xml += &format!(
+            "<reg name=\"priv\" bitsize=\"{}\" type=\"int\" regnum=\"{}\"/>",
+            bitsize, REGNUM_PRIV
+        );
+        xml += "</feature></target>";
+        xml
+    }
+
+    fn read_csr(&mut self, emu: &mut Emulator, csr: u16) -> u64 {
+        emu.execute_abstract_command(&AbstractCommand::ReadRegister(csr))
+            .unwrap_or(0)
+    }
+
+    fn write_csr(&mut self, emu: &mut Emulator, csr: u16, data: u64) {
+        let _ = emu.execute_abstract_command(&AbstractCommand::WriteRegister(csr, data));
+    }
+
+    fn register_size(&self, emu: &Emulator, regnum: usize) -> usize {
+        match regnum {
+            REGNUM_FIRST_FPR..=REGNUM_LAST_FPR => 8,
+            _ if regnum == REGNUM_FIRST_CSR + CSR_FFLAGS as usize => 4,
+            _ if regnum == REGNUM_FIRST_CSR + CSR_FRM as usize => 4,
+            _ if regnum == REGNUM_FIRST_CSR + CSR_FCSR as usize => 4,
+            _ => xlen_bytes(&emu.get_mxl()),
+        }
+    }
+
+    fn get_register(&mut self, emu: &mut Emulator, regnum: usize) -> Result<u64, CommandError> {
+        let regno = match regnum {
+            0..=31 => 0x1000 + regnum as u16,
+            REGNUM_PC => CSR_DPC,
+            REGNUM_FIRST_FPR..=REGNUM_LAST_FPR => 0x1020 + (regnum - REGNUM_FIRST_FPR) as u16,
+            REGNUM_FIRST_CSR..=REGNUM_LAST_CSR => (regnum - REGNUM_FIRST_CSR) as u16,
+            // the privilege mode which the hart resumes in.
+            REGNUM_PRIV => return Ok(self.read_csr(emu, CSR_DCSR) & CSR_DCSR_PRV),
+            _ => return Err(CommandError::NotSupported),
+        };
+        emu.execute_abstract_command(&AbstractCommand::ReadRegister(regno))
+    }
+
+    fn set_register(
+        &mut self,
+        emu: &mut Emulator,
+        regnum: usize,
+        value: u64,
+    ) -> Result<(), CommandError> {
+        let regno = match regnum {
+            0..=31 => 0x1000 + regnum as u16,
+            REGNUM_PC => CSR_DPC,
+            REGNUM_FIRST_FPR..=REGNUM_LAST_FPR => 0x1020 + (regnum - REGNUM_FIRST_FPR) as u16,
+            REGNUM_FIRST_CSR..=REGNUM_LAST_CSR => (regnum - REGNUM_FIRST_CSR) as u16,
+            REGNUM_PRIV => {
+                let dcsr = self.read_csr(emu, CSR_DCSR);
+                self.write_csr(
+                    emu,
+                    CSR_DCSR,
+                    (dcsr & !CSR_DCSR_PRV) | (value & CSR_DCSR_PRV),
+                );
+                return Ok(());
+            }
+            _ => return Err(CommandError::NotSupported),
+        };
+        emu.execute_abstract_command(&AbstractCommand::WriteRegister(regno, value))
+            .map(|_| ())
+    }
+
+    fn read_register(&mut self, emu: &mut Emulator, regnum: usize) -> Option<String> {
+        let size = self.register_size(emu, regnum);
+        let value = self.get_register(emu, regnum).ok()?;
+        Some(to_hex(&value.to_le_bytes()[..size]))
+    }
+
+    fn write_register(&mut self, emu: &mut Emulator, regnum: usize, text: &str) -> Option<String> {
+        let size = self.register_size(emu, regnum);
+        let data = from_hex(text)?;
+        if data.len() != size {
+            return None;
+        }
+        let mut bytes = [0; 8];
+        bytes[..size].copy_from_slice(&data);
+        self.set_register(emu, regnum, u64::from_le_bytes(bytes))
+            .ok()
+            .map(|_| String::from("OK"))
+    }
+
+    /// The g packet holds the integer registers and pc.
+    fn read_registers(&mut self, emu: &mut Emulator) -> Option<String> {
+        let mut response = String::new();
+        for regnum in 0..=REGNUM_PC {
+            response += &self.read_register(emu, regnum)?;
+        }
+        Some(response)
+    }
+
+    fn write_registers(&mut self, emu: &mut Emulator, text: &str) -> Option<String> {
+        let size = xlen_bytes(&emu.get_mxl()) * 2;
+        for regnum in 0..=REGNUM_PC {
+            if let Some(value) = text.get(regnum * size..(regnum + 1) * size) {
+                self.write_register(emu, regnum, value)?;
+            }
+        }
+        Some(String::from("OK"))
+    }
+
+    /// Accesses the virtual memory in the privilege mode which the hart resumes in.
+    /// Aligned words are accessed at once, because some device registers only
+    /// allow 32-bit accesses.
+    fn read_memory(&mut self, emu: &mut Emulator, address: u64, length: u64) -> Option<Vec<u8>> {
+        // the reply must fit in a packet with '$', '#' and the checksum, and
+        // GDB reads the rest with the next packets.
+        let length = length.min((PACKET_SIZE as u64 - 4) / 2);
+        let mut data = vec![];
+        while (data.len() as u64) < length {
+            let current = address.wrapping_add(data.len() as u64);
+            let size = match current.is_multiple_of(4) && length - data.len() as u64 >= 4 {
+                true => 4,
+                false => 1,
+            };
+            let value = emu
+                .execute_abstract_command(&AbstractCommand::ReadMemory {
+                    address: current,
+                    size,
+                    virtual_address: true,
+                })
+                .ok()?;
+            data.extend_from_slice(&value.to_le_bytes()[..size as usize]);
+        }
+        Some(data)
+    }
+
+    fn write_memory(&mut self, emu: &mut Emulator, address: u64, data: &[u8]) -> Option<()> {
+        let mut offset = 0;
+        while offset < data.len() {
+            let current = address.wrapping_add(offset as u64);
+            let size = match current.is_multiple_of(4) && data.len() - offset >= 4 {
+                true => 4,
+                false => 1,
+            };
+            let mut bytes = [0; 8];
+            bytes[..size].copy_from_slice(&data[offset..offset + size]);
+            emu.execute_abstract_command(&AbstractCommand::WriteMemory {
+                address: current,
+                size: size as u8,
+                virtual_address: true,
+                data: u64::from_le_bytes(bytes),
+            })
+            .ok()?;
+            offset += size;
+        }
+        Some(())
+    }
+
+    /// Resumes the hart until it halts again, or until GDB sends Ctrl-C. A
+    /// software breakpoint at the PC where the hart resumes does not stop it.
+    fn resume(&mut self, emu: &mut Emulator, step: bool) -> String {
+        let dcsr = self.read_csr(emu, CSR_DCSR);
+        let dcsr = match step {
+            true => dcsr | CSR_DCSR_STEP,
+            false => dcsr & !CSR_DCSR_STEP,
+        };
+        self.write_csr(emu, CSR_DCSR, dcsr);
+        emu.debug_resume();
+        let conditions = StopConditions {
+            breakpoints: self.software_breakpoints(),
+            cycle_limit: Some(POLL_CYCLES),
+            ..StopConditions::default()
+        };
+        while !emu.is_debug_mode() {
+            if let StopReason::Breakpoint(_) = emu.run_until(&conditions) {
+                emu.debug_halt();
+                return String::from("T05swbreak:;");
+            }
+            if emu.is_debug_mode() {
+                break;
+            }
+            // run_until does not check the breakpoints at the PC where it starts.
+            if self.breakpoints.contains(&emu.get_pc()) {
+                emu.debug_halt();
+                return String::from("T05swbreak:;");
+            }
+            if self.connection.poll() == Some(0x03) {
+                emu.debug_halt();
+                break;
+            }
+        }
+        self.stop_reply(emu)
+    }
+
+    fn software_breakpoints(&self) -> Vec<Breakpoint> {
+        self.breakpoints
+            .iter()
+            .map(|address| Breakpoint::Virtual(*address))
+            .collect()
+    }
+
+    /// Runs the hart backwards to the previous instruction, or to the last
+    /// breakpoint or watchpoint hit before the current state.
+    fn reverse(&mut self, emu: &mut Emulator, step: bool) -> String {
+        if !emu.is_reverse_execution_enabled() {
+            return String::from("E01");
+        }
+        let reason = match step {
+            true => emu.reverse_step(),
+            false => {
+                let mut conditions = StopConditions {
+                    breakpoints: self.software_breakpoints(),
+                    ..StopConditions::default()
+                };
+                // chained triggers hold the same point.
+                let mut points: Vec<HwPoint> = vec![];
+                for point in self.triggers.iter().flatten() {
+                    if !points
+                        .iter()
+                        .any(|p| p.kind == point.kind && p.address == point.address)
+                    {
+                        points.push(*point);
+                    }
+                }
+                for point in points {
+                    let (read, write) = match point.kind {
+                        HwPointKind::Breakpoint => {
+                            conditions
+                                .breakpoints
+                                .push(Breakpoint::Virtual(point.address));
+                            continue;
+                        }
+                        HwPointKind::WriteWatchpoint => (false, true),
+                        HwPointKind::ReadWatchpoint => (true, false),
+                        HwPointKind::AccessWatchpoint => (true, true),
+                    };
+                    conditions.watchpoints.push(Watchpoint {
+                        address: point.address,
+                        size: point.length.max(1),
+                        read,
+                        write,
+                    });
+                }
+                emu.reverse_until(&conditions)
+            }
+        };
+        match reason {
+            StopReason::Breakpoint(address) if self.breakpoints.contains(&address) => {
+                String::from("T05swbreak:;")
+            }
+            StopReason::Breakpoint(_) => String::from("T05hwbreak:;"),
+            StopReason::Watchpoint { address, write } => {
+                let point = self.triggers.iter().flatten().find(|p| {
+                    p.kind != HwPointKind::Breakpoint
+                        && address.wrapping_sub(p.address) < p.length.max(1)
+                        && (p.kind != HwPointKind::WriteWatchpoint || write)
+                        && (p.kind != HwPointKind::ReadWatchpoint || !write)
+                });
+                match point {
+                    Some(point) => {
+                        let watch = match point.kind {
+                            HwPointKind::ReadWatchpoint => "rwatch",
+                            HwPointKind::AccessWatchpoint => "awatch",
+                            _ => "watch",
+                        };
+                        format!("T05{}:{:x};", watch, point.address)
+                    }
+                    None => String::from("T05"),
+                }
+            }
+            StopReason::HistoryStart => String::from("T05replaylog:begin;"),
+            _ => String::from("T05"),
+        }
+    }
+
+    fn stop_reply(&mut self, emu: &mut Emulator) -> String {
+        let dcsr = self.read_csr(emu, CSR_DCSR);
+        match (dcsr & CSR_DCSR_CAUSE) >> 6 {
+            // ebreak
+            1 => String::from("T05swbreak:;"),
+            // trigger
+            2 => {
+                let mut reply = String::from("T05hwbreak:;");
+                for i in 0..TRIGGER_COUNT {
+                    let point = match self.triggers[i] {
+                        Some(point) => point,
+                        None => continue,
+                    };
+                    self.write_csr(emu, CSR_TSELECT, i as u64);
+                    let tdata1 = self.read_csr(emu, CSR_TDATA1);
+                    if tdata1 & MCONTROL6_HIT0 == 0 {
+                        continue;
+                    }
+                    self.write_csr(emu, CSR_TDATA1, tdata1 & !MCONTROL6_HIT0);
+                    let watch = match point.kind {
+                        HwPointKind::Breakpoint => continue,
+                        HwPointKind::WriteWatchpoint => "watch",
+                        HwPointKind::ReadWatchpoint => "rwatch",
+                        HwPointKind::AccessWatchpoint => "awatch",
+                    };
+                    reply = format!("T05{}:{:x};", watch, point.address);
+                }
+                reply
+            }
+            // halt request (Ctrl-C)
+            3 => String::from("T02"),
+            _ => String::from("T05"),
+        }
+    }
+
+    fn handle_breakpoint(
+        &mut self,
+        emu: &mut Emulator,
+        insert: bool,
+        args: &str,
+    ) -> Option<String> {
+        let mut fields = args.splitn(2, ',');
+        let kind = match fields.next()? {
+            "0" => None,
+            "1" => Some(HwPointKind::Breakpoint),
+            "2" => Some(HwPointKind::WriteWatchpoint),
+            "3" => Some(HwPointKind::ReadWatchpoint),
+            "4" => Some(HwPointKind::AccessWatchpoint),
+            _ => return Some(String::new()),
+        };
+        let (address, length) = parse_address_length(fields.next()?)?;
+        match (kind, insert) {
+            (None, true) => self.insert_breakpoint(address, length)?,
+            (None, false) => self.remove_breakpoint(address)?,
+            (Some(kind), true) => self.insert_hw_point(
+                emu,
+                HwPoint {
+                    kind,
+                    address,
+                    length,
+                },
+            )?,
+            (Some(kind), false) => self.remove_hw_point(emu, kind, address)?,
+        }
+        Some(String::from("OK"))
+    }
+
+    /// kind is the size of the instruction, 2 or 4.
+    fn insert_breakpoint(&mut self, address: u64, kind: u64) -> Option<()> {
+        if kind != 2 && kind != 4 {
+            return None;
+        }
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+        Some(())
+    }
+
+    fn remove_breakpoint(&mut self, address: u64) -> Option<()> {
+        let index = self.breakpoints.iter().position(|a| *a == address)?;
+        self.breakpoints.remove(index);
+        Some(())
+    }
+
+    fn write_trigger(&mut self, emu: &mut Emulator, index: usize, tdata1: u64, tdata2: u64) {
+        self.write_csr(emu, CSR_TSELECT, index as u64);
+        self.write_csr(emu, CSR_TDATA1, 0);
+        self.write_csr(emu, CSR_TDATA2, tdata2);
+        self.write_csr(emu, CSR_TDATA1, tdata1);
+    }
+
+    /// A naturally aligned power-of-two range uses one trigger, and other ranges use
+    /// two chained triggers comparing the start and the end.
+    fn insert_hw_point(&mut self, emu: &mut Emulator, point: HwPoint) -> Option<()> {
+        let shift = match emu.get_mxl() {
+            Xlen::X32 => 28,
+            Xlen::X64 => 60,
+        };
+        let access = match point.kind {
+            HwPointKind::Breakpoint => MCONTROL6_EXECUTE,
+            HwPointKind::WriteWatchpoint => MCONTROL6_STORE,
+            HwPointKind::ReadWatchpoint => MCONTROL6_LOAD,
+            HwPointKind::AccessWatchpoint => MCONTROL6_LOAD | MCONTROL6_STORE,
+        };
+        // the triggers enter debug mode (action=1) in any privilege mode.
+        let tdata1 = TRIGGER_TYPE_MCONTROL6 << shift
+            | 1 << (shift - 1)
+            | 1 << 12
+            | MCONTROL6_M
+            | MCONTROL6_S
+            | MCONTROL6_U
+            | access;
+
+        let length = point.length.max(1);
+        let napot = point.kind == HwPointKind::Breakpoint
+            || (length.is_power_of_two() && point.address.is_multiple_of(length));
+        if napot {
+            let index = (0..TRIGGER_COUNT).find(|i| self.triggers[*i].is_none())?;
+            let (match_type, tdata2) = match length {
+                _ if point.kind == HwPointKind::Breakpoint => (0, point.address),
+                1 => (0, point.address),
+                _ => (1, point.address | ((length >> 1) - 1)),
+            };
+            self.write_trigger(emu, index, tdata1 | match_type << 7, tdata2);
+            self.triggers[index] = Some(point);
+        } else {
+            let index = (0..TRIGGER_COUNT - 1)
+                .find(|i| self.triggers[*i].is_none() && self.triggers[*i + 1].is_none())?;
+            let end = point.address.wrapping_add(length);
+            self.write_trigger(emu, index, tdata1 | 2 << 7 | MCONTROL6_CHAIN, point.address);
+            self.write_trigger(emu, index + 1, tdata1 | 3 << 7, end);
+            self.triggers[index] = Some(point);
+            self.triggers[index + 1] = Some(point);
+        }
+        Some(())
+    }
+
+    fn remove_hw_point(
+        &mut self,
+        emu: &mut Emulator,
+        kind: HwPointKind,
+        address: u64,
+    ) -> Option<()> {
+        let mut found = false;
+        for i in 0..TRIGGER_COUNT {
+            if let Some(point) = self.triggers[i] {
+                if point.kind == kind && point.address == address {
+                    self.write_trigger(emu, i, 0, 0);
+                    self.triggers[i] = None;
+                    found = true;
+                }
+            }
+        }
+        match found {
+            true => Some(()),
+            false => None,
+        }
+    }
+}
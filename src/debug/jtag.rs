@@ -0,0 +1,206 @@
+use crate::cpu::cpu::Cpu;
+use crate::debug::debug_module::DebugModule;
+
+const IR_LENGTH: usize = 5;
+const IDCODE: u32 = 0x20000913;
+
+// JTAG instructions of the Debug Transport Module.
+const IR_IDCODE: u32 = 0x01;
+const IR_DTMCS: u32 = 0x10;
+const IR_DMI: u32 = 0x11;
+
+const DMI_ADDRESS_BITS: usize = 7;
+const DMI_LENGTH: usize = DMI_ADDRESS_BITS + 34;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TapState {
+    TestLogicReset,
+    RunTestIdle,
+    SelectDrScan,
+    CaptureDr,
+    ShiftDr,
+    Exit1Dr,
+    PauseDr,
+    Exit2Dr,
+    UpdateDr,
+    SelectIrScan,
+    CaptureIr,
+    ShiftIr,
+    Exit1Ir,
+    PauseIr,
+    Exit2Ir,
+    UpdateIr,
+}
+
+impl TapState {
+    fn next(self, tms: bool) -> TapState {
+        match (self, tms) {
+            (TapState::TestLogicReset, false) => TapState::RunTestIdle,
+            (TapState::TestLogicReset, true) => TapState::TestLogicReset,
+            (TapState::RunTestIdle, false) => TapState::RunTestIdle,
+            (TapState::RunTestIdle, true) => TapState::SelectDrScan,
+            (TapState::SelectDrScan, false) => TapState::CaptureDr,
+            (TapState::SelectDrScan, true) => TapState::SelectIrScan,
+            (TapState::CaptureDr, false) => TapState::ShiftDr,
+            (TapState::CaptureDr, true) => TapState::Exit1Dr,
+            (TapState::ShiftDr, false) => TapState::ShiftDr,
+            (TapState::ShiftDr, true) => TapState::Exit1Dr,
+            (TapState::Exit1Dr, false) => TapState::PauseDr,
+            (TapState::Exit1Dr, true) => TapState::UpdateDr,
+            (TapState::PauseDr, false) => TapState::PauseDr,
+            (TapState::PauseDr, true) => TapState::Exit2Dr,
+            (TapState::Exit2Dr, false) => TapState::ShiftDr,
+            (TapState::Exit2Dr, true) => TapState::UpdateDr,
+            (TapState::UpdateDr, false) => TapState::RunTestIdle,
+            (TapState::UpdateDr, true) => TapState::SelectDrScan,
+            (TapState::SelectIrScan, false) => TapState::CaptureIr,
+            (TapState::SelectIrScan, true) => TapState::TestLogicReset,
+            (TapState::CaptureIr, false) => TapState::ShiftIr,
+            (TapState::CaptureIr, true) => TapState::Exit1Ir,
+            (TapState::ShiftIr, false) => TapState::ShiftIr,
+            (TapState::ShiftIr, true) => TapState::Exit1Ir,
+            (TapState::Exit1Ir, false) => TapState::PauseIr,
+            (TapState::Exit1Ir, true) => TapState::UpdateIr,
+            (TapState::PauseIr, false) => TapState::PauseIr,
+            (TapState::PauseIr, true) => TapState::Exit2Ir,
+            (TapState::Exit2Ir, false) => TapState::ShiftIr,
+            (TapState::Exit2Ir, true) => TapState::UpdateIr,
+            (TapState::UpdateIr, false) => TapState::RunTestIdle,
+            (TapState::UpdateIr, true) => TapState::SelectDrScan,
+        }
+    }
+}
+
+/// JTAG TAP with the Debug Transport Module (DTM) of the debug spec 1.0.
+/// The DMI register accesses the Debug Module. The pins are driven by
+/// `set_pins`, e.g. from an OpenOCD remote-bitbang connection.
+pub struct JtagTap {
+    state: TapState,
+    tck: bool,
+    tms: bool,
+    tdi: bool,
+    tdo: bool,
+    ir: u32,
+    /// shift register of the current scan, the least significant bit goes to TDO.
+    shift: u64,
+    shift_length: usize,
+    /// result of the last DMI access, which is captured by the next DMI scan.
+    dmi_address: u32,
+    dmi_data: u32,
+}
+
+impl JtagTap {
+    pub fn new() -> Self {
+        JtagTap {
+            state: TapState::TestLogicReset,
+            tck: false,
+            tms: false,
+            tdi: false,
+            tdo: false,
+            ir: IR_IDCODE,
+            shift: 0,
+            shift_length: 0,
+            dmi_address: 0,
+            dmi_data: 0,
+        }
+    }
+
+    /// Test reset (TRST).
+    pub fn reset(&mut self) {
+        self.state = TapState::TestLogicReset;
+        self.ir = IR_IDCODE;
+    }
+
+    pub fn get_tdo(&self) -> bool {
+        self.tdo
+    }
+
+    /// Drives TCK, TMS and TDI. TMS and TDI are sampled on the rising edge of
+    /// TCK, and TDO changes on the falling edge.
+    pub fn set_pins(
+        &mut self,
+        debug_module: &mut DebugModule,
+        cpu: &mut Cpu,
+        tck: bool,
+        tms: bool,
+        tdi: bool,
+    ) {
+        let rising = !self.tck && tck;
+        let falling = self.tck && !tck;
+        self.tck = tck;
+        self.tms = tms;
+        self.tdi = tdi;
+        if rising {
+            self.clock(debug_module, cpu);
+        }
+        if falling {
+            self.tdo = match self.state {
+                TapState::ShiftDr | TapState::ShiftIr => self.shift & 1 != 0,
+                _ => false,
+            };
+        }
+    }
+
+    fn clock(&mut self, debug_module: &mut DebugModule, cpu: &mut Cpu) {
+        if let TapState::ShiftDr | TapState::ShiftIr = self.state {
+            let msb = (self.tdi as u64) << (self.shift_length - 1);
+            self.shift = (self.shift >> 1) | msb;
+        }
+        self.state = self.state.next(self.tms);
+        match self.state {
+            TapState::TestLogicReset => self.ir = IR_IDCODE,
+            TapState::CaptureIr => {
+                // the two least significant bits of the captured IR must be 0b01.
+                self.shift = 0x1;
+                self.shift_length = IR_LENGTH;
+            }
+            TapState::UpdateIr => self.ir = self.shift as u32,
+            TapState::CaptureDr => self.capture_dr(),
+            TapState::UpdateDr => self.update_dr(debug_module, cpu),
+            _ => {}
+        }
+    }
+
+    fn capture_dr(&mut self) {
+        let (value, length) = match self.ir {
+            IR_IDCODE => (IDCODE as u64, 32),
+            // version=1 (1.0), abits, dmistat and idle=1
+            IR_DTMCS => (1 << 12 | (DMI_ADDRESS_BITS as u64) << 4 | 1, 32),
+            IR_DMI => (
+                (self.dmi_address as u64) << 34 | (self.dmi_data as u64) << 2,
+                DMI_LENGTH,
+            ),
+            _ => (0, 1),
+        };
+        self.shift = value;
+        self.shift_length = length;
+    }
+
+    fn update_dr(&mut self, debug_module: &mut DebugModule, cpu: &mut Cpu) {
+        if self.ir != IR_DMI {
+            return;
+        }
+        let op = self.shift & 0x3;
+        let data = (self.shift >> 2) as u32;
+        let address = ((self.shift >> 34) as u32) & ((1 << DMI_ADDRESS_BITS) - 1);
+        // the accesses are completed immediately, so dmistat is always 0
+        // and dmireset in dtmcs has nothing to clear.
+        match op {
+            1 => {
+                self.dmi_data = debug_module.read(cpu, address);
+                self.dmi_address = address;
+            }
+            2 => {
+                debug_module.write(cpu, address, data);
+                self.dmi_address = address;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for JtagTap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,3 @@
+pub mod debug_module;
+pub mod gdb_stub;
+pub mod jtag;
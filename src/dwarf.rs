@@ -0,0 +1,361 @@
+// Line number information of DWARF 2-5 (.debug_line)
+// https://dwarfstd.org/doc/DWARF5.pdf (6.2 Line Number Information)
+
+// standard opcodes
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_FILE: u8 = 0x04;
+const DW_LNS_CONST_ADD_PC: u8 = 0x08;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 0x09;
+
+// extended opcodes
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+const DW_LNE_DEFINE_FILE: u8 = 0x03;
+
+// line number header entry formats (DWARF 5)
+const DW_LNCT_PATH: u64 = 0x1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 0x2;
+
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+
+/// Row of the line number table. `file` indexes the file names returned
+/// together with the rows. The row of an end of a sequence has no line.
+#[derive(Clone, Debug)]
+pub struct LineRow {
+    pub address: u64,
+    pub file: usize,
+    pub line: u64,
+    pub end_sequence: bool,
+}
+
+/// Line number table of all the compilation units.
+#[derive(Default)]
+pub struct LineTable {
+    pub files: Vec<String>,
+    pub rows: Vec<LineRow>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], offset: usize) -> Self {
+        Reader { data, offset }
+    }
+
+    fn is_end(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    fn read(&mut self, size: usize) -> Option<u64> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(size)?)?;
+        self.offset += size;
+        Some(
+            bytes
+                .iter()
+                .rev()
+                .fold(0, |value, &byte| (value << 8) | byte as u64),
+        )
+    }
+
+    fn read8(&mut self) -> Option<u8> {
+        self.read(1).map(|value| value as u8)
+    }
+
+    fn uleb128(&mut self) -> Option<u64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+    }
+
+    fn sleb128(&mut self) -> Option<i64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Some(value);
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let bytes = self.data.get(self.offset..)?;
+        let length = bytes.iter().position(|&c| c == 0)?;
+        self.offset += length + 1;
+        Some(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    }
+}
+
+/// Parses the line number programs of .debug_line. .debug_line_str and
+/// .debug_str hold the strings referred by DWARF 5 headers. A unit which
+/// can not be parsed is skipped.
+pub fn parse_debug_line(
+    debug_line: &[u8],
+    debug_line_str: Option<&[u8]>,
+    debug_str: Option<&[u8]>,
+    address_size: usize,
+) -> LineTable {
+    let mut table = LineTable::default();
+    let mut reader = Reader::new(debug_line, 0);
+    while !reader.is_end() {
+        let (unit_length, offset_size) = match reader.read(4) {
+            Some(0xffffffff) => match reader.read(8) {
+                Some(length) => (length as usize, 8),
+                None => break,
+            },
+            Some(length) => (length as usize, 4),
+            None => break,
+        };
+        let unit_end = reader.offset.saturating_add(unit_length);
+        let unit = match debug_line.get(..unit_end) {
+            Some(unit) => unit,
+            None => break,
+        };
+        let strings = [debug_line_str, debug_str];
+        let _ = parse_unit(
+            &mut Reader::new(unit, reader.offset),
+            offset_size,
+            address_size,
+            &strings,
+            &mut table,
+        );
+        reader.offset = unit_end;
+    }
+    table
+}
+
+fn parse_unit(
+    reader: &mut Reader,
+    offset_size: usize,
+    address_size: usize,
+    strings: &[Option<&[u8]>; 2],
+    table: &mut LineTable,
+) -> Option<()> {
+    let version = reader.read(2)?;
+    if !(2..=5).contains(&version) {
+        return None;
+    }
+    let address_size = match version {
+        5 => {
+            let size = reader.read8()? as usize;
+            let _segment_selector_size = reader.read8()?;
+            size
+        }
+        _ => address_size,
+    };
+    let header_length = reader.read(offset_size)? as usize;
+    let program_offset = reader.offset.saturating_add(header_length);
+    let minimum_instruction_length = reader.read8()? as u64;
+    if version >= 4 {
+        let _maximum_operations_per_instruction = reader.read8()?;
+    }
+    let _default_is_stmt = reader.read8()?;
+    let line_base = reader.read8()? as i8 as i64;
+    let line_range = reader.read8()?;
+    let opcode_base = reader.read8()?;
+    if line_range == 0 || opcode_base == 0 {
+        return None;
+    }
+    let mut standard_opcode_lengths = vec![];
+    for _ in 1..opcode_base {
+        standard_opcode_lengths.push(reader.read8()?);
+    }
+
+    // file names of the unit, which are indexed from 1 before DWARF 5.
+    let mut files = vec![];
+    match version {
+        5 => {
+            let directories = parse_entries(reader, offset_size, strings)?;
+            for (path, directory) in parse_entries(reader, offset_size, strings)? {
+                files.push(join_path(directories.get(directory).map(|d| &d.0), path));
+            }
+        }
+        _ => {
+            let mut directories = vec![];
+            loop {
+                match reader.string()? {
+                    directory if directory.is_empty() => break,
+                    directory => directories.push(directory),
+                }
+            }
+            files.push(String::new());
+            loop {
+                let path = reader.string()?;
+                if path.is_empty() {
+                    break;
+                }
+                let directory = reader.uleb128()? as usize;
+                let _mtime = reader.uleb128()?;
+                let _length = reader.uleb128()?;
+                files.push(join_path(directories.get(directory.wrapping_sub(1)), path));
+            }
+        }
+    }
+    let file_base = table.files.len();
+    table.files.extend(files.iter().cloned());
+
+    // line number program.
+    reader.offset = program_offset;
+    let mut address: u64 = 0;
+    let mut file = 1;
+    let mut line: u64 = 1;
+    while !reader.is_end() {
+        let opcode = reader.read8()?;
+        let mut emit = false;
+        if opcode >= opcode_base {
+            let adjusted = opcode - opcode_base;
+            address =
+                address.wrapping_add((adjusted / line_range) as u64 * minimum_instruction_length);
+            line = (line as i64).wrapping_add(line_base + (adjusted % line_range) as i64) as u64;
+            emit = true;
+        } else {
+            match opcode {
+                0 => {
+                    let length = reader.uleb128()? as usize;
+                    let next = reader.offset.saturating_add(length);
+                    match reader.read8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            table.rows.push(LineRow {
+                                address,
+                                file: file_base,
+                                line: 0,
+                                end_sequence: true,
+                            });
+                            address = 0;
+                            file = 1;
+                            line = 1;
+                        }
+                        DW_LNE_SET_ADDRESS => address = reader.read(address_size)?,
+                        DW_LNE_DEFINE_FILE => {
+                            let path = reader.string()?;
+                            table.files.push(path.clone());
+                            files.push(path);
+                        }
+                        _ => {}
+                    }
+                    reader.offset = next;
+                }
+                DW_LNS_COPY => emit = true,
+                DW_LNS_ADVANCE_PC => {
+                    address = address
+                        .wrapping_add(reader.uleb128()?.wrapping_mul(minimum_instruction_length));
+                }
+                DW_LNS_ADVANCE_LINE => line = (line as i64).wrapping_add(reader.sleb128()?) as u64,
+                DW_LNS_SET_FILE => file = reader.uleb128()? as usize,
+                DW_LNS_CONST_ADD_PC => {
+                    address = address.wrapping_add(
+                        ((255 - opcode_base) / line_range) as u64 * minimum_instruction_length,
+                    );
+                }
+                DW_LNS_FIXED_ADVANCE_PC => address = address.wrapping_add(reader.read(2)?),
+                _ => {
+                    // skip the operands of the other standard opcodes.
+                    for _ in 0..standard_opcode_lengths[opcode as usize - 1] {
+                        reader.uleb128()?;
+                    }
+                }
+            }
+        }
+        if emit && file < files.len() {
+            table.rows.push(LineRow {
+                address,
+                file: file_base + file,
+                line,
+                end_sequence: false,
+            });
+        }
+    }
+    Some(())
+}
+
+/// Parses the directory or the file name entries of a DWARF 5 header, and
+/// returns the paths with the directory indexes.
+fn parse_entries(
+    reader: &mut Reader,
+    offset_size: usize,
+    strings: &[Option<&[u8]>; 2],
+) -> Option<Vec<(String, usize)>> {
+    let mut formats = vec![];
+    for _ in 0..reader.read8()? {
+        formats.push((reader.uleb128()?, reader.uleb128()?));
+    }
+    let mut entries = vec![];
+    for _ in 0..reader.uleb128()? {
+        let mut path = String::new();
+        let mut directory = 0;
+        for &(content_type, form) in formats.iter() {
+            let (string, value) = match form {
+                DW_FORM_STRING => (Some(reader.string()?), 0),
+                DW_FORM_LINE_STRP | DW_FORM_STRP => {
+                    let section = match form {
+                        DW_FORM_LINE_STRP => strings[0]?,
+                        _ => strings[1]?,
+                    };
+                    let offset = reader.read(offset_size)? as usize;
+                    (Some(Reader::new(section, offset).string()?), 0)
+                }
+                DW_FORM_UDATA => (None, reader.uleb128()?),
+                DW_FORM_DATA1 => (None, reader.read(1)?),
+                DW_FORM_DATA2 => (None, reader.read(2)?),
+                DW_FORM_DATA4 => (None, reader.read(4)?),
+                DW_FORM_DATA8 => (None, reader.read(8)?),
+                DW_FORM_DATA16 => {
+                    reader.offset += 16;
+                    (None, 0)
+                }
+                DW_FORM_BLOCK => {
+                    let length = reader.uleb128()? as usize;
+                    reader.offset = reader.offset.saturating_add(length);
+                    (None, 0)
+                }
+                _ => return None,
+            };
+            match content_type {
+                DW_LNCT_PATH => path = string?,
+                DW_LNCT_DIRECTORY_INDEX => directory = value as usize,
+                _ => {}
+            }
+        }
+        entries.push((path, directory));
+    }
+    Some(entries)
+}
+
+fn join_path(directory: Option<&String>, path: String) -> String {
+    match directory {
+        Some(directory) if !path.starts_with('/') && !directory.is_empty() => {
+            format!("{}/{}", directory, path)
+        }
+        _ => path,
+    }
+}
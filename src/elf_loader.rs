@@ -1,6 +1,15 @@
 const HEADER_MAGIC: u32 = 0x464c457f; // 0x7f 'E' 'L' 'F'
 const TOHOST: u64 = 0x0074736f686f742e; // .tohost
 
+// e_flags: the program is built for the RV32E/RV64E base ISA.
+pub const EF_RISCV_RVE: u32 = 0x0008;
+
+// st_info: symbol types.
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+// st_shndx: undefined symbol.
+const SHN_UNDEF: u16 = 0;
+
 pub struct ElfHeader {
     pub e_indent: Ei,
     pub e_type: EType,
@@ -43,6 +52,17 @@ pub struct SectionHeader {
     pub sh_entsize: u64,
 }
 
+/// Code symbol of the symbol table (.symtab).
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+    /// STT_FUNC. The other code symbols are labels (STT_NOTYPE).
+    pub is_function: bool,
+    pub is_global: bool,
+}
+
 #[derive(Debug)]
 pub enum ShType {
     Null = 0x0,          // Section header table entry unused
@@ -466,6 +486,111 @@ impl ElfLoader {
         None
     }
 
+    /// Returns the name of a section from the section header string table.
+    pub fn get_section_name(
+        &self,
+        elf_header: &ElfHeader,
+        sec_headers: &[SectionHeader],
+        sec_header: &SectionHeader,
+    ) -> String {
+        match sec_headers.get(elf_header.e_shstrndx as usize) {
+            Some(shstrtab) => {
+                self.read_string((shstrtab.sh_offset + sec_header.sh_name as u64) as usize)
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Returns the contents of the section named `name`, e.g. ".debug_line".
+    pub fn get_section_data(
+        &self,
+        elf_header: &ElfHeader,
+        sec_headers: &[SectionHeader],
+        name: &str,
+    ) -> Option<&[u8]> {
+        sec_headers
+            .iter()
+            .filter(|sh| !matches!(sh.sh_type, ShType::Nobits | ShType::Null))
+            .find(|sh| self.get_section_name(elf_header, sec_headers, sh) == name)
+            .and_then(|sh| {
+                self.data
+                    .get(sh.sh_offset as usize..(sh.sh_offset + sh.sh_size) as usize)
+            })
+    }
+
+    /// Returns the defined function and label symbols of the symbol table.
+    /// Section, file and object symbols, and the mapping symbols ($x, $d) are skipped.
+    pub fn get_symbols(
+        &self,
+        elf_header: &ElfHeader,
+        sec_headers: &[SectionHeader],
+    ) -> Vec<Symbol> {
+        let mut symbols = vec![];
+        for symtab in sec_headers.iter() {
+            match symtab.sh_type {
+                ShType::Sysmtab => {}
+                _ => continue,
+            }
+            let strtab = match sec_headers.get(symtab.sh_link as usize) {
+                Some(strtab) => strtab,
+                None => continue,
+            };
+            let entry_size = match elf_header.e_indent.ei_classs {
+                EiClass::Class32 => 16,
+                _ => 24,
+            };
+            let end = (symtab.sh_offset + symtab.sh_size) as usize;
+            let mut offset = symtab.sh_offset as usize;
+            while offset + entry_size <= end.min(self.data.len()) {
+                let (st_name, st_value, st_size, st_info, st_shndx) =
+                    match elf_header.e_indent.ei_classs {
+                        EiClass::Class32 => (
+                            self.read32(offset),
+                            self.read32(offset + 4) as u64,
+                            self.read32(offset + 8) as u64,
+                            self.read8(offset + 12),
+                            self.read16(offset + 14),
+                        ),
+                        _ => (
+                            self.read32(offset),
+                            self.read64(offset + 8),
+                            self.read64(offset + 16),
+                            self.read8(offset + 4),
+                            self.read16(offset + 6),
+                        ),
+                    };
+                offset += entry_size;
+
+                let st_type = st_info & 0xf;
+                if (st_type != STT_FUNC && st_type != STT_NOTYPE) || st_shndx == SHN_UNDEF {
+                    continue;
+                }
+                let name = self.read_string((strtab.sh_offset + st_name as u64) as usize);
+                if name.is_empty() || name.starts_with('$') {
+                    continue;
+                }
+                symbols.push(Symbol {
+                    name,
+                    address: st_value,
+                    size: st_size,
+                    is_function: st_type == STT_FUNC,
+                    is_global: st_info >> 4 != 0,
+                });
+            }
+        }
+        symbols
+    }
+
+    /// Reads a null-terminated string.
+    fn read_string(&self, offset: usize) -> String {
+        let bytes = match self.data.get(offset..) {
+            Some(bytes) => bytes,
+            None => return String::new(),
+        };
+        let length = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..length]).into_owned()
+    }
+
     pub fn read8(&self, offset: usize) -> u8 {
         self.data[offset]
     }
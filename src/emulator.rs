@@ -1,36 +1,277 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, BufWriter, Read, Write};
 use std::path::Path;
+use std::rc::Rc;
 
 use crate::bus::bus::Device;
-use crate::console::Console;
-use crate::cpu::cpu::{Cpu, Xlen};
-use crate::elf_loader::{EMachine, EiClass, ElfLoader, ShType};
+use crate::console::{CapturingConsole, Console};
+use crate::cosim::{ArchState, Divergence, Lockstep, ReferenceModel};
+use crate::coverage::{Coverage, Module};
+use crate::cpu::cpu::{
+    AbstractCommand, CommandError, Cpu, DebugCause, Privilege, StepResult, Xlen,
+};
+use crate::cpu::cpu_csr::{CSR_DCSR, CSR_DCSR_CAUSE, CSR_DCSR_STEP, CSR_DSCRATCH0, CSR_DSCRATCH1};
+use crate::cpu::mmu::{MemoryAccessType, MemoryWrite, Watchpoint};
+use crate::cpu::stats::Stats;
+use crate::cpu::trap::{Exception, Interrupt, Trap};
+use crate::debug::debug_module::DebugModule;
+use crate::debug::jtag::JtagTap;
+use crate::elf_loader::{EMachine, EiClass, ElfLoader, ShType, EF_RISCV_RVE};
+use crate::fdt::Fdt;
 use crate::machine::Machine;
+use crate::profiler::{FunctionProfile, Profiler};
+use crate::replay::{parse_input_log, InputEvent, InputRecord, InputRecorder, ReplayConsole};
+use crate::reverse::{Checkpoint, Checkpoints};
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
+use crate::symbols::SymbolTable;
+use crate::trace::Tracer;
+
+/// PC breakpoint of `Emulator::run_until`.
+#[derive(Clone, Debug)]
+pub enum Breakpoint {
+    Virtual(u64),
+    /// physical address, which the PC is translated to as an instruction fetch.
+    Physical(u64),
+}
+
+/// Conditions which stop `Emulator::run_until`. The limits are counted from the
+/// start of the call. The `tohost` protocol of the test mode always stops it.
+#[derive(Clone, Debug, Default)]
+pub struct StopConditions {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// number of retired instructions.
+    pub instruction_limit: Option<u64>,
+    pub cycle_limit: Option<u64>,
+    pub privilege_change: bool,
+    pub exceptions: Vec<Exception>,
+    pub interrupts: Vec<Interrupt>,
+    /// string printed on the console.
+    pub console_output: Option<String>,
+}
+
+/// Reason why `Emulator::run_until` stopped.
+#[derive(Clone, Debug)]
+pub enum StopReason {
+    /// the PC reached the breakpoint. The instruction there is not executed yet.
+    Breakpoint(u64),
+    /// an instruction accessed a watched range. The access has been performed.
+    Watchpoint {
+        address: u64,
+        write: bool,
+    },
+    InstructionLimit,
+    CycleLimit,
+    PrivilegeChange {
+        from: Privilege,
+        to: Privilege,
+    },
+    /// the exception has been taken, so the PC is at the trap handler.
+    Exception(Trap),
+    Interrupt(Interrupt),
+    ConsoleOutput,
+    /// the hart entered debug mode.
+    DebugHalt,
+    /// the test program wrote `tohost`. 1 means the test passed.
+    ToHost(u32),
+    /// the state differs from the reference model after the step.
+    Divergence(Box<Divergence>),
+    /// reverse execution reached the earliest checkpoint.
+    HistoryStart,
+}
+
+/// Why `Emulator::run` stopped without the test passing.
+#[derive(Clone, Debug)]
+pub enum RunError {
+    /// the test program wrote `tohost` other than 1.
+    ToHost(u32),
+    /// the state differs from the reference model after a step.
+    Divergence(Box<Divergence>),
+}
 
 pub struct Emulator {
     cpu: Cpu,
     machine: Machine,
     testmode: bool,
     tohost: u64,
+    debug_module: DebugModule,
+    jtag: JtagTap,
+    /// console output captured while `run_until` waits for a string.
+    console_capture: Rc<RefCell<Option<Vec<u8>>>>,
+    lockstep: Option<Lockstep>,
+    /// records or replays the console input, shared with the console.
+    input_recorder: Rc<RefCell<InputRecorder>>,
+    /// checkpoints of reverse execution, if it is enabled.
+    checkpoints: Option<Checkpoints>,
+    /// the history has diverged, so a checkpoint is taken before the next step.
+    pending_checkpoint: bool,
+    /// the pending checkpoint follows a step which halted the hart.
+    pending_halt: bool,
+    /// cycle of the checkpoint taken or restored last.
+    last_checkpoint: Option<u64>,
+    /// cycle after the last step which halted the hart.
+    halted_at: Option<u64>,
+    /// state of the machine at the checkpoint without the memories, which
+    /// restore their own checkpoints.
+    checkpoint: Option<Vec<u8>>,
+    /// executed blocks of the loaded programs.
+    coverage: Coverage,
+    /// retired instructions by the call stack.
+    profiler: Profiler,
 }
 
 impl Emulator {
     pub fn new(machine_: Machine, tty: Box<dyn Console>, testmode_: bool) -> Emulator {
+        let console_capture = Rc::new(RefCell::new(None));
+        let input_recorder = Rc::new(RefCell::new(InputRecorder::new()));
+        let tty = Box::new(ReplayConsole::new(tty, input_recorder.clone()));
+        let console = Box::new(CapturingConsole::new(tty, console_capture.clone()));
         Self {
-            cpu: Cpu::new(machine_.clone(), tty, testmode_),
+            cpu: Cpu::new(machine_.clone(), console, testmode_),
             machine: machine_,
             testmode: testmode_,
             tohost: 0,
+            debug_module: DebugModule::new(),
+            jtag: JtagTap::new(),
+            console_capture,
+            lockstep: None,
+            input_recorder,
+            checkpoints: None,
+            pending_checkpoint: false,
+            pending_halt: false,
+            last_checkpoint: None,
+            halted_at: None,
+            checkpoint: None,
+            coverage: Coverage::new(),
+            profiler: Profiler::new(),
         }
     }
 
     pub fn reset(&mut self) {
-        self.cpu.reset()
+        self.cpu.reset();
+        self.reset_history();
     }
 
     pub fn set_pc(&mut self, addr: u64) {
-        self.cpu.set_pc(addr)
+        self.diverge();
+        self.cpu.set_pc(addr);
+        self.debug_module.set_reset_pc(addr);
+    }
+
+    /// Selects the RV32E/RV64E base ISA, which only has 16 integer registers.
+    pub fn set_rve(&mut self, rve: bool) {
+        self.cpu.set_rve(rve)
+    }
+
+    /// Returns the trace subsystem, which takes the sinks, the categories and the filter.
+    pub fn get_tracer(&mut self) -> &mut Tracer {
+        &mut self.cpu.mmu.tracer
+    }
+
+    /// Sets the reference model which `step` and `run_until` run in lockstep,
+    /// comparing the architectural state and the stores after every step. The
+    /// reference must have been loaded with the same program.
+    pub fn set_reference(&mut self, reference: Option<Box<dyn ReferenceModel>>) {
+        self.lockstep = reference.map(Lockstep::new);
+    }
+
+    /// Starts or stops recording the executed basic blocks of the loaded
+    /// programs. The recorded blocks are kept.
+    pub fn set_coverage(&mut self, enabled: bool) {
+        self.coverage.set_enabled(enabled);
+    }
+
+    /// Returns the coverage, which has the executed blocks and the modules.
+    pub fn get_coverage(&self) -> &Coverage {
+        &self.coverage
+    }
+
+    /// Forgets the recorded blocks.
+    pub fn clear_coverage(&mut self) {
+        self.coverage.clear();
+    }
+
+    /// Saves the executed blocks of the loaded programs to a drcov file.
+    pub fn save_drcov(&self, filename: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        self.coverage.write_drcov(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Saves the line coverage of the loaded programs to an lcov tracefile.
+    pub fn save_lcov(&self, filename: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        self.coverage.write_lcov(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Starts or stops counting the retired instructions by the function and
+    /// the call stack. The counts are kept.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiler.set_enabled(enabled);
+    }
+
+    pub fn get_profiler(&self) -> &Profiler {
+        &self.profiler
+    }
+
+    /// Forgets the counts of the profiler.
+    pub fn clear_profile(&mut self) {
+        self.profiler.clear();
+    }
+
+    /// Saves the counts of the profiler to a file of the folded stacks, which
+    /// flamegraph.pl and inferno turn into a flame graph.
+    pub fn save_profile(&self, filename: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        self.profiler
+            .write_folded(self.cpu.get_symbols(), &mut writer)?;
+        writer.flush()
+    }
+
+    /// Returns the functions of the loaded program sorted by the retired
+    /// instructions in themselves.
+    pub fn get_hot_functions(&self) -> Vec<FunctionProfile> {
+        self.profiler.get_hot_functions(self.cpu.get_symbols())
+    }
+
+    /// Starts or stops gathering the execution statistics, which are the
+    /// instruction mix, the traps, the privilege modes, the loads and the
+    /// stores by the region, and the page walks. The counters are kept.
+    pub fn set_statistics(&mut self, enabled: bool) {
+        self.cpu.mmu.stats.set_enabled(enabled);
+    }
+
+    /// Returns the execution statistics, which format the report by `Display`.
+    pub fn get_statistics(&self) -> &Stats {
+        &self.cpu.mmu.stats
+    }
+
+    /// Resets the counters of the execution statistics.
+    pub fn clear_statistics(&mut self) {
+        self.cpu.mmu.stats.clear();
+    }
+
+    /// Returns the symbols of the loaded program.
+    pub fn get_symbols(&self) -> &SymbolTable {
+        self.cpu.get_symbols()
+    }
+
+    pub fn get_isa_string(&self) -> String {
+        self.cpu.get_isa_string()
+    }
+
+    /// Makes the entropy source behind the seed CSR deterministic.
+    pub fn set_entropy_seed(&mut self, seed: u64) {
+        self.cpu.csr.set_entropy_seed(seed)
+    }
+
+    /// Sets the cache block size of Zicbom/Zicboz/Zicbop. It is also written to
+    /// the DTB, so this must be called before the DTB is set.
+    pub fn set_cache_block_size(&mut self, size: u64) {
+        self.cpu.mmu.set_cache_block_size(size)
     }
 
     pub fn get_console(&mut self) -> &mut Box<dyn Console> {
@@ -45,18 +286,56 @@ impl Emulator {
                     Err(why) => panic!("Failed to read {}: {}", filename.display(), why),
                     _ => {}
                 };
-                let bus = self.cpu.mmu.get_bus();
-                bus.set_device_data(device, data);
+                self.set_data_from_binary(device, data);
             }
             Err(why) => panic!("Falied to open {}: {}", filename.display(), why),
         };
     }
 
     pub fn set_data_from_binary(&mut self, device: Device, data: Vec<u8>) {
+        let data = match device {
+            Device::DTB => self.update_dtb(data),
+            _ => data,
+        };
         let bus = self.cpu.mmu.get_bus();
         bus.set_device_data(device, data);
     }
 
+    /// Adds the cache block sizes and the multi-letter extensions of the hart
+    /// to the cpu nodes.
+    /// The DTB is passed through as it is if it can not be parsed.
+    fn update_dtb(&mut self, data: Vec<u8>) -> Vec<u8> {
+        let mut fdt = match Fdt::parse(&data) {
+            Some(fdt) => fdt,
+            None => return data,
+        };
+        let block_size = self.cpu.mmu.get_cache_block_size() as u32;
+        let hart_isa = self.cpu.get_isa_string();
+        for cpu in fdt.get_cpu_nodes_mut() {
+            cpu.set_property_u32("riscv,cbom-block-size", block_size);
+            cpu.set_property_u32("riscv,cbop-block-size", block_size);
+            cpu.set_property_u32("riscv,cboz-block-size", block_size);
+            if let Some(mut isa) = cpu.get_property_str("riscv,isa") {
+                for extension in hart_isa.split('_').skip(1) {
+                    if !isa.split('_').any(|e| e == extension) {
+                        isa += "_";
+                        isa += extension;
+                    }
+                }
+                cpu.set_property_str("riscv,isa", &isa);
+            }
+        }
+        fdt.to_bytes()
+    }
+
+    /// Overwrites the data of the device from the offset, e.g. to change a
+    /// part of the disk image between the runs.
+    pub fn write_device_data(&mut self, device: Device, offset: u64, data: &[u8]) {
+        self.diverge();
+        let bus = self.cpu.mmu.get_bus();
+        bus.write_device_data(device, offset, data);
+    }
+
     pub fn set_dram_data(&mut self, data: Vec<u8>) {
         let bus = self.cpu.mmu.get_bus();
         bus.set_device_data(Device::Dram, data);
@@ -84,7 +363,7 @@ impl Emulator {
                     EMachine::RISCV => {}
                     _ => panic!("{} is not program for RISC-V machine!", filename.display()),
                 }
-                self.load_program(loader);
+                self.load_program(loader, &filename.display().to_string());
             }
             Err(why) => panic!("Falied to open {}: {}", filename.display(), why),
         };
@@ -105,17 +384,23 @@ impl Emulator {
             EMachine::RISCV => {}
             _ => panic!("{} is not program for RISC-V machine!"),
         }
-        self.load_program(loader);
+        self.load_program(loader, "program");
     }
 
-    fn load_program(&mut self, loader: ElfLoader) {
+    /// Loads the ELF program, which is added to the modules of the coverage as
+    /// `path`.
+    fn load_program(&mut self, loader: ElfLoader, path: &str) {
         let elf_header = loader.get_elf_header();
         self.cpu.set_pc(elf_header.e_entry);
+        self.debug_module.set_reset_pc(elf_header.e_entry);
         self.cpu.set_xlen(match elf_header.e_indent.ei_classs {
             EiClass::Class32 => Xlen::X32,
             EiClass::Class64 => Xlen::X64,
             _ => panic!("Unexpected class size: {:?}", elf_header.e_indent.ei_classs),
         });
+        if elf_header.e_flags & EF_RISCV_RVE != 0 {
+            self.cpu.set_rve(true);
+        }
 
         let sec_headers = loader.get_section_header(&elf_header);
         let mut progbits_sec_headers = vec![];
@@ -128,6 +413,9 @@ impl Emulator {
             }
         }
 
+        let symbols = Rc::new(SymbolTable::from_elf(&loader, &elf_header, &sec_headers));
+        self.cpu.set_symbols(symbols.clone());
+
         let target_device_addr;
         match self.machine {
             Machine::QemuVirt => {
@@ -137,6 +425,9 @@ impl Emulator {
         }
 
         let program_headers = loader.get_program_header(&elf_header);
+        // range of the loaded sections by their virtual addresses.
+        let mut base = u64::MAX;
+        let mut end = 0;
         for i in 0..progbits_sec_headers.len() {
             if !((progbits_sec_headers[i].sh_addr >= target_device_addr)
                 && progbits_sec_headers[i].sh_offset > 0)
@@ -153,15 +444,30 @@ impl Emulator {
                     break;
                 }
             }
+            base = base.min(progbits_sec_headers[i].sh_addr);
+            end = end.max(progbits_sec_headers[i].sh_addr + p_size);
 
             for j in 0..p_size {
                 let data = loader.read8((progbits_sec_headers[i].sh_offset + j) as usize);
-                match self.cpu.mmu.write8(p_addr + j as u64, data) {
+                match self
+                    .cpu
+                    .mmu
+                    .store_physical(p_addr + j as u64, 1, data as u64)
+                {
                     Err(e) => panic!("{:?}", e.exception),
                     _ => {}
                 }
             }
         }
+        if base < end {
+            self.coverage.add_module(Module {
+                path: path.to_string(),
+                base,
+                end,
+                entry: elf_header.e_entry,
+                symbols,
+            });
+        }
 
         if self.testmode {
             self.tohost = match loader.search_tohost(&progbits_sec_headers, &strtab_sec_headers) {
@@ -171,25 +477,769 @@ impl Emulator {
         }
     }
 
-    pub fn run(&mut self) -> Result<u32, u32> {
+    /// Saves the state of the whole machine, which are the hart, the devices,
+    /// the memories and the buffers of the console, to a snapshot file.
+    /// The debug module, the JTAG TAP, the tracer and the symbols are not saved.
+    pub fn save_snapshot(&self, filename: &Path) -> Result<(), SnapshotError> {
+        let data = self.save_snapshot_to_binary();
+        File::create(filename)?.write_all(&data)?;
+        Ok(())
+    }
+
+    pub fn save_snapshot_to_binary(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new(self.machine.clone() as u8);
+        self.save_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    fn save_state(&self, writer: &mut SnapshotWriter) {
+        self.cpu.save(writer);
+        writer.write_tag("emulator");
+        writer.write_u64(self.tohost);
+    }
+
+    /// Restores the machine from a snapshot file saved by `save_snapshot` on
+    /// the same machine. The emulator must be created with the same machine,
+    /// and the state is undefined if it fails. It forgets the checkpoint
+    /// taken by `take_checkpoint`.
+    pub fn load_snapshot(&mut self, filename: &Path) -> Result<(), SnapshotError> {
+        let mut data = vec![];
+        File::open(filename)?.read_to_end(&mut data)?;
+        self.load_snapshot_from_binary(&data)
+    }
+
+    pub fn load_snapshot_from_binary(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        let result = self.restore_snapshot(data);
+        self.checkpoint = None;
+        self.reset_history();
+        result
+    }
+
+    fn restore_snapshot(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        let mut reader = SnapshotReader::new(data, self.machine.clone() as u8)?;
+        self.restore_state(&mut reader)
+    }
+
+    fn restore_state(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        self.cpu.restore(reader)?;
+        reader.expect_tag("emulator")?;
+        self.tohost = reader.read_u64()?;
+        reader.finish()
+    }
+
+    /// Takes a checkpoint of the whole machine in memory, which
+    /// `restore_checkpoint` restores much faster than a snapshot, e.g. to run
+    /// each input of a fuzzer from the state after the boot. The memories and
+    /// the disk image are not copied, but the pages written after the
+    /// checkpoint are copied back when it is restored. It replaces the
+    /// previous checkpoint.
+    pub fn take_checkpoint(&mut self) {
+        let mut writer = SnapshotWriter::without_memories(self.machine.clone() as u8);
+        self.save_state(&mut writer);
+        self.cpu.mmu.bus.take_memory_checkpoint();
+        self.checkpoint = Some(writer.into_bytes());
+    }
+
+    /// Restores the machine to the checkpoint taken by `take_checkpoint`,
+    /// which is kept so that it can be restored again. It panics if no
+    /// checkpoint has been taken.
+    pub fn restore_checkpoint(&mut self) {
+        let data = self
+            .checkpoint
+            .take()
+            .expect("No checkpoint has been taken");
+        self.cpu.mmu.bus.restore_memory_checkpoint();
+        let result = SnapshotReader::without_memories(&data, self.machine.clone() as u8)
+            .and_then(|mut reader| self.restore_state(&mut reader));
+        if let Err(e) = result {
+            panic!("Failed to restore the checkpoint: {}", e);
+        }
+        self.checkpoint = Some(data);
+        self.reset_history();
+    }
+
+    pub fn has_checkpoint(&self) -> bool {
+        self.checkpoint.is_some()
+    }
+
+    /// Records the inputs from outside of the machine, which are the console
+    /// input and the seed of the entropy source, with the cycles at which they
+    /// are consumed. Replaying the log from the same state reproduces the run.
+    pub fn record_inputs(&mut self, writer: Box<dyn Write>) {
+        let seed = self.cpu.csr.get_entropy_seed();
+        self.input_recorder
+            .borrow_mut()
+            .start_recording(writer, self.cpu.get_cycle(), seed);
+    }
+
+    pub fn record_inputs_to_file(&mut self, filename: &Path) -> io::Result<()> {
+        let file = File::create(filename)?;
+        self.record_inputs(Box::new(BufWriter::new(file)));
+        Ok(())
+    }
+
+    /// Replays the inputs recorded by `record_inputs` instead of the console
+    /// input. The machine must be in the state where the recording started,
+    /// e.g. just after loading the same program, or restored from a snapshot.
+    pub fn replay_inputs_from_file(&mut self, filename: &Path) -> io::Result<()> {
+        let mut data = vec![];
+        File::open(filename)?.read_to_end(&mut data)?;
+        self.replay_inputs_from_binary(&data)
+    }
+
+    pub fn replay_inputs_from_binary(&mut self, data: &[u8]) -> io::Result<()> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut records = parse_input_log(data)?;
+        let (cycle, seed) = match records.first() {
+            Some(InputRecord {
+                cycle,
+                event: InputEvent::EntropySeed(seed),
+            }) => (*cycle, *seed),
+            _ => return Err(invalid("The input log has no entropy seed")),
+        };
+        if records[1..]
+            .iter()
+            .any(|record| matches!(record.event, InputEvent::EntropySeed(_)))
+        {
+            return Err(invalid("The input log has another entropy seed"));
+        }
+        if cycle != self.cpu.get_cycle() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The input log starts at cycle {}, but the machine is at cycle {}",
+                    cycle,
+                    self.cpu.get_cycle()
+                ),
+            ));
+        }
+        self.cpu.csr.set_entropy_seed(seed);
+        records.remove(0);
+        self.input_recorder.borrow_mut().start_replaying(records);
+        Ok(())
+    }
+
+    /// Stops recording or replaying the inputs.
+    pub fn stop_inputs(&mut self) {
+        self.input_recorder.borrow_mut().stop();
+    }
+
+    /// Keeps a checkpoint of the machine every `interval` cycles, up to
+    /// `capacity` of them, and the console input, so that the emulator can run
+    /// backwards by restoring a checkpoint and executing again from it. The
+    /// oldest checkpoint is dropped when there are too many. The debugger is
+    /// not part of the history, so a change of the machine made by it, e.g. a
+    /// register write, forgets the history after it.
+    pub fn enable_reverse_execution(&mut self, interval: u64, capacity: usize) {
+        self.checkpoints = Some(Checkpoints::new(interval, capacity, self.cpu.get_cycle()));
+        self.reset_history();
+    }
+
+    pub fn disable_reverse_execution(&mut self) {
+        self.checkpoints = None;
+        self.reset_history();
+    }
+
+    pub fn is_reverse_execution_enabled(&self) -> bool {
+        self.checkpoints.is_some()
+    }
+
+    /// Starts the history from the current state.
+    fn reset_history(&mut self) {
+        let cycle = self.cpu.get_cycle();
+        if let Some(checkpoints) = self.checkpoints.as_mut() {
+            checkpoints.clear(cycle);
+        }
+        self.input_recorder
+            .borrow_mut()
+            .keep_history(self.checkpoints.is_some(), cycle);
+        self.pending_checkpoint = true;
+        self.pending_halt = false;
+        self.last_checkpoint = None;
+        self.halted_at = None;
+    }
+
+    /// Forgets the history from the current cycle because the machine has
+    /// been changed outside of the execution, e.g. by the debugger. A
+    /// checkpoint is taken before the next step so that executing again from
+    /// an earlier checkpoint does not pass the change.
+    fn diverge(&mut self) {
+        let cycle = self.cpu.get_cycle();
+        if let Some(checkpoints) = self.checkpoints.as_mut() {
+            checkpoints.truncate(cycle);
+            self.input_recorder.borrow_mut().truncate_history(cycle);
+            self.pending_checkpoint = true;
+        }
+    }
+
+    /// The write is made by the debugger to its own registers, which the
+    /// execution does not depend on.
+    fn is_debugger_csr_write(&mut self, csr: u16, data: u64) -> bool {
+        match csr {
+            CSR_DSCRATCH0 | CSR_DSCRATCH1 => true,
+            CSR_DCSR => {
+                let dcsr = self.cpu.read_csr_direct(CSR_DCSR);
+                (dcsr ^ data) & !(CSR_DCSR_STEP | CSR_DCSR_CAUSE) == 0
+            }
+            _ => false,
+        }
+    }
+
+    fn take_history_checkpoint(&mut self, discontinuity: bool, halted: bool) {
+        let cycle = self.cpu.get_cycle();
+        let data = self.save_snapshot_to_binary();
+        if let Some(checkpoints) = self.checkpoints.as_mut() {
+            checkpoints.insert(Checkpoint {
+                cycle,
+                data,
+                discontinuity,
+                halted,
+            });
+        }
+        self.last_checkpoint = Some(cycle);
+    }
+
+    /// Restores the checkpoint of the cycle, from which the console inputs
+    /// are consumed again. The debugger registers are also restored. The
+    /// buffers of the console are kept, since their input and output are
+    /// outside of the machine.
+    fn restore_history_checkpoint(&mut self, cycle: u64) {
+        let checkpoints = self.checkpoints.take().unwrap();
+        let data = &checkpoints.get(cycle).unwrap().data;
+        let (input, output) = self.get_console().get_buffers();
+        if let Err(e) = self.restore_snapshot(data) {
+            panic!("Failed to restore the checkpoint at cycle {}: {}", cycle, e);
+        }
+        self.get_console().set_buffers(input, output);
+        self.checkpoints = Some(checkpoints);
+        self.input_recorder.borrow_mut().rewind(cycle);
+        self.last_checkpoint = Some(cycle);
+    }
+
+    /// Prepares the next step: takes or restores the checkpoint of the cycle
+    /// if reverse execution is enabled, and tells the cycle to the input recorder.
+    fn before_step(&mut self) {
+        let cycle = self.cpu.get_cycle();
+        if self.checkpoints.is_some() {
+            // a halted hart or a stall ended by a halt is not reproduced by executing again.
+            if self.cpu.take_debug_wakeup() || self.cpu.is_debug_mode() {
+                self.diverge();
+            }
+            if !self.cpu.is_debug_mode() {
+                self.update_checkpoints(cycle);
+            }
+            if let Some(checkpoints) = self.checkpoints.as_mut() {
+                checkpoints.advance(cycle);
+            }
+        }
+        self.set_step_cycle(cycle);
+    }
+
+    /// Tells the cycle of the next step to the input recorder. The statistics
+    /// do not count the cycle if it is executed again.
+    fn set_step_cycle(&mut self, cycle: u64) {
+        let mut recorder = self.input_recorder.borrow_mut();
+        recorder.set_cycle(cycle);
+        self.cpu.mmu.stats.set_replaying(recorder.is_in_history());
+    }
+
+    fn update_checkpoints(&mut self, cycle: u64) {
+        let checkpoints = self.checkpoints.as_ref().unwrap();
+        if self.pending_checkpoint {
+            let halted = self.pending_halt;
+            self.pending_checkpoint = false;
+            self.pending_halt = false;
+            self.take_history_checkpoint(true, halted);
+            return;
+        }
+        // running forward in the history reaches a change made by the debugger.
+        match checkpoints.get(cycle) {
+            Some(checkpoint)
+                if checkpoint.discontinuity
+                    && checkpoints.is_history(cycle)
+                    && self.last_checkpoint != Some(cycle) =>
+            {
+                match checkpoint.halted && self.halted_at != Some(cycle) {
+                    // the hart has not halted as it did in the history.
+                    true => {
+                        self.diverge();
+                        self.pending_checkpoint = false;
+                        self.take_history_checkpoint(true, false);
+                    }
+                    false => {
+                        let triggers = self.cpu.mmu.triggers.clone();
+                        let dcsr = self.cpu.read_csr_direct(CSR_DCSR);
+                        self.restore_history_checkpoint(cycle);
+                        self.cpu.mmu.triggers = triggers;
+                        self.cpu.csr.write_direct(CSR_DCSR, dcsr);
+                    }
+                }
+            }
+            _ if checkpoints.is_due(cycle) => self.take_history_checkpoint(false, false),
+            _ => {}
+        }
+    }
+
+    /// Counts the executed block for the coverage and the retired instruction
+    /// for the profiler, and checks whether the step halted the hart instead
+    /// of completing it, which executing again reproduces only with the same
+    /// triggers.
+    fn after_step(&mut self, result: &StepResult) {
+        if self.coverage.is_enabled() {
+            self.coverage.record(result, self.cpu.pc);
+        }
+        if self.profiler.is_enabled() {
+            self.profiler.record(result, self.cpu.pc);
+        }
+        if self.checkpoints.is_none() || !result.debug_halt {
+            return;
+        }
+        let dcsr = self.cpu.read_csr_direct(CSR_DCSR);
+        if (dcsr & CSR_DCSR_CAUSE) >> 6 == DebugCause::Step as u64 {
+            return;
+        }
+        let cycle = self.cpu.get_cycle();
+        self.halted_at = Some(cycle);
+        let checkpoints = self.checkpoints.as_ref().unwrap();
+        let repeated = checkpoints.is_history(cycle)
+            && matches!(checkpoints.get(cycle), Some(checkpoint) if checkpoint.halted);
+        if !repeated {
+            self.diverge();
+            self.pending_halt = true;
+        }
+    }
+
+    /// Runs the emulator until the test program writes `tohost` or the state
+    /// differs from the reference model.
+    pub fn run(&mut self) -> Result<u32, RunError> {
         loop {
-            self.cpu.tick();
+            match self.run_until(&StopConditions::default()) {
+                StopReason::ToHost(1) => return Ok(1),
+                StopReason::ToHost(n) => return Err(RunError::ToHost(n)),
+                StopReason::Divergence(divergence) => return Err(RunError::Divergence(divergence)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Runs the emulator until one of the conditions is met. A breakpoint at
+    /// the current PC does not stop it, so that it can resume from a breakpoint.
+    pub fn run_until(&mut self, conditions: &StopConditions) -> StopReason {
+        self.cpu.mmu.set_watchpoints(conditions.watchpoints.clone());
+        if conditions.console_output.is_some() {
+            *self.console_capture.borrow_mut() = Some(vec![]);
+        }
+        let reason = self.run_until_stop(conditions);
+        self.cpu.mmu.tracer.flush();
+        self.cpu.mmu.set_watchpoints(vec![]);
+        *self.console_capture.borrow_mut() = None;
+        reason
+    }
+
+    fn run_until_stop(&mut self, conditions: &StopConditions) -> StopReason {
+        let mut instructions = 0;
+        let mut cycles = 0;
+        let mut console_checked = 0;
+        loop {
+            if cycles > 0 {
+                if let Some(address) = self.check_breakpoints(&conditions.breakpoints) {
+                    return StopReason::Breakpoint(address);
+                }
+            }
+
+            let privilege = self.cpu.privilege.clone();
+            let mut result = self.step();
+            if let Some(divergence) = result.divergence.take() {
+                return StopReason::Divergence(divergence);
+            }
+            cycles += 1;
+            if result.instruction.is_some() && result.exception.is_none() {
+                instructions += 1;
+            }
+
+            if result.debug_halt {
+                return StopReason::DebugHalt;
+            }
+            if let Some(trap) = result.exception {
+                if conditions.exceptions.contains(&trap.exception) {
+                    return StopReason::Exception(trap);
+                }
+            }
+            if let Some(interrupt) = result.interrupt {
+                if conditions.interrupts.contains(&interrupt) {
+                    return StopReason::Interrupt(interrupt);
+                }
+            }
+            if let Some((address, write)) = self.cpu.mmu.take_watchpoint_hit() {
+                return StopReason::Watchpoint { address, write };
+            }
+            if conditions.privilege_change && self.cpu.privilege != privilege {
+                return StopReason::PrivilegeChange {
+                    from: privilege,
+                    to: self.cpu.privilege.clone(),
+                };
+            }
+            if let Some(pattern) = &conditions.console_output {
+                if let Some(output) = self.console_capture.borrow().as_ref() {
+                    if output.len() > console_checked {
+                        // only the new output and the tail of the old one can match.
+                        let start = (console_checked + 1).saturating_sub(pattern.len());
+                        if pattern.is_empty()
+                            || output[start..]
+                                .windows(pattern.len())
+                                .any(|window| window == pattern.as_bytes())
+                        {
+                            return StopReason::ConsoleOutput;
+                        }
+                        console_checked = output.len();
+                    }
+                }
+            }
             if self.testmode && self.tohost != 0 {
                 match self.cpu.mmu.read32_direct(self.tohost) {
                     Ok(data) => match data {
                         0 => {}
-                        1 => return Ok(1),
-                        n => return Err(n),
+                        n => return StopReason::ToHost(n),
                     },
                     Err(e) => panic!("Faild to read .tohost: {:?}", e.exception),
                 }
             }
+            if matches!(conditions.instruction_limit, Some(limit) if instructions >= limit) {
+                return StopReason::InstructionLimit;
+            }
+            if matches!(conditions.cycle_limit, Some(limit) if cycles >= limit) {
+                return StopReason::CycleLimit;
+            }
+        }
+    }
+
+    /// Returns the breakpoint which the current PC hits.
+    fn check_breakpoints(&mut self, breakpoints: &[Breakpoint]) -> Option<u64> {
+        let pc = self.cpu.pc;
+        for breakpoint in breakpoints {
+            match *breakpoint {
+                Breakpoint::Virtual(address) if address == pc => return Some(address),
+                Breakpoint::Physical(address) => {
+                    match self.cpu.mmu.translate_address(pc, MemoryAccessType::Fetch) {
+                        Ok(p_addr) if p_addr == address => return Some(address),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
         }
+        None
     }
 
-    pub fn run_steps(&mut self, steps: u32) {
+    /// Executes the cycles, and stops at the first step whose state differs
+    /// from the reference model.
+    pub fn run_steps(&mut self, steps: u32) -> Option<Box<Divergence>> {
         for _i in 0..steps {
-            self.cpu.tick();
+            if let Some(divergence) = self.step().divergence {
+                return Some(divergence);
+            }
+        }
+        None
+    }
+
+    /// Executes a cycle, and returns the instruction, the exception and the
+    /// interrupt of it. The reference model, if it is set, executes the cycle
+    /// too, and a mismatch is returned in `divergence`.
+    pub fn step(&mut self) -> StepResult {
+        self.before_step();
+        let result = match self.lockstep.as_mut() {
+            Some(lockstep) => lockstep.step(&mut self.cpu),
+            None => self.cpu.step(),
+        };
+        self.after_step(&result);
+        result
+    }
+
+    /// Goes back over the last step which executed an instruction or took an
+    /// interrupt, so that the hart is in the state before it. A halted hart
+    /// stays halted.
+    pub fn reverse_step(&mut self) -> StopReason {
+        self.reverse(&StopConditions::default(), true)
+    }
+
+    /// Runs the emulator backwards until one of the conditions is met, that is
+    /// to the last state before the current one where the PC is at a
+    /// breakpoint or the next step accesses a watched range, or until the
+    /// limits counted backwards are reached. The other conditions are ignored.
+    /// It stops at the earliest checkpoint if no condition is met.
+    pub fn reverse_until(&mut self, conditions: &StopConditions) -> StopReason {
+        self.reverse(conditions, false)
+    }
+
+    fn reverse(&mut self, conditions: &StopConditions, step: bool) -> StopReason {
+        if self.checkpoints.is_none() {
+            return StopReason::HistoryStart;
+        }
+        let now = self.cpu.get_cycle();
+        let halted = self.cpu.is_debug_mode();
+        let triggers = self.cpu.mmu.triggers.clone();
+        let dcsr = self.cpu.read_csr_direct(CSR_DCSR);
+        // steps which are counted backwards, which are not retired
+        // instructions but the steps which did anything for `reverse_step`.
+        let mut remaining = match step {
+            true => Some(1),
+            false => conditions.instruction_limit,
+        };
+        let cycle_target = conditions
+            .cycle_limit
+            .map(|limit| now.saturating_sub(limit));
+
+        // the history is searched backwards between the checkpoints.
+        let mut end = now;
+        let (target, reason) = loop {
+            let start = match self.checkpoints.as_ref().unwrap().latest_before(end) {
+                Some(start) => start,
+                None => break (end, StopReason::HistoryStart),
+            };
+            let mut stops = vec![];
+            if let Some(target) = cycle_target.filter(|target| *target >= start) {
+                stops.push((target, StopReason::CycleLimit));
+            }
+            self.replay_from(start);
+            self.cpu.mmu.set_watchpoints(conditions.watchpoints.clone());
+            let mut counted = VecDeque::new();
+            for cycle in start..end {
+                if let Some(address) = self.check_breakpoints(&conditions.breakpoints) {
+                    stops.push((cycle, StopReason::Breakpoint(address)));
+                }
+                self.set_step_cycle(cycle);
+                let result = self.cpu.step();
+                let counts = match step {
+                    true => result.instruction.is_some() || result.interrupt.is_some(),
+                    false => result.instruction.is_some() && result.exception.is_none(),
+                };
+                if let Some(remaining) = remaining.filter(|_| counts) {
+                    counted.push_back(cycle);
+                    if counted.len() as u64 > remaining {
+                        counted.pop_front();
+                    }
+                }
+                if let Some((address, write)) = self.cpu.mmu.take_watchpoint_hit() {
+                    stops.push((cycle, StopReason::Watchpoint { address, write }));
+                }
+            }
+            self.cpu.mmu.set_watchpoints(vec![]);
+            if let Some(limit) = remaining {
+                match counted.front() {
+                    Some(cycle) if counted.len() as u64 == limit => {
+                        stops.push((*cycle, StopReason::InstructionLimit))
+                    }
+                    _ => remaining = Some(limit - counted.len() as u64),
+                }
+            }
+            // the stop closest to the current state is met first.
+            if let Some(stop) = stops.into_iter().max_by_key(|(cycle, _)| *cycle) {
+                break stop;
+            }
+            end = start;
+        };
+
+        if target != now {
+            let start = self
+                .checkpoints
+                .as_ref()
+                .unwrap()
+                .latest_before(target + 1)
+                .unwrap();
+            self.replay_from(start);
+            for cycle in start..target {
+                self.set_step_cycle(cycle);
+                self.cpu.step();
+            }
+        }
+        self.cpu.mmu.triggers = triggers;
+        self.cpu
+            .mmu
+            .triggers
+            .set_debug_mode(self.cpu.is_debug_mode());
+        self.cpu.csr.write_direct(CSR_DCSR, dcsr);
+        self.cpu.take_debug_wakeup();
+        if halted {
+            self.cpu.debug_halt();
+        }
+        reason
+    }
+
+    /// Restores the checkpoint to execute the history again from it. The
+    /// triggers of the checkpoint are kept, which halt the hart as they did,
+    /// but the hart does not single step.
+    fn replay_from(&mut self, cycle: u64) {
+        self.restore_history_checkpoint(cycle);
+        let dcsr = self.cpu.read_csr_direct(CSR_DCSR);
+        self.cpu.csr.write_direct(CSR_DCSR, dcsr & !CSR_DCSR_STEP);
+    }
+
+    pub fn get_pc(&self) -> u64 {
+        self.cpu.pc
+    }
+
+    /// Returns the number of the cycles executed since the reset.
+    pub fn get_cycle(&self) -> u64 {
+        self.cpu.get_cycle()
+    }
+
+    /// Reads an integer register, truncated to the current XLEN.
+    pub fn read_register(&self, index: usize) -> u64 {
+        match self.cpu.xlen {
+            Xlen::X32 => self.cpu.x[index] as u64 & 0xffffffff,
+            Xlen::X64 => self.cpu.x[index] as u64,
+        }
+    }
+
+    /// Writes an integer register. Writes to x0 are ignored.
+    pub fn write_register(&mut self, index: usize, value: u64) {
+        if index != 0 {
+            self.diverge();
+            self.cpu.x[index] = match self.cpu.xlen {
+                Xlen::X32 => value as i32 as i64,
+                Xlen::X64 => value as i64,
+            };
+        }
+    }
+
+    /// Reads the bits of a floating-point register.
+    pub fn read_fp_register(&self, index: usize) -> u64 {
+        self.cpu.f[index].to_bits()
+    }
+
+    pub fn write_fp_register(&mut self, index: usize, bits: u64) {
+        self.diverge();
+        self.cpu.f[index] = f64::from_bits(bits);
+    }
+
+    /// Reads a CSR regardless of the current privilege mode.
+    pub fn read_csr(&mut self, csr: u16) -> u64 {
+        self.cpu.read_csr_direct(csr)
+    }
+
+    /// Writes a CSR regardless of the current privilege mode.
+    pub fn write_csr(&mut self, csr: u16, data: u64) {
+        if !self.is_debugger_csr_write(csr, data) {
+            self.diverge();
+        }
+        self.cpu.write_csr_direct(csr, data)
+    }
+
+    pub fn get_privilege(&self) -> Privilege {
+        self.cpu.privilege.clone()
+    }
+
+    pub fn set_privilege(&mut self, privilege: Privilege) {
+        self.diverge();
+        self.cpu.change_privilege(privilege)
+    }
+
+    /// Translates a virtual address in the current privilege mode. A page fault
+    /// is returned as the trap which the access would raise.
+    pub fn translate_address(
+        &mut self,
+        v_addr: u64,
+        access_type: MemoryAccessType,
+    ) -> Result<u64, Trap> {
+        self.cpu.mmu.translate_address(v_addr, access_type)
+    }
+
+    /// Reads 1, 2, 4 or 8 bytes of the virtual memory in the current privilege mode.
+    pub fn read_virtual_memory(&mut self, v_addr: u64, size: u8) -> Result<u64, Trap> {
+        self.cpu.mmu.load(v_addr, size)
+    }
+
+    pub fn write_virtual_memory(&mut self, v_addr: u64, size: u8, data: u64) -> Result<(), Trap> {
+        self.diverge();
+        self.cpu.mmu.store(v_addr, size, data)
+    }
+
+    /// Reads 1, 2, 4 or 8 bytes of the physical memory.
+    pub fn read_physical_memory(&mut self, p_addr: u64, size: u8) -> Result<u64, Trap> {
+        self.cpu.mmu.load_physical(p_addr, size)
+    }
+
+    pub fn write_physical_memory(&mut self, p_addr: u64, size: u8, data: u64) -> Result<(), Trap> {
+        self.diverge();
+        self.cpu.mmu.store_physical(p_addr, size, data)
+    }
+
+    /// Returns the machine XLEN (misa.MXL).
+    pub fn get_mxl(&self) -> Xlen {
+        self.cpu.csr.get_mxl()
+    }
+
+    /// Halts the hart in debug mode, as a debugger does.
+    pub fn debug_halt(&mut self) {
+        self.cpu.debug_halt()
+    }
+
+    /// Resumes the hart halted in debug mode.
+    pub fn debug_resume(&mut self) {
+        self.cpu.debug_resume()
+    }
+
+    pub fn is_debug_mode(&self) -> bool {
+        self.cpu.is_debug_mode()
+    }
+
+    /// Executes an abstract command (register or memory access) on the halted hart.
+    pub fn execute_abstract_command(
+        &mut self,
+        command: &AbstractCommand,
+    ) -> Result<u64, CommandError> {
+        let debugger = match *command {
+            AbstractCommand::WriteRegister(regno @ 0x0000..=0x0fff, data) => {
+                self.is_debugger_csr_write(regno, data)
+            }
+            AbstractCommand::WriteRegister(..) | AbstractCommand::WriteMemory { .. } => false,
+            _ => true,
+        };
+        if !debugger {
+            self.diverge();
         }
+        self.cpu.execute_abstract_command(command)
+    }
+
+    /// Reads a Debug Module register through the DMI.
+    pub fn dmi_read(&mut self, addr: u32) -> u32 {
+        self.debug_module.read(&mut self.cpu, addr)
+    }
+
+    /// Writes a Debug Module register through the DMI.
+    pub fn dmi_write(&mut self, addr: u32, data: u32) {
+        self.debug_module.write(&mut self.cpu, addr, data)
+    }
+
+    /// Drives TCK, TMS and TDI of the JTAG TAP, which accesses the Debug Module.
+    pub fn jtag_set_pins(&mut self, tck: bool, tms: bool, tdi: bool) {
+        self.jtag
+            .set_pins(&mut self.debug_module, &mut self.cpu, tck, tms, tdi)
+    }
+
+    pub fn jtag_get_tdo(&self) -> bool {
+        self.jtag.get_tdo()
+    }
+
+    /// Resets the JTAG TAP (TRST).
+    pub fn jtag_reset(&mut self) {
+        self.jtag.reset()
+    }
+}
+
+impl ReferenceModel for Emulator {
+    fn step(&mut self, interrupt: Option<Interrupt>, mmio_reads: Vec<u64>) -> Vec<MemoryWrite> {
+        self.cpu.set_next_interrupt(interrupt);
+        self.cpu.mmu.set_mmio_replay(Some(mmio_reads));
+        self.cpu.mmu.start_access_log();
+        self.before_step();
+        let result = self.cpu.step();
+        self.after_step(&result);
+        self.cpu.mmu.set_mmio_replay(None);
+        self.cpu.mmu.take_access_log().writes
+    }
+
+    fn get_arch_state(&mut self) -> ArchState {
+        ArchState::from_cpu(&mut self.cpu)
     }
 }
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_HEADER_SIZE: usize = 40;
+
+// structure block tokens.
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// A node of the device tree.
+pub struct FdtNode {
+    pub name: String,
+    pub properties: Vec<(String, Vec<u8>)>,
+    pub children: Vec<FdtNode>,
+}
+
+/// Flattened device tree (DTB), which is parsed into a tree of nodes so that
+/// properties can be added or updated before it is passed to the guest.
+pub struct Fdt {
+    pub root: FdtNode,
+    boot_cpuid_phys: u32,
+    mem_rsvmap: Vec<(u64, u64)>,
+}
+
+impl FdtNode {
+    pub fn get_property(&self, name: &str) -> Option<&[u8]> {
+        self.properties
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    pub fn set_property(&mut self, name: &str, value: Vec<u8>) {
+        match self.properties.iter_mut().find(|(n, _)| n == name) {
+            Some(property) => property.1 = value,
+            None => self.properties.push((String::from(name), value)),
+        }
+    }
+
+    pub fn set_property_u32(&mut self, name: &str, value: u32) {
+        self.set_property(name, value.to_be_bytes().to_vec());
+    }
+
+    /// Returns a string property without the terminating NUL.
+    pub fn get_property_str(&self, name: &str) -> Option<String> {
+        let value = self.get_property(name)?;
+        let s = value.split(|c| *c == 0).next().unwrap_or(&[]);
+        String::from_utf8(s.to_vec()).ok()
+    }
+
+    pub fn set_property_str(&mut self, name: &str, value: &str) {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        self.set_property(name, bytes);
+    }
+}
+
+impl Fdt {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if read_u32(data, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let off_dt_struct = read_u32(data, 8)? as usize;
+        let off_dt_strings = read_u32(data, 12)? as usize;
+        let off_mem_rsvmap = read_u32(data, 16)? as usize;
+        let boot_cpuid_phys = read_u32(data, 28)?;
+        let strings = data.get(off_dt_strings..)?;
+
+        let mut mem_rsvmap = vec![];
+        let mut offset = off_mem_rsvmap;
+        loop {
+            let address = read_u64(data, offset)?;
+            let size = read_u64(data, offset + 8)?;
+            if address == 0 && size == 0 {
+                break;
+            }
+            mem_rsvmap.push((address, size));
+            offset += 16;
+        }
+
+        let mut offset = off_dt_struct;
+        let root = loop {
+            match read_u32(data, offset)? {
+                FDT_NOP => offset += 4,
+                FDT_BEGIN_NODE => break parse_node(data, strings, &mut offset)?,
+                _ => return None,
+            }
+        };
+        Some(Fdt {
+            root,
+            boot_cpuid_phys,
+            mem_rsvmap,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut dt_struct = vec![];
+        let mut dt_strings = vec![];
+        let mut string_offsets = HashMap::new();
+        build_node(
+            &self.root,
+            &mut dt_struct,
+            &mut dt_strings,
+            &mut string_offsets,
+        );
+        dt_struct.extend_from_slice(&FDT_END.to_be_bytes());
+
+        let mut mem_rsvmap = vec![];
+        for (address, size) in self.mem_rsvmap.iter().chain([(0, 0)].iter()) {
+            mem_rsvmap.extend_from_slice(&address.to_be_bytes());
+            mem_rsvmap.extend_from_slice(&size.to_be_bytes());
+        }
+
+        // the memory reservation block must be 8-byte aligned.
+        let off_mem_rsvmap = (FDT_HEADER_SIZE + 7) & !7;
+        let off_dt_struct = off_mem_rsvmap + mem_rsvmap.len();
+        let off_dt_strings = off_dt_struct + dt_struct.len();
+        let totalsize = off_dt_strings + dt_strings.len();
+
+        let mut data = vec![];
+        for field in [
+            FDT_MAGIC,
+            totalsize as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            self.boot_cpuid_phys,
+            dt_strings.len() as u32,
+            dt_struct.len() as u32,
+        ]
+        .iter()
+        {
+            data.extend_from_slice(&field.to_be_bytes());
+        }
+        data.resize(off_mem_rsvmap, 0);
+        data.extend(mem_rsvmap);
+        data.extend(dt_struct);
+        data.extend(dt_strings);
+        data
+    }
+
+    /// Returns the nodes of /cpus/cpu@*.
+    pub fn get_cpu_nodes_mut(&mut self) -> Vec<&mut FdtNode> {
+        match self.root.children.iter_mut().find(|n| n.name == "cpus") {
+            Some(cpus) => cpus
+                .children
+                .iter_mut()
+                .filter(|n| n.name == "cpu" || n.name.starts_with("cpu@"))
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some((read_u32(data, offset)? as u64) << 32 | read_u32(data, offset + 4)? as u64)
+}
+
+/// Reads a NUL-terminated string.
+fn read_str(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|c| *c == 0)?;
+    String::from_utf8(bytes[..len].to_vec()).ok()
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Parses a node whose FDT_BEGIN_NODE token is at `offset`, and moves `offset`
+/// to the next token of its FDT_END_NODE.
+fn parse_node(data: &[u8], strings: &[u8], offset: &mut usize) -> Option<FdtNode> {
+    let name = read_str(data, *offset + 4)?;
+    *offset = align4(*offset + 4 + name.len() + 1);
+    let mut node = FdtNode {
+        name,
+        properties: vec![],
+        children: vec![],
+    };
+    loop {
+        match read_u32(data, *offset)? {
+            FDT_BEGIN_NODE => node.children.push(parse_node(data, strings, offset)?),
+            FDT_END_NODE => {
+                *offset += 4;
+                return Some(node);
+            }
+            FDT_PROP => {
+                let len = read_u32(data, *offset + 4)? as usize;
+                let nameoff = read_u32(data, *offset + 8)? as usize;
+                let value = data.get(*offset + 12..*offset + 12 + len)?;
+                node.properties
+                    .push((read_str(strings, nameoff)?, value.to_vec()));
+                *offset = align4(*offset + 12 + len);
+            }
+            FDT_NOP => *offset += 4,
+            _ => return None,
+        }
+    }
+}
+
+fn build_node(
+    node: &FdtNode,
+    dt_struct: &mut Vec<u8>,
+    dt_strings: &mut Vec<u8>,
+    string_offsets: &mut HashMap<String, u32>,
+) {
+    dt_struct.extend_from_slice(&FDT_BEGIN_NODE.to_be_bytes());
+    dt_struct.extend_from_slice(node.name.as_bytes());
+    dt_struct.push(0);
+    dt_struct.resize(align4(dt_struct.len()), 0);
+
+    for (name, value) in node.properties.iter() {
+        let nameoff = *string_offsets.entry(name.clone()).or_insert_with(|| {
+            let nameoff = dt_strings.len() as u32;
+            dt_strings.extend_from_slice(name.as_bytes());
+            dt_strings.push(0);
+            nameoff
+        });
+        dt_struct.extend_from_slice(&FDT_PROP.to_be_bytes());
+        dt_struct.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        dt_struct.extend_from_slice(&nameoff.to_be_bytes());
+        dt_struct.extend_from_slice(value);
+        dt_struct.resize(align4(dt_struct.len()), 0);
+    }
+
+    for child in node.children.iter() {
+        build_node(child, dt_struct, dt_strings, string_offsets);
+    }
+    dt_struct.extend_from_slice(&FDT_END_NODE.to_be_bytes());
+}
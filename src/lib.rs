@@ -3,8 +3,19 @@ extern crate lazy_static;
 
 pub mod bus;
 pub mod console;
+pub mod cosim;
+pub mod coverage;
 pub mod cpu;
+pub mod debug;
+pub mod dwarf;
 pub mod elf_loader;
 pub mod emulator;
+pub mod fdt;
 pub mod machine;
 pub mod peripherals;
+pub mod profiler;
+pub mod replay;
+pub mod reverse;
+pub mod snapshot;
+pub mod symbols;
+pub mod trace;
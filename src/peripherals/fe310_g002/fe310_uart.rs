@@ -2,6 +2,7 @@
 // https://static.dev.sifive.com/FE310-G000.pdf
 
 use crate::console::Console;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
 
 const UART_TXEN: u32 = 0x1;
 const UART_RXEN: u32 = 0x1;
@@ -148,3 +149,52 @@ impl Fe310Uart {
         }
     }
 }
+
+/// The buffers of the console are saved with the registers.
+impl Snapshot for Fe310Uart {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("fe310_uart");
+        writer.write_u32s(&[
+            self.txdata,
+            self.rxdata,
+            self.txctrl,
+            self.rxctrl,
+            self.ie,
+            self.ip,
+            self.div,
+        ]);
+        writer.write_bytes(&self.r_fifo);
+        writer.write_bytes(&self.t_fifo);
+        writer.write_u64(self.cycle);
+        let (input, output) = self.console.get_buffers();
+        writer.write_bytes(&input);
+        writer.write_bytes(&output);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("fe310_uart")?;
+        let mut values = [0; 7];
+        reader.read_u32_array(&mut values)?;
+        for (register, value) in [
+            &mut self.txdata,
+            &mut self.rxdata,
+            &mut self.txctrl,
+            &mut self.rxctrl,
+            &mut self.ie,
+            &mut self.ip,
+            &mut self.div,
+        ]
+        .iter_mut()
+        .zip(values.iter())
+        {
+            **register = *value;
+        }
+        self.r_fifo = reader.read_bytes()?;
+        self.t_fifo = reader.read_bytes()?;
+        self.cycle = reader.read_u64()?;
+        let input = reader.read_bytes()?;
+        let output = reader.read_bytes()?;
+        self.console.set_buffers(input, output);
+        Ok(())
+    }
+}
@@ -2,6 +2,8 @@
 // https://static.dev.sifive.com/FE310-G000.pdf
 // https://bitbucket.org/nuttx/nuttx/src/master/arch/risc-v/src/fe310/fe310_gpio.c
 
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
+
 pub struct Gpio {
     /// Pin value
     input_val: u32,
@@ -116,3 +118,59 @@ impl Gpio {
         }
     }
 }
+
+impl Snapshot for Gpio {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("gpio");
+        writer.write_u32s(&[
+            self.input_val,
+            self.input_en,
+            self.output_en,
+            self.output_val,
+            self.pue,
+            self.ds,
+            self.rise_ie,
+            self.rise_ip,
+            self.fall_ie,
+            self.fall_ip,
+            self.high_ie,
+            self.high_ip,
+            self.low_ie,
+            self.low_ip,
+            self.iof_en,
+            self.iof_sel,
+            self.out_xor,
+        ]);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("gpio")?;
+        let mut values = [0; 17];
+        reader.read_u32_array(&mut values)?;
+        for (field, value) in [
+            &mut self.input_val,
+            &mut self.input_en,
+            &mut self.output_en,
+            &mut self.output_val,
+            &mut self.pue,
+            &mut self.ds,
+            &mut self.rise_ie,
+            &mut self.rise_ip,
+            &mut self.fall_ie,
+            &mut self.fall_ip,
+            &mut self.high_ie,
+            &mut self.high_ip,
+            &mut self.low_ie,
+            &mut self.low_ip,
+            &mut self.iof_en,
+            &mut self.iof_sel,
+            &mut self.out_xor,
+        ]
+        .iter_mut()
+        .zip(values.iter())
+        {
+            **field = *value;
+        }
+        Ok(())
+    }
+}
@@ -2,6 +2,8 @@
 // https://sifive.cdn.prismic.io/sifive%2F9ecbb623-7c7f-4acc-966f-9bb10ecdb62e_fe310-g002.pdf
 // https://bitbucket.org/nuttx/nuttx/src/master/arch/risc-v/src/fe310/fe310_clockconfig.c
 
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
+
 pub struct Prci {
     hfrosccfg: u32,
     hfxosccfg: u32,
@@ -47,3 +49,35 @@ impl Prci {
         }
     }
 }
+
+impl Snapshot for Prci {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("prci");
+        writer.write_u32s(&[
+            self.hfrosccfg,
+            self.hfxosccfg,
+            self.pllcfg,
+            self.plloutdiv,
+            self.procmoncfg,
+        ]);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("prci")?;
+        let mut values = [0; 5];
+        reader.read_u32_array(&mut values)?;
+        for (field, value) in [
+            &mut self.hfrosccfg,
+            &mut self.hfxosccfg,
+            &mut self.pllcfg,
+            &mut self.plloutdiv,
+            &mut self.procmoncfg,
+        ]
+        .iter_mut()
+        .zip(values.iter())
+        {
+            **field = *value;
+        }
+        Ok(())
+    }
+}
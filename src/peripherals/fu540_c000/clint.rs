@@ -2,6 +2,7 @@
 // https://static.dev.sifive.com/FU540-C000-v1.0.pdf
 
 use crate::peripherals::timer::Timer;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
 
 pub struct Clint {
     /// current clock cycle.
@@ -103,3 +104,22 @@ impl Timer for Clint {
         }
     }
 }
+
+impl Snapshot for Clint {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("clint");
+        writer.write_u64(self.cycle);
+        writer.write_u32s(&self.msip);
+        writer.write_u64s(&self.mtimecmp);
+        writer.write_u64(self.mtime);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("clint")?;
+        self.cycle = reader.read_u64()?;
+        reader.read_u32_array(&mut self.msip)?;
+        reader.read_u64_array(&mut self.mtimecmp)?;
+        self.mtime = reader.read_u64()?;
+        Ok(())
+    }
+}
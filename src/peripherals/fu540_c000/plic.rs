@@ -15,6 +15,7 @@
 */
 
 use crate::peripherals::intc::Intc;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
 
 const _PLIC_PRIORITY_BASE: u64 = 0;
 const PLIC_PENDING_BASE: u64 = 0x1000;
@@ -232,3 +233,30 @@ impl Intc for Plic {
         }
     }
 }
+
+impl Snapshot for Plic {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("plic");
+        writer.write_u32s(&self.priority);
+        writer.write_u32(self.pending);
+        writer.write_u32s(&self.menable);
+        writer.write_u32s(&self.senable);
+        writer.write_u32s(&self.mthreshold);
+        writer.write_u32s(&self.sthreshold);
+        writer.write_u32s(&self.mclaim);
+        writer.write_u32s(&self.sclaim);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("plic")?;
+        reader.read_u32_array(&mut self.priority)?;
+        self.pending = reader.read_u32()?;
+        reader.read_u32_array(&mut self.menable)?;
+        reader.read_u32_array(&mut self.senable)?;
+        reader.read_u32_array(&mut self.mthreshold)?;
+        reader.read_u32_array(&mut self.sthreshold)?;
+        reader.read_u32_array(&mut self.mclaim)?;
+        reader.read_u32_array(&mut self.sclaim)?;
+        Ok(())
+    }
+}
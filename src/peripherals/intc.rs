@@ -1,6 +1,8 @@
 // INTC (Interrupt Controller)
 
-pub trait Intc {
+use crate::snapshot::Snapshot;
+
+pub trait Intc: Snapshot {
     fn tick(&mut self, core: usize, interrupts: Vec<usize>) -> Vec<bool>;
     fn read(&mut self, addr: u64) -> u32;
     fn write(&mut self, addr: u64, data: u32);
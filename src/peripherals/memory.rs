@@ -1,24 +1,124 @@
+// Memory with dirty tracking per page.
+//
+// The bytes are kept in a flat buffer, and the pages written since the
+// checkpoint are tracked so that restoring the checkpoint copies back only
+// them. The contents of a page at the checkpoint are copied when it is first
+// written after the checkpoint, and kept while the checkpoint lasts, so
+// restoring it again and again copies no more than the pages written each time.
+
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
+
+pub const PAGE_SIZE: usize = 4096;
+const PAGE_SHIFT: usize = 12;
+
 pub struct Memory {
-    pub mem: Vec<u8>,
+    mem: Vec<u8>,
+    /// bitmap of the pages written since the checkpoint.
+    dirty: Vec<u64>,
+    /// pages written since the checkpoint.
+    dirty_pages: Vec<usize>,
+    /// contents of the pages at the checkpoint, if a checkpoint is taken.
+    /// A page is copied when it is first written after the checkpoint.
+    originals: Option<Vec<Option<Box<[u8]>>>>,
 }
 
 impl Memory {
     pub fn new(max_size: usize) -> Self {
+        let pages = max_size.div_ceil(PAGE_SIZE);
         Self {
             mem: vec![0; max_size],
+            dirty: vec![0; pages.div_ceil(64)],
+            dirty_pages: vec![],
+            originals: None,
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.mem.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mem.is_empty()
+    }
+
     pub fn initialize(&mut self, data: Vec<u8>) {
-        self.mem.splice(..data.len(), data.iter().cloned());
+        self.write_bytes(0, &data);
+    }
+
+    pub fn write_bytes(&mut self, addr: u64, data: &[u8]) {
+        let index = addr as usize;
+        self.touch(index, data.len());
+        self.mem[index..index + data.len()].copy_from_slice(data);
+    }
+
+    /// Marks the pages of `size` bytes from `index` written, and copies
+    /// their contents at the checkpoint before the first write.
+    #[inline]
+    fn touch(&mut self, index: usize, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let first = index >> PAGE_SHIFT;
+        let last = (index + size - 1) >> PAGE_SHIFT;
+        for page in first..=last {
+            if self.dirty[page / 64] & (1 << (page % 64)) == 0 {
+                self.mark_dirty(page);
+            }
+        }
+    }
+
+    #[cold]
+    fn mark_dirty(&mut self, page: usize) {
+        self.dirty[page / 64] |= 1 << (page % 64);
+        self.dirty_pages.push(page);
+        if let Some(originals) = self.originals.as_mut() {
+            if originals[page].is_none() {
+                let start = page << PAGE_SHIFT;
+                let end = (start + PAGE_SIZE).min(self.mem.len());
+                originals[page] = Some(self.mem[start..end].into());
+            }
+        }
+    }
+
+    /// Takes a checkpoint of the contents, which `restore_checkpoint` restores.
+    /// It replaces the previous checkpoint.
+    pub fn take_checkpoint(&mut self) {
+        for page in self.dirty_pages.drain(..) {
+            self.dirty[page / 64] &= !(1 << (page % 64));
+        }
+        let pages = self.mem.len().div_ceil(PAGE_SIZE);
+        self.originals = Some(vec![None; pages]);
+    }
+
+    /// Copies back the pages written since the checkpoint. The checkpoint is
+    /// kept, so it can be restored again. It does nothing without a checkpoint.
+    pub fn restore_checkpoint(&mut self) {
+        let originals = match self.originals.as_ref() {
+            Some(originals) => originals,
+            None => return,
+        };
+        for page in self.dirty_pages.drain(..) {
+            self.dirty[page / 64] &= !(1 << (page % 64));
+            if let Some(original) = originals[page].as_ref() {
+                let start = page << PAGE_SHIFT;
+                self.mem[start..start + original.len()].copy_from_slice(original);
+            }
+        }
+    }
+
+    /// Returns the number of the pages written since the checkpoint.
+    pub fn dirty_page_count(&self) -> usize {
+        self.dirty_pages.len()
     }
 
     pub fn write8(&mut self, addr: u64, data: u8) {
+        self.touch(addr as usize, 1);
         self.mem[addr as usize] = data;
     }
 
     pub fn write16(&mut self, addr: u64, data: u16) {
         let index = addr as usize;
+        self.touch(index, 2);
         for i in 0..2 {
             self.mem[index + i] = ((data >> (i * 8)) & 0xff) as u8;
         }
@@ -26,6 +126,7 @@ impl Memory {
 
     pub fn write32(&mut self, addr: u64, data: u32) {
         let index = addr as usize;
+        self.touch(index, 4);
         for i in 0..4 {
             self.mem[index + i] = ((data >> (i * 8)) & 0xff) as u8;
         }
@@ -33,6 +134,7 @@ impl Memory {
 
     pub fn write64(&mut self, addr: u64, data: u64) {
         let index = addr as usize;
+        self.touch(index, 8);
         for i in 0..8 {
             self.mem[index + i] = ((data >> (i * 8)) & 0xff) as u8;
         }
@@ -70,3 +172,30 @@ impl Memory {
         data
     }
 }
+
+/// The contents are not saved in the snapshots without the memories, whose
+/// memories are restored by their own checkpoints. A snapshot with them
+/// overwrites only the pages which differ, so that the checkpoint is kept.
+impl Snapshot for Memory {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        if writer.includes_memories() {
+            writer.write_sparse(&self.mem);
+        }
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        if !reader.includes_memories() {
+            return Ok(());
+        }
+        let mut mem = vec![0; self.mem.len()];
+        reader.read_sparse(&mut mem)?;
+        for (page, contents) in mem.chunks(PAGE_SIZE).enumerate() {
+            let start = page << PAGE_SHIFT;
+            if self.mem[start..start + contents.len()] != *contents {
+                self.touch(start, contents.len());
+                self.mem[start..start + contents.len()].copy_from_slice(contents);
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
-pub trait Timer {
+use crate::snapshot::Snapshot;
+
+pub trait Timer: Snapshot {
     fn tick(&mut self);
     fn is_pending_software_interrupt(&mut self, core: usize) -> bool;
     fn is_pending_timer_interrupt(&mut self, core: usize) -> bool;
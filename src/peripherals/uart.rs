@@ -2,6 +2,7 @@
 // http://byterunner.com/16550.html
 
 use crate::console::Console;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
 
 const IER_DATA_READY: u8 = 0x01;
 const IER_THR_EMPTY: u8 = 0x02;
@@ -150,3 +151,48 @@ impl Uart {
         return irq;
     }
 }
+
+/// The buffers of the console are saved with the registers.
+impl Snapshot for Uart {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("uart");
+        writer.write_bytes(&[
+            self.rhr, self.thr, self.ier, self.isr, self.fcr, self.lcr, self.mcr, self.lsr,
+            self.msr, self.spr,
+        ]);
+        writer.write_u64(self.cycle);
+        let (input, output) = self.console.get_buffers();
+        writer.write_bytes(&input);
+        writer.write_bytes(&output);
+    }
+
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("uart")?;
+        let values = reader.read_bytes()?;
+        if values.len() != 10 {
+            return Err(reader.corrupted());
+        }
+        for (register, value) in [
+            &mut self.rhr,
+            &mut self.thr,
+            &mut self.ier,
+            &mut self.isr,
+            &mut self.fcr,
+            &mut self.lcr,
+            &mut self.mcr,
+            &mut self.lsr,
+            &mut self.msr,
+            &mut self.spr,
+        ]
+        .iter_mut()
+        .zip(values.iter())
+        {
+            **register = *value;
+        }
+        self.cycle = reader.read_u64()?;
+        let input = reader.read_bytes()?;
+        let output = reader.read_bytes()?;
+        self.console.set_buffers(input, output);
+        Ok(())
+    }
+}
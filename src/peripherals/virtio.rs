@@ -5,6 +5,7 @@
 // https://syuu1228.github.io/howto_implement_hypervisor/part20.html
 
 use crate::peripherals::memory::Memory;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
 
 const CONFIG_QUEUE_NUM_MAX: u32 = 0x1000; // Linux boot fails if the value is too small.
 const CONFIG_DISK_SECTOR_SIZE: u64 = 512;
@@ -61,7 +62,7 @@ pub struct Virtio {
     /// current clock cycle.
     cycle: u64,
     /// real user disk data.
-    disk_image: Vec<u64>,
+    disk: Memory,
     /// last available ring index
     last_available_idx: u64,
     /// Main Memory Base Address
@@ -97,7 +98,7 @@ impl Virtio {
     pub fn new(dram_base_addr_: u64) -> Self {
         Virtio {
             cycle: 0,
-            disk_image: vec![],
+            disk: Memory::new(0),
             last_available_idx: 0,
             dram_base_addr: dram_base_addr_,
             device_features_sel: 0,
@@ -116,12 +117,23 @@ impl Virtio {
     }
 
     pub fn init(&mut self, data: Vec<u8>) {
-        self.disk_image.resize((data.len() + 7) / 8, 0);
-        for i in 0..data.len() {
-            let idx = (i >> 3) as usize;
-            let pos = (i % 8) * 8;
-            self.disk_image[idx] |= (data[i] as u64) << pos;
-        }
+        self.disk = Memory::new(data.len().div_ceil(8) * 8);
+        self.disk.initialize(data);
+    }
+
+    /// Overwrites a part of the disk image.
+    pub fn write_disk(&mut self, offset: u64, data: &[u8]) {
+        self.disk.write_bytes(offset, data);
+    }
+
+    /// Takes a checkpoint of the disk image, which `restore_checkpoint` restores.
+    pub fn take_checkpoint(&mut self) {
+        self.disk.take_checkpoint();
+    }
+
+    /// Copies back the sectors written since the checkpoint.
+    pub fn restore_checkpoint(&mut self) {
+        self.disk.restore_checkpoint();
     }
 
     pub fn tick(&mut self, dram: &mut Memory) {
@@ -217,7 +229,7 @@ impl Virtio {
             } else {
                 for i in 0..descriptor1.len as u64 {
                     let data = dram.read8(descriptor1.addr + i);
-                    self.write_disk8(disk_addr + i, data);
+                    self.disk.write8(disk_addr + i, data);
                 }
             }
         } else {
@@ -226,7 +238,7 @@ impl Virtio {
                 self.dma_disk_to_memory(dram, descriptor1.addr, disk_addr, descriptor1.len as u64);
             } else {
                 for i in 0..descriptor1.len as u64 {
-                    let data = self.read_disk8(disk_addr + i);
+                    let data = self.disk.read8(disk_addr + i);
                     dram.write8(descriptor1.addr + i, data);
                 }
             }
@@ -326,27 +338,74 @@ impl Virtio {
 
     fn dma_disk_to_memory(&mut self, dram: &mut Memory, mem_addr: u64, disk_addr: u64, len: u64) {
         for i in 0..(len / 8) {
-            let idx = ((disk_addr + i * 8) >> 3) as usize;
-            dram.write64(mem_addr + i * 8, self.disk_image[idx]);
+            dram.write64(mem_addr + i * 8, self.disk.read64(disk_addr + i * 8));
         }
     }
 
     fn dma_memory_to_disk(&mut self, dram: &mut Memory, mem_addr: u64, disk_addr: u64, len: u64) {
         for i in 0..(len / 8) {
-            let idx = ((disk_addr + i * 8) >> 3) as usize;
-            self.disk_image[idx] = dram.read64(mem_addr + i * 8);
+            self.disk
+                .write64(disk_addr + i * 8, dram.read64(mem_addr + i * 8));
         }
     }
+}
 
-    fn read_disk8(&mut self, addr: u64) -> u8 {
-        let idx = (addr >> 3) as usize;
-        let pos = (addr % 8) * 8;
-        (self.disk_image[idx] >> pos) as u8
+/// The disk image is saved with the registers and the queue notifications so
+/// that the writes of the guest to the disk are kept.
+impl Snapshot for Virtio {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_tag("virtio");
+        writer.write_u64(self.cycle);
+        self.disk.save(writer);
+        writer.write_u64(self.last_available_idx);
+        writer.write_u64(self.dram_base_addr);
+        writer.write_u32s(&[
+            self.device_features_sel,
+            self.driver_features,
+            self.driver_features_sel,
+            self.guest_page_size,
+            self.queue_sel,
+            self.queue_num,
+            self.queue_align,
+            self.queue_pfn,
+            self.interrupt_status,
+            self.device_status,
+        ]);
+        writer.write_u64s(&self.queue_notify);
+        writer.write_u32s(&self.config_space);
     }
 
-    fn write_disk8(&mut self, addr: u64, data: u8) {
-        let idx = (addr >> 3) as usize;
-        let pos = (addr % 8) * 8;
-        self.disk_image[idx] = (self.disk_image[idx] & !(0xff << pos)) | ((data as u64) << pos);
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError> {
+        reader.expect_tag("virtio")?;
+        self.cycle = reader.read_u64()?;
+        // the disk of the snapshot replaces the one given to the emulator.
+        if reader.includes_memories() && reader.read_sparse_length()? != self.disk.len() {
+            self.disk = Memory::new(reader.read_sparse_length()?);
+        }
+        self.disk.restore(reader)?;
+        self.last_available_idx = reader.read_u64()?;
+        self.dram_base_addr = reader.read_u64()?;
+        let mut values = [0; 10];
+        reader.read_u32_array(&mut values)?;
+        for (register, value) in [
+            &mut self.device_features_sel,
+            &mut self.driver_features,
+            &mut self.driver_features_sel,
+            &mut self.guest_page_size,
+            &mut self.queue_sel,
+            &mut self.queue_num,
+            &mut self.queue_align,
+            &mut self.queue_pfn,
+            &mut self.interrupt_status,
+            &mut self.device_status,
+        ]
+        .iter_mut()
+        .zip(values.iter())
+        {
+            **register = *value;
+        }
+        self.queue_notify = reader.read_u64s()?;
+        self.config_space = reader.read_u32s()?;
+        Ok(())
     }
 }
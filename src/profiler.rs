@@ -0,0 +1,317 @@
+// Function-level profiler of the guest programs.
+//
+// Every retired instruction is counted with its PC under the call stack at
+// the time. The call stack is reconstructed from the jumps by the hints of the
+// link registers (ra and t0) of the RISC-V psABI, which the return address
+// stack of a hart also follows: a jump linking a register is a call, and a
+// `jalr` through a link register without linking it is a return. A trap pushes
+// a frame of the handler, which the xRET instruction pops. The stacks are
+// kept as a call tree, so that counting an instruction needs no allocation.
+// The counts are written as folded stacks, which flamegraph.pl and inferno
+// read, and summarized per function.
+// https://github.com/brendangregg/FlameGraph
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use crate::cpu::cpu::{ExecutedInstruction, StepResult};
+use crate::symbols::SymbolTable;
+
+/// Maximum depth of the call stack. The deeper calls are not pushed, e.g. of
+/// a deep recursion, but their returns are still matched.
+const MAX_DEPTH: usize = 256;
+
+/// Name of the code which no symbol contains.
+const UNKNOWN: &str = "[unknown]";
+
+/// Node of the call tree. The root is the index 0, which has no function.
+struct Node {
+    parent: usize,
+    /// address of the function, which is the target of the call or the trap handler.
+    function: u64,
+    children: HashMap<u64, usize>,
+}
+
+struct Frame {
+    node: usize,
+    /// address which the call returns to, or `None` for a trap.
+    return_address: Option<u64>,
+}
+
+/// Control transfer of an instruction which changes the call stack.
+enum Transfer {
+    Call,
+    Return,
+    /// `jalr` popping a link register and pushing the other, i.e. a coroutine swap.
+    ReturnAndCall,
+    TrapReturn,
+    None,
+}
+
+fn classify(instruction: &ExecutedInstruction) -> Transfer {
+    let word = instruction.expanded_word;
+    let rd = (word >> 7) & 0x1f;
+    let rs1 = (word >> 15) & 0x1f;
+    let is_link = |register: u32| register == 1 || register == 5;
+    match instruction.mnemonic {
+        "jal" if is_link(rd) => Transfer::Call,
+        "jalr" => match (is_link(rd), is_link(rs1)) {
+            (true, false) => Transfer::Call,
+            (false, true) => Transfer::Return,
+            (true, true) if rd == rs1 => Transfer::Call,
+            (true, true) => Transfer::ReturnAndCall,
+            (false, false) => Transfer::None,
+        },
+        "mret" | "sret" | "uret" => Transfer::TrapReturn,
+        _ => Transfer::None,
+    }
+}
+
+/// Retired instructions of a function.
+#[derive(Clone, Debug)]
+pub struct FunctionProfile {
+    pub name: String,
+    /// instructions in the function itself.
+    pub self_count: u64,
+    /// instructions in the function and the functions it called.
+    pub total_count: u64,
+}
+
+pub struct Profiler {
+    enabled: bool,
+    nodes: Vec<Node>,
+    stack: Vec<Frame>,
+    /// calls which are not pushed because the stack is full.
+    overflow: usize,
+    /// retired instructions by the node of the call tree and the PC.
+    samples: HashMap<(usize, u64), u64>,
+    instructions: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            enabled: false,
+            nodes: vec![Node {
+                parent: 0,
+                function: 0,
+                children: HashMap::new(),
+            }],
+            stack: vec![],
+            overflow: 0,
+            samples: HashMap::new(),
+            instructions: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Starts or stops counting the instructions. The counts are kept, and
+    /// the call stack starts empty again.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.stack.clear();
+        self.overflow = 0;
+    }
+
+    /// Forgets the counts.
+    pub fn clear(&mut self) {
+        *self = Profiler {
+            enabled: self.enabled,
+            ..Profiler::new()
+        };
+    }
+
+    /// Returns the number of the counted instructions.
+    pub fn get_instruction_count(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Counts the instruction of the step, and follows the calls, the returns
+    /// and the traps. `pc` is the PC after the step.
+    pub fn record(&mut self, result: &StepResult, pc: u64) {
+        if result.interrupt.is_some() {
+            let handler = match result.instruction.as_ref() {
+                Some(instruction) => instruction.address,
+                None => pc,
+            };
+            self.push(handler, None);
+        }
+        let instruction = match result.instruction.as_ref() {
+            Some(instruction) => instruction,
+            None => {
+                if result.exception.is_some() {
+                    self.push(pc, None);
+                }
+                return;
+            }
+        };
+        if result.exception.is_some() {
+            self.push(pc, None);
+            return;
+        }
+
+        let node = self.stack.last().map_or(0, |frame| frame.node);
+        *self.samples.entry((node, instruction.address)).or_insert(0) += 1;
+        self.instructions += 1;
+
+        let return_address = instruction.address + instruction.length as u64;
+        match classify(instruction) {
+            Transfer::Call => self.push(pc, Some(return_address)),
+            Transfer::Return => self.pop(pc),
+            Transfer::ReturnAndCall => {
+                self.pop(pc);
+                self.push(pc, Some(return_address));
+            }
+            Transfer::TrapReturn => {
+                if let Some(index) = self
+                    .stack
+                    .iter()
+                    .rposition(|frame| frame.return_address.is_none())
+                {
+                    self.stack.truncate(index);
+                }
+            }
+            Transfer::None => {}
+        }
+    }
+
+    fn push(&mut self, function: u64, return_address: Option<u64>) {
+        if self.stack.len() >= MAX_DEPTH {
+            if return_address.is_some() {
+                self.overflow += 1;
+            }
+            return;
+        }
+        let parent = self.stack.last().map_or(0, |frame| frame.node);
+        let node = match self.nodes[parent].children.get(&function) {
+            Some(node) => *node,
+            None => {
+                let node = self.nodes.len();
+                self.nodes.push(Node {
+                    parent,
+                    function,
+                    children: HashMap::new(),
+                });
+                self.nodes[parent].children.insert(function, node);
+                node
+            }
+        };
+        self.stack.push(Frame {
+            node,
+            return_address,
+        });
+    }
+
+    /// Pops the calls up to the one returning to `target`. A return which no
+    /// call since the last trap matches, e.g. of a context switch, pops all of
+    /// them, since the stack it returns on is unknown.
+    fn pop(&mut self, target: u64) {
+        if self.overflow > 0 {
+            self.overflow -= 1;
+            return;
+        }
+        let base = self
+            .stack
+            .iter()
+            .rposition(|frame| frame.return_address.is_none())
+            .map_or(0, |index| index + 1);
+        match self.stack[base..]
+            .iter()
+            .rposition(|frame| frame.return_address == Some(target))
+        {
+            Some(index) => self.stack.truncate(base + index),
+            None => self.stack.truncate(base),
+        }
+    }
+
+    /// Returns the names of the functions of each node from the outermost.
+    fn node_frames(&self, symbols: &SymbolTable) -> Vec<Vec<String>> {
+        let mut frames: Vec<Vec<String>> = Vec::with_capacity(self.nodes.len());
+        frames.push(vec![]);
+        // a node is always created after its parent.
+        for node in self.nodes.iter().skip(1) {
+            let mut names = frames[node.parent].clone();
+            names.push(function_name(symbols, node.function));
+            frames.push(names);
+        }
+        frames
+    }
+
+    /// Returns the functions of each counted stack from the outermost with
+    /// the count. The function of the PC is added unless it is the innermost
+    /// frame, e.g. after a tail call.
+    fn stacks(&self, symbols: &SymbolTable) -> Vec<(Vec<String>, u64)> {
+        let frames = self.node_frames(symbols);
+        self.samples
+            .iter()
+            .map(|(&(node, pc), &count)| {
+                let mut names = frames[node].clone();
+                let leaf = function_name(symbols, pc);
+                if names.last() != Some(&leaf) {
+                    names.push(leaf);
+                }
+                (names, count)
+            })
+            .collect()
+    }
+
+    /// Writes the counts as folded stacks, i.e. the functions from the
+    /// outermost separated by semicolons and the count on each line.
+    pub fn write_folded(&self, symbols: &SymbolTable, writer: &mut dyn Write) -> io::Result<()> {
+        let mut folded: BTreeMap<String, u64> = BTreeMap::new();
+        for (names, count) in self.stacks(symbols) {
+            *folded.entry(names.join(";")).or_insert(0) += count;
+        }
+        for (stack, count) in folded {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the functions sorted by the instructions in themselves.
+    pub fn get_hot_functions(&self, symbols: &SymbolTable) -> Vec<FunctionProfile> {
+        let mut functions: HashMap<String, FunctionProfile> = HashMap::new();
+        for (mut names, count) in self.stacks(symbols) {
+            let leaf = names.last().cloned().unwrap_or_default();
+            // a recursive function is counted once in the total.
+            names.sort();
+            names.dedup();
+            for name in names {
+                let function = functions
+                    .entry(name.clone())
+                    .or_insert_with(|| FunctionProfile {
+                        name,
+                        self_count: 0,
+                        total_count: 0,
+                    });
+                function.total_count += count;
+                if function.name == leaf {
+                    function.self_count += count;
+                }
+            }
+        }
+        let mut functions: Vec<FunctionProfile> = functions.into_values().collect();
+        functions.sort_by(|a, b| {
+            b.self_count
+                .cmp(&a.self_count)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        functions
+    }
+}
+
+fn function_name(symbols: &SymbolTable, address: u64) -> String {
+    match symbols.lookup_function(address) {
+        Some((name, _)) => name.to_string(),
+        None => UNKNOWN.to_string(),
+    }
+}
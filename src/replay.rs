@@ -0,0 +1,308 @@
+// Record and replay of the inputs from outside of the machine.
+//
+// The machine is deterministic except for the bytes typed on the console and
+// the seed of the entropy source. The time CSR and the timers count the
+// cycles rather than the host clock, so they need not be recorded.
+//
+// An input log is a text file. The first line is the header with the version,
+// and each of the other lines is an input with the cycle at which the machine
+// consumed it:
+//
+//     riscv_emu input log 1
+//     0 entropy 9e3779b97f4a7c15
+//     52428800 console 6c
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::console::Console;
+
+const INPUT_LOG_HEADER: &str = "riscv_emu input log";
+
+/// Version of the input log format.
+pub const INPUT_LOG_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    /// byte read from the console.
+    Console(u8),
+    /// seed which continues the sequence of the entropy source from the cycle.
+    EntropySeed(u64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputRecord {
+    /// cycle of the hart at which the input is consumed.
+    pub cycle: u64,
+    pub event: InputEvent,
+}
+
+impl InputRecord {
+    fn to_line(&self) -> String {
+        match self.event {
+            InputEvent::Console(c) => format!("{} console {:02x}", self.cycle, c),
+            InputEvent::EntropySeed(seed) => format!("{} entropy {:016x}", self.cycle, seed),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let cycle = fields.next()?.parse::<u64>().ok()?;
+        let event = match (fields.next()?, fields.next()?) {
+            ("console", c) => InputEvent::Console(u8::from_str_radix(c, 16).ok()?),
+            ("entropy", seed) => InputEvent::EntropySeed(u64::from_str_radix(seed, 16).ok()?),
+            _ => return None,
+        };
+        match fields.next() {
+            Some(_) => None,
+            None => Some(InputRecord { cycle, event }),
+        }
+    }
+}
+
+/// Parses an input log written by the recorder.
+pub fn parse_input_log(data: &[u8]) -> io::Result<Vec<InputRecord>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let text = std::str::from_utf8(data).map_err(|_| invalid(String::from("Not an input log")))?;
+    let mut lines = text.lines();
+    match lines.next() {
+        Some(header) if header == format!("{} {}", INPUT_LOG_HEADER, INPUT_LOG_VERSION) => {}
+        Some(header) if header.starts_with(INPUT_LOG_HEADER) => {
+            return Err(invalid(format!("Unsupported input log: {}", header)))
+        }
+        _ => return Err(invalid(String::from("Not an input log"))),
+    }
+    lines
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            InputRecord::parse(line)
+                .ok_or_else(|| invalid(format!("Invalid input at line {}: {}", i + 2, line)))
+        })
+        .collect()
+}
+
+enum Mode {
+    /// the inputs are passed through.
+    Off,
+    /// the inputs are passed through and written to the log.
+    Recording(Box<dyn Write>),
+    /// the inputs are taken from the log instead of the console.
+    Replaying(VecDeque<InputRecord>),
+}
+
+/// Shared between the emulator, which tells the current cycle, and the
+/// console, which consumes the inputs.
+pub struct InputRecorder {
+    mode: Mode,
+    cycle: u64,
+    /// console inputs kept for reverse execution, which are consumed again
+    /// when the cycles before `live_cycle` are executed again.
+    history: Option<Vec<InputRecord>>,
+    /// index of the next input of `history` which is executed again.
+    history_index: usize,
+    /// first cycle which has never been executed.
+    live_cycle: u64,
+    /// the current cycle is executed again, so the inputs come from `history`.
+    in_history: bool,
+}
+
+impl InputRecorder {
+    pub fn new() -> Self {
+        InputRecorder {
+            mode: Mode::Off,
+            cycle: 0,
+            history: None,
+            history_index: 0,
+            live_cycle: 0,
+            in_history: false,
+        }
+    }
+
+    /// Tells the cycle of the next step.
+    pub fn set_cycle(&mut self, cycle: u64) {
+        self.cycle = cycle;
+        self.in_history = self.history.is_some() && cycle < self.live_cycle;
+        if self.history.is_some() && !self.in_history {
+            self.live_cycle = cycle + 1;
+        }
+    }
+
+    /// Returns whether the current cycle has been executed before.
+    pub fn is_in_history(&self) -> bool {
+        self.in_history
+    }
+
+    /// Starts writing the inputs to `writer`. The log is flushed at every
+    /// input so that it is complete even if the emulator is killed.
+    pub fn start_recording(&mut self, mut writer: Box<dyn Write>, cycle: u64, entropy_seed: u64) {
+        let _ = writeln!(writer, "{} {}", INPUT_LOG_HEADER, INPUT_LOG_VERSION);
+        self.mode = Mode::Recording(writer);
+        self.cycle = cycle;
+        self.record(InputEvent::EntropySeed(entropy_seed));
+    }
+
+    /// Starts taking the inputs from the records.
+    pub fn start_replaying(&mut self, records: Vec<InputRecord>) {
+        self.mode = Mode::Replaying(records.into_iter().collect());
+    }
+
+    pub fn stop(&mut self) {
+        if let Mode::Recording(writer) = &mut self.mode {
+            let _ = writer.flush();
+        }
+        self.mode = Mode::Off;
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.mode, Mode::Replaying(_))
+    }
+
+    /// Starts or stops keeping the console inputs from the cycle for reverse
+    /// execution. The inputs kept so far are dropped.
+    pub fn keep_history(&mut self, keep: bool, cycle: u64) {
+        self.history = match keep {
+            true => Some(vec![]),
+            false => None,
+        };
+        self.history_index = 0;
+        self.live_cycle = cycle;
+        self.in_history = false;
+    }
+
+    /// Goes back to the cycle restored from a checkpoint, so that the inputs
+    /// from it are consumed again.
+    pub fn rewind(&mut self, cycle: u64) {
+        if let Some(history) = &self.history {
+            self.history_index = history.partition_point(|record| record.cycle < cycle);
+        }
+    }
+
+    /// Drops the inputs from the cycle, which are no longer consumed because
+    /// the machine has been changed.
+    pub fn truncate_history(&mut self, cycle: u64) {
+        if let Some(history) = &mut self.history {
+            history.retain(|record| record.cycle < cycle);
+            self.history_index = history.len();
+            self.live_cycle = cycle;
+        }
+    }
+
+    fn record(&mut self, event: InputEvent) {
+        if let Mode::Recording(writer) = &mut self.mode {
+            let record = InputRecord {
+                cycle: self.cycle,
+                event: event.clone(),
+            };
+            let _ = writeln!(writer, "{}", record.to_line());
+            let _ = writer.flush();
+        }
+        if let (Some(history), InputEvent::Console(_)) = (&mut self.history, &event) {
+            history.push(InputRecord {
+                cycle: self.cycle,
+                event,
+            });
+            self.history_index = history.len();
+        }
+    }
+
+    /// Returns the console byte which is consumed at the cycle again, or 0.
+    fn replay_history(&mut self) -> u8 {
+        let cycle = self.cycle;
+        if let Some(history) = &self.history {
+            if let Some(InputRecord {
+                cycle: due,
+                event: InputEvent::Console(c),
+            }) = history.get(self.history_index)
+            {
+                if *due <= cycle {
+                    self.history_index += 1;
+                    return *c;
+                }
+            }
+        }
+        0
+    }
+
+    /// Returns the console byte of the log which is due, or 0. A byte is
+    /// returned late rather than dropped if the replay has diverged.
+    fn replay_console(&mut self) -> u8 {
+        let cycle = self.cycle;
+        if let Mode::Replaying(records) = &mut self.mode {
+            if let Some(InputRecord {
+                cycle: due,
+                event: InputEvent::Console(c),
+            }) = records.front()
+            {
+                if *due <= cycle {
+                    let c = *c;
+                    records.pop_front();
+                    return c;
+                }
+            }
+        }
+        0
+    }
+}
+
+impl Default for InputRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Console which records the input of another console, or replays the input
+/// of the log instead of it. The cycles executed again for reverse execution
+/// consume the input of the history, and their output is dropped since it was
+/// passed through when they were executed first.
+pub struct ReplayConsole {
+    console: Box<dyn Console>,
+    recorder: Rc<RefCell<InputRecorder>>,
+}
+
+impl ReplayConsole {
+    pub fn new(console: Box<dyn Console>, recorder: Rc<RefCell<InputRecorder>>) -> Self {
+        ReplayConsole { console, recorder }
+    }
+}
+
+impl Console for ReplayConsole {
+    fn putchar(&mut self, c: u8) {
+        if !self.recorder.borrow().in_history {
+            self.console.putchar(c)
+        }
+    }
+
+    fn getchar(&mut self) -> u8 {
+        let mut recorder = self.recorder.borrow_mut();
+        if recorder.in_history {
+            return recorder.replay_history();
+        }
+        let c = match recorder.is_replaying() {
+            true => recorder.replay_console(),
+            false => self.console.getchar(),
+        };
+        if c != 0 {
+            recorder.record(InputEvent::Console(c));
+        }
+        c
+    }
+
+    fn set_input(&mut self, c: u8) {
+        self.console.set_input(c)
+    }
+
+    fn get_output(&mut self) -> u8 {
+        self.console.get_output()
+    }
+
+    fn get_buffers(&self) -> (Vec<u8>, Vec<u8>) {
+        self.console.get_buffers()
+    }
+
+    fn set_buffers(&mut self, input: Vec<u8>, output: Vec<u8>) {
+        self.console.set_buffers(input, output)
+    }
+}
@@ -0,0 +1,95 @@
+// Checkpoints of reverse execution.
+//
+// The machine is deterministic once the inputs are replayed, so an earlier
+// state is reached by restoring the last checkpoint before it and executing
+// again from there.
+
+/// Snapshot of the machine before the step of the cycle.
+pub struct Checkpoint {
+    pub cycle: u64,
+    pub data: Vec<u8>,
+    /// the debugger changed the machine at the cycle, so executing up to the
+    /// cycle does not reach the state of the checkpoint.
+    pub discontinuity: bool,
+    /// the step before the cycle halted the hart.
+    pub halted: bool,
+}
+
+pub struct Checkpoints {
+    /// cycles between the periodic checkpoints.
+    interval: u64,
+    /// maximum number of checkpoints. The oldest one is dropped beyond it.
+    capacity: usize,
+    /// checkpoints sorted by the cycle.
+    checkpoints: Vec<Checkpoint>,
+    /// first cycle which has never been executed. The cycles before it are
+    /// the history, which is executed again rather than for the first time.
+    present: u64,
+}
+
+impl Checkpoints {
+    pub fn new(interval: u64, capacity: usize, cycle: u64) -> Self {
+        Checkpoints {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            checkpoints: vec![],
+            present: cycle,
+        }
+    }
+
+    /// A periodic checkpoint is due at the cycle and has not been taken yet.
+    pub fn is_due(&self, cycle: u64) -> bool {
+        cycle.is_multiple_of(self.interval) && self.get(cycle).is_none()
+    }
+
+    pub fn insert(&mut self, checkpoint: Checkpoint) {
+        let index = self
+            .checkpoints
+            .partition_point(|c| c.cycle < checkpoint.cycle);
+        match self.checkpoints.get(index) {
+            Some(c) if c.cycle == checkpoint.cycle => self.checkpoints[index] = checkpoint,
+            _ => self.checkpoints.insert(index, checkpoint),
+        }
+        if self.checkpoints.len() > self.capacity {
+            self.checkpoints.remove(0);
+        }
+    }
+
+    pub fn get(&self, cycle: u64) -> Option<&Checkpoint> {
+        self.checkpoints
+            .binary_search_by_key(&cycle, |c| c.cycle)
+            .ok()
+            .map(|index| &self.checkpoints[index])
+    }
+
+    /// Returns the cycle of the last checkpoint before the cycle.
+    pub fn latest_before(&self, cycle: u64) -> Option<u64> {
+        match self.checkpoints.partition_point(|c| c.cycle < cycle) {
+            0 => None,
+            index => Some(self.checkpoints[index - 1].cycle),
+        }
+    }
+
+    /// Forgets all the checkpoints, and starts the history from the cycle.
+    pub fn clear(&mut self, cycle: u64) {
+        self.checkpoints.clear();
+        self.present = cycle;
+    }
+
+    /// Marks the cycle executed.
+    pub fn advance(&mut self, cycle: u64) {
+        self.present = self.present.max(cycle.wrapping_add(1));
+    }
+
+    /// The cycle has been executed before.
+    pub fn is_history(&self, cycle: u64) -> bool {
+        cycle < self.present
+    }
+
+    /// Forgets the checkpoints from the cycle, whose states are no longer
+    /// reached because the machine has been changed.
+    pub fn truncate(&mut self, cycle: u64) {
+        self.checkpoints.retain(|c| c.cycle < cycle);
+        self.present = cycle;
+    }
+}
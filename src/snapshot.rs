@@ -0,0 +1,379 @@
+// Snapshot of the whole machine.
+//
+// A snapshot file is the magic "RVEMUSNP", the format version (u32) and the
+// machine (u8), followed by the state of the hart, the bus and the console.
+// All the values are little endian, and the state of each component starts
+// with a tag of its name so that a broken file is detected where it breaks.
+
+use std::fmt;
+use std::io;
+
+use crate::cpu::cpu::{Privilege, Xlen};
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"RVEMUSNP";
+
+/// Version of the snapshot format. It must be incremented when the state of
+/// any component is changed.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Size of the blocks in which memories are saved. Blocks of zeros are skipped.
+const BLOCK_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// the data is not a snapshot.
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    /// the snapshot was taken on another machine.
+    MachineMismatch,
+    /// the data ended or a value is out of range while reading the state of the component.
+    Corrupted(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::InvalidMagic => write!(f, "Not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported snapshot version: {}", version)
+            }
+            SnapshotError::MachineMismatch => write!(f, "Snapshot of another machine"),
+            SnapshotError::Corrupted(component) => write!(f, "Corrupted snapshot: {}", component),
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+/// State of a component which is saved in snapshots.
+pub trait Snapshot {
+    fn save(&self, writer: &mut SnapshotWriter);
+    fn restore(&mut self, reader: &mut SnapshotReader) -> Result<(), SnapshotError>;
+}
+
+pub struct SnapshotWriter {
+    data: Vec<u8>,
+    /// the contents of the memories are saved.
+    memories: bool,
+}
+
+impl SnapshotWriter {
+    /// Starts a snapshot with the header.
+    pub fn new(machine: u8) -> Self {
+        let mut writer = SnapshotWriter {
+            data: vec![],
+            memories: true,
+        };
+        writer.data.extend_from_slice(SNAPSHOT_MAGIC);
+        writer.write_u32(SNAPSHOT_VERSION);
+        writer.write_u8(machine);
+        writer
+    }
+
+    /// Starts a snapshot which leaves out the contents of the memories, which
+    /// are kept by the checkpoints of the memories instead.
+    pub fn without_memories(machine: u8) -> Self {
+        let mut writer = Self::new(machine);
+        writer.memories = false;
+        writer
+    }
+
+    pub fn includes_memories(&self) -> bool {
+        self.memories
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Starts the state of a component.
+    pub fn write_tag(&mut self, tag: &str) {
+        self.write_bytes(tag.as_bytes());
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_option_u64(&mut self, value: Option<u64>) {
+        self.write_bool(value.is_some());
+        self.write_u64(value.unwrap_or(0));
+    }
+
+    pub fn write_xlen(&mut self, xlen: &Xlen) {
+        self.write_u8(xlen.clone() as u8);
+    }
+
+    pub fn write_privilege(&mut self, privilege: &Privilege) {
+        self.write_u8(privilege.clone() as u8);
+    }
+
+    /// Writes the length and the bytes.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn write_u32s(&mut self, values: &[u32]) {
+        self.write_u64(values.len() as u64);
+        for value in values {
+            self.write_u32(*value);
+        }
+    }
+
+    pub fn write_u64s(&mut self, values: &[u64]) {
+        self.write_u64(values.len() as u64);
+        for value in values {
+            self.write_u64(*value);
+        }
+    }
+
+    /// Writes the length and the blocks which are not all zeros.
+    pub fn write_sparse(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        let zeros = [0; BLOCK_SIZE];
+        for (i, block) in bytes.chunks(BLOCK_SIZE).enumerate() {
+            if *block != zeros[..block.len()] {
+                self.write_u64(i as u64);
+                self.data.extend_from_slice(block);
+            }
+        }
+        self.write_u64(u64::MAX);
+    }
+}
+
+pub struct SnapshotReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    /// component being read, which is reported when the data is broken.
+    component: String,
+    /// the contents of the memories are in the data.
+    memories: bool,
+}
+
+impl<'a> SnapshotReader<'a> {
+    /// Checks the header, and returns the reader of the state after it.
+    pub fn new(data: &'a [u8], machine: u8) -> Result<Self, SnapshotError> {
+        if !data.starts_with(SNAPSHOT_MAGIC) {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        let mut reader = SnapshotReader {
+            data,
+            offset: SNAPSHOT_MAGIC.len(),
+            component: String::from("header"),
+            memories: true,
+        };
+        match reader.read_u32()? {
+            SNAPSHOT_VERSION => {}
+            version => return Err(SnapshotError::UnsupportedVersion(version)),
+        }
+        if reader.read_u8()? != machine {
+            return Err(SnapshotError::MachineMismatch);
+        }
+        Ok(reader)
+    }
+
+    /// Reads a snapshot saved by `SnapshotWriter::without_memories`.
+    pub fn without_memories(data: &'a [u8], machine: u8) -> Result<Self, SnapshotError> {
+        let mut reader = Self::new(data, machine)?;
+        reader.memories = false;
+        Ok(reader)
+    }
+
+    pub fn includes_memories(&self) -> bool {
+        self.memories
+    }
+
+    /// Returns the error of broken data in the current component.
+    pub fn corrupted(&self) -> SnapshotError {
+        SnapshotError::Corrupted(self.component.clone())
+    }
+
+    /// Checks the start of the state of a component.
+    pub fn expect_tag(&mut self, tag: &str) -> Result<(), SnapshotError> {
+        self.component = String::from(tag);
+        match self.read_bytes()? == tag.as_bytes() {
+            true => Ok(()),
+            false => Err(self.corrupted()),
+        }
+    }
+
+    fn read(&mut self, size: usize) -> Result<&'a [u8], SnapshotError> {
+        let data: &'a [u8] = self.data;
+        match self
+            .offset
+            .checked_add(size)
+            .and_then(|end| data.get(self.offset..end))
+        {
+            Some(bytes) => {
+                self.offset += size;
+                Ok(bytes)
+            }
+            None => Err(self.corrupted()),
+        }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.read(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(self.corrupted()),
+        }
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SnapshotError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_option_u64(&mut self) -> Result<Option<u64>, SnapshotError> {
+        let some = self.read_bool()?;
+        let value = self.read_u64()?;
+        Ok(match some {
+            true => Some(value),
+            false => None,
+        })
+    }
+
+    pub fn read_xlen(&mut self) -> Result<Xlen, SnapshotError> {
+        match self.read_u8()? {
+            0 => Ok(Xlen::X32),
+            1 => Ok(Xlen::X64),
+            _ => Err(self.corrupted()),
+        }
+    }
+
+    pub fn read_privilege(&mut self) -> Result<Privilege, SnapshotError> {
+        match self.read_u8()? {
+            0 => Ok(Privilege::User),
+            1 => Ok(Privilege::Supervisor),
+            2 => Ok(Privilege::Hypervisor),
+            3 => Ok(Privilege::Machine),
+            _ => Err(self.corrupted()),
+        }
+    }
+
+    /// Reads a length, which must fit in the rest of the data.
+    fn read_length(&mut self, element_size: usize) -> Result<usize, SnapshotError> {
+        let length = self.read_u64()? as usize;
+        match length.checked_mul(element_size) {
+            Some(size) if size <= self.data.len() - self.offset => Ok(length),
+            _ => Err(self.corrupted()),
+        }
+    }
+
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, SnapshotError> {
+        let length = self.read_length(1)?;
+        Ok(self.read(length)?.to_vec())
+    }
+
+    pub fn read_u32s(&mut self) -> Result<Vec<u32>, SnapshotError> {
+        let length = self.read_length(4)?;
+        let mut values = Vec::with_capacity(length);
+        for _ in 0..length {
+            values.push(self.read_u32()?);
+        }
+        Ok(values)
+    }
+
+    pub fn read_u64s(&mut self) -> Result<Vec<u64>, SnapshotError> {
+        let length = self.read_length(8)?;
+        let mut values = Vec::with_capacity(length);
+        for _ in 0..length {
+            values.push(self.read_u64()?);
+        }
+        Ok(values)
+    }
+
+    /// Reads an array of a fixed length.
+    pub fn read_u32_array(&mut self, array: &mut [u32]) -> Result<(), SnapshotError> {
+        let values = self.read_u32s()?;
+        match values.len() == array.len() {
+            true => {
+                array.copy_from_slice(&values);
+                Ok(())
+            }
+            false => Err(self.corrupted()),
+        }
+    }
+
+    pub fn read_u64_array(&mut self, array: &mut [u64]) -> Result<(), SnapshotError> {
+        let values = self.read_u64s()?;
+        match values.len() == array.len() {
+            true => {
+                array.copy_from_slice(&values);
+                Ok(())
+            }
+            false => Err(self.corrupted()),
+        }
+    }
+
+    /// Returns the length of the data written by `write_sparse`, which is next,
+    /// so that the buffer of `read_sparse` can be allocated.
+    pub fn read_sparse_length(&self) -> Result<usize, SnapshotError> {
+        let mut bytes = [0; 8];
+        match self.data.get(self.offset..self.offset + 8) {
+            Some(length) => bytes.copy_from_slice(length),
+            None => return Err(self.corrupted()),
+        }
+        Ok(u64::from_le_bytes(bytes) as usize)
+    }
+
+    /// Reads the data written by `write_sparse` into `bytes`, which must have
+    /// the same length. The skipped blocks are filled with zeros.
+    pub fn read_sparse(&mut self, bytes: &mut [u8]) -> Result<(), SnapshotError> {
+        if self.read_u64()? != bytes.len() as u64 {
+            return Err(self.corrupted());
+        }
+        bytes.fill(0);
+        loop {
+            let index = self.read_u64()?;
+            if index == u64::MAX {
+                return Ok(());
+            }
+            let start = match (index as usize).checked_mul(BLOCK_SIZE) {
+                Some(start) if start < bytes.len() => start,
+                _ => return Err(self.corrupted()),
+            };
+            let end = (start + BLOCK_SIZE).min(bytes.len());
+            bytes[start..end].copy_from_slice(self.read(end - start)?);
+        }
+    }
+
+    /// Checks that all the data has been read.
+    pub fn finish(&self) -> Result<(), SnapshotError> {
+        match self.offset == self.data.len() {
+            true => Ok(()),
+            false => Err(SnapshotError::Corrupted(String::from("trailing data"))),
+        }
+    }
+}
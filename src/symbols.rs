@@ -0,0 +1,129 @@
+use crate::dwarf::{parse_debug_line, LineRow};
+use crate::elf_loader::{EiClass, ElfHeader, ElfLoader, SectionHeader, Symbol};
+
+/// Symbols and line number information of the loaded program, which map
+/// addresses to `function+offset` and `file:line`.
+#[derive(Default)]
+pub struct SymbolTable {
+    /// code symbols sorted by the address. One symbol is kept for an address.
+    symbols: Vec<Symbol>,
+    files: Vec<String>,
+    /// rows sorted by the address.
+    lines: Vec<LineRow>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads .symtab and the DWARF line number information (.debug_line).
+    pub fn from_elf(
+        loader: &ElfLoader,
+        elf_header: &ElfHeader,
+        sec_headers: &[SectionHeader],
+    ) -> Self {
+        let mut symbols = loader.get_symbols(elf_header, sec_headers);
+        // a function is preferred to a label, and a global symbol to a local one.
+        symbols.sort_by_key(|symbol| (symbol.address, !symbol.is_function, !symbol.is_global));
+        symbols.dedup_by_key(|symbol| symbol.address);
+
+        let (files, mut lines) =
+            match loader.get_section_data(elf_header, sec_headers, ".debug_line") {
+                Some(debug_line) => {
+                    let address_size = match elf_header.e_indent.ei_classs {
+                        EiClass::Class32 => 4,
+                        _ => 8,
+                    };
+                    let table = parse_debug_line(
+                        debug_line,
+                        loader.get_section_data(elf_header, sec_headers, ".debug_line_str"),
+                        loader.get_section_data(elf_header, sec_headers, ".debug_str"),
+                        address_size,
+                    );
+                    (table.files, table.rows)
+                }
+                None => (vec![], vec![]),
+            };
+        // the end of a sequence comes before the start of the next one at the same address.
+        lines.sort_by_key(|row| (row.address, !row.end_sequence));
+
+        SymbolTable {
+            symbols,
+            files,
+            lines,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty() && self.lines.is_empty()
+    }
+
+    /// Returns the symbol containing `address` and the offset from it. A symbol
+    /// without a size extends to the next symbol.
+    pub fn lookup(&self, address: u64) -> Option<(&str, u64)> {
+        let index = self
+            .symbols
+            .partition_point(|symbol| symbol.address <= address);
+        let symbol = self.symbols.get(index.checked_sub(1)?)?;
+        let offset = address - symbol.address;
+        match symbol.size {
+            0 => Some((&symbol.name, offset)),
+            size if offset < size => Some((&symbol.name, offset)),
+            _ => None,
+        }
+    }
+
+    /// Returns the function containing `address` like `lookup`, skipping the
+    /// local labels, e.g. of loops. A global label is taken as a function,
+    /// since the functions written in assembly often have no type.
+    pub fn lookup_function(&self, address: u64) -> Option<(&str, u64)> {
+        let index = self
+            .symbols
+            .partition_point(|symbol| symbol.address <= address);
+        let symbol = self.symbols[..index]
+            .iter()
+            .rev()
+            .find(|symbol| symbol.is_function || symbol.is_global)?;
+        let offset = address - symbol.address;
+        match symbol.size {
+            0 => Some((&symbol.name, offset)),
+            size if offset < size => Some((&symbol.name, offset)),
+            _ => None,
+        }
+    }
+
+    /// Returns the address of the symbol named `name`.
+    pub fn find_symbol(&self, name: &str) -> Option<u64> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .map(|symbol| symbol.address)
+    }
+
+    /// Returns the source file and the line of `address`.
+    pub fn find_line(&self, address: u64) -> Option<(&str, u64)> {
+        let index = self.lines.partition_point(|row| row.address <= address);
+        let row = self.lines.get(index.checked_sub(1)?)?;
+        match row.end_sequence {
+            true => None,
+            false => Some((&self.files[row.file], row.line)),
+        }
+    }
+
+    /// Returns the source files and the rows of the line number table, which
+    /// are sorted by the address.
+    pub fn get_line_table(&self) -> (&[String], &[LineRow]) {
+        (&self.files, &self.lines)
+    }
+
+    /// Formats `address` as `function+0xoffset`, or as a hex number when no
+    /// symbol contains it.
+    pub fn symbolize(&self, address: u64) -> String {
+        match self.lookup(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+0x{:x}", name, offset),
+            None => format!("0x{:x}", address),
+        }
+    }
+}
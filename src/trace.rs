@@ -0,0 +1,882 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::cpu::cpu::{DebugCause, Privilege, Xlen};
+use crate::cpu::cpu_csr::csr_name;
+use crate::cpu::trap::{Exception, Interrupt};
+use crate::symbols::SymbolTable;
+
+/// Category of trace events, which are enabled one by one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceCategory {
+    Instruction = 0,
+    RegisterWrite = 1,
+    MemoryAccess = 2,
+    Trap = 3,
+    Interrupt = 4,
+    Mmio = 5,
+    PageWalk = 6,
+    CsrWrite = 7,
+    Branch = 8,
+    Call = 9,
+}
+
+impl TraceCategory {
+    pub const ALL: [TraceCategory; 10] = [
+        TraceCategory::Instruction,
+        TraceCategory::RegisterWrite,
+        TraceCategory::MemoryAccess,
+        TraceCategory::Trap,
+        TraceCategory::Interrupt,
+        TraceCategory::Mmio,
+        TraceCategory::PageWalk,
+        TraceCategory::CsrWrite,
+        TraceCategory::Branch,
+        TraceCategory::Call,
+    ];
+
+    fn mask(self) -> u32 {
+        1 << self as u32
+    }
+
+    /// Parses a category name used on the command line.
+    pub fn from_name(name: &str) -> Option<TraceCategory> {
+        match name {
+            "instruction" => Some(TraceCategory::Instruction),
+            "register" => Some(TraceCategory::RegisterWrite),
+            "memory" => Some(TraceCategory::MemoryAccess),
+            "trap" => Some(TraceCategory::Trap),
+            "interrupt" => Some(TraceCategory::Interrupt),
+            "mmio" => Some(TraceCategory::Mmio),
+            "pagewalk" => Some(TraceCategory::PageWalk),
+            "csr" => Some(TraceCategory::CsrWrite),
+            "branch" => Some(TraceCategory::Branch),
+            "call" => Some(TraceCategory::Call),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum TraceEvent {
+    /// instruction about to be executed. A compressed instruction has the
+    /// length 2, `raw_word` holds its 16 bits and `word` is the decompressed
+    /// one. `xlen` is the effective XLEN of the instruction.
+    Instruction {
+        word: u32,
+        raw_word: u32,
+        length: u8,
+        xlen: Xlen,
+        disassembly: String,
+    },
+    IntRegisterWrite {
+        register: u8,
+        value: u64,
+    },
+    /// bits of the floating-point register.
+    FpRegisterWrite {
+        register: u8,
+        value: u64,
+    },
+    /// load or store of an instruction at a virtual address.
+    MemoryAccess {
+        address: u64,
+        size: u8,
+        value: u64,
+        write: bool,
+    },
+    Trap {
+        exception: Exception,
+        value: u64,
+    },
+    Interrupt {
+        interrupt: Interrupt,
+    },
+    /// access to a device register at a physical address.
+    Mmio {
+        address: u64,
+        size: u8,
+        value: u64,
+        write: bool,
+    },
+    /// PTE read by the page walk translating `address`.
+    PageWalk {
+        address: u64,
+        level: u8,
+        pte_address: u64,
+        pte: u64,
+    },
+    CsrWrite {
+        csr: u16,
+        value: u64,
+    },
+    /// control transfer of a branch, a jump or a return from a trap, or a
+    /// branch not taken. `target` is the next PC.
+    Branch {
+        target: u64,
+        taken: bool,
+    },
+    /// the hart entered debug mode. `dpc` is the PC which it resumes at.
+    DebugEntry {
+        cause: DebugCause,
+        dpc: u64,
+    },
+    /// exception of an instruction in the program buffer, which aborts the
+    /// abstract command. It is not taken by the hart.
+    ProgramBufferException {
+        exception: Exception,
+        value: u64,
+    },
+    /// jump linking ra or t0. `target` is the address of the called function.
+    Call {
+        target: u64,
+    },
+    /// jump through ra or t0 which does not link.
+    Return {
+        target: u64,
+    },
+}
+
+impl TraceEvent {
+    pub fn category(&self) -> TraceCategory {
+        match self {
+            TraceEvent::Instruction { .. } => TraceCategory::Instruction,
+            TraceEvent::IntRegisterWrite { .. } | TraceEvent::FpRegisterWrite { .. } => {
+                TraceCategory::RegisterWrite
+            }
+            TraceEvent::MemoryAccess { .. } => TraceCategory::MemoryAccess,
+            TraceEvent::Trap { .. }
+            | TraceEvent::DebugEntry { .. }
+            | TraceEvent::ProgramBufferException { .. } => TraceCategory::Trap,
+            TraceEvent::Interrupt { .. } => TraceCategory::Interrupt,
+            TraceEvent::Mmio { .. } => TraceCategory::Mmio,
+            TraceEvent::PageWalk { .. } => TraceCategory::PageWalk,
+            TraceEvent::CsrWrite { .. } => TraceCategory::CsrWrite,
+            TraceEvent::Branch { .. } => TraceCategory::Branch,
+            TraceEvent::Call { .. } | TraceEvent::Return { .. } => TraceCategory::Call,
+        }
+    }
+}
+
+/// Event with the instruction which caused it.
+#[derive(Clone, Debug)]
+pub struct TraceRecord {
+    /// number of the instructions started since the tracing began, including this one.
+    pub count: u64,
+    pub pc: u64,
+    pub privilege: Privilege,
+    pub event: TraceEvent,
+}
+
+pub trait TraceSink {
+    fn record(&mut self, record: &TraceRecord);
+    fn flush(&mut self) {}
+    /// Takes the symbols of the loaded program, e.g. to print the functions.
+    fn set_symbols(&mut self, _symbols: Rc<SymbolTable>) {}
+}
+
+/// Sink writing a line of text per event.
+pub struct TextSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(writer: W) -> Self {
+        TextSink { writer }
+    }
+}
+
+impl TextSink<Printer> {
+    pub fn stdout() -> Self {
+        Self::new(Printer {})
+    }
+}
+
+/// Writer to stdout through `print!`, whose output is captured by the test harness.
+pub struct Printer {}
+
+impl Write for Printer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        print!("{}", String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stdout().flush()
+    }
+}
+
+impl<W: Write> TraceSink for TextSink<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        let access = |write: &bool| match write {
+            true => "W",
+            false => "R",
+        };
+        let _ = match &record.event {
+            TraceEvent::Instruction {
+                word,
+                length,
+                disassembly,
+                ..
+            } => writeln!(
+                self.writer,
+                "[PC]: {:016x} [P]: {:?} |    {:08x}    {}{}",
+                record.pc,
+                record.privilege,
+                word,
+                match length {
+                    2 => "(C)",
+                    _ => "   ",
+                },
+                disassembly
+            ),
+            TraceEvent::IntRegisterWrite { register, value } => {
+                writeln!(self.writer, "    x{:<2} <- {:016x}", register, value)
+            }
+            TraceEvent::FpRegisterWrite { register, value } => {
+                writeln!(self.writer, "    f{:<2} <- {:016x}", register, value)
+            }
+            TraceEvent::MemoryAccess {
+                address,
+                size,
+                value,
+                write,
+            } => writeln!(
+                self.writer,
+                "    mem {} {:016x} ({}) {:x}",
+                access(write),
+                address,
+                size,
+                value
+            ),
+            TraceEvent::Mmio {
+                address,
+                size,
+                value,
+                write,
+            } => writeln!(
+                self.writer,
+                "    mmio {} {:016x} ({}) {:x}",
+                access(write),
+                address,
+                size,
+                value
+            ),
+            TraceEvent::PageWalk {
+                address,
+                level,
+                pte_address,
+                pte,
+            } => writeln!(
+                self.writer,
+                "    page walk {:016x} level {} pte[{:016x}] = {:016x}",
+                address, level, pte_address, pte
+            ),
+            TraceEvent::CsrWrite { csr, value } => {
+                writeln!(self.writer, "    csr {:03x} <- {:016x}", csr, value)
+            }
+            TraceEvent::Branch { target, taken } => writeln!(
+                self.writer,
+                "    branch {} {:016x}",
+                match taken {
+                    true => "taken",
+                    false => "not taken",
+                },
+                target
+            ),
+            TraceEvent::Trap { exception, value } => writeln!(
+                self.writer,
+                "  >> Exception: {:?} ({:016x}) {:?}, {:x}",
+                exception, value, record.privilege, record.pc
+            ),
+            TraceEvent::Interrupt { interrupt } => writeln!(
+                self.writer,
+                "  >> Interrupt: {:?} ({:x}, {:?})",
+                interrupt, record.pc, record.privilege
+            ),
+            TraceEvent::DebugEntry { cause, dpc } => writeln!(
+                self.writer,
+                "  >> Enter debug mode: {:?} ({:016x})",
+                cause, dpc
+            ),
+            TraceEvent::ProgramBufferException { exception, value } => writeln!(
+                self.writer,
+                "  >> Exception in program buffer: {:?} ({:016x})",
+                exception, value
+            ),
+            TraceEvent::Call { target } => writeln!(
+                self.writer,
+                "  >> Call: {:016x} from {:016x}",
+                target, record.pc
+            ),
+            TraceEvent::Return { target } => writeln!(
+                self.writer,
+                "  >> Return: to {:016x} from {:016x}",
+                target, record.pc
+            ),
+        };
+    }
+
+    fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Sink writing the events in a compact binary form. All the values are
+/// little endian. A record is the event kind (u8), the count (u64), the PC
+/// (u64) and the privilege (u8), followed by the fields of the event:
+///
+/// | kind | event            | fields                                          |
+/// |------|------------------|-------------------------------------------------|
+/// | 0    | Instruction      | word (u32), length (u8), raw word (u32), XLEN (u8) |
+/// | 1    | IntRegisterWrite | register (u8), value (u64)                      |
+/// | 2    | FpRegisterWrite  | register (u8), value (u64)                      |
+/// | 3    | MemoryAccess     | address (u64), size (u8), write (u8), value (u64) |
+/// | 4    | Trap             | exception code (u8), value (u64)                |
+/// | 5    | Interrupt        | interrupt code (u8)                             |
+/// | 6    | Mmio             | address (u64), size (u8), write (u8), value (u64) |
+/// | 7    | PageWalk         | address (u64), level (u8), PTE address (u64), PTE (u64) |
+/// | 8    | CsrWrite         | csr (u16), value (u64)                          |
+/// | 9    | Branch           | target (u64), taken (u8)                        |
+/// | 10   | DebugEntry       | cause (u8), dpc (u64)                           |
+/// | 11   | ProgramBufferException | exception code (u8), value (u64)          |
+/// | 12   | Call             | target (u64)                                    |
+/// | 13   | Return           | target (u64)                                    |
+pub struct BinarySink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> BinarySink<W> {
+    pub fn new(writer: W) -> Self {
+        BinarySink { writer }
+    }
+}
+
+impl BinarySink<BufWriter<File>> {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> TraceSink for BinarySink<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        let mut data = vec![];
+        let kind = match record.event {
+            TraceEvent::Instruction { .. } => 0,
+            TraceEvent::IntRegisterWrite { .. } => 1,
+            TraceEvent::FpRegisterWrite { .. } => 2,
+            TraceEvent::MemoryAccess { .. } => 3,
+            TraceEvent::Trap { .. } => 4,
+            TraceEvent::Interrupt { .. } => 5,
+            TraceEvent::Mmio { .. } => 6,
+            TraceEvent::PageWalk { .. } => 7,
+            TraceEvent::CsrWrite { .. } => 8,
+            TraceEvent::Branch { .. } => 9,
+            TraceEvent::DebugEntry { .. } => 10,
+            TraceEvent::ProgramBufferException { .. } => 11,
+            TraceEvent::Call { .. } => 12,
+            TraceEvent::Return { .. } => 13,
+        };
+        data.push(kind);
+        data.extend_from_slice(&record.count.to_le_bytes());
+        data.extend_from_slice(&record.pc.to_le_bytes());
+        data.push(record.privilege.clone() as u8);
+        match &record.event {
+            TraceEvent::Instruction {
+                word,
+                raw_word,
+                length,
+                xlen,
+                ..
+            } => {
+                data.extend_from_slice(&word.to_le_bytes());
+                data.push(*length);
+                data.extend_from_slice(&raw_word.to_le_bytes());
+                data.push(xlen.clone() as u8);
+            }
+            TraceEvent::IntRegisterWrite { register, value }
+            | TraceEvent::FpRegisterWrite { register, value } => {
+                data.push(*register);
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            TraceEvent::MemoryAccess {
+                address,
+                size,
+                value,
+                write,
+            }
+            | TraceEvent::Mmio {
+                address,
+                size,
+                value,
+                write,
+            } => {
+                data.extend_from_slice(&address.to_le_bytes());
+                data.push(*size);
+                data.push(*write as u8);
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            TraceEvent::Trap { exception, value }
+            | TraceEvent::ProgramBufferException { exception, value } => {
+                data.push(*exception as u8);
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            TraceEvent::Interrupt { interrupt } => data.push(*interrupt as u8),
+            TraceEvent::PageWalk {
+                address,
+                level,
+                pte_address,
+                pte,
+            } => {
+                data.extend_from_slice(&address.to_le_bytes());
+                data.push(*level);
+                data.extend_from_slice(&pte_address.to_le_bytes());
+                data.extend_from_slice(&pte.to_le_bytes());
+            }
+            TraceEvent::CsrWrite { csr, value } => {
+                data.extend_from_slice(&csr.to_le_bytes());
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            TraceEvent::Branch { target, taken } => {
+                data.extend_from_slice(&target.to_le_bytes());
+                data.push(*taken as u8);
+            }
+            TraceEvent::DebugEntry { cause, dpc } => {
+                data.push(cause.clone() as u8);
+                data.extend_from_slice(&dpc.to_le_bytes());
+            }
+            TraceEvent::Call { target } | TraceEvent::Return { target } => {
+                data.extend_from_slice(&target.to_le_bytes());
+            }
+        }
+        let _ = self.writer.write_all(&data);
+    }
+
+    fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Categories which `SpikeCommitLogSink` needs.
+pub const COMMIT_LOG_CATEGORIES: [TraceCategory; 6] = [
+    TraceCategory::Instruction,
+    TraceCategory::RegisterWrite,
+    TraceCategory::MemoryAccess,
+    TraceCategory::Trap,
+    TraceCategory::Interrupt,
+    TraceCategory::CsrWrite,
+];
+
+/// Retired instruction whose line of the commit log is not written yet.
+struct Commit {
+    count: u64,
+    pc: u64,
+    privilege: Privilege,
+    raw_word: u32,
+    length: u8,
+    xlen: Xlen,
+    /// register and CSR writes keyed like Spike, so that they are sorted in its order.
+    writes: BTreeMap<u32, TraceEvent>,
+    loads: Vec<u64>,
+    stores: Vec<(u64, u8, u64)>,
+}
+
+/// Sink writing the retired instructions in the format of Spike's
+/// `--log-commits`, so that the logs of the same program can be diffed to
+/// find the first divergent instruction:
+///
+/// ```text
+/// core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000
+/// core   0: 3 0x0000000080000004 (0x0062b023) mem 0x0000000080001000 0x0000000080000000
+/// ```
+///
+/// An instruction is written when the next one starts, and the sink needs
+/// the categories of `COMMIT_LOG_CATEGORIES`. An instruction which traps is not written.
+/// Unlike Spike, the implicit fflags updates of the floating-point
+/// instructions are not logged, and single-precision values are not NaN-boxed.
+pub struct SpikeCommitLogSink<W: Write> {
+    writer: W,
+    commit: Option<Commit>,
+}
+
+impl<W: Write> SpikeCommitLogSink<W> {
+    pub fn new(writer: W) -> Self {
+        SpikeCommitLogSink {
+            writer,
+            commit: None,
+        }
+    }
+
+    fn write_commit(&mut self) {
+        let commit = match self.commit.take() {
+            Some(commit) => commit,
+            None => return,
+        };
+        let xlen = match commit.xlen {
+            Xlen::X32 => 32,
+            Xlen::X64 => 64,
+        };
+        let mut line = format!(
+            "core   0: {} {} ({})",
+            commit.privilege as u8,
+            hex(commit.pc, xlen),
+            hex(commit.raw_word as u64, commit.length as u32 * 8)
+        );
+        for event in commit.writes.values() {
+            line += &match event {
+                TraceEvent::IntRegisterWrite { register, value } => {
+                    format!(" x{:<2} {}", register, hex(*value, xlen))
+                }
+                TraceEvent::FpRegisterWrite { register, value } => {
+                    format!(" f{:<2} {}", register, hex(*value, 64))
+                }
+                TraceEvent::CsrWrite { csr, value } => {
+                    format!(" c{}_{} {}", csr, csr_name(*csr), hex(*value, xlen))
+                }
+                _ => String::new(),
+            };
+        }
+        for address in commit.loads.iter() {
+            line += &format!(" mem {}", hex(*address, xlen));
+        }
+        for (address, size, value) in commit.stores.iter() {
+            line += &format!(
+                " mem {} {}",
+                hex(*address, xlen),
+                hex(*value, *size as u32 * 8)
+            );
+        }
+        let _ = writeln!(self.writer, "{}", line);
+    }
+}
+
+/// Formats the lower `bits` bits of `value` like Spike, e.g. 0x0000abcd for 32 bits.
+fn hex(value: u64, bits: u32) -> String {
+    let value = match bits {
+        64 => value,
+        _ => value & ((1 << bits) - 1),
+    };
+    format!("0x{:0width$x}", value, width = bits as usize / 4)
+}
+
+impl<W: Write> TraceSink for SpikeCommitLogSink<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        // events of an instruction which has already been written are ignored.
+        let commit = match &mut self.commit {
+            Some(commit) if commit.count == record.count => Some(commit),
+            _ => None,
+        };
+        match (&record.event, commit) {
+            (
+                TraceEvent::Instruction {
+                    raw_word,
+                    length,
+                    xlen,
+                    ..
+                },
+                _,
+            ) => {
+                self.write_commit();
+                self.commit = Some(Commit {
+                    count: record.count,
+                    pc: record.pc,
+                    privilege: record.privilege.clone(),
+                    raw_word: *raw_word,
+                    length: *length,
+                    xlen: xlen.clone(),
+                    writes: BTreeMap::new(),
+                    loads: vec![],
+                    stores: vec![],
+                });
+            }
+            (TraceEvent::IntRegisterWrite { register, .. }, Some(commit)) => {
+                let key = (*register as u32) << 4;
+                commit.writes.insert(key, record.event.clone());
+            }
+            (TraceEvent::FpRegisterWrite { register, .. }, Some(commit)) => {
+                let key = (*register as u32) << 4 | 1;
+                commit.writes.insert(key, record.event.clone());
+            }
+            (TraceEvent::CsrWrite { csr, .. }, Some(commit)) => {
+                let key = (*csr as u32) << 4 | 4;
+                commit.writes.insert(key, record.event.clone());
+            }
+            (
+                TraceEvent::MemoryAccess {
+                    address,
+                    size,
+                    value,
+                    write,
+                },
+                Some(commit),
+            ) => match write {
+                true => commit.stores.push((*address, *size, *value)),
+                false => commit.loads.push(*address),
+            },
+            // the instruction has trapped and is not retired.
+            (TraceEvent::Trap { .. }, Some(_)) => self.commit = None,
+            (TraceEvent::Trap { .. }, None)
+            | (TraceEvent::Interrupt { .. }, _)
+            | (TraceEvent::DebugEntry { .. }, _) => self.write_commit(),
+            _ => {}
+        }
+    }
+
+    fn flush(&mut self) {
+        self.write_commit();
+        let _ = self.writer.flush();
+    }
+}
+
+/// Sink writing the instructions with the functions and the source lines of
+/// the loaded program, and the calls and returns between the functions:
+///
+/// ```text
+/// 0000000080000010 <main+0x10> main.c:12    00c000ef    jal ra, 12
+///   >> Call: f (000000008000001c) from main+0x10
+/// ```
+///
+/// The other events are ignored.
+pub struct SymbolizedSink<W: Write> {
+    writer: W,
+    symbols: Rc<SymbolTable>,
+}
+
+impl<W: Write> SymbolizedSink<W> {
+    pub fn new(writer: W) -> Self {
+        SymbolizedSink {
+            writer,
+            symbols: Rc::new(SymbolTable::new()),
+        }
+    }
+}
+
+impl SymbolizedSink<Printer> {
+    pub fn stdout() -> Self {
+        Self::new(Printer {})
+    }
+}
+
+impl<W: Write> TraceSink for SymbolizedSink<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        let symbols = &self.symbols;
+        let _ = match &record.event {
+            TraceEvent::Instruction {
+                word, disassembly, ..
+            } => {
+                let mut location = format!("{:016x} <{}>", record.pc, symbols.symbolize(record.pc));
+                if let Some((file, line)) = symbols.find_line(record.pc) {
+                    location += &format!(" {}:{}", file, line);
+                }
+                writeln!(
+                    self.writer,
+                    "{}    {:08x}    {}",
+                    location, word, disassembly
+                )
+            }
+            TraceEvent::Call { target } => writeln!(
+                self.writer,
+                "  >> Call: {} ({:016x}) from {}",
+                symbols.symbolize(*target),
+                target,
+                symbols.symbolize(record.pc)
+            ),
+            TraceEvent::Return { target } => writeln!(
+                self.writer,
+                "  >> Return: to {} ({:016x}) from {}",
+                symbols.symbolize(*target),
+                target,
+                symbols.symbolize(record.pc)
+            ),
+            _ => Ok(()),
+        };
+    }
+
+    fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+
+    fn set_symbols(&mut self, symbols: Rc<SymbolTable>) {
+        self.symbols = symbols;
+    }
+}
+
+/// Sink keeping the latest `capacity` records in memory, e.g. to look back
+/// at what led to a crash. The buffer is shared with the owner of the sink.
+pub struct RingBufferSink {
+    buffer: Rc<RefCell<VecDeque<TraceRecord>>>,
+    capacity: usize,
+}
+
+impl RingBufferSink {
+    pub fn new(capacity: usize) -> Self {
+        RingBufferSink {
+            buffer: Rc::new(RefCell::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn get_buffer(&self) -> Rc<RefCell<VecDeque<TraceRecord>>> {
+        self.buffer.clone()
+    }
+}
+
+impl TraceSink for RingBufferSink {
+    fn record(&mut self, record: &TraceRecord) {
+        let mut buffer = self.buffer.borrow_mut();
+        if buffer.len() >= self.capacity {
+            buffer.pop_front();
+        }
+        if self.capacity > 0 {
+            buffer.push_back(record.clone());
+        }
+    }
+}
+
+/// Filter of the events by the instruction which caused them.
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    /// range of the PC, the end is exclusive.
+    pub pc_range: Option<(u64, u64)>,
+    /// privilege modes to trace. All the modes are traced if it is empty.
+    pub privileges: Vec<Privilege>,
+    /// range of the instruction count, the end is exclusive.
+    pub count_range: Option<(u64, u64)>,
+}
+
+impl TraceFilter {
+    fn matches(&self, count: u64, pc: u64, privilege: &Privilege) -> bool {
+        let in_range = |range: Option<(u64, u64)>, value: u64| match range {
+            Some((start, end)) => start <= value && value < end,
+            None => true,
+        };
+        in_range(self.pc_range, pc)
+            && in_range(self.count_range, count)
+            && (self.privileges.is_empty() || self.privileges.contains(privilege))
+    }
+}
+
+/// Trace subsystem. The hart and the MMU ask `is_enabled` before building an
+/// event, so tracing costs a check of a mask while it is disabled.
+pub struct Tracer {
+    /// categories being traced. It is zero while there is no sink.
+    mask: u32,
+    categories: u32,
+    filter: TraceFilter,
+    sinks: Vec<Box<dyn TraceSink>>,
+    /// symbols of the loaded program, which are given to the sinks.
+    symbols: Rc<SymbolTable>,
+    /// the instruction which the events belong to.
+    count: u64,
+    pc: u64,
+    privilege: Privilege,
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Tracer {
+            mask: 0,
+            categories: 0,
+            filter: TraceFilter::default(),
+            sinks: vec![],
+            symbols: Rc::new(SymbolTable::new()),
+            count: 0,
+            pc: 0,
+            privilege: Privilege::Machine,
+        }
+    }
+
+    pub fn set_categories(&mut self, categories: &[TraceCategory]) {
+        self.categories = categories
+            .iter()
+            .fold(0, |mask, category| mask | category.mask());
+        self.update_mask();
+    }
+
+    pub fn set_filter(&mut self, filter: TraceFilter) {
+        self.filter = filter;
+    }
+
+    pub fn add_sink(&mut self, mut sink: Box<dyn TraceSink>) {
+        sink.set_symbols(self.symbols.clone());
+        self.sinks.push(sink);
+        self.update_mask();
+    }
+
+    /// Gives the symbols of the loaded program to the sinks, and to the sinks
+    /// added later.
+    pub fn set_symbols(&mut self, symbols: Rc<SymbolTable>) {
+        for sink in self.sinks.iter_mut() {
+            sink.set_symbols(symbols.clone());
+        }
+        self.symbols = symbols;
+    }
+
+    /// Flushes and removes all the sinks, which disables tracing.
+    pub fn clear_sinks(&mut self) {
+        self.flush();
+        self.sinks.clear();
+        self.update_mask();
+    }
+
+    fn update_mask(&mut self) {
+        self.mask = match self.sinks.is_empty() {
+            true => 0,
+            false => self.categories,
+        };
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.mask != 0
+    }
+
+    pub fn is_enabled(&self, category: TraceCategory) -> bool {
+        self.mask & category.mask() != 0
+    }
+
+    /// Starts an instruction, which the following events belong to.
+    pub fn start_instruction(&mut self, pc: u64, privilege: &Privilege) {
+        self.count = self.count.wrapping_add(1);
+        self.set_context(pc, privilege);
+    }
+
+    /// Sets the PC and the privilege mode of the following events, e.g. of
+    /// an interrupt taken between instructions.
+    pub fn set_context(&mut self, pc: u64, privilege: &Privilege) {
+        self.pc = pc;
+        self.privilege = privilege.clone();
+    }
+
+    pub fn record(&mut self, event: TraceEvent) {
+        if !self.is_enabled(event.category())
+            || !self.filter.matches(self.count, self.pc, &self.privilege)
+        {
+            return;
+        }
+        let record = TraceRecord {
+            count: self.count,
+            pc: self.pc,
+            privilege: self.privilege.clone(),
+            event,
+        };
+        for sink in self.sinks.iter_mut() {
+            sink.record(&record);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        for sink in self.sinks.iter_mut() {
+            sink.flush();
+        }
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,186 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{emulator, words, DATA_ADDRESS, DRAM_BASE};
+use riscv_emu::bus::bus::Device;
+use riscv_emu::emulator::Emulator;
+
+const A0: usize = 10;
+const A1: usize = 11;
+
+const PAGE_SIZE: u64 = 4096;
+
+const PROGRAM: [u32; 2] = [
+    0x00b53023, // sd a1,0(a0)
+    0x0000006f, // j .
+];
+
+fn read64(emu: &mut Emulator, address: u64) -> u64 {
+    emu.read_physical_memory(address, 8).unwrap()
+}
+
+fn write64(emu: &mut Emulator, address: u64, data: u64) {
+    emu.write_physical_memory(address, 8, data).unwrap()
+}
+
+#[test]
+fn restore_store_across_pages() {
+    // DATA_ADDRESS is at the start of a page.
+    let address = DATA_ADDRESS + PAGE_SIZE - 4;
+    let mut emu = emulator(words(&PROGRAM));
+    write64(&mut emu, address, 0x1111_2222_3333_4444);
+    emu.write_register(A0, address);
+    emu.write_register(A1, 0x5555_6666_7777_8888);
+    emu.take_checkpoint();
+
+    emu.step();
+    assert_eq!(0x5555_6666_7777_8888, read64(&mut emu, address));
+    emu.restore_checkpoint();
+    assert_eq!(0x1111_2222_3333_4444, read64(&mut emu, address));
+    assert_eq!(DRAM_BASE, emu.get_pc());
+
+    // a write of the debugger across the pages is restored as well.
+    write64(&mut emu, address, 0x9999_aaaa_bbbb_cccc);
+    emu.restore_checkpoint();
+    assert_eq!(0x1111_2222_3333_4444, read64(&mut emu, address));
+}
+
+#[test]
+fn restore_twice() {
+    let first = DATA_ADDRESS;
+    let second = DATA_ADDRESS + PAGE_SIZE * 4;
+    let mut emu = emulator(words(&PROGRAM));
+    write64(&mut emu, first, 1);
+    write64(&mut emu, second, 2);
+    emu.take_checkpoint();
+
+    write64(&mut emu, first, 3);
+    emu.restore_checkpoint();
+    assert_eq!(1, read64(&mut emu, first));
+
+    // the pages written before the first restore and the others are restored again.
+    write64(&mut emu, first, 4);
+    write64(&mut emu, second, 5);
+    emu.restore_checkpoint();
+    assert_eq!(1, read64(&mut emu, first));
+    assert_eq!(2, read64(&mut emu, second));
+    assert!(emu.has_checkpoint());
+}
+
+#[test]
+fn load_snapshot_while_checkpoint_taken() {
+    let mut emu = emulator(words(&PROGRAM));
+    write64(&mut emu, DATA_ADDRESS, 1);
+    emu.take_checkpoint();
+    write64(&mut emu, DATA_ADDRESS, 2);
+    let snapshot = emu.save_snapshot_to_binary();
+    write64(&mut emu, DATA_ADDRESS, 3);
+
+    // the snapshot replaces the machine, and the checkpoint is forgotten.
+    emu.load_snapshot_from_binary(&snapshot).unwrap();
+    assert_eq!(2, read64(&mut emu, DATA_ADDRESS));
+    assert!(!emu.has_checkpoint());
+
+    // a new checkpoint restores the state of the snapshot.
+    emu.take_checkpoint();
+    write64(&mut emu, DATA_ADDRESS, 4);
+    emu.restore_checkpoint();
+    assert_eq!(2, read64(&mut emu, DATA_ADDRESS));
+}
+
+//***********************************************************************
+// Virtio disk
+//***********************************************************************
+const VIRTIO_BASE: u64 = 0x1000_1000;
+const VIRTIO_GUEST_PAGE_SIZE: u64 = 0x028;
+const VIRTIO_QUEUE_NUM: u64 = 0x038;
+const VIRTIO_QUEUE_PFN: u64 = 0x040;
+const VIRTIO_QUEUE_NOTIFY: u64 = 0x050;
+
+const QUEUE_NUM: u64 = 8;
+const QUEUE: u64 = DRAM_BASE + 0x10000;
+const REQUEST: u64 = DRAM_BASE + 0x20000;
+const STATUS: u64 = REQUEST + 0x100;
+const BUFFER: u64 = DRAM_BASE + 0x21000;
+
+const SECTOR_SIZE: usize = 512;
+const VRING_DESC_F_NEXT: u64 = 0x1;
+const VRING_DESC_F_WRITE: u64 = 0x2;
+
+fn write_descriptor(emu: &mut Emulator, index: u64, address: u64, length: u64, flags: u64) {
+    let entry = QUEUE + index * 16;
+    write64(emu, entry, address);
+    write64(emu, entry + 8, length | flags << 32 | (index + 1) << 48);
+}
+
+/// Sets up the queue of the legacy virtio-mmio disk in the DRAM.
+fn setup_queue(emu: &mut Emulator) {
+    for (register, value) in [
+        (VIRTIO_GUEST_PAGE_SIZE, PAGE_SIZE),
+        (VIRTIO_QUEUE_NUM, QUEUE_NUM),
+        (VIRTIO_QUEUE_PFN, QUEUE / PAGE_SIZE),
+    ]
+    .iter()
+    {
+        emu.write_physical_memory(VIRTIO_BASE + register, 4, *value)
+            .unwrap();
+    }
+}
+
+/// Reads or writes a sector between the disk and BUFFER, as a driver does
+/// with the request of the descriptors 0-2. The available ring is left zero,
+/// so each of its entries is the descriptor 0.
+fn transfer_sector(emu: &mut Emulator, sector: u64, write: bool) {
+    write64(emu, REQUEST, write as u64);
+    write64(emu, REQUEST + 8, sector);
+    write_descriptor(emu, 0, REQUEST, 16, VRING_DESC_F_NEXT);
+    let flags = match write {
+        true => VRING_DESC_F_NEXT,
+        false => VRING_DESC_F_NEXT | VRING_DESC_F_WRITE,
+    };
+    write_descriptor(emu, 1, BUFFER, SECTOR_SIZE as u64, flags);
+    write_descriptor(emu, 2, STATUS, 2, VRING_DESC_F_NEXT);
+    emu.write_physical_memory(VIRTIO_BASE + VIRTIO_QUEUE_NOTIFY, 4, 0)
+        .unwrap();
+    // the device transfers the data after a delay.
+    emu.run_steps(256);
+}
+
+fn read_buffer(emu: &mut Emulator) -> Vec<u8> {
+    (0..SECTOR_SIZE as u64)
+        .map(|i| emu.read_physical_memory(BUFFER + i, 1).unwrap() as u8)
+        .collect()
+}
+
+fn write_buffer(emu: &mut Emulator, data: &[u8]) {
+    for (i, byte) in data.iter().enumerate() {
+        emu.write_physical_memory(BUFFER + i as u64, 1, *byte as u64)
+            .unwrap();
+    }
+}
+
+#[test]
+fn restore_virtio_disk() {
+    let original: Vec<u8> = (0..SECTOR_SIZE * 4).map(|i| i as u8).collect();
+    let mut emu = emulator(words(&[0x0000006f])); // j .
+    emu.set_data_from_binary(Device::Disk, original.clone());
+    setup_queue(&mut emu);
+    emu.take_checkpoint();
+
+    let written = vec![0xa5; SECTOR_SIZE];
+    write_buffer(&mut emu, &written);
+    transfer_sector(&mut emu, 1, true);
+    write_buffer(&mut emu, &[0; SECTOR_SIZE]);
+    transfer_sector(&mut emu, 1, false);
+    assert_eq!(written, read_buffer(&mut emu));
+
+    // the sector which the device wrote to the disk is restored as the DRAM is.
+    emu.restore_checkpoint();
+    assert_eq!(vec![0; SECTOR_SIZE], read_buffer(&mut emu));
+    transfer_sector(&mut emu, 1, false);
+    assert_eq!(
+        &original[SECTOR_SIZE..SECTOR_SIZE * 2],
+        &read_buffer(&mut emu)[..]
+    );
+}
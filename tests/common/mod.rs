@@ -0,0 +1,150 @@
+// Helpers of the tests running hand-assembled programs.
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+
+use riscv_emu::console::{Console, TtyDummy};
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+
+pub const DRAM_BASE: u64 = 0x8000_0000;
+
+/// Address of the data used by the programs, after the code.
+pub const DATA_ADDRESS: u64 = DRAM_BASE + 0x1000;
+
+/// Returns the little-endian bytes of the 32-bit instructions.
+pub fn words(program: &[u32]) -> Vec<u8> {
+    program.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Returns the little-endian bytes of the 16-bit parcels, e.g. of the
+/// compressed instructions.
+pub fn parcels(program: &[u16]) -> Vec<u8> {
+    program
+        .iter()
+        .flat_map(|parcel| parcel.to_le_bytes())
+        .collect()
+}
+
+/// Returns the emulator of the virt machine (RV64) with the program at the
+/// start of the DRAM, and the PC at it.
+pub fn emulator(program: Vec<u8>) -> Emulator {
+    console_emulator(program, Box::new(TtyDummy::new()))
+}
+
+pub fn console_emulator(program: Vec<u8>, console: Box<dyn Console>) -> Emulator {
+    let mut emu = Emulator::new(Machine::QemuVirt, console, false);
+    emu.set_dram_data(program);
+    emu.set_pc(DRAM_BASE);
+    emu
+}
+
+/// Console which types the given input, and keeps the output in the buffer
+/// which is saved in snapshots.
+pub struct BufferConsole {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl BufferConsole {
+    pub fn new(input: &[u8]) -> Self {
+        BufferConsole {
+            input: input.iter().copied().collect(),
+            output: vec![],
+        }
+    }
+}
+
+impl Console for BufferConsole {
+    fn putchar(&mut self, c: u8) {
+        self.output.push(c);
+    }
+
+    fn getchar(&mut self) -> u8 {
+        self.input.pop_front().unwrap_or(0)
+    }
+
+    fn set_input(&mut self, c: u8) {
+        self.input.push_back(c);
+    }
+
+    fn get_output(&mut self) -> u8 {
+        match self.output.is_empty() {
+            true => 0,
+            false => self.output.remove(0),
+        }
+    }
+
+    fn get_buffers(&self) -> (Vec<u8>, Vec<u8>) {
+        (self.input.iter().copied().collect(), self.output.clone())
+    }
+
+    fn set_buffers(&mut self, input: Vec<u8>, output: Vec<u8>) {
+        self.input = input.into_iter().collect();
+        self.output = output;
+    }
+}
+
+/// Returns the ELF file (RV64) with the program in .text at the start of the
+/// DRAM, the global functions at the offsets from it in .symtab, and the line
+/// number information in .debug_line.
+pub fn elf(program: &[u32], functions: &[(&str, u64)], debug_line: &[u8]) -> Vec<u8> {
+    let text = words(program);
+    let mut symtab = vec![0; 24];
+    let mut strtab = vec![0];
+    for (name, offset) in functions.iter() {
+        symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes()); // st_name
+        symtab.push(0x12); // st_info: STB_GLOBAL, STT_FUNC
+        symtab.push(0); // st_other
+        symtab.extend_from_slice(&1u16.to_le_bytes()); // st_shndx: .text
+        symtab.extend_from_slice(&(DRAM_BASE + offset).to_le_bytes()); // st_value
+        symtab.extend_from_slice(&0u64.to_le_bytes()); // st_size
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+    }
+    let shstrtab = b"\0.text\0.debug_line\0.symtab\0.strtab\0.shstrtab\0".to_vec();
+
+    let mut data = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    data.extend_from_slice(&2u16.to_le_bytes()); // e_type: ET_EXEC
+    data.extend_from_slice(&0xf3u16.to_le_bytes()); // e_machine: EM_RISCV
+    data.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    data.extend_from_slice(&DRAM_BASE.to_le_bytes()); // e_entry
+    data.extend_from_slice(&0u64.to_le_bytes()); // e_phoff
+    let shoff = data.len();
+    data.extend_from_slice(&0u64.to_le_bytes()); // e_shoff, which is filled later
+    data.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    for half in [0x40u16, 56, 0, 64, 6, 5].iter() {
+        // e_ehsize, e_phentsize, e_phnum, e_shentsize, e_shnum, e_shstrndx
+        data.extend_from_slice(&half.to_le_bytes());
+    }
+
+    // name, type, flags, address, link and contents of the sections.
+    let sections = [
+        (0u32, 0u32, 0u64, 0u64, 0u32, &[][..]),
+        (1, 1, 0x6, DRAM_BASE, 0, &text[..]),
+        (7, 1, 0, 0, 0, debug_line),
+        (19, 2, 0, 0, 4, &symtab[..]),
+        (27, 3, 0, 0, 0, &strtab[..]),
+        (35, 3, 0, 0, 0, &shstrtab[..]),
+    ];
+    let mut offsets = vec![];
+    for section in sections.iter() {
+        data.resize((data.len() + 7) & !7, 0);
+        offsets.push(data.len() as u64);
+        data.extend_from_slice(section.5);
+    }
+    data.resize((data.len() + 7) & !7, 0);
+    let end = data.len() as u64;
+    data[shoff..shoff + 8].copy_from_slice(&end.to_le_bytes());
+    for ((name, kind, flags, address, link, contents), offset) in sections.iter().zip(offsets) {
+        data.extend_from_slice(&name.to_le_bytes());
+        data.extend_from_slice(&kind.to_le_bytes());
+        data.extend_from_slice(&flags.to_le_bytes());
+        data.extend_from_slice(&address.to_le_bytes());
+        data.extend_from_slice(&offset.to_le_bytes());
+        data.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+        data.extend_from_slice(&link.to_le_bytes());
+        data.extend_from_slice(&[0; 20]); // info, addralign and entsize
+    }
+    data
+}
@@ -0,0 +1,126 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{emulator, words, DATA_ADDRESS};
+use riscv_emu::cosim::{ArchState, ReferenceModel};
+use riscv_emu::cpu::mmu::MemoryWrite;
+use riscv_emu::cpu::trap::Interrupt;
+use riscv_emu::emulator::{Emulator, RunError, StopConditions, StopReason};
+
+const A0: usize = 10;
+const A2: usize = 12;
+const S0: usize = 8;
+
+const PROGRAM: [u32; 3] = [
+    0x00150513, // addi a0,a0,1
+    0x00a43023, // sd a0,0(s0)
+    0xff9ff06f, // j -8
+];
+
+fn program_emulator() -> Emulator {
+    let mut emu = emulator(words(&PROGRAM));
+    emu.write_register(S0, DATA_ADDRESS);
+    emu
+}
+
+/// Reference model which changes a2 after the step `inject_at`.
+struct InjectedReference {
+    emu: Emulator,
+    steps: u64,
+    inject_at: u64,
+}
+
+impl ReferenceModel for InjectedReference {
+    fn step(&mut self, interrupt: Option<Interrupt>, mmio_reads: Vec<u64>) -> Vec<MemoryWrite> {
+        let writes = ReferenceModel::step(&mut self.emu, interrupt, mmio_reads);
+        self.steps += 1;
+        if self.steps == self.inject_at {
+            self.emu.write_register(A2, 0x5a);
+        }
+        writes
+    }
+
+    fn get_arch_state(&mut self) -> ArchState {
+        self.emu.get_arch_state()
+    }
+}
+
+#[test]
+fn lockstep_same_program() {
+    let mut emu = program_emulator();
+    emu.set_reference(Some(Box::new(program_emulator())));
+    let reason = emu.run_until(&StopConditions {
+        instruction_limit: Some(300),
+        ..StopConditions::default()
+    });
+    match reason {
+        StopReason::InstructionLimit => {}
+        reason => panic!("{:?}", reason),
+    }
+    assert_eq!(100, emu.read_register(A0));
+    assert_eq!(100, emu.read_physical_memory(DATA_ADDRESS, 8).unwrap());
+}
+
+#[test]
+fn lockstep_injected_register_change() {
+    let mut emu = program_emulator();
+    emu.set_reference(Some(Box::new(InjectedReference {
+        emu: program_emulator(),
+        steps: 0,
+        inject_at: 10,
+    })));
+    let reason = emu.run_until(&StopConditions {
+        instruction_limit: Some(300),
+        ..StopConditions::default()
+    });
+    let divergence = match reason {
+        StopReason::Divergence(divergence) => divergence,
+        reason => panic!("{:?}", reason),
+    };
+    assert_eq!(10, divergence.step);
+    assert_eq!(
+        vec![(
+            String::from("x12"),
+            String::from("0x0"),
+            String::from("0x5a")
+        )],
+        divergence.differences
+    );
+    assert!(divergence.to_string().contains("x12"));
+}
+
+#[test]
+fn lockstep_step_and_run_steps() {
+    let mut emu = program_emulator();
+    emu.set_reference(Some(Box::new(InjectedReference {
+        emu: program_emulator(),
+        steps: 0,
+        inject_at: 3,
+    })));
+    assert!(emu.step().divergence.is_none());
+
+    // stops at the step which diverges.
+    let divergence = emu.run_steps(10).unwrap();
+    assert_eq!(3, divergence.step);
+    assert_eq!(3, emu.get_cycle());
+
+    // the next step is compared with the reference too.
+    let divergence = emu.step().divergence.unwrap();
+    assert_eq!(4, divergence.step);
+    assert_eq!(String::from("x12"), divergence.differences[0].0);
+}
+
+#[test]
+fn lockstep_run_returns_divergence() {
+    let mut emu = program_emulator();
+    emu.set_reference(Some(Box::new(InjectedReference {
+        emu: program_emulator(),
+        steps: 0,
+        inject_at: 10,
+    })));
+    match emu.run() {
+        Err(RunError::Divergence(divergence)) => assert_eq!(10, divergence.step),
+        result => panic!("{:?}", result),
+    }
+}
@@ -0,0 +1,162 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{elf, DRAM_BASE};
+use riscv_emu::console::TtyDummy;
+use riscv_emu::coverage::Block;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+
+/// Counts a0 down from 3, and loops at the end. Each instruction is a line of
+/// loop.S from 1.
+const PROGRAM: [u32; 5] = [
+    0x00300513, // li a0,3
+    0xfff50513, // addi a0,a0,-1
+    0xfe051ee3, // bnez a0,-4
+    0x0000006f, // j .
+    0x00000013, // nop
+];
+
+/// Returns the line number program (DWARF 3) with a row for each instruction
+/// of the program.
+fn debug_line() -> Vec<u8> {
+    let mut header = vec![
+        1,    // minimum_instruction_length
+        1,    // default_is_stmt
+        0xfb, // line_base (-5)
+        14,   // line_range
+        13,   // opcode_base
+    ];
+    header.extend_from_slice(&[0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+    // no include directories, and loop.S in the current directory.
+    header.push(0);
+    header.extend_from_slice(b"loop.S\0");
+    header.extend_from_slice(&[0, 0, 0, 0]);
+
+    // DW_LNE_set_address and DW_LNS_copy.
+    let mut program = vec![0, 9, 2];
+    program.extend_from_slice(&DRAM_BASE.to_le_bytes());
+    program.push(1);
+    // special opcodes of 4 bytes and a line each.
+    for _ in 1..PROGRAM.len() {
+        program.push(13 + (1 + 5) + 14 * 4);
+    }
+    // DW_LNS_advance_pc and DW_LNE_end_sequence.
+    program.extend_from_slice(&[2, 4, 0, 1, 1]);
+
+    let mut unit = vec![3, 0];
+    unit.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    unit.extend(header);
+    unit.extend(program);
+    let mut data = (unit.len() as u32).to_le_bytes().to_vec();
+    data.extend(unit);
+    data
+}
+
+fn coverage_emulator() -> Emulator {
+    let mut emu = Emulator::new(Machine::QemuVirt, Box::new(TtyDummy::new()), false);
+    emu.load_program_from_binary(elf(&PROGRAM, &[], &debug_line()));
+    emu.set_coverage(true);
+    emu
+}
+
+/// Returns the text of the drcov file, and the entries of its BB table.
+fn drcov(emu: &Emulator) -> (String, Vec<(u32, u16, u16)>) {
+    let mut data = vec![];
+    emu.get_coverage().write_drcov(&mut data).unwrap();
+    let table = data.windows(4).position(|w| w == b"bbs\n").unwrap() + 4;
+    let entries = data[table..]
+        .chunks(8)
+        .map(|entry| {
+            (
+                u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]),
+                u16::from_le_bytes([entry[4], entry[5]]),
+                u16::from_le_bytes([entry[6], entry[7]]),
+            )
+        })
+        .collect();
+    (String::from_utf8(data[..table].to_vec()).unwrap(), entries)
+}
+
+#[test]
+fn blocks_of_loop() {
+    let mut emu = coverage_emulator();
+    // li, 3 times addi and bnez, and 5 times j.
+    emu.run_steps(12);
+    assert_eq!(
+        vec![
+            Block {
+                start: DRAM_BASE,
+                end: DRAM_BASE + 0xc,
+                count: 1
+            },
+            Block {
+                start: DRAM_BASE + 0x4,
+                end: DRAM_BASE + 0xc,
+                count: 2
+            },
+            Block {
+                start: DRAM_BASE + 0xc,
+                end: DRAM_BASE + 0x10,
+                count: 5
+            },
+        ],
+        emu.get_coverage().get_blocks()
+    );
+
+    let (header, entries) = drcov(&emu);
+    assert_eq!(
+        format!(
+            "DRCOV VERSION: 2\n\
+             DRCOV FLAVOR: riscv_emu\n\
+             Module Table: version 2, count 1\n\
+             Columns: id, base, end, entry, checksum, timestamp, path\n  \
+             0, 0x{:016x}, 0x{:016x}, 0x{:016x}, 0x00000000, 0x00000000, program\n\
+             BB Table: 3 bbs\n",
+            DRAM_BASE,
+            DRAM_BASE + 0x14,
+            DRAM_BASE
+        ),
+        header
+    );
+    assert_eq!(vec![(0, 12, 0), (4, 8, 0), (0xc, 4, 0)], entries);
+}
+
+#[test]
+fn drcov_merges_blocks_of_same_start() {
+    let mut emu = coverage_emulator();
+    // stopping the coverage ends the block after li and addi.
+    emu.run_steps(2);
+    emu.set_coverage(false);
+    emu.set_pc(DRAM_BASE);
+    emu.set_coverage(true);
+    emu.run_steps(3);
+    assert_eq!(2, emu.get_coverage().get_blocks().len());
+
+    let (header, entries) = drcov(&emu);
+    assert!(header.ends_with("BB Table: 1 bbs\n"), "{}", header);
+    assert_eq!(vec![(0, 12, 0)], entries);
+}
+
+#[test]
+fn lcov_counts_lines() {
+    let mut emu = coverage_emulator();
+    emu.run_steps(12);
+    let mut data = vec![];
+    emu.get_coverage().write_lcov(&mut data).unwrap();
+    // addi and bnez are in the first block and twice in the loop, and nop is not executed.
+    assert_eq!(
+        "TN:\n\
+         SF:loop.S\n\
+         DA:1,1\n\
+         DA:2,3\n\
+         DA:3,3\n\
+         DA:4,5\n\
+         DA:5,0\n\
+         LF:5\n\
+         LH:4\n\
+         end_of_record\n",
+        String::from_utf8(data).unwrap()
+    );
+}
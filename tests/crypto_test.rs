@@ -0,0 +1,336 @@
+extern crate riscv_emu;
+
+mod common;
+
+use std::convert::TryInto;
+
+use common::{emulator, DRAM_BASE};
+use riscv_emu::cpu::cpu_csr::{CSR_SEED, CSR_SEED_OPST_ES16};
+use riscv_emu::emulator::Emulator;
+
+const A0: usize = 10;
+const A1: usize = 11;
+const A2: usize = 12;
+
+/// Executes the instruction `op a0,a1,a2` and returns a0.
+fn execute(emu: &mut Emulator, word: u32, rs1: u64, rs2: u64) -> u64 {
+    emu.write_physical_memory(DRAM_BASE, 4, word as u64)
+        .unwrap();
+    emu.set_pc(DRAM_BASE);
+    emu.write_register(A1, rs1);
+    emu.write_register(A2, rs2);
+    let result = emu.step();
+    assert!(result.exception.is_none(), "{:08x}", word);
+    emu.read_register(A0)
+}
+
+fn be_words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks(4)
+        .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+        .collect()
+}
+
+//***********************************************************************
+// AES (Zkne/Zknd)
+//***********************************************************************
+const AES64KS1I: u32 = 0x31059513; // aes64ks1i a0,a1,0
+const AES64KS2: u32 = 0x7ec58533; // aes64ks2 a0,a1,a2
+const AES64ES: u32 = 0x32c58533; // aes64es a0,a1,a2
+const AES64ESM: u32 = 0x36c58533; // aes64esm a0,a1,a2
+const AES64DS: u32 = 0x3ac58533; // aes64ds a0,a1,a2
+const AES64DSM: u32 = 0x3ec58533; // aes64dsm a0,a1,a2
+const AES64IM: u32 = 0x30059513; // aes64im a0,a1
+
+/// Returns the state or the key of 16 bytes as the low and the high halves.
+fn aes_halves(bytes: &[u8]) -> (u64, u64) {
+    (
+        u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+        u64::from_le_bytes(bytes[8..].try_into().unwrap()),
+    )
+}
+
+fn aes128_round_keys(emu: &mut Emulator, key: &[u8]) -> Vec<(u64, u64)> {
+    let mut keys = vec![aes_halves(key)];
+    for rnum in 0..10 {
+        let (low, high) = *keys.last().unwrap();
+        let t = execute(emu, AES64KS1I | rnum << 20, high, 0);
+        let low = execute(emu, AES64KS2, t, low);
+        let high = execute(emu, AES64KS2, low, high);
+        keys.push((low, high));
+    }
+    keys
+}
+
+/// FIPS-197 Appendix C.1.
+#[test]
+fn aes128_fips197() {
+    let key: Vec<u8> = (0..16).collect();
+    let plaintext: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+    let ciphertext = [
+        0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5,
+        0x5a,
+    ];
+    let mut emu = emulator(vec![]);
+    let keys = aes128_round_keys(&mut emu, &key);
+    // the last round key of the key expansion in FIPS-197 Appendix A.1 for its key.
+    let a1_keys = aes128_round_keys(
+        &mut emu,
+        &[
+            0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf,
+            0x4f, 0x3c,
+        ],
+    );
+    assert_eq!(
+        aes_halves(&[
+            0xd0, 0x14, 0xf9, 0xa8, 0xc9, 0xee, 0x25, 0x89, 0xe1, 0x3f, 0x0c, 0xc8, 0xb6, 0x63,
+            0x0c, 0xa6,
+        ]),
+        a1_keys[10]
+    );
+
+    // encryption
+    let (mut low, mut high) = aes_halves(&plaintext);
+    low ^= keys[0].0;
+    high ^= keys[0].1;
+    for (round, key) in keys.iter().enumerate().skip(1) {
+        let op = match round {
+            10 => AES64ES,
+            _ => AES64ESM,
+        };
+        let next_low = execute(&mut emu, op, low, high) ^ key.0;
+        high = execute(&mut emu, op, high, low) ^ key.1;
+        low = next_low;
+    }
+    assert_eq!(aes_halves(&ciphertext), (low, high));
+
+    // decryption by the equivalent inverse cipher.
+    let (mut low, mut high) = aes_halves(&ciphertext);
+    low ^= keys[10].0;
+    high ^= keys[10].1;
+    for round in (0..10).rev() {
+        let (op, key) = match round {
+            0 => (AES64DS, keys[0]),
+            _ => (
+                AES64DSM,
+                (
+                    execute(&mut emu, AES64IM, keys[round].0, 0),
+                    execute(&mut emu, AES64IM, keys[round].1, 0),
+                ),
+            ),
+        };
+        let next_low = execute(&mut emu, op, low, high) ^ key.0;
+        high = execute(&mut emu, op, high, low) ^ key.1;
+        low = next_low;
+    }
+    assert_eq!(aes_halves(&plaintext), (low, high));
+}
+
+//***********************************************************************
+// SM4 (Zksed)
+//***********************************************************************
+const SM4ED: u32 = 0x30c58533; // sm4ed a0,a1,a2,0
+const SM4KS: u32 = 0x34c58533; // sm4ks a0,a1,a2,0
+
+/// Applies the four bytes of the round function or the key schedule to `x`,
+/// and adds it to `acc`.
+fn sm4_round(emu: &mut Emulator, op: u32, acc: u32, x: u32) -> u32 {
+    (0..4).fold(acc, |acc, bs| {
+        execute(emu, op | bs << 30, acc as u64, x as u64) as u32
+    })
+}
+
+/// The sample of GB/T 32907-2016 Appendix A.1.
+#[test]
+fn sm4_sample() {
+    let data = be_words(&[
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32,
+        0x10,
+    ]);
+    let fk = [0xa3b1bac6, 0x56aa3350, 0x677d9197, 0xb27022dc];
+    let mut emu = emulator(vec![]);
+
+    let mut k: Vec<u32> = data.iter().zip(fk.iter()).map(|(m, f)| m ^ f).collect();
+    for i in 0..32 {
+        let ck = u32::from_be_bytes([0, 1, 2, 3].map(|j| ((4 * i + j) * 7) as u8));
+        let next = sm4_round(&mut emu, SM4KS, k[i], k[i + 1] ^ k[i + 2] ^ k[i + 3] ^ ck);
+        k.push(next);
+    }
+    assert_eq!(0xf12186f9, k[4]);
+    assert_eq!(0x9124a012, k[35]);
+
+    let mut x = data.clone();
+    for i in 0..32 {
+        let next = sm4_round(
+            &mut emu,
+            SM4ED,
+            x[i],
+            x[i + 1] ^ x[i + 2] ^ x[i + 3] ^ k[i + 4],
+        );
+        x.push(next);
+    }
+    assert_eq!(
+        vec![0x681edf34, 0xd206965e, 0x86b3e94f, 0x536e4246],
+        [x[35], x[34], x[33], x[32]]
+    );
+}
+
+//***********************************************************************
+// SHA-256 (Zknh)
+//***********************************************************************
+const SHA256SIG0: u32 = 0x10259513; // sha256sig0 a0,a1
+const SHA256SIG1: u32 = 0x10359513; // sha256sig1 a0,a1
+const SHA256SUM0: u32 = 0x10059513; // sha256sum0 a0,a1
+const SHA256SUM1: u32 = 0x10159513; // sha256sum1 a0,a1
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 of "abc" (FIPS 180-4), whose sigma and sum functions are the
+/// instructions.
+#[test]
+fn sha256_abc() {
+    let mut emu = emulator(vec![]);
+    let mut f = |op: u32, x: u32| execute(&mut emu, op, x as u64, 0) as u32;
+
+    let mut w: Vec<u32> = vec![0x61626380, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x18];
+    for i in 16..64 {
+        let word = f(SHA256SIG1, w[i - 2])
+            .wrapping_add(w[i - 7])
+            .wrapping_add(f(SHA256SIG0, w[i - 15]))
+            .wrapping_add(w[i - 16]);
+        w.push(word);
+    }
+    let iv: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let [mut a, mut b, mut c, mut d, mut e, mut f_, mut g, mut h] = iv;
+    for i in 0..64 {
+        let t1 = h
+            .wrapping_add(f(SHA256SUM1, e))
+            .wrapping_add((e & f_) ^ (!e & g))
+            .wrapping_add(SHA256_K[i])
+            .wrapping_add(w[i]);
+        let t2 = f(SHA256SUM0, a).wrapping_add((a & b) ^ (a & c) ^ (b & c));
+        h = g;
+        g = f_;
+        f_ = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+    let digest: Vec<u32> = [a, b, c, d, e, f_, g, h]
+        .iter()
+        .zip(iv.iter())
+        .map(|(x, v)| x.wrapping_add(*v))
+        .collect();
+    assert_eq!(
+        vec![
+            0xba7816bf, 0x8f01cfea, 0x414140de, 0x5dae2223, 0xb00361a3, 0x96177a9c, 0xb410ff61,
+            0xf20015ad
+        ],
+        digest
+    );
+}
+
+//***********************************************************************
+// SM3 (Zksh)
+//***********************************************************************
+const SM3P0: u32 = 0x10859513; // sm3p0 a0,a1
+const SM3P1: u32 = 0x10959513; // sm3p1 a0,a1
+
+/// SM3 of "abc" (GB/T 32905-2016 Appendix A.1), whose permutations are the
+/// instructions.
+#[test]
+fn sm3_abc() {
+    let mut emu = emulator(vec![]);
+    let mut p = |op: u32, x: u32| execute(&mut emu, op, x as u64, 0) as u32;
+
+    let mut w: Vec<u32> = vec![0x61626380, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x18];
+    for j in 16..68 {
+        let word = p(SM3P1, w[j - 16] ^ w[j - 9] ^ w[j - 3].rotate_left(15))
+            ^ w[j - 13].rotate_left(7)
+            ^ w[j - 6];
+        w.push(word);
+    }
+    let iv: [u32; 8] = [
+        0x7380166f, 0x4914b2b9, 0x172442d7, 0xda8a0600, 0xa96f30bc, 0x163138aa, 0xe38dee4d,
+        0xb0fb0e4e,
+    ];
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = iv;
+    for j in 0..64 {
+        let (t, ff, gg) = match j {
+            0..=15 => (0x79cc4519u32, a ^ b ^ c, e ^ f ^ g),
+            _ => (0x7a879d8a, (a & b) | (a & c) | (b & c), (e & f) | (!e & g)),
+        };
+        let ss1 = a
+            .rotate_left(12)
+            .wrapping_add(e)
+            .wrapping_add(t.rotate_left(j as u32 % 32))
+            .rotate_left(7);
+        let ss2 = ss1 ^ a.rotate_left(12);
+        let tt1 = ff
+            .wrapping_add(d)
+            .wrapping_add(ss2)
+            .wrapping_add(w[j] ^ w[j + 4]);
+        let tt2 = gg.wrapping_add(h).wrapping_add(ss1).wrapping_add(w[j]);
+        d = c;
+        c = b.rotate_left(9);
+        b = a;
+        a = tt1;
+        h = g;
+        g = f.rotate_left(19);
+        f = e;
+        e = p(SM3P0, tt2);
+    }
+    let digest: Vec<u32> = [a, b, c, d, e, f, g, h]
+        .iter()
+        .zip(iv.iter())
+        .map(|(x, v)| x ^ v)
+        .collect();
+    assert_eq!(
+        vec![
+            0x66c7f0f4, 0x62eeedd9, 0xd1f2d46b, 0xdc10e4e2, 0x4167c487, 0x5cf2f7a2, 0x297da02b,
+            0x8f4ba8e0
+        ],
+        digest
+    );
+}
+
+//***********************************************************************
+// Entropy source (Zkr)
+//***********************************************************************
+/// Reading the seed CSR without an instruction, e.g. by a debugger, does not
+/// consume the entropy.
+#[test]
+fn zkr_seed_direct_read() {
+    let csrrw = 0x01501573; // csrrw a0,seed,zero
+    let read = |peek: bool| {
+        let mut emu = emulator(vec![]);
+        emu.set_entropy_seed(1);
+        let mut seeds = vec![];
+        for _ in 0..3 {
+            if peek {
+                emu.read_csr(CSR_SEED);
+            }
+            seeds.push(execute(&mut emu, csrrw, 0, 0));
+            assert_eq!(seeds.last().copied().unwrap(), emu.read_csr(CSR_SEED));
+        }
+        seeds
+    };
+    let seeds = read(false);
+    assert!(seeds.iter().all(|seed| seed & CSR_SEED_OPST_ES16 != 0));
+    assert_ne!(seeds[0], seeds[1]);
+    assert_eq!(seeds, read(true));
+}
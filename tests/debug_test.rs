@@ -0,0 +1,177 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{emulator, words, DATA_ADDRESS, DRAM_BASE};
+use riscv_emu::cpu::trap::Exception;
+use riscv_emu::debug::debug_module::{
+    DM_ABSTRACTAUTO, DM_ABSTRACTCS, DM_COMMAND, DM_DATA0, DM_DMCONTROL, DM_DMSTATUS, DM_PROGBUF0,
+};
+use riscv_emu::emulator::Emulator;
+
+const S0: usize = 8;
+const S1: usize = 9;
+const A0: usize = 10;
+
+const IDCODE: u32 = 0x20000913;
+const IR_DMI: u64 = 0x11;
+const DMI_LENGTH: usize = 41;
+
+const DMCONTROL_DMACTIVE: u32 = 0x00000001;
+const DMCONTROL_HALTREQ: u32 = 0x80000000;
+const DMSTATUS_ALLHALTED: u32 = 1 << 9;
+
+// abstract command fields.
+const COMMAND_SIZE_32: u32 = 2 << 20;
+const COMMAND_SIZE_64: u32 = 3 << 20;
+const COMMAND_POSTEXEC: u32 = 1 << 18;
+const COMMAND_TRANSFER: u32 = 1 << 17;
+const REGNO_GPR: u32 = 0x1000;
+
+fn cmderr(emu: &mut Emulator) -> u32 {
+    (emu.dmi_read(DM_ABSTRACTCS) >> 8) & 0x7
+}
+
+/// Drives a TCK cycle, and returns TDO after the falling edge.
+fn clock(emu: &mut Emulator, tms: bool, tdi: bool) -> bool {
+    emu.jtag_set_pins(false, tms, tdi);
+    let tdo = emu.jtag_get_tdo();
+    emu.jtag_set_pins(true, tms, tdi);
+    tdo
+}
+
+/// Moves the TAP to Run-Test/Idle through Test-Logic-Reset.
+fn reset_tap(emu: &mut Emulator) {
+    for _ in 0..5 {
+        clock(emu, true, false);
+    }
+    clock(emu, false, false);
+}
+
+/// Shifts `value` into the register from Shift-DR or Shift-IR, and returns
+/// the shifted-out value. The TAP goes back to Run-Test/Idle.
+fn shift(emu: &mut Emulator, value: u64, length: usize) -> u64 {
+    let mut out = 0;
+    for i in 0..length {
+        let tdo = clock(emu, i == length - 1, (value >> i) & 1 != 0);
+        out |= (tdo as u64) << i;
+    }
+    clock(emu, true, false); // Update-DR/IR
+    clock(emu, false, false); // Run-Test/Idle
+    out
+}
+
+fn scan_ir(emu: &mut Emulator, ir: u64) {
+    for tms in [true, true, false, false].iter() {
+        clock(emu, *tms, false);
+    }
+    shift(emu, ir, 5);
+}
+
+fn scan_dr(emu: &mut Emulator, value: u64, length: usize) -> u64 {
+    for tms in [true, false, false].iter() {
+        clock(emu, *tms, false);
+    }
+    shift(emu, value, length)
+}
+
+/// Scans a DMI access, and returns the data of the previous access.
+fn scan_dmi(emu: &mut Emulator, address: u32, data: u32, op: u64) -> u32 {
+    let value = (address as u64) << 34 | (data as u64) << 2 | op;
+    let captured = scan_dr(emu, value, DMI_LENGTH);
+    assert_eq!(0, captured & 0x3, "dmistat");
+    (captured >> 2) as u32
+}
+
+#[test]
+fn jtag_idcode_and_dmi_halt() {
+    let mut emu = emulator(words(&[0x0000006f])); // j .
+    reset_tap(&mut emu);
+    assert_eq!(IDCODE as u64, scan_dr(&mut emu, 0, 32));
+
+    scan_ir(&mut emu, IR_DMI);
+    scan_dmi(
+        &mut emu,
+        DM_DMCONTROL,
+        DMCONTROL_DMACTIVE | DMCONTROL_HALTREQ,
+        2,
+    );
+    assert!(emu.is_debug_mode());
+    scan_dmi(&mut emu, DM_DMSTATUS, 0, 1);
+    let dmstatus = scan_dmi(&mut emu, 0, 0, 0);
+    assert_ne!(0, dmstatus & DMSTATUS_ALLHALTED);
+}
+
+#[test]
+fn abstract_register_read() {
+    let mut emu = emulator(words(&[0x0000006f])); // j .
+    emu.write_register(A0, 0x1234_5678_9abc_def0);
+    emu.dmi_write(DM_DMCONTROL, DMCONTROL_DMACTIVE | DMCONTROL_HALTREQ);
+    assert!(emu.is_debug_mode());
+
+    emu.dmi_write(
+        DM_COMMAND,
+        COMMAND_SIZE_64 | COMMAND_TRANSFER | (REGNO_GPR + A0 as u32),
+    );
+    assert_eq!(0, cmderr(&mut emu));
+    assert_eq!(0x9abc_def0, emu.dmi_read(DM_DATA0));
+    assert_eq!(0x1234_5678, emu.dmi_read(DM_DATA0 + 1));
+}
+
+#[test]
+fn autoexec_block_read() {
+    let mut emu = emulator(words(&[0x0000006f])); // j .
+    for (i, word) in [0x1111_1111, 0x2222_2222, 0x3333_3333].iter().enumerate() {
+        emu.write_physical_memory(DATA_ADDRESS + i as u64 * 4, 4, *word)
+            .unwrap();
+    }
+    emu.write_register(S0, DATA_ADDRESS);
+    emu.dmi_write(DM_DMCONTROL, DMCONTROL_DMACTIVE | DMCONTROL_HALTREQ);
+    emu.dmi_write(DM_PROGBUF0, 0x00042483); // lw s1,0(s0)
+    emu.dmi_write(DM_PROGBUF0 + 1, 0x00440413); // addi s0,s0,4
+    emu.dmi_write(DM_PROGBUF0 + 2, 0x00100073); // ebreak
+
+    // loads the first word, and then reads it while loading the next one.
+    emu.dmi_write(DM_COMMAND, COMMAND_SIZE_32 | COMMAND_POSTEXEC);
+    emu.dmi_write(
+        DM_COMMAND,
+        COMMAND_SIZE_32 | COMMAND_POSTEXEC | COMMAND_TRANSFER | (REGNO_GPR + S1 as u32),
+    );
+    assert_eq!(0, cmderr(&mut emu));
+
+    // each read returns the previous result, and runs the command again.
+    emu.dmi_write(DM_ABSTRACTAUTO, 1);
+    assert_eq!(0x1111_1111, emu.dmi_read(DM_DATA0));
+    assert_eq!(0x2222_2222, emu.dmi_read(DM_DATA0));
+    emu.dmi_write(DM_ABSTRACTAUTO, 0);
+    assert_eq!(0x3333_3333, emu.dmi_read(DM_DATA0));
+    assert_eq!(0, cmderr(&mut emu));
+}
+
+#[test]
+fn progbuf_illegal_instruction() {
+    let mut emu = emulator(words(&[0x0000006f])); // j .
+    emu.dmi_write(DM_DMCONTROL, DMCONTROL_DMACTIVE | DMCONTROL_HALTREQ);
+
+    // an unknown opcode, and an unknown funct3 of LOAD.
+    for word in [0x0000007b, 0x00007003].iter() {
+        emu.dmi_write(DM_PROGBUF0, *word);
+        emu.dmi_write(DM_PROGBUF0 + 1, 0x00100073); // ebreak
+        emu.dmi_write(DM_COMMAND, COMMAND_SIZE_32 | COMMAND_POSTEXEC);
+        assert_eq!(3, cmderr(&mut emu), "{:08x}", word);
+        assert!(emu.is_debug_mode());
+        assert_eq!(DRAM_BASE, emu.get_pc());
+
+        // cmderr is cleared by writing 1s.
+        emu.dmi_write(DM_ABSTRACTCS, 0x7 << 8);
+        assert_eq!(0, cmderr(&mut emu));
+    }
+}
+
+#[test]
+fn unknown_opcode_raises_illegal_instruction() {
+    let mut emu = emulator(words(&[0x0000007b]));
+    let trap = emu.step().exception.unwrap();
+    assert_eq!(Exception::IllegalInstruction, trap.exception);
+    assert_eq!(DRAM_BASE, trap.value);
+}
@@ -0,0 +1,542 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{emulator, parcels, words, DATA_ADDRESS, DRAM_BASE};
+use riscv_emu::cpu::cpu::Privilege;
+use riscv_emu::cpu::cpu_csr::{
+    CSR_ENVCFG_PBMTE, CSR_FFLAGS, CSR_MENVCFG, CSR_MISA, CSR_MISA_E, CSR_MISA_I, CSR_MSTATUS,
+    CSR_SPTBR, CSR_STATUS_FS, CSR_STATUS_TW, CSR_STATUS_UXL,
+};
+use riscv_emu::cpu::mmu::{MemoryAccessType, Watchpoint};
+use riscv_emu::cpu::softfloat::{FLAG_NV, FLAG_NX, FLAG_OF};
+use riscv_emu::cpu::trap::Exception;
+use riscv_emu::emulator::{Emulator, StopConditions, StopReason};
+
+const A0: usize = 10;
+const A1: usize = 11;
+const A2: usize = 12;
+const A3: usize = 13;
+const RA: usize = 1;
+const SP: usize = 2;
+const S0: usize = 8;
+const S1: usize = 9;
+
+//***********************************************************************
+// Integer Conditional Operations (Zicond)
+//***********************************************************************
+#[test]
+fn zicond_czero() {
+    let program = [
+        0x0ec5d533, // czero.eqz a0,a1,a2
+        0x0ec5f6b3, // czero.nez a3,a1,a2
+    ];
+    for (condition, eqz, nez) in [(0, 0, 0x1234), (5, 0x1234, 0)] {
+        let mut emu = emulator(words(&program));
+        emu.write_register(A1, 0x1234);
+        emu.write_register(A2, condition);
+        emu.write_register(A0, 0xdead);
+        emu.write_register(A3, 0xdead);
+        emu.run_steps(2);
+        assert_eq!(eqz, emu.read_register(A0));
+        assert_eq!(nez, emu.read_register(A3));
+    }
+}
+
+//***********************************************************************
+// Atomic Compare-and-Swap (Zacas)
+//***********************************************************************
+fn amocas(word: u32, memory: u64, expected: u64, size: u8) -> (u64, u64) {
+    let mut emu = emulator(words(&[word]));
+    emu.write_physical_memory(DATA_ADDRESS, 8, memory).unwrap();
+    emu.write_register(A0, DATA_ADDRESS);
+    emu.write_register(A1, expected);
+    emu.write_register(A2, 0x5555_6666_7777_8888);
+    emu.step();
+    (
+        emu.read_register(A1),
+        emu.read_physical_memory(DATA_ADDRESS, size).unwrap(),
+    )
+}
+
+#[test]
+fn zacas_amocas_w() {
+    let word = 0x28c525af; // amocas.w a1,a2,(a0)
+
+    // the loaded word is sign-extended.
+    let (loaded, memory) = amocas(word, 0x8000_0001, 0xffff_ffff_8000_0001, 4);
+    assert_eq!(0xffff_ffff_8000_0001, loaded);
+    assert_eq!(0x7777_8888, memory);
+
+    let (loaded, memory) = amocas(word, 0x8000_0001, 0x8000_0002, 4);
+    assert_eq!(0xffff_ffff_8000_0001, loaded);
+    assert_eq!(0x8000_0001, memory);
+}
+
+#[test]
+fn zacas_amocas_d() {
+    let word = 0x28c535af; // amocas.d a1,a2,(a0)
+    let (loaded, memory) = amocas(word, 0x1111_2222_3333_4444, 0x1111_2222_3333_4444, 8);
+    assert_eq!(0x1111_2222_3333_4444, loaded);
+    assert_eq!(0x5555_6666_7777_8888, memory);
+
+    let (loaded, memory) = amocas(word, 0x1111_2222_3333_4444, 0x1111_2222_3333_4445, 8);
+    assert_eq!(0x1111_2222_3333_4444, loaded);
+    assert_eq!(0x1111_2222_3333_4444, memory);
+}
+
+//***********************************************************************
+// Wait-on-Reservation-Set (Zawrs)
+//***********************************************************************
+#[test]
+fn zawrs_wrs_nto_timeout_wait() {
+    let program = [
+        0x00d00073, // wrs.nto
+    ];
+    for (privilege, tw, illegal) in [
+        (Privilege::Machine, true, false),
+        (Privilege::Supervisor, false, false),
+        (Privilege::Supervisor, true, true),
+        (Privilege::User, true, true),
+    ] {
+        let mut emu = emulator(words(&program));
+        if tw {
+            emu.write_csr(CSR_MSTATUS, CSR_STATUS_TW);
+        }
+        emu.set_privilege(privilege);
+        let result = emu.step();
+        assert_eq!(
+            illegal,
+            result.exception.map(|trap| trap.exception) == Some(Exception::IllegalInstruction)
+        );
+    }
+}
+
+//***********************************************************************
+// Cache-Block Zero Instructions (Zicboz)
+//***********************************************************************
+#[test]
+fn zicboz_cbo_zero_store_path() {
+    let program = [
+        0x0045200f, // cbo.zero (a0)
+    ];
+    let block_size = 64;
+    let mut emu = emulator(words(&program));
+    emu.set_cache_block_size(block_size);
+    for offset in (0..block_size * 2).step_by(8) {
+        emu.write_physical_memory(DATA_ADDRESS + offset, 8, !0)
+            .unwrap();
+    }
+    emu.write_register(A0, DATA_ADDRESS + 0x18);
+
+    // the zeros are stored as the 8-byte stores, which the watchpoints see.
+    let reason = emu.run_until(&StopConditions {
+        watchpoints: vec![Watchpoint {
+            address: DATA_ADDRESS + 0x30,
+            size: 8,
+            read: false,
+            write: true,
+        }],
+        instruction_limit: Some(1),
+        ..StopConditions::default()
+    });
+    match reason {
+        StopReason::Watchpoint { address, write } => {
+            assert_eq!(DATA_ADDRESS + 0x30, address);
+            assert!(write);
+        }
+        reason => panic!("{:?}", reason),
+    }
+    for offset in (0..block_size).step_by(8) {
+        assert_eq!(
+            0,
+            emu.read_physical_memory(DATA_ADDRESS + offset, 8).unwrap()
+        );
+    }
+    assert_eq!(
+        !0,
+        emu.read_physical_memory(DATA_ADDRESS + block_size, 8)
+            .unwrap()
+    );
+}
+
+#[test]
+fn zicboz_cbo_zero_access_fault() {
+    let program = [
+        0x0045200f, // cbo.zero (a0)
+    ];
+    let mut emu = emulator(words(&program));
+    emu.write_register(A0, 0x2000_0008);
+    let trap = emu.step().exception.unwrap();
+    assert_eq!(Exception::StoreAccessFault, trap.exception);
+    assert_eq!(0x2000_0008, trap.value);
+}
+
+//***********************************************************************
+// NAPOT Translation Contiguity and Page-Based Memory Types (Svnapot/Svpbmt)
+//***********************************************************************
+const PTE_V: u64 = 0x01;
+const PTE_RWXAD: u64 = 0xce;
+const PTE_PBMT_NC: u64 = 1 << 61;
+const PTE_N: u64 = 1 << 63;
+
+/// Returns the S-mode emulator with the Sv39 tables mapping the 4 KiB page of
+/// VPN[0]=0x13 under 0x4000_0000 by `leaf`.
+fn sv39_emulator(leaf: u64) -> Emulator {
+    let root = DRAM_BASE + 0x10000;
+    let level1 = DRAM_BASE + 0x11000;
+    let level0 = DRAM_BASE + 0x12000;
+    let mut emu = emulator(vec![]);
+    emu.write_physical_memory(root + 8, 8, ((level1 >> 12) << 10) | PTE_V)
+        .unwrap();
+    emu.write_physical_memory(level1, 8, ((level0 >> 12) << 10) | PTE_V)
+        .unwrap();
+    emu.write_physical_memory(level0 + 0x13 * 8, 8, leaf)
+        .unwrap();
+    emu.write_csr(CSR_SPTBR, (8 << 60) | (root >> 12));
+    emu.set_privilege(Privilege::Supervisor);
+    emu
+}
+
+#[test]
+fn svnapot_64k_page() {
+    // the NAPOT PTE of 64 KiB at 0x8010_0000, encoded as PPN[3:0]=8 and N=1.
+    let leaf = PTE_N | (0x80108 << 10) | PTE_RWXAD | PTE_V;
+    let mut emu = sv39_emulator(leaf);
+    let p_addr = emu.translate_address(0x4001_3abc, MemoryAccessType::Read);
+    assert_eq!(0x8010_3abc, p_addr.unwrap());
+
+    // the other encodings of PPN[3:0] are reserved.
+    let mut emu = sv39_emulator(PTE_N | (0x80104 << 10) | PTE_RWXAD | PTE_V);
+    let trap = emu
+        .translate_address(0x4001_3abc, MemoryAccessType::Read)
+        .unwrap_err();
+    assert_eq!(Exception::LoadPageFault, trap.exception);
+    assert_eq!(0x4001_3abc, trap.value);
+}
+
+#[test]
+fn svpbmt_requires_menvcfg_pbmte() {
+    let leaf = PTE_N | PTE_PBMT_NC | (0x80108 << 10) | PTE_RWXAD | PTE_V;
+    let mut emu = sv39_emulator(leaf);
+    let trap = emu
+        .translate_address(0x4001_3abc, MemoryAccessType::Write)
+        .unwrap_err();
+    assert_eq!(Exception::StorePageFault, trap.exception);
+    assert_eq!(0x4001_3abc, trap.value);
+
+    emu.write_csr(CSR_MENVCFG, CSR_ENVCFG_PBMTE);
+    let p_addr = emu.translate_address(0x4001_3abc, MemoryAccessType::Write);
+    assert_eq!(0x8010_3abc, p_addr.unwrap());
+}
+
+//***********************************************************************
+// Compressed Instructions (Zcb)
+//***********************************************************************
+#[test]
+fn zcb_arithmetic() {
+    for (parcel, a0, a1, expected) in [
+        (0x9d4d, 7, -6i64 as u64, -42i64 as u64), // c.mul a0,a1
+        (0x9d75, 0x1234, 0, !0x1234),             // c.not a0
+        (0x9d71, 0xffff_ffff_8000_0001, 0, 0x8000_0001), // c.zext.w a0
+        (0x9d65, 0x1234_5680, 0, 0xffff_ffff_ffff_ff80), // c.sext.b a0
+    ] {
+        let mut emu = emulator(parcels(&[parcel]));
+        emu.write_register(A0, a0);
+        emu.write_register(A1, a1);
+        emu.step();
+        assert_eq!(expected, emu.read_register(A0));
+        assert_eq!(DRAM_BASE + 2, emu.get_pc());
+    }
+}
+
+//***********************************************************************
+// Push/Pop Instructions (Zcmp)
+//***********************************************************************
+#[test]
+fn zcmp_push_popret() {
+    let program = [
+        0xb862, // cm.push {ra,s0-s1},-32
+        0xbe62, // cm.popret {ra,s0-s1},32
+    ];
+    let sp = DATA_ADDRESS + 0x100;
+    let ra = DRAM_BASE + 0x40;
+    let mut emu = emulator(parcels(&program));
+    emu.write_register(SP, sp);
+    emu.write_register(RA, ra);
+    emu.write_register(S0, 0x1111);
+    emu.write_register(S1, 0x2222);
+
+    // the registers are stored below sp from s1 down to ra.
+    emu.step();
+    assert_eq!(sp - 32, emu.read_register(SP));
+    assert_eq!(0x2222, emu.read_physical_memory(sp - 8, 8).unwrap());
+    assert_eq!(0x1111, emu.read_physical_memory(sp - 16, 8).unwrap());
+    assert_eq!(ra, emu.read_physical_memory(sp - 24, 8).unwrap());
+
+    emu.write_register(RA, 0);
+    emu.write_register(S0, 0);
+    emu.write_register(S1, 0);
+    emu.step();
+    assert_eq!(sp, emu.read_register(SP));
+    assert_eq!(ra, emu.read_register(RA));
+    assert_eq!(0x1111, emu.read_register(S0));
+    assert_eq!(0x2222, emu.read_register(S1));
+    assert_eq!(ra, emu.get_pc());
+}
+
+//***********************************************************************
+// Embedded Base ISA (RV32E/RV64E)
+//***********************************************************************
+#[test]
+fn rve_reserved_registers() {
+    let illegal = |program: Vec<u8>| {
+        let mut emu = emulator(program);
+        emu.set_rve(true);
+        emu.write_register(A0, 5);
+        let result = emu.step();
+        result.exception.map(|trap| trap.exception) == Some(Exception::IllegalInstruction)
+    };
+    assert!(illegal(words(&[0x00a50833]))); // add x16,a0,a0
+    assert!(illegal(words(&[0x00080513]))); // addi a0,x16,0
+    assert!(illegal(parcels(&[0x882a]))); // c.mv x16,a0
+    assert!(illegal(parcels(&[0x0805]))); // c.addi x16,1
+    assert!(!illegal(words(&[0x00a507b3]))); // add a5,a0,a0
+    assert!(!illegal(parcels(&[0x87aa]))); // c.mv a5,a0
+
+    let mut emu = emulator(parcels(&[0x87aa])); // c.mv a5,a0
+    emu.set_rve(true);
+    emu.write_register(A0, 5);
+    emu.step();
+    assert_eq!(5, emu.read_register(15));
+    let misa = emu.read_csr(CSR_MISA);
+    assert_eq!((CSR_MISA_E, 0), (misa & CSR_MISA_E, misa & CSR_MISA_I));
+}
+
+//***********************************************************************
+// RV32 User Mode (mstatus.UXL)
+//***********************************************************************
+/// Returns the emulator in U-mode with UXL=1 (32-bit).
+fn uxl32_emulator(program: Vec<u8>) -> Emulator {
+    let mut emu = emulator(program);
+    let mstatus = emu.read_csr(CSR_MSTATUS);
+    emu.write_csr(CSR_MSTATUS, (mstatus & !CSR_STATUS_UXL) | 1 << 32);
+    emu.set_privilege(Privilege::User);
+    emu
+}
+
+#[test]
+fn uxl32_rv64_only_instructions() {
+    let illegal = |program: Vec<u8>| {
+        let mut emu = uxl32_emulator(program);
+        emu.write_register(S0, DATA_ADDRESS);
+        let result = emu.step();
+        result.exception.map(|trap| trap.exception) == Some(Exception::IllegalInstruction)
+    };
+    assert!(illegal(words(&[0x0015051b]))); // addiw a0,a0,1
+    assert!(illegal(words(&[0x00b5053b]))); // addw a0,a0,a1
+    assert!(illegal(words(&[0x00043503]))); // ld a0,0(s0)
+    assert!(illegal(words(&[0x00046503]))); // lwu a0,0(s0)
+    assert!(illegal(words(&[0x00a43023]))); // sd a0,0(s0)
+    assert!(illegal(parcels(&[0x9d2d]))); // c.addw a0,a1
+    assert!(illegal(parcels(&[0x9d0d]))); // c.subw a0,a1
+    assert!(!illegal(words(&[0x00150513]))); // addi a0,a0,1
+    assert!(!illegal(words(&[0x00042503]))); // lw a0,0(s0)
+
+    // c.addiw is c.jal in RV32C.
+    let mut emu = uxl32_emulator(parcels(&[0x2505])); // c.addiw a0,1
+    emu.step();
+    assert_eq!(DRAM_BASE + 2, emu.read_register(RA));
+}
+
+//***********************************************************************
+// Half-Precision Floating-Point (Zfh)
+//***********************************************************************
+/// Returns the emulator with the floating-point unit enabled (mstatus.FS=Dirty).
+fn fp_emulator(program: &[u32]) -> Emulator {
+    let mut emu = emulator(words(program));
+    emu.write_csr(CSR_MSTATUS, CSR_STATUS_FS);
+    emu
+}
+
+/// Returns a half-precision value NaN-boxed in an f register.
+fn boxed_half(bits: u64) -> u64 {
+    0xffff_ffff_ffff_0000 | bits
+}
+
+#[test]
+fn zfh_arithmetic() {
+    let program = [
+        0xf40580d3, // fmv.h.x f1,a1
+        0xf4060153, // fmv.h.x f2,a2
+        0x0420f1d3, // fadd.h f3,f1,f2
+        0x1420f253, // fmul.h f4,f1,f2
+        0x1c1172d3, // fdiv.h f5,f2,f1
+        0x5c017353, // fsqrt.h f6,f2
+        0x0c20f3c3, // fmadd.h f7,f1,f2,f1
+    ];
+    let mut emu = fp_emulator(&program);
+    emu.write_register(A1, 0x3e00); // 1.5
+    emu.write_register(A2, 0x4080); // 2.25
+    emu.run_steps(program.len() as u32);
+    assert_eq!(boxed_half(0x4380), emu.read_fp_register(3)); // 3.75
+    assert_eq!(boxed_half(0x42c0), emu.read_fp_register(4)); // 3.375
+    assert_eq!(boxed_half(0x3e00), emu.read_fp_register(5)); // 1.5
+    assert_eq!(boxed_half(0x3e00), emu.read_fp_register(6)); // 1.5
+    assert_eq!(boxed_half(0x44e0), emu.read_fp_register(7)); // 4.875
+    assert_eq!(0, emu.read_csr(CSR_FFLAGS));
+}
+
+#[test]
+fn zfh_rounding_and_flags() {
+    // 1.0 + 2^-11 is halfway between 1.0 and the next half-precision value.
+    for (word, sum, flags) in [
+        (0x0420f1d3, 0x3c00, FLAG_NX), // fadd.h f3,f1,f2
+        (0x0420b1d3, 0x3c01, FLAG_NX), // fadd.h f3,f1,f2,rup
+    ] {
+        let mut emu = fp_emulator(&[word]);
+        emu.write_fp_register(1, boxed_half(0x3c00));
+        emu.write_fp_register(2, boxed_half(0x1000));
+        emu.step();
+        assert_eq!(boxed_half(sum), emu.read_fp_register(3));
+        assert_eq!(flags, emu.read_csr(CSR_FFLAGS));
+    }
+
+    // 256 * 256 overflows to infinity.
+    let mut emu = fp_emulator(&[0x1420f253]); // fmul.h f4,f1,f2
+    emu.write_fp_register(1, boxed_half(0x5c00));
+    emu.write_fp_register(2, boxed_half(0x5c00));
+    emu.step();
+    assert_eq!(boxed_half(0x7c00), emu.read_fp_register(4));
+    assert_eq!(FLAG_OF | FLAG_NX, emu.read_csr(CSR_FFLAGS));
+}
+
+#[test]
+fn zfh_conversions() {
+    let program = [
+        0x4020f153, // fcvt.s.h f2,f1
+        0x441271d3, // fcvt.h.d f3,f4
+        0xc4009553, // fcvt.w.h a0,f1,rtz
+        0xd405f2d3, // fcvt.h.w f5,a1
+    ];
+    let mut emu = fp_emulator(&program);
+    emu.write_fp_register(1, boxed_half(0xbe00)); // -1.5
+    emu.write_fp_register(4, 65520f64.to_bits());
+    emu.write_register(A1, -3i64 as u64);
+    emu.run_steps(program.len() as u32);
+    assert_eq!(0xffff_ffff_bfc0_0000, emu.read_fp_register(2));
+    // 65520 rounds to even, which is 2^16 and overflows.
+    assert_eq!(boxed_half(0x7c00), emu.read_fp_register(3));
+    assert_eq!(-1i64 as u64, emu.read_register(A0));
+    assert_eq!(boxed_half(0xc200), emu.read_fp_register(5));
+    assert_eq!(FLAG_OF | FLAG_NX, emu.read_csr(CSR_FFLAGS));
+}
+
+#[test]
+fn zfh_nan_boxing_and_fs() {
+    // a single-precision value is not a NaN-boxed half, and is read as the canonical NaN.
+    let mut emu = fp_emulator(&[0x04217353]); // fadd.h f6,f2,f2
+    emu.write_fp_register(2, 0xffff_ffff_3f80_0000);
+    emu.step();
+    assert_eq!(boxed_half(0x7e00), emu.read_fp_register(6));
+
+    // the floating-point instructions are illegal while mstatus.FS is Off.
+    let mut emu = emulator(words(&[0x04217353]));
+    let result = emu.step();
+    assert_eq!(
+        Some(Exception::IllegalInstruction),
+        result.exception.map(|trap| trap.exception)
+    );
+}
+
+//***********************************************************************
+// Additional Floating-Point Instructions (Zfa)
+//***********************************************************************
+#[test]
+fn zfa_fli() {
+    // fli.fmt f1,index
+    let fli = |funct7: u32, index: u32| funct7 << 25 | 1 << 20 | index << 15 | 1 << 7 | 0x53;
+    for (word, expected) in [
+        (fli(0x79, 0), 0xbff0_0000_0000_0000),  // fli.d f1,-1.0
+        (fli(0x79, 21), 0x4004_0000_0000_0000), // fli.d f1,2.5
+        (fli(0x78, 1), 0xffff_ffff_0080_0000),  // fli.s f1,min
+        (fli(0x78, 31), 0xffff_ffff_7fc0_0000), // fli.s f1,nan
+        (fli(0x7a, 1), boxed_half(0x0400)),     // fli.h f1,min
+        (fli(0x7a, 2), boxed_half(0x0100)),     // fli.h f1,2^-16
+        (fli(0x7a, 29), boxed_half(0x7c00)),    // fli.h f1,2^16
+    ] {
+        let mut emu = fp_emulator(&[word]);
+        emu.step();
+        assert_eq!(expected, emu.read_fp_register(1));
+    }
+}
+
+#[test]
+fn zfa_fminm_fmaxm() {
+    let program = [
+        0x2a20a1d3, // fminm.d f3,f1,f2
+        0x2a208253, // fmin.d f4,f1,f2
+    ];
+    let mut emu = fp_emulator(&program);
+    emu.write_fp_register(1, 1f64.to_bits());
+    emu.write_fp_register(2, f64::NAN.to_bits());
+    emu.run_steps(program.len() as u32);
+    assert_eq!(0x7ff8_0000_0000_0000, emu.read_fp_register(3));
+    assert_eq!(1f64.to_bits(), emu.read_fp_register(4));
+
+    // -0 is less than +0.
+    let mut emu = fp_emulator(&[0x2820b2d3]); // fmaxm.s f5,f1,f2
+    emu.write_fp_register(1, 0xffff_ffff_8000_0000);
+    emu.write_fp_register(2, 0xffff_ffff_0000_0000);
+    emu.step();
+    assert_eq!(0xffff_ffff_0000_0000, emu.read_fp_register(5));
+}
+
+#[test]
+fn zfa_fround() {
+    for (word, rounded, flags) in [
+        (0x4240f153, 2.0, 0),       // fround.d f2,f1
+        (0x4250f153, 2.0, FLAG_NX), // froundnx.d f2,f1
+        (0x4240b153, 3.0, 0),       // fround.d f2,f1,rup
+    ] {
+        let mut emu = fp_emulator(&[word]);
+        emu.write_fp_register(1, 2.5f64.to_bits());
+        emu.step();
+        assert_eq!(f64::to_bits(rounded), emu.read_fp_register(2));
+        assert_eq!(flags, emu.read_csr(CSR_FFLAGS));
+    }
+}
+
+#[test]
+fn zfa_fcvtmod_w_d() {
+    for (value, expected, flags) in [
+        (-1.5, -1i64, FLAG_NX),
+        (4294967301.75, 5, FLAG_NV),
+        (3e9, -1294967296, FLAG_NV),
+        (f64::NAN, 0, FLAG_NV),
+        (f64::INFINITY, 0, FLAG_NV),
+    ] {
+        let mut emu = fp_emulator(&[0xc2809553]); // fcvtmod.w.d a0,f1,rtz
+        emu.write_fp_register(1, f64::to_bits(value));
+        emu.step();
+        assert_eq!(expected as u64, emu.read_register(A0));
+        assert_eq!(flags, emu.read_csr(CSR_FFLAGS));
+    }
+}
+
+#[test]
+fn zfa_quiet_comparisons() {
+    for (word, flags) in [
+        (0xa220d553, 0),       // fltq.d a0,f1,f2
+        (0xa220c553, 0),       // fleq.d a0,f1,f2
+        (0xa2209553, FLAG_NV), // flt.d a0,f1,f2
+    ] {
+        let mut emu = fp_emulator(&[word]);
+        emu.write_fp_register(1, 1f64.to_bits());
+        emu.write_fp_register(2, f64::NAN.to_bits());
+        emu.write_register(A0, 0xdead);
+        emu.step();
+        assert_eq!(0, emu.read_register(A0));
+        assert_eq!(flags, emu.read_csr(CSR_FFLAGS));
+    }
+}
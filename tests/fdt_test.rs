@@ -0,0 +1,147 @@
+extern crate riscv_emu;
+
+mod common;
+
+use std::fs;
+
+use common::emulator;
+use riscv_emu::bus::bus::Device;
+use riscv_emu::console::TtyDummy;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::fdt::{Fdt, FdtNode};
+use riscv_emu::machine::Machine;
+
+const DTB_ADDRESS: u64 = 0x1020;
+
+fn bundled_dtb() -> Vec<u8> {
+    fs::read("artifacts/linux/dtb/qemu_virtio.dtb").unwrap()
+}
+
+fn find<'a>(node: &'a FdtNode, path: &[&str]) -> &'a FdtNode {
+    match path.split_first() {
+        Some((name, rest)) => find(
+            node.children.iter().find(|n| n.name == *name).unwrap(),
+            rest,
+        ),
+        None => node,
+    }
+}
+
+/// Returns the nodes and the properties as the lines of `path name=value`.
+fn flatten(node: &FdtNode, path: &str, lines: &mut Vec<String>) {
+    let path = format!("{}/{}", path, node.name);
+    for (name, value) in node.properties.iter() {
+        lines.push(format!("{} {}={:02x?}", path, name, value));
+    }
+    for child in node.children.iter() {
+        flatten(child, &path, lines);
+    }
+}
+
+/// Reads the DTB which the emulator passes to the guest.
+fn read_dtb(emu: &mut Emulator, size: usize) -> Vec<u8> {
+    (0..size as u64)
+        .map(|i| emu.read_physical_memory(DTB_ADDRESS + i, 1).unwrap() as u8)
+        .collect()
+}
+
+fn be_u32(value: &[u8]) -> u32 {
+    u32::from_be_bytes([value[0], value[1], value[2], value[3]])
+}
+
+#[test]
+fn round_trip_bundled_dtb() {
+    let data = bundled_dtb();
+    let fdt = Fdt::parse(&data).unwrap();
+    let bytes = fdt.to_bytes();
+    let reparsed = Fdt::parse(&bytes).unwrap();
+
+    let mut expected = vec![];
+    let mut actual = vec![];
+    flatten(&fdt.root, "", &mut expected);
+    flatten(&reparsed.root, "", &mut actual);
+    assert!(!expected.is_empty());
+    assert_eq!(expected, actual);
+
+    // the serialized tree is stable.
+    assert_eq!(bytes, reparsed.to_bytes());
+    assert_eq!(
+        Some("riscv-virtio".to_string()),
+        reparsed.root.get_property_str("compatible")
+    );
+}
+
+#[test]
+fn parse_rejects_bad_magic() {
+    let mut data = bundled_dtb();
+    data[0] ^= 0xff;
+    assert!(Fdt::parse(&data).is_none());
+    assert!(Fdt::parse(&[]).is_none());
+}
+
+#[test]
+fn update_dtb_adds_cache_block_sizes() {
+    let mut emu = emulator(vec![]);
+    emu.set_cache_block_size(128);
+    emu.set_data_from_binary(Device::DTB, bundled_dtb());
+
+    let header = read_dtb(&mut emu, 8);
+    let totalsize = be_u32(&header[4..8]) as usize;
+    let fdt = Fdt::parse(&read_dtb(&mut emu, totalsize)).unwrap();
+    let cpu = find(&fdt.root, &["cpus", "cpu@0"]);
+    for name in [
+        "riscv,cbom-block-size",
+        "riscv,cbop-block-size",
+        "riscv,cboz-block-size",
+    ]
+    .iter()
+    {
+        assert_eq!(128, be_u32(cpu.get_property(name).unwrap()), "{}", name);
+    }
+    // the multi-letter extensions of the hart are advertised.
+    let isa = cpu.get_property_str("riscv,isa").unwrap();
+    let hart_isa = emu.get_isa_string();
+    assert!(hart_isa.split('_').count() > 20, "{}", hart_isa);
+    for extension in hart_isa.split('_').skip(1) {
+        assert!(
+            isa.split('_').any(|e| e == extension),
+            "{} {}",
+            isa,
+            extension
+        );
+    }
+
+    // the rest of the tree is kept.
+    let mut original = vec![];
+    flatten(&Fdt::parse(&bundled_dtb()).unwrap().root, "", &mut original);
+    let mut updated = vec![];
+    flatten(&fdt.root, "", &mut updated);
+    for line in original.iter().filter(|line| !line.contains("riscv,isa")) {
+        assert!(updated.contains(line), "{}", line);
+    }
+}
+
+#[test]
+fn isa_string_follows_machine() {
+    let isa = emulator(vec![]).get_isa_string();
+    assert!(
+        isa.starts_with(
+            "rv64imafdc_zicbom_zicbop_zicboz_zicond_zihintpause_zacas_zawrs_zfa_zfh_zcb"
+        ),
+        "{}",
+        isa
+    );
+    assert!(isa.ends_with("_sdext_sdtrig_svnapot_svpbmt"), "{}", isa);
+
+    // the FE310 has neither the F and D extensions nor S-mode.
+    let mut emu = Emulator::new(Machine::SiFiveE, Box::new(TtyDummy::new()), false);
+    let isa = emu.get_isa_string();
+    assert!(isa[4..].starts_with("imac_zicbom"), "{}", isa);
+    for extension in ["zfa", "zfh", "svnapot", "svpbmt"].iter() {
+        assert!(!isa.split('_').any(|e| e == *extension), "{}", isa);
+    }
+    assert!(isa.contains("_zacas_zawrs_zcb_zcmp_"), "{}", isa);
+
+    emu.set_rve(true);
+    assert!(emu.get_isa_string()[4..].starts_with("emac_"));
+}
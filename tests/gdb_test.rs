@@ -0,0 +1,174 @@
+extern crate riscv_emu;
+
+mod common;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use common::{emulator, words, DRAM_BASE};
+use riscv_emu::debug::gdb_stub::{GdbConnection, GdbStub};
+use riscv_emu::emulator::Emulator;
+
+/// Connection which sends the scripted packets, and keeps what the stub writes.
+struct ScriptedConnection {
+    input: VecDeque<u8>,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl GdbConnection for ScriptedConnection {
+    fn read(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        self.output.borrow_mut().extend_from_slice(data);
+    }
+}
+
+fn packet(data: &str) -> String {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    format!("${}#{:02x}", data, checksum)
+}
+
+/// Serves the packets until the script ends, and returns the replies.
+fn serve(program: &[u32], packets: &[&str]) -> Vec<String> {
+    serve_emulator(emulator(words(program)), packets)
+}
+
+fn serve_emulator(mut emu: Emulator, packets: &[&str]) -> Vec<String> {
+    let output = Rc::new(RefCell::new(vec![]));
+    let input: String = packets.iter().map(|data| packet(data)).collect();
+    let mut stub = GdbStub::new(Box::new(ScriptedConnection {
+        input: input.into_bytes().into_iter().collect(),
+        output: output.clone(),
+    }));
+    assert!(stub.serve(&mut emu));
+
+    // each packet is acknowledged, and the reply follows.
+    let output = String::from_utf8(output.borrow().clone()).unwrap();
+    let replies: Vec<String> = output
+        .split("+$")
+        .skip(1)
+        .map(|reply| {
+            let end = reply.find('#').unwrap();
+            assert_eq!(packet(&reply[..end]), format!("${}", reply));
+            String::from(&reply[..end])
+        })
+        .collect();
+    assert_eq!(packets.len(), replies.len());
+    replies
+}
+
+/// Returns the hex of a 64-bit register in the target byte order.
+fn register(value: u64) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+const PROGRAM: [u32; 4] = [
+    0x00150513, // addi a0,a0,1
+    0x00150513, // addi a0,a0,1
+    0x00150513, // addi a0,a0,1
+    0x0000006f, // j .
+];
+
+#[test]
+fn registers_breakpoints_and_step() {
+    let breakpoint = DRAM_BASE + 0xc;
+    let insert = format!("Z0,{:x},4", breakpoint);
+    let remove = format!("z0,{:x},4", breakpoint);
+    let replies = serve(
+        &PROGRAM,
+        &[
+            "qSupported:multiprocess+;swbreak+",
+            "g",
+            "Pa=0500000000000000",
+            "pa",
+            "s",
+            "p20",
+            &insert,
+            "c",
+            "p20",
+            "pa",
+            &remove,
+        ],
+    );
+    assert_eq!(
+        vec![
+            String::from("PacketSize=1000;qXfer:features:read+;swbreak+;hwbreak+"),
+            // a1 holds the DTB address, and pc is the start of the program.
+            "0".repeat(16 * 11) + &register(0x1020) + &"0".repeat(16 * 20) + &register(DRAM_BASE),
+            String::from("OK"),
+            register(5),
+            String::from("T05"),
+            register(DRAM_BASE + 4),
+            String::from("OK"),
+            String::from("T05swbreak:;"),
+            register(breakpoint),
+            register(8),
+            String::from("OK"),
+        ],
+        replies
+    );
+}
+
+#[test]
+fn read_memory() {
+    let read = format!("m{:x},6", DRAM_BASE + 2);
+    let replies = serve(&PROGRAM, &[&read, "m0,4x"]);
+    assert_eq!(vec!["150013051500", "E01"], replies[..]);
+}
+
+#[test]
+fn read_memory_reply_fits_in_packet() {
+    let read = format!("m{:x},100000", DRAM_BASE);
+    let replies = serve(&PROGRAM, &[&read]);
+    // PacketSize=0x1000 minus '$', '#' and the checksum.
+    assert_eq!(0x1000 - 4, replies[0].len());
+    assert!(replies[0].starts_with("13051500"));
+}
+
+#[test]
+fn reverse_step_and_continue() {
+    let breakpoint = DRAM_BASE + 4;
+    let insert = format!("Z0,{:x},4", breakpoint);
+    let mut emu = emulator(words(&PROGRAM));
+    emu.enable_reverse_execution(1 << 20, 4);
+    let replies = serve_emulator(
+        emu,
+        &[
+            "s", "s", "s", "bs", "pa", &insert, "bc", "p20", "pa", "bc", "p20",
+        ],
+    );
+    assert_eq!(
+        vec![
+            String::from("T05"),
+            String::from("T05"),
+            String::from("T05"),
+            String::from("T05"),
+            register(2),
+            String::from("OK"),
+            String::from("T05swbreak:;"),
+            register(breakpoint),
+            register(1),
+            // no breakpoint is hit before, so the hart is at the start of the history.
+            String::from("T05replaylog:begin;"),
+            register(DRAM_BASE),
+        ],
+        replies
+    );
+}
+
+#[test]
+fn reverse_without_history() {
+    let replies = serve(&PROGRAM, &["bs", "bc"]);
+    assert_eq!(vec!["E01", "E01"], replies[..]);
+}
@@ -7,7 +7,7 @@ use std::path::Path;
 use std::path::PathBuf;
 
 use riscv_emu::console::TtyDummy;
-use riscv_emu::emulator::Emulator;
+use riscv_emu::emulator::{Emulator, RunError};
 use riscv_emu::machine::Machine;
 
 fn _read_file(filename: &Path) -> io::Result<Vec<u8>> {
@@ -35,8 +35,8 @@ fn instruction_test(filename: &'static str) -> u32 {
     let mut emu = Emulator::new(Machine::SiFiveU, tty, testmode);
     emu.load_program_from_file(root.as_path());
     let result = match emu.run() {
-        Ok(ret) => ret,
-        Err(ret) => ret,
+        Ok(ret) | Err(RunError::ToHost(ret)) => ret,
+        Err(RunError::Divergence(divergence)) => panic!("{}", divergence),
     };
     println!("instruction test result is {}", result);
     result
@@ -1510,6 +1510,470 @@ fn rv64uc_v_rvc() {
     assert_eq!(1, instruction_test("rv64uc-v-rvc"));
 }
 
+//***********************************************************************
+// rv32uf (RV32F user-level, Single-Precision Floating-Point), virtual memory is disabled
+//***********************************************************************
+#[test]
+fn rv32uf_p_fadd() {
+    assert_eq!(1, instruction_test("rv32uf-p-fadd"));
+}
+
+#[test]
+fn rv32uf_p_fclass() {
+    assert_eq!(1, instruction_test("rv32uf-p-fclass"));
+}
+
+#[test]
+fn rv32uf_p_fcmp() {
+    assert_eq!(1, instruction_test("rv32uf-p-fcmp"));
+}
+
+#[test]
+fn rv32uf_p_fcvt() {
+    assert_eq!(1, instruction_test("rv32uf-p-fcvt"));
+}
+
+#[test]
+fn rv32uf_p_fcvt_w() {
+    assert_eq!(1, instruction_test("rv32uf-p-fcvt_w"));
+}
+
+#[test]
+fn rv32uf_p_fdiv() {
+    assert_eq!(1, instruction_test("rv32uf-p-fdiv"));
+}
+
+#[test]
+fn rv32uf_p_fmadd() {
+    assert_eq!(1, instruction_test("rv32uf-p-fmadd"));
+}
+
+#[test]
+fn rv32uf_p_fmin() {
+    assert_eq!(1, instruction_test("rv32uf-p-fmin"));
+}
+
+#[test]
+fn rv32uf_p_ldst() {
+    assert_eq!(1, instruction_test("rv32uf-p-ldst"));
+}
+
+#[test]
+fn rv32uf_p_move() {
+    assert_eq!(1, instruction_test("rv32uf-p-move"));
+}
+
+#[test]
+fn rv32uf_p_recoding() {
+    assert_eq!(1, instruction_test("rv32uf-p-recoding"));
+}
+
+//***********************************************************************
+// rv32uf (RV32F user-level, Single-Precision Floating-Point), virtual memory is enabled
+//***********************************************************************
+#[test]
+fn rv32uf_v_fadd() {
+    assert_eq!(1, instruction_test("rv32uf-v-fadd"));
+}
+
+#[test]
+fn rv32uf_v_fclass() {
+    assert_eq!(1, instruction_test("rv32uf-v-fclass"));
+}
+
+#[test]
+fn rv32uf_v_fcmp() {
+    assert_eq!(1, instruction_test("rv32uf-v-fcmp"));
+}
+
+#[test]
+fn rv32uf_v_fcvt() {
+    assert_eq!(1, instruction_test("rv32uf-v-fcvt"));
+}
+
+#[test]
+fn rv32uf_v_fcvt_w() {
+    assert_eq!(1, instruction_test("rv32uf-v-fcvt_w"));
+}
+
+#[test]
+fn rv32uf_v_fdiv() {
+    assert_eq!(1, instruction_test("rv32uf-v-fdiv"));
+}
+
+#[test]
+fn rv32uf_v_fmadd() {
+    assert_eq!(1, instruction_test("rv32uf-v-fmadd"));
+}
+
+#[test]
+fn rv32uf_v_fmin() {
+    assert_eq!(1, instruction_test("rv32uf-v-fmin"));
+}
+
+#[test]
+fn rv32uf_v_ldst() {
+    assert_eq!(1, instruction_test("rv32uf-v-ldst"));
+}
+
+#[test]
+fn rv32uf_v_move() {
+    assert_eq!(1, instruction_test("rv32uf-v-move"));
+}
+
+#[test]
+fn rv32uf_v_recoding() {
+    assert_eq!(1, instruction_test("rv32uf-v-recoding"));
+}
+
+//***********************************************************************
+// rv32ud (RV32D user-level, Double-Precision Floating-Point), virtual memory is disabled
+//***********************************************************************
+#[test]
+fn rv32ud_p_fadd() {
+    assert_eq!(1, instruction_test("rv32ud-p-fadd"));
+}
+
+#[test]
+fn rv32ud_p_fclass() {
+    assert_eq!(1, instruction_test("rv32ud-p-fclass"));
+}
+
+#[test]
+fn rv32ud_p_fcmp() {
+    assert_eq!(1, instruction_test("rv32ud-p-fcmp"));
+}
+
+#[test]
+fn rv32ud_p_fcvt() {
+    assert_eq!(1, instruction_test("rv32ud-p-fcvt"));
+}
+
+#[test]
+fn rv32ud_p_fcvt_w() {
+    assert_eq!(1, instruction_test("rv32ud-p-fcvt_w"));
+}
+
+#[test]
+fn rv32ud_p_fdiv() {
+    assert_eq!(1, instruction_test("rv32ud-p-fdiv"));
+}
+
+#[test]
+fn rv32ud_p_fmadd() {
+    assert_eq!(1, instruction_test("rv32ud-p-fmadd"));
+}
+
+#[test]
+fn rv32ud_p_fmin() {
+    assert_eq!(1, instruction_test("rv32ud-p-fmin"));
+}
+
+#[test]
+fn rv32ud_p_ldst() {
+    assert_eq!(1, instruction_test("rv32ud-p-ldst"));
+}
+
+#[test]
+fn rv32ud_p_recoding() {
+    assert_eq!(1, instruction_test("rv32ud-p-recoding"));
+}
+
+//***********************************************************************
+// rv32ud (RV32D user-level, Double-Precision Floating-Point), virtual memory is enabled
+//***********************************************************************
+#[test]
+fn rv32ud_v_fadd() {
+    assert_eq!(1, instruction_test("rv32ud-v-fadd"));
+}
+
+#[test]
+fn rv32ud_v_fclass() {
+    assert_eq!(1, instruction_test("rv32ud-v-fclass"));
+}
+
+#[test]
+fn rv32ud_v_fcmp() {
+    assert_eq!(1, instruction_test("rv32ud-v-fcmp"));
+}
+
+#[test]
+fn rv32ud_v_fcvt() {
+    assert_eq!(1, instruction_test("rv32ud-v-fcvt"));
+}
+
+#[test]
+fn rv32ud_v_fcvt_w() {
+    assert_eq!(1, instruction_test("rv32ud-v-fcvt_w"));
+}
+
+#[test]
+fn rv32ud_v_fdiv() {
+    assert_eq!(1, instruction_test("rv32ud-v-fdiv"));
+}
+
+#[test]
+fn rv32ud_v_fmadd() {
+    assert_eq!(1, instruction_test("rv32ud-v-fmadd"));
+}
+
+#[test]
+fn rv32ud_v_fmin() {
+    assert_eq!(1, instruction_test("rv32ud-v-fmin"));
+}
+
+#[test]
+fn rv32ud_v_ldst() {
+    assert_eq!(1, instruction_test("rv32ud-v-ldst"));
+}
+
+#[test]
+fn rv32ud_v_recoding() {
+    assert_eq!(1, instruction_test("rv32ud-v-recoding"));
+}
+
+//***********************************************************************
+// rv64uf (RV64F user-level, Single-Precision Floating-Point), virtual memory is disabled
+//***********************************************************************
+#[test]
+fn rv64uf_p_fadd() {
+    assert_eq!(1, instruction_test("rv64uf-p-fadd"));
+}
+
+#[test]
+fn rv64uf_p_fclass() {
+    assert_eq!(1, instruction_test("rv64uf-p-fclass"));
+}
+
+#[test]
+fn rv64uf_p_fcmp() {
+    assert_eq!(1, instruction_test("rv64uf-p-fcmp"));
+}
+
+#[test]
+fn rv64uf_p_fcvt() {
+    assert_eq!(1, instruction_test("rv64uf-p-fcvt"));
+}
+
+#[test]
+fn rv64uf_p_fcvt_w() {
+    assert_eq!(1, instruction_test("rv64uf-p-fcvt_w"));
+}
+
+#[test]
+fn rv64uf_p_fdiv() {
+    assert_eq!(1, instruction_test("rv64uf-p-fdiv"));
+}
+
+#[test]
+fn rv64uf_p_fmadd() {
+    assert_eq!(1, instruction_test("rv64uf-p-fmadd"));
+}
+
+#[test]
+fn rv64uf_p_fmin() {
+    assert_eq!(1, instruction_test("rv64uf-p-fmin"));
+}
+
+#[test]
+fn rv64uf_p_ldst() {
+    assert_eq!(1, instruction_test("rv64uf-p-ldst"));
+}
+
+#[test]
+fn rv64uf_p_move() {
+    assert_eq!(1, instruction_test("rv64uf-p-move"));
+}
+
+#[test]
+fn rv64uf_p_recoding() {
+    assert_eq!(1, instruction_test("rv64uf-p-recoding"));
+}
+
+//***********************************************************************
+// rv64uf (RV64F user-level, Single-Precision Floating-Point), virtual memory is enabled
+//***********************************************************************
+#[test]
+fn rv64uf_v_fadd() {
+    assert_eq!(1, instruction_test("rv64uf-v-fadd"));
+}
+
+#[test]
+fn rv64uf_v_fclass() {
+    assert_eq!(1, instruction_test("rv64uf-v-fclass"));
+}
+
+#[test]
+fn rv64uf_v_fcmp() {
+    assert_eq!(1, instruction_test("rv64uf-v-fcmp"));
+}
+
+#[test]
+fn rv64uf_v_fcvt() {
+    assert_eq!(1, instruction_test("rv64uf-v-fcvt"));
+}
+
+#[test]
+fn rv64uf_v_fcvt_w() {
+    assert_eq!(1, instruction_test("rv64uf-v-fcvt_w"));
+}
+
+#[test]
+fn rv64uf_v_fdiv() {
+    assert_eq!(1, instruction_test("rv64uf-v-fdiv"));
+}
+
+#[test]
+fn rv64uf_v_fmadd() {
+    assert_eq!(1, instruction_test("rv64uf-v-fmadd"));
+}
+
+#[test]
+fn rv64uf_v_fmin() {
+    assert_eq!(1, instruction_test("rv64uf-v-fmin"));
+}
+
+#[test]
+fn rv64uf_v_ldst() {
+    assert_eq!(1, instruction_test("rv64uf-v-ldst"));
+}
+
+#[test]
+fn rv64uf_v_move() {
+    assert_eq!(1, instruction_test("rv64uf-v-move"));
+}
+
+#[test]
+fn rv64uf_v_recoding() {
+    assert_eq!(1, instruction_test("rv64uf-v-recoding"));
+}
+
+//***********************************************************************
+// rv64ud (RV64D user-level, Double-Precision Floating-Point), virtual memory is disabled
+//***********************************************************************
+#[test]
+fn rv64ud_p_fadd() {
+    assert_eq!(1, instruction_test("rv64ud-p-fadd"));
+}
+
+#[test]
+fn rv64ud_p_fclass() {
+    assert_eq!(1, instruction_test("rv64ud-p-fclass"));
+}
+
+#[test]
+fn rv64ud_p_fcmp() {
+    assert_eq!(1, instruction_test("rv64ud-p-fcmp"));
+}
+
+#[test]
+fn rv64ud_p_fcvt() {
+    assert_eq!(1, instruction_test("rv64ud-p-fcvt"));
+}
+
+#[test]
+fn rv64ud_p_fcvt_w() {
+    assert_eq!(1, instruction_test("rv64ud-p-fcvt_w"));
+}
+
+#[test]
+fn rv64ud_p_fdiv() {
+    assert_eq!(1, instruction_test("rv64ud-p-fdiv"));
+}
+
+#[test]
+fn rv64ud_p_fmadd() {
+    assert_eq!(1, instruction_test("rv64ud-p-fmadd"));
+}
+
+#[test]
+fn rv64ud_p_fmin() {
+    assert_eq!(1, instruction_test("rv64ud-p-fmin"));
+}
+
+#[test]
+fn rv64ud_p_ldst() {
+    assert_eq!(1, instruction_test("rv64ud-p-ldst"));
+}
+
+#[test]
+fn rv64ud_p_move() {
+    assert_eq!(1, instruction_test("rv64ud-p-move"));
+}
+
+#[test]
+fn rv64ud_p_recoding() {
+    assert_eq!(1, instruction_test("rv64ud-p-recoding"));
+}
+
+#[test]
+fn rv64ud_p_structural() {
+    assert_eq!(1, instruction_test("rv64ud-p-structural"));
+}
+
+//***********************************************************************
+// rv64ud (RV64D user-level, Double-Precision Floating-Point), virtual memory is enabled
+//***********************************************************************
+#[test]
+fn rv64ud_v_fadd() {
+    assert_eq!(1, instruction_test("rv64ud-v-fadd"));
+}
+
+#[test]
+fn rv64ud_v_fclass() {
+    assert_eq!(1, instruction_test("rv64ud-v-fclass"));
+}
+
+#[test]
+fn rv64ud_v_fcmp() {
+    assert_eq!(1, instruction_test("rv64ud-v-fcmp"));
+}
+
+#[test]
+fn rv64ud_v_fcvt() {
+    assert_eq!(1, instruction_test("rv64ud-v-fcvt"));
+}
+
+#[test]
+fn rv64ud_v_fcvt_w() {
+    assert_eq!(1, instruction_test("rv64ud-v-fcvt_w"));
+}
+
+#[test]
+fn rv64ud_v_fdiv() {
+    assert_eq!(1, instruction_test("rv64ud-v-fdiv"));
+}
+
+#[test]
+fn rv64ud_v_fmadd() {
+    assert_eq!(1, instruction_test("rv64ud-v-fmadd"));
+}
+
+#[test]
+fn rv64ud_v_fmin() {
+    assert_eq!(1, instruction_test("rv64ud-v-fmin"));
+}
+
+#[test]
+fn rv64ud_v_ldst() {
+    assert_eq!(1, instruction_test("rv64ud-v-ldst"));
+}
+
+#[test]
+fn rv64ud_v_move() {
+    assert_eq!(1, instruction_test("rv64ud-v-move"));
+}
+
+#[test]
+fn rv64ud_v_recoding() {
+    assert_eq!(1, instruction_test("rv64ud-v-recoding"));
+}
+
+#[test]
+fn rv64ud_v_structural() {
+    assert_eq!(1, instruction_test("rv64ud-v-structural"));
+}
+
 //***********************************************************************
 // rv32/64si (supervisor-level), integer only
 //***********************************************************************
@@ -1535,8 +1999,10 @@ fn rv32si_p_scall() {
 //#[test]
 //fn rv32si_p_wfi() { assert_eq!(1, instruction_test("rv32si-p-wfi")); }
 
-//#[test]
-//fn rv64si_p_csr() { assert_eq!(1, instruction_test("rv64si-p-csr")); }
+#[test]
+fn rv64si_p_csr() {
+    assert_eq!(1, instruction_test("rv64si-p-csr"));
+}
 
 #[test]
 fn rv64si_p_icache() {
@@ -1590,8 +2056,10 @@ fn rv32mi_p_ma_addr() {
     assert_eq!(1, instruction_test("rv32mi-p-ma_addr"));
 }
 
-//#[test]
-//fn rv32mi_p_mcsr() { assert_eq!(1, instruction_test("rv32mi-p-mcsr")); }
+#[test]
+fn rv32mi_p_mcsr() {
+    assert_eq!(1, instruction_test("rv32mi-p-mcsr"));
+}
 
 #[test]
 fn rv32mi_p_scall() {
@@ -1601,16 +2069,20 @@ fn rv32mi_p_scall() {
 //#[test]
 //fn rv64mi_p_access() { assert_eq!(1, instruction_test("rv64mi-p-access")); }
 
-//#[test]
-//fn rv64mi_p_csr() { assert_eq!(1, instruction_test("rv64mi-p-csr")); }
+#[test]
+fn rv64mi_p_csr() {
+    assert_eq!(1, instruction_test("rv64mi-p-csr"));
+}
 
 #[test]
 fn rv64mi_p_ma_addr() {
     assert_eq!(1, instruction_test("rv64mi-p-ma_addr"));
 }
 
-//#[test]
-//fn rv64mi_p_mcsr() { assert_eq!(1, instruction_test("rv64mi-p-mcsr")); }
+#[test]
+fn rv64mi_p_mcsr() {
+    assert_eq!(1, instruction_test("rv64mi-p-mcsr"));
+}
 
 #[test]
 fn rv64mi_p_scall() {
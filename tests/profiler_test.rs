@@ -0,0 +1,72 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::elf;
+use riscv_emu::console::TtyDummy;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+
+/// main calls f, which calls k through t0 and g through ra, and loops.
+const PROGRAM: [u32; 10] = [
+    // main
+    0x008000ef, // jal ra,f
+    0x00000013, // nop
+    // f
+    0x00000297, // auipc t0,0
+    0x01428293, // addi t0,t0,20
+    0x000282e7, // jalr t0,0(t0)
+    0x010000ef, // jal ra,g
+    0x0000006f, // j .
+    // k
+    0x00150513, // addi a0,a0,1
+    0x00028067, // jalr x0,0(t0)
+    // g
+    0x00008067, // jalr x0,0(ra)
+];
+const FUNCTIONS: [(&str, u64); 4] = [("main", 0x0), ("f", 0x8), ("k", 0x1c), ("g", 0x24)];
+
+fn profiled_emulator() -> Emulator {
+    let mut emu = Emulator::new(Machine::QemuVirt, Box::new(TtyDummy::new()), false);
+    emu.load_program_from_binary(elf(&PROGRAM, &FUNCTIONS, &[]));
+    emu.set_profiling(true);
+    emu
+}
+
+#[test]
+fn folded_stacks_of_calls_and_returns() {
+    let mut emu = profiled_emulator();
+    // main: jal, f: auipc, addi and jalr, k: addi and jalr, f: jal, g: jalr,
+    // and f: 4 times j.
+    emu.run_steps(12);
+    assert_eq!(12, emu.get_profiler().get_instruction_count());
+
+    let mut data = vec![];
+    emu.get_profiler()
+        .write_folded(emu.get_symbols(), &mut data)
+        .unwrap();
+    // jal ra and jalr t0,0(t0) push a frame, which jalr x0,0(t0) and jalr
+    // x0,0(ra) pop, so that the loop is counted in f.
+    assert_eq!(
+        "f 8\n\
+         f;g 1\n\
+         f;k 2\n\
+         main 1\n",
+        String::from_utf8(data).unwrap()
+    );
+
+    let functions: Vec<(String, u64, u64)> = emu
+        .get_hot_functions()
+        .into_iter()
+        .map(|function| (function.name, function.self_count, function.total_count))
+        .collect();
+    assert_eq!(
+        vec![
+            (String::from("f"), 8, 11),
+            (String::from("k"), 2, 2),
+            (String::from("g"), 1, 1),
+            (String::from("main"), 1, 1),
+        ],
+        functions
+    );
+}
@@ -0,0 +1,98 @@
+extern crate riscv_emu;
+
+mod common;
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use common::{console_emulator, words, BufferConsole};
+use riscv_emu::emulator::Emulator;
+
+const A0: usize = 10;
+const A1: usize = 11;
+
+/// Sums the bytes read from the UART into a0, and XORs the seeds into a1.
+const PROGRAM: [u32; 6] = [
+    0x100002b7, // lui t0,0x10000
+    0x0002c303, // lbu t1,0(t0)
+    0x00650533, // add a0,a0,t1
+    0x015013f3, // csrrw t2,seed,x0
+    0x0075c5b3, // xor a1,a1,t2
+    0xff1ff06f, // j -16
+];
+
+/// The UART takes a byte from the console every 65536 cycles.
+const STEPS: u32 = 0x40000;
+
+/// Writer whose data is kept after the emulator drops it.
+struct SharedWriter(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn reading_emulator(input: &[u8], seed: u64) -> Emulator {
+    let mut emu = console_emulator(words(&PROGRAM), Box::new(BufferConsole::new(input)));
+    emu.set_entropy_seed(seed);
+    emu
+}
+
+fn registers(emu: &mut Emulator) -> Vec<u64> {
+    (0..32).map(|i| emu.read_register(i)).collect()
+}
+
+#[test]
+fn replay_console_and_seed() {
+    let log = Rc::new(RefCell::new(vec![]));
+    let mut emu = reading_emulator(b"abc", 0x1234);
+    emu.record_inputs(Box::new(SharedWriter(log.clone())));
+    emu.run_steps(STEPS);
+    emu.stop_inputs();
+    let expected = registers(&mut emu);
+    assert_eq!(0x61 + 0x62 + 0x63, expected[A0]);
+    assert_ne!(0, expected[A1]);
+
+    let log = log.borrow().clone();
+    let text = String::from_utf8(log.clone()).unwrap();
+    assert!(
+        text.starts_with("riscv_emu input log 1\n0 entropy "),
+        "{}",
+        text
+    );
+    assert_eq!(3, text.matches(" console ").count(), "{}", text);
+
+    // the replay takes neither the input of the console nor the seed of the emulator.
+    let mut emu = reading_emulator(b"xyz", 0x5678);
+    emu.replay_inputs_from_binary(&log).unwrap();
+    emu.run_steps(STEPS);
+    assert_eq!(expected, registers(&mut emu));
+
+    // without the log, the run differs.
+    let mut emu = reading_emulator(b"xyz", 0x5678);
+    emu.run_steps(STEPS);
+    let registers = registers(&mut emu);
+    assert_ne!(expected[A0], registers[A0]);
+    assert_ne!(expected[A1], registers[A1]);
+}
+
+#[test]
+fn replay_rejects_other_start() {
+    let log = Rc::new(RefCell::new(vec![]));
+    let mut emu = reading_emulator(b"", 0x1234);
+    emu.record_inputs(Box::new(SharedWriter(log.clone())));
+    emu.run_steps(10);
+    emu.stop_inputs();
+
+    let mut emu = reading_emulator(b"", 0x1234);
+    emu.run_steps(1);
+    assert!(emu.replay_inputs_from_binary(&log.borrow()).is_err());
+    assert!(emu.replay_inputs_from_binary(b"not a log").is_err());
+}
@@ -0,0 +1,218 @@
+extern crate riscv_emu;
+
+mod common;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use common::{console_emulator, emulator, words, BufferConsole, DATA_ADDRESS, DRAM_BASE};
+use riscv_emu::console::Console;
+use riscv_emu::cpu::mmu::Watchpoint;
+use riscv_emu::emulator::{Breakpoint, Emulator, StopConditions, StopReason};
+
+const S0: usize = 8;
+const A0: usize = 10;
+const A1: usize = 11;
+
+/// A checkpoint holds the whole DRAM, so the tests take a few of them.
+const INTERVAL: u64 = 1 << 20;
+const CAPACITY: usize = 4;
+
+const PROGRAM: [u32; 3] = [
+    0x00150513, // addi a0,a0,1
+    0x00a43023, // sd a0,0(s0)
+    0xff9ff06f, // j -8
+];
+
+fn reverse_emulator() -> Emulator {
+    let mut emu = emulator(words(&PROGRAM));
+    emu.write_register(S0, DATA_ADDRESS);
+    emu.enable_reverse_execution(INTERVAL, CAPACITY);
+    emu
+}
+
+fn registers(emu: &mut Emulator) -> Vec<u64> {
+    (0..32).map(|i| emu.read_register(i)).collect()
+}
+
+#[test]
+fn reverse_step_restores_registers() {
+    let mut emu = reverse_emulator();
+    let mut states = vec![];
+    for _ in 0..5 {
+        states.push((registers(&mut emu), emu.get_pc()));
+        emu.step();
+    }
+
+    // each reverse step goes back to the state before the instruction.
+    while let Some(state) = states.pop() {
+        match emu.reverse_step() {
+            StopReason::InstructionLimit => {}
+            reason => panic!("{:?}", reason),
+        }
+        assert_eq!(state, (registers(&mut emu), emu.get_pc()));
+    }
+    match emu.reverse_step() {
+        StopReason::HistoryStart => {}
+        reason => panic!("{:?}", reason),
+    }
+    assert_eq!(0, emu.get_cycle());
+}
+
+#[test]
+fn reverse_until_last_hit() {
+    let mut emu = reverse_emulator();
+    // addi, sd and j 3 times, and addi.
+    emu.run_steps(10);
+    assert_eq!(4, emu.read_register(A0));
+
+    // stops before the last store, which is not performed yet.
+    let reason = emu.reverse_until(&StopConditions {
+        watchpoints: vec![Watchpoint {
+            address: DATA_ADDRESS,
+            size: 8,
+            read: false,
+            write: true,
+        }],
+        ..StopConditions::default()
+    });
+    match reason {
+        StopReason::Watchpoint {
+            address: DATA_ADDRESS,
+            write: true,
+        } => {}
+        reason => panic!("{:?}", reason),
+    }
+    assert_eq!(DRAM_BASE + 4, emu.get_pc());
+    assert_eq!(3, emu.read_register(A0));
+    assert_eq!(2, emu.read_physical_memory(DATA_ADDRESS, 8).unwrap());
+
+    // the breakpoint at the PC of the current state is not met again.
+    let breakpoint = DRAM_BASE + 8;
+    let reason = emu.reverse_until(&StopConditions {
+        breakpoints: vec![Breakpoint::Physical(breakpoint)],
+        ..StopConditions::default()
+    });
+    match reason {
+        StopReason::Breakpoint(address) if address == breakpoint => {}
+        reason => panic!("{:?}", reason),
+    }
+    assert_eq!(breakpoint, emu.get_pc());
+    assert_eq!(2, emu.read_register(A0));
+
+    // running forward again reaches the same state.
+    emu.run_steps(5);
+    assert_eq!(4, emu.read_register(A0));
+    assert_eq!(3, emu.read_physical_memory(DATA_ADDRESS, 8).unwrap());
+}
+
+#[test]
+fn register_write_truncates_history() {
+    let mut emu = reverse_emulator();
+    emu.run_steps(10);
+    let reason = emu.reverse_until(&StopConditions {
+        instruction_limit: Some(4),
+        ..StopConditions::default()
+    });
+    match reason {
+        StopReason::InstructionLimit => {}
+        reason => panic!("{:?}", reason),
+    }
+    assert_eq!(2, emu.read_register(A0));
+
+    // the history after the write is executed again with the written value.
+    emu.write_register(A1, 0x55);
+    emu.run_steps(4);
+    assert_eq!(4, emu.read_register(A0));
+    assert_eq!(0x55, emu.read_register(A1));
+    emu.reverse_step();
+    assert_eq!(3, emu.read_register(A0));
+    assert_eq!(0x55, emu.read_register(A1));
+}
+
+//***********************************************************************
+// Console
+//***********************************************************************
+/// Echoes the bytes read from the UART, and sums them into a0.
+const ECHO_PROGRAM: [u32; 6] = [
+    0x100002b7, // lui t0,0x10000
+    0x0002c303, // lbu t1,0(t0)
+    0xfe030ee3, // beqz t1,-4
+    0x00650533, // add a0,a0,t1
+    0x00628023, // sb t1,0(t0)
+    0xff1ff06f, // j -16
+];
+
+/// The UART takes a byte from the console every 65536 cycles.
+const ECHO_STEPS: u32 = 0x40000;
+
+/// Console like a terminal, whose input is typed once and whose output is
+/// not kept in the snapshots.
+struct Terminal {
+    input: VecDeque<u8>,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Console for Terminal {
+    fn putchar(&mut self, c: u8) {
+        self.output.borrow_mut().push(c);
+    }
+
+    fn getchar(&mut self) -> u8 {
+        self.input.pop_front().unwrap_or(0)
+    }
+
+    fn set_input(&mut self, c: u8) {
+        self.input.push_back(c);
+    }
+
+    fn get_output(&mut self) -> u8 {
+        0
+    }
+}
+
+#[test]
+fn replayed_console_input() {
+    let output = Rc::new(RefCell::new(vec![]));
+    let terminal = Terminal {
+        input: b"abc".iter().copied().collect(),
+        output: output.clone(),
+    };
+    let mut emu = console_emulator(words(&ECHO_PROGRAM), Box::new(terminal));
+    emu.enable_reverse_execution(INTERVAL, CAPACITY);
+    emu.run_steps(ECHO_STEPS);
+    let expected = registers(&mut emu);
+    assert_eq!(0x61 + 0x62 + 0x63, expected[A0]);
+    assert_eq!(b"abc", &output.borrow()[..]);
+
+    // the terminal has no input left, so the bytes come from the history.
+    let reason = emu.reverse_until(&StopConditions {
+        instruction_limit: Some(ECHO_STEPS as u64 - 0x10000),
+        ..StopConditions::default()
+    });
+    match reason {
+        StopReason::InstructionLimit => {}
+        reason => panic!("{:?}", reason),
+    }
+    assert!(emu.read_register(A0) < expected[A0]);
+    emu.run_steps(ECHO_STEPS - 0x10000);
+    assert_eq!(expected, registers(&mut emu));
+
+    // the output of the cycles executed again is not printed twice.
+    assert_eq!(b"abc", &output.borrow()[..]);
+}
+
+#[test]
+fn console_buffers_kept() {
+    let mut emu = console_emulator(words(&ECHO_PROGRAM), Box::new(BufferConsole::new(b"abc")));
+    emu.enable_reverse_execution(INTERVAL, CAPACITY);
+    emu.run_steps(ECHO_STEPS);
+    assert_eq!((vec![], b"abc".to_vec()), emu.get_console().get_buffers());
+
+    // the checkpoint does not take back the input read and the output written.
+    emu.reverse_step();
+    assert_eq!((vec![], b"abc".to_vec()), emu.get_console().get_buffers());
+    emu.step();
+    assert_eq!((vec![], b"abc".to_vec()), emu.get_console().get_buffers());
+}
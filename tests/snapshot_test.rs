@@ -0,0 +1,94 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{console_emulator, emulator, words, BufferConsole, DRAM_BASE};
+use riscv_emu::console::TtyDummy;
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+use riscv_emu::snapshot::SnapshotError;
+
+/// Prints 'a' to 'p' on the UART forever.
+const PROGRAM: [u32; 6] = [
+    0x100002b7, // lui t0,0x10000
+    0x00150513, // addi a0,a0,1
+    0x00f57313, // andi t1,a0,15
+    0x06130313, // addi t1,t1,97
+    0x00628023, // sb t1,0(t0)
+    0xff1ff06f, // j -16
+];
+
+fn printing_emulator() -> Emulator {
+    console_emulator(words(&PROGRAM), Box::new(BufferConsole::new(&[])))
+}
+
+/// Returns the registers, the PC and the console output.
+fn state(emu: &mut Emulator) -> (Vec<u64>, u64, Vec<u8>) {
+    let registers = (0..32).map(|i| emu.read_register(i)).collect();
+    (registers, emu.get_pc(), emu.get_console().get_buffers().1)
+}
+
+// A snapshot holds the whole DRAM, so the tests save as few as they can.
+#[test]
+fn load_and_run_again() {
+    let mut emu = printing_emulator();
+    emu.run_steps(40);
+    let snapshot = emu.save_snapshot_to_binary();
+
+    emu.run_steps(400);
+    let expected = state(&mut emu);
+    assert!(expected.2.len() > 4);
+
+    emu.load_snapshot_from_binary(&snapshot).unwrap();
+    assert!(state(&mut emu).2.len() < expected.2.len());
+    emu.run_steps(400);
+    assert_eq!(expected, state(&mut emu));
+
+    // another emulator of the same machine continues the run as well.
+    let mut other = printing_emulator();
+    other.load_snapshot_from_binary(&snapshot).unwrap();
+    other.run_steps(400);
+    assert_eq!(expected, state(&mut other));
+}
+
+#[test]
+fn load_rejects_bad_snapshot() {
+    let snapshot = printing_emulator().save_snapshot_to_binary();
+
+    let mut emu = Emulator::new(Machine::SiFiveU, Box::new(TtyDummy::new()), false);
+    match emu.load_snapshot_from_binary(&snapshot) {
+        Err(SnapshotError::MachineMismatch) => {}
+        result => panic!("{:?}", result),
+    }
+
+    let mut emu = emulator(vec![]);
+    let mut data = snapshot.clone();
+    data[0] ^= 0xff;
+    match emu.load_snapshot_from_binary(&data) {
+        Err(SnapshotError::InvalidMagic) => {}
+        result => panic!("{:?}", result),
+    }
+
+    // the version follows the magic.
+    let mut data = snapshot.clone();
+    data[8] = data[8].wrapping_add(1);
+    match emu.load_snapshot_from_binary(&data) {
+        Err(SnapshotError::UnsupportedVersion(_)) => {}
+        result => panic!("{:?}", result),
+    }
+
+    // the tag of the hart follows the machine.
+    let mut data = snapshot.clone();
+    data[13] ^= 0xff;
+    match emu.load_snapshot_from_binary(&data) {
+        Err(SnapshotError::Corrupted(_)) => {}
+        result => panic!("{:?}", result),
+    }
+
+    match emu.load_snapshot_from_binary(&snapshot[..snapshot.len() / 2]) {
+        Err(SnapshotError::Corrupted(_)) => {}
+        result => panic!("{:?}", result),
+    }
+    emu.load_snapshot_from_binary(&snapshot).unwrap();
+    assert_eq!(DRAM_BASE, emu.get_pc());
+}
@@ -0,0 +1,164 @@
+extern crate riscv_emu;
+
+mod common;
+
+use common::{emulator, words, DATA_ADDRESS, DRAM_BASE};
+use riscv_emu::cpu::cpu::Privilege;
+use riscv_emu::cpu::cpu_csr::{CSR_MEPC, CSR_MSTATUS, CSR_MTVEC, CSR_SPTBR, CSR_STATUS_MPP};
+use riscv_emu::cpu::trap::Exception;
+use riscv_emu::emulator::{Emulator, StopConditions};
+
+const T1: usize = 6;
+const S0: usize = 8;
+
+const UART_ADDRESS: u64 = 0x1000_0000;
+
+/// Accesses the DRAM and the UART in M-mode, and traps from U-mode.
+const PROGRAM: [u32; 8] = [
+    0x00a43023, // sd a0,0(s0)
+    0x00043583, // ld a1,0(s0)
+    0x00034283, // lbu t0,0(t1)
+    0x05050505, // c.addi a0,1; c.addi a0,1
+    0x30200073, // mret
+    0x00150513, // addi a0,a0,1
+    0x00000073, // ecall
+    0x0000006f, // j .
+];
+const USER: u64 = DRAM_BASE + 0x14;
+const HANDLER: u64 = DRAM_BASE + 0x1c;
+
+fn stats_emulator() -> Emulator {
+    let mut emu = emulator(words(&PROGRAM));
+    emu.write_register(S0, DATA_ADDRESS);
+    emu.write_register(T1, UART_ADDRESS);
+    emu.write_csr(CSR_MEPC, USER);
+    emu.write_csr(CSR_MTVEC, HANDLER);
+    let mstatus = emu.read_csr(CSR_MSTATUS);
+    emu.write_csr(CSR_MSTATUS, mstatus & !CSR_STATUS_MPP);
+    emu.set_statistics(true);
+    emu
+}
+
+fn mnemonics(emu: &Emulator) -> Vec<(&'static str, u64)> {
+    let mut mnemonics: Vec<_> = emu
+        .get_statistics()
+        .mnemonics
+        .iter()
+        .map(|(mnemonic, count)| (*mnemonic, *count))
+        .collect();
+    mnemonics.sort();
+    mnemonics
+}
+
+#[test]
+fn instruction_mix_and_privileges() {
+    let mut emu = stats_emulator();
+    // 6 instructions in M-mode, addi and ecall in U-mode, and twice j.
+    emu.run_steps(10);
+    let stats = emu.get_statistics();
+    assert_eq!(10, stats.cycles);
+
+    // the ecall is counted as the trap, and c.addi as addi.
+    assert_eq!(9, stats.instructions);
+    assert_eq!(2, stats.compressed_instructions);
+    assert_eq!(
+        vec![
+            ("addi", 3),
+            ("jal", 2),
+            ("lbu", 1),
+            ("ld", 1),
+            ("mret", 1),
+            ("sd", 1),
+        ],
+        mnemonics(&emu)
+    );
+    assert_eq!(
+        vec![(Exception::EnvironmentCallFromUMode, 1)],
+        stats.exceptions
+    );
+    assert!(stats.interrupts.is_empty());
+
+    assert_eq!(8, stats.privilege_cycles[Privilege::Machine as usize]);
+    assert_eq!(2, stats.privilege_cycles[Privilege::User as usize]);
+    assert_eq!(0, stats.privilege_cycles[Privilege::Supervisor as usize]);
+
+    // the instruction fetches are not counted.
+    let mut regions: Vec<_> = stats
+        .regions
+        .iter()
+        .map(|(region, accesses)| (*region, accesses.loads, accesses.stores))
+        .collect();
+    regions.sort();
+    assert_eq!(vec![("dram", 1, 1), ("uart", 1, 0)], regions);
+    assert_eq!(0, stats.page_walks);
+
+    let report = stats.to_string();
+    assert!(report.starts_with("Cycles: 10\n"), "{}", report);
+}
+
+#[test]
+fn statistics_stopped_and_cleared() {
+    let mut emu = stats_emulator();
+    emu.run_steps(3);
+    emu.set_statistics(false);
+    emu.run_steps(2);
+    assert_eq!(3, emu.get_statistics().cycles);
+
+    // the counters are kept until they are cleared.
+    emu.set_statistics(true);
+    emu.run_steps(1);
+    assert_eq!(4, emu.get_statistics().instructions);
+    emu.clear_statistics();
+    assert_eq!(0, emu.get_statistics().instructions);
+    assert!(emu.get_statistics().mnemonics.is_empty());
+}
+
+#[test]
+fn page_walks_of_sv39() {
+    // the gigapage at 0x8000_0000 maps the DRAM to itself.
+    let root = DRAM_BASE + 0x10000;
+    let mut emu = emulator(words(&[
+        0x00043583, // ld a1,0(s0)
+        0x00150513, // addi a0,a0,1
+    ]));
+    emu.write_physical_memory(root + 2 * 8, 8, (0x80000 << 10) | 0xcf)
+        .unwrap();
+    emu.write_csr(CSR_SPTBR, (8 << 60) | (root >> 12));
+    emu.set_privilege(Privilege::Supervisor);
+    emu.write_register(S0, DATA_ADDRESS);
+    emu.set_statistics(true);
+    emu.run_steps(2);
+
+    // the fetches and the load walk the table, which has a leaf at the root.
+    let stats = emu.get_statistics();
+    assert_eq!(2, stats.privilege_cycles[Privilege::Supervisor as usize]);
+    assert_eq!(3, stats.page_walks);
+    assert_eq!(3, stats.pte_reads);
+}
+
+#[test]
+fn replayed_cycles_counted_once() {
+    let mut reference = stats_emulator();
+    reference.run_steps(10);
+
+    let mut emu = stats_emulator();
+    emu.enable_reverse_execution(1 << 20, 4);
+    emu.run_steps(10);
+    emu.reverse_until(&StopConditions {
+        instruction_limit: Some(4),
+        ..StopConditions::default()
+    });
+    // back over twice j, addi and mret, since the ecall is not counted.
+    assert_eq!(5, emu.get_cycle());
+    assert_eq!(10, emu.get_statistics().cycles);
+
+    // the cycles executed again are not counted again.
+    emu.run_steps(5);
+    assert_eq!(10, emu.get_cycle());
+    let (stats, expected) = (emu.get_statistics(), reference.get_statistics());
+    assert_eq!(expected.cycles, stats.cycles);
+    assert_eq!(expected.instructions, stats.instructions);
+    assert_eq!(expected.privilege_cycles, stats.privilege_cycles);
+    assert_eq!(expected.exceptions, stats.exceptions);
+    assert_eq!(mnemonics(&reference), mnemonics(&emu));
+}
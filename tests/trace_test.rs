@@ -0,0 +1,323 @@
+extern crate riscv_emu;
+
+mod common;
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use common::{elf, emulator, words, DATA_ADDRESS, DRAM_BASE};
+use riscv_emu::console::TtyDummy;
+use riscv_emu::cpu::cpu::{DebugCause, Privilege};
+use riscv_emu::cpu::cpu_csr::CSR_MTVEC;
+use riscv_emu::cpu::trap::Exception;
+use riscv_emu::debug::debug_module::{DM_COMMAND, DM_DMCONTROL, DM_PROGBUF0};
+use riscv_emu::emulator::Emulator;
+use riscv_emu::machine::Machine;
+use riscv_emu::trace::{
+    BinarySink, RingBufferSink, SpikeCommitLogSink, SymbolizedSink, TextSink, TraceCategory,
+    TraceEvent, TraceFilter, TraceSink, COMMIT_LOG_CATEGORIES,
+};
+
+/// Writer into the buffer shared with the test, which reads it after the
+/// sink has written it.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const S0: usize = 8;
+
+/// Stores, loads and writes a CSR, and traps at the load from the address 0.
+const PROGRAM: [u32; 8] = [
+    0x00500513, // li a0,5
+    0x00a43023, // sd a0,0(s0)
+    0x00043583, // ld a1,0(s0)
+    0x34051673, // csrrw a2,mscratch,a0
+    0x00150013, // addi zero,a0,1
+    0x00050463, // beqz a0,8
+    0x00003683, // ld a3,0(zero)
+    0x0000006f, // j .
+];
+const HANDLER: u64 = DRAM_BASE + 0x1c;
+
+/// Returns the emulator running the program with the sink of the categories,
+/// and executes the program to the trap handler.
+fn traced_emulator(sink: Box<dyn TraceSink>, categories: &[TraceCategory]) -> Emulator {
+    let mut emu = emulator(words(&PROGRAM));
+    emu.write_register(S0, DATA_ADDRESS);
+    emu.write_csr(CSR_MTVEC, HANDLER);
+    let tracer = emu.get_tracer();
+    tracer.add_sink(sink);
+    tracer.set_categories(categories);
+    emu.run_steps(PROGRAM.len() as u32);
+    emu.get_tracer().flush();
+    emu
+}
+
+//***********************************************************************
+// Sinks
+//***********************************************************************
+#[test]
+fn text_sink() {
+    let output = SharedBuffer::default();
+    traced_emulator(Box::new(TextSink::new(output.clone())), &TraceCategory::ALL);
+    assert_eq!(
+        "[PC]: 0000000080000000 [P]: Machine |    00500513       addi       a0:0,zero:0,5\n\
+         \x20   x10 <- 0000000000000005\n\
+         [PC]: 0000000080000004 [P]: Machine |    00a43023       sd         a0:5,0(s0:80001000)\n\
+         \x20   mem W 0000000080001000 (8) 5\n\
+         [PC]: 0000000080000008 [P]: Machine |    00043583       ld         a1:1020,0(s0:80001000)\n\
+         \x20   mem R 0000000080001000 (8) 5\n\
+         \x20   x11 <- 0000000000000005\n\
+         [PC]: 000000008000000c [P]: Machine |    34051673       csrrw      a2:0,340,a0:5\n\
+         \x20   csr 340 <- 0000000000000005\n\
+         \x20   x12 <- 0000000000000000\n\
+         [PC]: 0000000080000010 [P]: Machine |    00150013       addi       zero:0,a0:5,1\n\
+         [PC]: 0000000080000014 [P]: Machine |    00050463       beq        a0:5,zero:0,8\n\
+         \x20   branch not taken 0000000080000018\n\
+         [PC]: 0000000080000018 [P]: Machine |    00003683       ld         a3:0,0(zero:0)\n\
+         \x20 >> Exception: LoadPageFault (0000000000000000) Machine, 80000018\n\
+         [PC]: 000000008000001c [P]: Machine |    0000006f       jal        zero:0,0\n\
+         \x20   branch taken 000000008000001c\n",
+        output.text()
+    );
+}
+
+/// Returns the kind, the count, the PC and the privilege of a binary record.
+fn binary_header(kind: u8, count: u64, pc: u64) -> Vec<u8> {
+    let mut data = vec![kind];
+    data.extend_from_slice(&count.to_le_bytes());
+    data.extend_from_slice(&pc.to_le_bytes());
+    data.push(Privilege::Machine as u8);
+    data
+}
+
+#[test]
+fn binary_sink() {
+    // the instruction and its register write.
+    let output = SharedBuffer::default();
+    traced_emulator(
+        Box::new(BinarySink::new(output.clone())),
+        &[TraceCategory::Instruction, TraceCategory::RegisterWrite],
+    );
+    let mut expected = binary_header(0, 1, DRAM_BASE);
+    expected.extend_from_slice(&0x00500513u32.to_le_bytes());
+    expected.push(4);
+    expected.extend_from_slice(&0x00500513u32.to_le_bytes());
+    expected.push(1); // XLEN 64
+    expected.extend(binary_header(1, 1, DRAM_BASE));
+    expected.push(10);
+    expected.extend_from_slice(&5u64.to_le_bytes());
+    assert_eq!(expected, output.0.borrow()[..expected.len()]);
+
+    // the store and the load.
+    let output = SharedBuffer::default();
+    traced_emulator(
+        Box::new(BinarySink::new(output.clone())),
+        &[TraceCategory::MemoryAccess],
+    );
+    let mut expected = vec![];
+    for (count, write) in [(2, true), (3, false)].iter() {
+        expected.extend(binary_header(3, *count, DRAM_BASE + (*count - 1) * 4));
+        expected.extend_from_slice(&DATA_ADDRESS.to_le_bytes());
+        expected.push(8);
+        expected.push(*write as u8);
+        expected.extend_from_slice(&5u64.to_le_bytes());
+    }
+    assert_eq!(expected, *output.0.borrow());
+
+    // the trap.
+    let output = SharedBuffer::default();
+    traced_emulator(
+        Box::new(BinarySink::new(output.clone())),
+        &[TraceCategory::Trap],
+    );
+    let mut expected = binary_header(4, 7, DRAM_BASE + 0x18);
+    expected.push(Exception::LoadPageFault as u8);
+    expected.extend_from_slice(&0u64.to_le_bytes());
+    assert_eq!(expected, *output.0.borrow());
+}
+
+/// Returns the counts and the PCs of the records in the ring buffer.
+fn ring_buffer_records(capacity: usize, filter: TraceFilter) -> Vec<(u64, u64)> {
+    let sink = RingBufferSink::new(capacity);
+    let buffer = sink.get_buffer();
+    let mut emu = emulator(words(&PROGRAM));
+    emu.write_register(S0, DATA_ADDRESS);
+    emu.write_csr(CSR_MTVEC, HANDLER);
+    let tracer = emu.get_tracer();
+    tracer.add_sink(Box::new(sink));
+    tracer.set_categories(&[TraceCategory::Instruction, TraceCategory::Trap]);
+    tracer.set_filter(filter);
+    emu.run_steps(PROGRAM.len() as u32);
+    let records = buffer.borrow();
+    records
+        .iter()
+        .map(|record| (record.count, record.pc))
+        .collect()
+}
+
+#[test]
+fn ring_buffer_sink() {
+    // the latest records are kept, which are the trap and the handler.
+    assert_eq!(
+        vec![(7, DRAM_BASE + 0x18), (7, DRAM_BASE + 0x18), (8, HANDLER)],
+        ring_buffer_records(3, TraceFilter::default())
+    );
+    assert_eq!(9, ring_buffer_records(16, TraceFilter::default()).len());
+    assert!(ring_buffer_records(0, TraceFilter::default()).is_empty());
+}
+
+#[test]
+fn spike_commit_log_sink() {
+    let output = SharedBuffer::default();
+    traced_emulator(
+        Box::new(SpikeCommitLogSink::new(output.clone())),
+        &COMMIT_LOG_CATEGORIES,
+    );
+    // the writes are in the order of Spike, where x12 is before the CSR. The
+    // write to x0 is not logged, and the load which trapped is not retired.
+    assert_eq!(
+        "core   0: 3 0x0000000080000000 (0x00500513) x10 0x0000000000000005\n\
+         core   0: 3 0x0000000080000004 (0x00a43023) mem 0x0000000080001000 0x0000000000000005\n\
+         core   0: 3 0x0000000080000008 (0x00043583) x11 0x0000000000000005 mem 0x0000000080001000\n\
+         core   0: 3 0x000000008000000c (0x34051673) x12 0x0000000000000000 c832_mscratch 0x0000000000000005\n\
+         core   0: 3 0x0000000080000010 (0x00150013)\n\
+         core   0: 3 0x0000000080000014 (0x00050463)\n\
+         core   0: 3 0x000000008000001c (0x0000006f)\n",
+        output.text()
+    );
+}
+
+//***********************************************************************
+// Filter
+//***********************************************************************
+#[test]
+fn filter_by_pc_privilege_and_count() {
+    let pc_range = TraceFilter {
+        pc_range: Some((DRAM_BASE + 0x4, DRAM_BASE + 0xc)),
+        ..TraceFilter::default()
+    };
+    assert_eq!(
+        vec![(2, DRAM_BASE + 0x4), (3, DRAM_BASE + 0x8)],
+        ring_buffer_records(16, pc_range)
+    );
+
+    // the trap belongs to the instruction which raised it.
+    let count_range = TraceFilter {
+        count_range: Some((7, 8)),
+        ..TraceFilter::default()
+    };
+    assert_eq!(
+        vec![(7, DRAM_BASE + 0x18), (7, DRAM_BASE + 0x18)],
+        ring_buffer_records(16, count_range)
+    );
+
+    let user = TraceFilter {
+        privileges: vec![Privilege::User],
+        ..TraceFilter::default()
+    };
+    assert!(ring_buffer_records(16, user).is_empty());
+    let machine = TraceFilter {
+        privileges: vec![Privilege::User, Privilege::Machine],
+        ..TraceFilter::default()
+    };
+    assert_eq!(9, ring_buffer_records(16, machine).len());
+
+    // all the conditions have to be met.
+    let both = TraceFilter {
+        pc_range: Some((DRAM_BASE, DRAM_BASE + 0x10)),
+        count_range: Some((3, 16)),
+        ..TraceFilter::default()
+    };
+    assert_eq!(
+        vec![(3, DRAM_BASE + 0x8), (4, DRAM_BASE + 0xc)],
+        ring_buffer_records(16, both)
+    );
+}
+
+//***********************************************************************
+// Events
+//***********************************************************************
+#[test]
+fn debug_entry_and_program_buffer_exception() {
+    let mut emu = emulator(words(&[0x0000006f])); // j .
+    let output = SharedBuffer::default();
+    let ring = RingBufferSink::new(4);
+    let records = ring.get_buffer();
+    let tracer = emu.get_tracer();
+    tracer.add_sink(Box::new(ring));
+    tracer.add_sink(Box::new(TextSink::new(output.clone())));
+    tracer.set_categories(&[TraceCategory::Trap]);
+
+    emu.dmi_write(DM_DMCONTROL, 0x80000001); // haltreq, dmactive
+    emu.dmi_write(DM_PROGBUF0, 0x0000007b); // unknown opcode
+    emu.dmi_write(DM_PROGBUF0 + 1, 0x00100073); // ebreak
+    emu.dmi_write(DM_COMMAND, 2 << 20 | 1 << 18); // postexec
+
+    let events: Vec<TraceEvent> = records
+        .borrow()
+        .iter()
+        .map(|record| record.event.clone())
+        .collect();
+    match &events[..] {
+        [TraceEvent::DebugEntry {
+            cause: DebugCause::HaltRequest,
+            dpc: DRAM_BASE,
+        }, TraceEvent::ProgramBufferException {
+            exception: Exception::IllegalInstruction,
+            value: 0,
+        }] => {}
+        events => panic!("{:?}", events),
+    }
+    assert_eq!(
+        "  >> Enter debug mode: HaltRequest (0000000080000000)\n\
+         \x20 >> Exception in program buffer: IllegalInstruction (0000000000000000)\n",
+        output.text()
+    );
+}
+
+/// main calls f, which returns.
+const CALL_PROGRAM: [u32; 3] = [
+    // main
+    0x008000ef, // jal ra,f
+    0x0000006f, // j .
+    // f
+    0x00008067, // ret
+];
+
+#[test]
+fn symbolized_calls_and_returns() {
+    let mut emu = Emulator::new(Machine::QemuVirt, Box::new(TtyDummy::new()), false);
+    let output = SharedBuffer::default();
+    // the sink takes the symbols of the program loaded after it is added.
+    let tracer = emu.get_tracer();
+    tracer.add_sink(Box::new(SymbolizedSink::new(output.clone())));
+    tracer.set_categories(&[TraceCategory::Instruction, TraceCategory::Call]);
+    emu.load_program_from_binary(elf(&CALL_PROGRAM, &[("main", 0x0), ("f", 0x8)], &[]));
+    emu.run_steps(3);
+    assert_eq!(
+        "0000000080000000 <main>    008000ef    jal        ra:0,8\n\
+         \x20 >> Call: f (0000000080000008) from main\n\
+         0000000080000008 <f>    00008067    jalr       zero:0,0(ra:80000004)\n\
+         \x20 >> Return: to main+0x4 (0000000080000004) from f\n\
+         0000000080000004 <main+0x4>    0000006f    jal        zero:0,0\n",
+        output.text()
+    );
+}
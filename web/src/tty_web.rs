@@ -37,4 +37,16 @@ impl Console for TtyWeb {
             false => 0,
         }
     }
+
+    fn get_buffers(&self) -> (Vec<u8>, Vec<u8>) {
+        (
+            self.queue_i.iter().cloned().collect(),
+            self.queue_o.iter().cloned().collect(),
+        )
+    }
+
+    fn set_buffers(&mut self, input: Vec<u8>, output: Vec<u8>) {
+        self.queue_i = input.into_iter().collect();
+        self.queue_o = output.into_iter().collect();
+    }
 }